@@ -0,0 +1,370 @@
+//! Animations of a bounding box over time, with each time step rendered as one frame
+
+use crate::colormap::{clip_rgba, Colormap};
+use crate::format::{EncodeOptions, TileFormat};
+use crate::label;
+use anyhow::anyhow;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Rgba, RgbaImage};
+use rayon::prelude::*;
+use rocket::http::ContentType;
+use std::io::Write;
+use std::path::Path;
+use tiler::aggregate::TimeStep;
+use tiler::bounds::Bounds;
+use tiler::expr::Expr;
+
+/// Most frames in one animation
+pub const MAX_FRAMES: usize = 400;
+/// Largest frame width or height
+pub const MAX_FRAME_SIZE: usize = 2048;
+/// Most pixels over all frames, which are held in memory until they're encoded: about 800 MB of RGBA
+pub const MAX_PIXELS: usize = 200_000_000;
+
+/// How an animation is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    /// Animated PNG, which keeps full color unlike GIF
+    Apng,
+    /// A zip of PNG frames, e.g. for a video encoder
+    Zip,
+}
+
+impl AnimationFormat {
+    /// Format for an output file, by its extension
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gif") => Ok(AnimationFormat::Gif),
+            Some("png") | Some("apng") => Ok(AnimationFormat::Apng),
+            Some("zip") => Ok(AnimationFormat::Zip),
+            _ => Err(anyhow!("Unknown animation format for {:?}, expected .gif, .apng or .zip", path)),
+        }
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            AnimationFormat::Gif => ContentType::GIF,
+            AnimationFormat::Apng => ContentType::new("image", "apng"),
+            AnimationFormat::Zip => ContentType::ZIP,
+        }
+    }
+}
+
+/// What to draw on each frame
+pub struct AnimationOptions {
+    /// Lat/lng box, drawn equirectangular
+    pub bounds: Bounds,
+    pub width: usize,
+    pub height: usize,
+    pub expr: Expr,
+    pub lat_name: String,
+    pub lon_name: String,
+    pub colormap: Colormap,
+    /// Draw the date and a legend over each frame
+    pub overlay: bool,
+}
+
+/// Frame height that keeps a lat/lng box in proportion at a width, or None if the box has no area
+pub fn fit_height(bounds: &Bounds, width: usize) -> Option<usize> {
+    let (lng_span, lat_span) = (bounds.max_x - bounds.min_x, bounds.max_y - bounds.min_y);
+    (lng_span > 0.0 && lat_span > 0.0).then(|| ((width as f64 * lat_span / lng_span).round() as usize).max(1))
+}
+
+/// The width and height of an animation's frames, with the height from `fit_height` unless it's given. Errors if a
+/// frame is empty or more than `MAX_FRAME_SIZE` across, or the frames are over `MAX_FRAMES` or `MAX_PIXELS`.
+pub fn frame_size(
+    bounds: &Bounds,
+    width: usize,
+    height: Option<usize>,
+    frames: usize,
+) -> anyhow::Result<(usize, usize)> {
+    let height = match height {
+        Some(height) => height,
+        None => fit_height(bounds, width).ok_or_else(|| anyhow!("The bbox has no area to fit a height to"))?,
+    };
+    if width == 0 || height == 0 || width > MAX_FRAME_SIZE || height > MAX_FRAME_SIZE {
+        return Err(anyhow!("Frames must be 1 to {} pixels across, not {} x {}", MAX_FRAME_SIZE, width, height));
+    }
+    if frames > MAX_FRAMES {
+        return Err(anyhow!("{} time steps is more than the {} frame limit", frames, MAX_FRAMES));
+    }
+    if frames * width * height > MAX_PIXELS {
+        return Err(anyhow!(
+            "{} frames of {} x {} pixels is more than the {} pixel limit, use fewer steps or smaller frames",
+            frames,
+            width,
+            height,
+            MAX_PIXELS
+        ));
+    }
+    Ok((width, height))
+}
+
+/// One rendered time step
+pub struct Frame {
+    pub time: i64,
+    pub image: RgbaImage,
+}
+
+/// Render a frame for every step, in parallel. Steps without data in the box are blank frames, so the
+/// animation keeps a steady pace.
+pub fn render_frames(steps: &[TimeStep], options: &AnimationOptions) -> anyhow::Result<Vec<Frame>> {
+    // Steps share a grid, so one check of which pixels are inside it keeps the area around it transparent
+    let inside = match (options.colormap.paints_nodata(), steps.first()) {
+        (true, Some(step)) => Some(tiler::get_image_coverage(
+            &step.path,
+            options.bounds,
+            options.width,
+            options.height,
+            &options.lat_name,
+            &options.lon_name,
+        )?),
+        _ => None,
+    };
+    steps
+        .par_iter()
+        .map(|step| {
+            let data = tiler::get_image(
+                &step.path,
+                options.bounds,
+                options.width,
+                options.height,
+                &options.expr,
+                &options.lat_name,
+                &options.lon_name,
+            )?
+            .unwrap_or_else(|| vec![f64::NAN; options.width * options.height]);
+            let mut image = options.colormap.render_rgba(&data, options.width, options.height);
+            if let Some(inside) = &inside {
+                clip_rgba(&mut image, inside);
+            }
+            if options.overlay {
+                draw_overlay(&mut image, step.time, &options.colormap);
+            }
+            Ok(Frame { time: step.time, image })
+        })
+        .collect()
+}
+
+// Dates for steps at midnight, and otherwise the time too
+fn time_label(time: i64) -> String {
+    let label = tiler::time::format(time);
+    match time.rem_euclid(86400) {
+        0 => label[..10].to_string(),
+        _ => label[..16].replace('T', " "),
+    }
+}
+
+/// Short legend numbers, e.g. `0.25`, `10` or `1e-3`
+pub fn legend_value(v: f64) -> String {
+    if v != 0.0 && (v.abs() >= 10000.0 || v.abs() < 0.01) {
+        return format!("{:e}", v);
+    }
+    let s = format!("{:.2}", v);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+// The date in the top left, and a colorbar with its range in the bottom left
+fn draw_overlay(img: &mut RgbaImage, time: i64, colormap: &Colormap) {
+    let scale = (img.width() / 400).max(1);
+    let margin = 4 * scale as i64;
+    label::draw_label(img, margin, margin, &time_label(time), scale);
+
+    let (min_label, max_label) = (legend_value(colormap.min_value()), legend_value(colormap.max_value()));
+    let (text_width, text_height) = label::text_size(&format!("{}  {}", min_label, max_label), scale);
+    let bar_width = (img.width() / 3).max(text_width);
+    let bar_height = 6 * scale;
+    let pad = 2 * scale;
+    let (panel_width, panel_height) = (bar_width + 2 * pad, bar_height + text_height + 3 * pad);
+    let (x, y) = (margin, img.height() as i64 - margin - panel_height as i64);
+
+    label::blend_rect(img, x, y, panel_width, panel_height, Rgba([0, 0, 0, 160]));
+    let (bar_x, bar_y) = (x + pad as i64, y + pad as i64);
+    for i in 0..bar_width {
+        let color = colormap.ramp(i as f64 / (bar_width - 1).max(1) as f64);
+        label::fill_rect(img, bar_x + i as i64, bar_y, 1, bar_height, Rgba(color));
+    }
+    let text_y = bar_y + (bar_height + pad) as i64;
+    let white = Rgba([255, 255, 255, 255]);
+    label::draw_text(img, bar_x, text_y, &min_label, scale, white);
+    let max_x = bar_x + bar_width as i64 - label::text_size(&max_label, scale).0 as i64;
+    label::draw_text(img, max_x, text_y, &max_label, scale, white);
+}
+
+/// Encode frames as an animation, showing each for the delay
+pub fn encode(frames: Vec<Frame>, format: AnimationFormat, delay_ms: u16) -> anyhow::Result<Vec<u8>> {
+    if frames.is_empty() {
+        return Err(anyhow!("No frames to animate"));
+    }
+    let mut bytes = Vec::new();
+    match format {
+        AnimationFormat::Gif => {
+            let mut encoder = GifEncoder::new_with_speed(&mut bytes, 10);
+            encoder.set_repeat(Repeat::Infinite)?;
+            let delay = Delay::from_numer_denom_ms(delay_ms as u32, 1);
+            encoder.encode_frames(frames.into_iter().map(|f| image::Frame::from_parts(f.image, 0, 0, delay)))?;
+        }
+        AnimationFormat::Apng => {
+            let (width, height) = frames[0].image.dimensions();
+            let mut encoder = png::Encoder::new(&mut bytes, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            // Loop forever
+            encoder.set_animated(frames.len() as u32, 0)?;
+            encoder.set_frame_delay(delay_ms, 1000)?;
+            let mut writer = encoder.write_header()?;
+            for frame in &frames {
+                writer.write_image_data(frame.image.as_raw())?;
+            }
+            writer.finish()?;
+        }
+        AnimationFormat::Zip => {
+            let options = EncodeOptions::new(None, None);
+            let entries = frames
+                .iter()
+                .enumerate()
+                .map(|(i, frame)| {
+                    let name = format!("{:04}_{}.png", i, time_label(frame.time).replace([' ', ':'], "_"));
+                    Ok((name, TileFormat::Png.encode_rgba(&frame.image, &options)?))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            write_zip(&mut bytes, &entries)?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Write files to a zip archive without compression, which PNGs don't need
+pub fn write_zip(out: &mut impl Write, entries: &[(String, Vec<u8>)]) -> anyhow::Result<()> {
+    // 1980-01-01 00:00, the earliest DOS date
+    const DOS_TIME: u16 = 0;
+    const DOS_DATE: u16 = 0x21;
+
+    let mut central = Vec::new();
+    let mut offset = 0u32;
+    for (name, data) in entries {
+        let crc = crc32fast::hash(data);
+        let len = u32::try_from(data.len()).map_err(|_| anyhow!("{} is too large for a zip", name))?;
+
+        // Fields shared by the local and central headers, from the version needed to the extra field length
+        let mut fields = Vec::with_capacity(26);
+        fields.extend(20u16.to_le_bytes());
+        fields.extend(0u16.to_le_bytes()); // flags
+        fields.extend(0u16.to_le_bytes()); // stored
+        fields.extend(DOS_TIME.to_le_bytes());
+        fields.extend(DOS_DATE.to_le_bytes());
+        fields.extend(crc.to_le_bytes());
+        fields.extend(len.to_le_bytes());
+        fields.extend(len.to_le_bytes());
+        fields.extend((name.len() as u16).to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+
+        out.write_all(&0x04034b50u32.to_le_bytes())?;
+        out.write_all(&fields)?;
+        out.write_all(name.as_bytes())?;
+        out.write_all(data)?;
+
+        central.extend(0x02014b50u32.to_le_bytes());
+        central.extend(20u16.to_le_bytes()); // version made by
+        central.extend(&fields);
+        central.extend(0u16.to_le_bytes()); // comment length
+        central.extend(0u16.to_le_bytes()); // disk
+        central.extend(0u16.to_le_bytes()); // internal attributes
+        central.extend(0u32.to_le_bytes()); // external attributes
+        central.extend(offset.to_le_bytes());
+        central.extend(name.as_bytes());
+
+        offset = (30 + name.len() as u32)
+            .checked_add(len)
+            .and_then(|entry_len| offset.checked_add(entry_len))
+            .ok_or_else(|| anyhow!("Too much data for a zip"))?;
+    }
+
+    let count = u16::try_from(entries.len()).map_err(|_| anyhow!("Too many files for a zip"))?;
+    out.write_all(&central)?;
+    out.write_all(&0x06054b50u32.to_le_bytes())?;
+    out.write_all(&0u16.to_le_bytes())?; // disk
+    out.write_all(&0u16.to_le_bytes())?; // disk with the central directory
+    out.write_all(&count.to_le_bytes())?;
+    out.write_all(&count.to_le_bytes())?;
+    out.write_all(&(central.len() as u32).to_le_bytes())?;
+    out.write_all(&offset.to_le_bytes())?;
+    out.write_all(&0u16.to_le_bytes())?; // comment length
+    Ok(())
+}
+
+#[cfg(test)]
+mod animation_tests {
+    use super::*;
+    use image::AnimationDecoder;
+
+    fn frames(count: usize) -> Vec<Frame> {
+        let colormap = Colormap::new(colorous::VIRIDIS, 0.0, 10.0, false);
+        (0..count)
+            .map(|i| {
+                let data = vec![i as f64; 64 * 48];
+                let mut image = colormap.render_rgba(&data, 64, 48);
+                draw_overlay(&mut image, tiler::time::from_ymd(2023, 4, 12 + i as u32), &colormap);
+                Frame {
+                    time: tiler::time::from_ymd(2023, 4, 12 + i as u32),
+                    image,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(time_label(tiler::time::from_ymd(2023, 4, 12)), "2023-04-12");
+        assert_eq!(time_label(tiler::time::from_ymd(2023, 4, 12) + 6 * 3600), "2023-04-12 06:00");
+        assert_eq!(legend_value(10.0), "10");
+        assert_eq!(legend_value(0.25), "0.25");
+        assert_eq!(legend_value(-1.5), "-1.5");
+        assert_eq!(legend_value(0.001), "1e-3");
+        assert_eq!(fit_height(&Bounds::new(-130.0, 48.0, -122.0, 52.0), 800), Some(400));
+    }
+
+    #[test]
+    fn test_frame_size() {
+        let bounds = Bounds::new(-130.0, 48.0, -122.0, 52.0);
+        assert_eq!(frame_size(&bounds, 800, None, 10).unwrap(), (800, 400));
+        assert_eq!(frame_size(&bounds, 800, Some(600), 10).unwrap(), (800, 600));
+        // A box with no width can't be fitted, rather than growing without bound
+        assert_eq!(fit_height(&Bounds::new(-130.0, 48.0, -130.0, 52.0), 800), None);
+        assert!(frame_size(&Bounds::new(-130.0, 48.0, -130.0, 52.0), 800, None, 10).is_err());
+        assert!(frame_size(&bounds, 0, Some(400), 10).is_err());
+        assert!(frame_size(&bounds, 4096, None, 10).is_err());
+        assert!(frame_size(&bounds, 800, None, MAX_FRAMES + 1).is_err());
+        // Under each limit on its own, but too many pixels together
+        assert!(frame_size(&bounds, 2048, Some(2048), MAX_FRAMES).is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        let gif = encode(frames(3), AnimationFormat::Gif, 500).unwrap();
+        let decoder = image::codecs::gif::GifDecoder::new(&gif[..]).unwrap();
+        let decoded = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[0].delay().numer_denom_ms(), (500, 1));
+
+        let apng = encode(frames(3), AnimationFormat::Apng, 500).unwrap();
+        let reader = png::Decoder::new(&apng[..]).read_info().unwrap();
+        assert_eq!(reader.info().animation_control().unwrap().num_frames, 3);
+
+        assert!(encode(Vec::new(), AnimationFormat::Gif, 500).is_err());
+    }
+
+    #[test]
+    fn test_zip() {
+        let zip = encode(frames(2), AnimationFormat::Zip, 500).unwrap();
+        assert_eq!(&zip[..4], &[0x50, 0x4b, 0x03, 0x04]);
+        assert_eq!(&zip[30..49], b"0000_2023-04-12.png");
+        // The end of central directory record counts both frames
+        let end = &zip[zip.len() - 22..];
+        assert_eq!(&end[..4], &[0x50, 0x4b, 0x05, 0x06]);
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 2);
+        let central_offset = u32::from_le_bytes([end[16], end[17], end[18], end[19]]) as usize;
+        assert_eq!(&zip[central_offset..central_offset + 4], &[0x50, 0x4b, 0x01, 0x02]);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::mbtiles::{MbtilesReader, MbtilesWriter};
+use crate::pmtiles::{PmtilesReader, PmtilesWriter};
+use anyhow::anyhow;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tiler::bounds::Bounds;
+
+/// Description of a tile set, written into archives that have somewhere to put it
+#[derive(Debug, Clone)]
+pub struct ArchiveMetadata {
+    pub name: String,
+    /// Tile file extension, e.g. `png` or `webp`
+    pub format: String,
+    pub bounds: Bounds,
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+}
+
+/// Somewhere to put rendered tiles. Tiles are addressed in XYZ order, with y = 0 at the top.
+pub trait TileWriter: Send {
+    /// Whether the tile was already written or recorded as empty, e.g. by an interrupted run
+    fn contains(&self, z: u8, x: u32, y: u32) -> anyhow::Result<bool>;
+
+    fn write_tile(&mut self, z: u8, x: u32, y: u32, bytes: &[u8]) -> anyhow::Result<()>;
+
+    /// Record that a tile has no data, so a resumed run doesn't render it again. No tile is written for it.
+    fn write_empty(&mut self, z: u8, x: u32, y: u32) -> anyhow::Result<()>;
+
+    /// Called once after all tiles are written
+    fn finish(&mut self, metadata: &ArchiveMetadata) -> anyhow::Result<()>;
+}
+
+/// Open a writer for the output path, picked by extension: `.mbtiles`, `.pmtiles`, or else a z/x/y directory tree
+pub fn open_writer(path: &Path, extension: &str) -> anyhow::Result<Box<dyn TileWriter>> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("mbtiles") => Ok(Box::new(MbtilesWriter::open(path)?)),
+        Some("pmtiles") => Ok(Box::new(PmtilesWriter::open(path)?)),
+        _ => Ok(Box::new(DirectoryWriter::open(path, extension)?)),
+    }
+}
+
+/// A pre-built tile archive. Tiles are addressed in XYZ order, with y = 0 at the top.
+pub trait TileReader: Send + Sync {
+    /// The tile, or None if the archive doesn't have it
+    fn get_tile(&self, z: u8, x: u32, y: u32) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Tile file extension, e.g. `png` or `webp`, if the archive records it
+    fn format(&self) -> Option<&str>;
+}
+
+/// Open an `.mbtiles` or `.pmtiles` archive for reading
+pub fn open_reader(path: &Path) -> anyhow::Result<Box<dyn TileReader>> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("mbtiles") => Ok(Box::new(MbtilesReader::open(path)?)),
+        Some("pmtiles") => Ok(Box::new(PmtilesReader::open(path)?)),
+        _ => Err(anyhow!("Unknown tile archive type: {:?}", path)),
+    }
+}
+
+// Lists the tiles without data as `z/x/y` lines, in the root of a directory tree
+const EMPTY_TILES_FILE: &str = ".empty-tiles";
+
+/// Tiles as `<root>/<z>/<x>/<y>.<extension>` files
+pub struct DirectoryWriter {
+    root: PathBuf,
+    extension: String,
+    /// Tiles recorded as empty, by this run or an earlier one
+    empty: HashSet<(u8, u32, u32)>,
+}
+
+impl DirectoryWriter {
+    pub fn open(root: &Path, extension: &str) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(root)?;
+        let empty = match std::fs::read_to_string(root.join(EMPTY_TILES_FILE)) {
+            Ok(lines) => lines.lines().filter_map(parse_tile).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            root: root.to_path_buf(),
+            extension: extension.to_string(),
+            empty,
+        })
+    }
+
+    pub fn tile_path(&self, z: u8, x: u32, y: u32) -> PathBuf {
+        self.root
+            .join(z.to_string())
+            .join(x.to_string())
+            .join(format!("{}.{}", y, self.extension))
+    }
+
+    /// Every tile in the tree, as (z, x, y, path)
+    pub fn tiles(&self) -> anyhow::Result<Vec<(u8, u32, u32, PathBuf)>> {
+        let suffix = format!(".{}", self.extension);
+        let mut tiles = Vec::new();
+        for z_entry in std::fs::read_dir(&self.root)? {
+            let z_entry = z_entry?;
+            let Some(z) = z_entry.file_name().to_str().and_then(|z| z.parse().ok()) else {
+                continue;
+            };
+            for x_entry in std::fs::read_dir(z_entry.path())? {
+                let x_entry = x_entry?;
+                let Some(x) = x_entry.file_name().to_str().and_then(|x| x.parse().ok()) else {
+                    continue;
+                };
+                for y_entry in std::fs::read_dir(x_entry.path())? {
+                    let y_entry = y_entry?;
+                    let name = y_entry.file_name();
+                    let Some(y) = name.to_str().and_then(|n| n.strip_suffix(&suffix)).and_then(|y| y.parse().ok()) else {
+                        continue;
+                    };
+                    tiles.push((z, x, y, y_entry.path()));
+                }
+            }
+        }
+        Ok(tiles)
+    }
+}
+
+// A `z/x/y` line of the empty tiles file
+fn parse_tile(line: &str) -> Option<(u8, u32, u32)> {
+    let mut parts = line.split('/');
+    let tile = (parts.next()?.parse().ok()?, parts.next()?.parse().ok()?, parts.next()?.parse().ok()?);
+    parts.next().is_none().then_some(tile)
+}
+
+impl TileWriter for DirectoryWriter {
+    fn contains(&self, z: u8, x: u32, y: u32) -> anyhow::Result<bool> {
+        Ok(self.empty.contains(&(z, x, y)) || self.tile_path(z, x, y).exists())
+    }
+
+    fn write_tile(&mut self, z: u8, x: u32, y: u32, bytes: &[u8]) -> anyhow::Result<()> {
+        let path = self.tile_path(z, x, y);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write then rename, so an interrupted run never leaves half a tile behind to be skipped
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn write_empty(&mut self, z: u8, x: u32, y: u32) -> anyhow::Result<()> {
+        let path = self.root.join(EMPTY_TILES_FILE);
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}/{}/{}", z, x, y)?;
+        self.empty.insert((z, x, y));
+        Ok(())
+    }
+
+    fn finish(&mut self, _metadata: &ArchiveMetadata) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod archive_tests {
+    use super::*;
+
+    #[test]
+    fn test_directory_writer() {
+        let root = crate::testing::TempPath::new("directory_writer");
+        let mut writer = DirectoryWriter::open(&root, "png").unwrap();
+        assert!(!writer.contains(3, 1, 2).unwrap());
+        writer.write_tile(3, 1, 2, b"tile").unwrap();
+        assert!(writer.contains(3, 1, 2).unwrap());
+        assert_eq!(std::fs::read(root.join("3/1/2.png")).unwrap(), b"tile");
+
+        let tiles = writer.tiles().unwrap();
+        assert_eq!(tiles.len(), 1);
+        assert_eq!((tiles[0].0, tiles[0].1, tiles[0].2), (3, 1, 2));
+
+        // Empty tiles are remembered by a reopened writer, and aren't tiles
+        writer.write_empty(3, 4, 5).unwrap();
+        let writer = DirectoryWriter::open(&root, "png").unwrap();
+        assert!(writer.contains(3, 4, 5).unwrap());
+        assert!(!writer.contains(3, 4, 6).unwrap());
+        assert_eq!(writer.tiles().unwrap().len(), 1);
+    }
+}
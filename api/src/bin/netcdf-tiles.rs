@@ -0,0 +1,210 @@
+use api::animation::{self, AnimationFormat, AnimationOptions};
+use api::archive;
+use api::colormap::StyleParams;
+use api::format::{Compression, EncodeOptions, TileFormat, TILE_SIZES};
+use api::params::BboxParam;
+use api::seed::{self, SeedJob};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rocket::form::Form;
+use std::path::PathBuf;
+use tiler::aggregate::Aggregation;
+use tiler::expr::Expr;
+
+#[derive(Parser)]
+#[command(name = "netcdf-tiles", about = "Tools for NetCDF map tiles")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Pre-render a tile pyramid to a directory, MBTiles or PMTiles
+    Seed(SeedArgs),
+    /// Animate a bounding box over a date range as a GIF, APNG or zip of PNG frames
+    Animate(AnimateArgs),
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FormatArg {
+    Png,
+    Png8,
+    Webp,
+    Jpg,
+}
+
+#[derive(Args)]
+struct SeedArgs {
+    /// NetCDF file to render
+    #[arg(long)]
+    file: PathBuf,
+    /// Variable to render
+    #[arg(long)]
+    var: String,
+    /// Band-math expression to render instead of the variable
+    #[arg(long)]
+    expr: Option<String>,
+    #[arg(long, default_value_t = 0)]
+    min_zoom: u8,
+    #[arg(long)]
+    max_zoom: u8,
+    /// A `.mbtiles` or `.pmtiles` file, or a directory for z/x/y files
+    #[arg(long)]
+    output: PathBuf,
+    #[arg(long, value_enum, default_value_t = FormatArg::Png)]
+    format: FormatArg,
+    /// Style as a query string, the same as the tile endpoint takes, e.g. `gradient=turbo&max_value=5`
+    #[arg(long, default_value = "")]
+    style: String,
+    #[arg(long, default_value = "lat")]
+    lat_dim: String,
+    #[arg(long, default_value = "lon")]
+    lon_dim: String,
+    /// Tile size in pixels, 256 or 512
+    #[arg(long, default_value_t = 256)]
+    tile_size: usize,
+    /// Render high-DPI tiles at twice the tile size, like `@2x` URLs
+    #[arg(long)]
+    retina: bool,
+    /// JPEG quality, 1-100
+    #[arg(long)]
+    quality: Option<u8>,
+    /// Worker threads, all cores by default
+    #[arg(long)]
+    threads: Option<usize>,
+}
+
+fn run_seed(args: SeedArgs) -> anyhow::Result<()> {
+    if args.min_zoom > args.max_zoom {
+        anyhow::bail!("--min-zoom is greater than --max-zoom");
+    }
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new().num_threads(threads).build_global()?;
+    }
+
+    if !TILE_SIZES.contains(&args.tile_size) {
+        anyhow::bail!("--tile-size must be one of {:?}", TILE_SIZES);
+    }
+    let tile_size = if args.retina { args.tile_size * 2 } else { args.tile_size };
+
+    let style = Form::<StyleParams>::parse(&args.style).map_err(|e| anyhow::anyhow!("Invalid style: {}", e))?;
+    let expr = match &args.expr {
+        Some(expr) => Expr::parse(expr)?,
+        None => Expr::variable(&args.var),
+    };
+    let format = match args.format {
+        FormatArg::Png => TileFormat::Png,
+        FormatArg::Png8 => TileFormat::IndexedPng,
+        FormatArg::Webp => TileFormat::WebP,
+        FormatArg::Jpg => TileFormat::Jpeg,
+    };
+
+    let job = SeedJob {
+        dset_path: args.file,
+        expr,
+        lat_name: args.lat_dim,
+        lon_name: args.lon_dim,
+        colormap: style.colormap(),
+        format,
+        // Seeding happens once, so spend the time on smaller tiles
+        options: EncodeOptions::new(Some(Compression::Best), args.quality),
+        tile_size,
+        min_zoom: args.min_zoom,
+        max_zoom: args.max_zoom,
+    };
+
+    let writer = archive::open_writer(&args.output, seed::tile_extension(format))?;
+    let summary = seed::seed(&job, writer, &args.var)?;
+    println!(
+        "Rendered {} tiles, skipped {} already done, {} empty",
+        summary.rendered, summary.skipped, summary.empty
+    );
+    Ok(())
+}
+
+#[derive(Args)]
+struct AnimateArgs {
+    /// Path template of the daily files, e.g. `/data/chl/{year}/{month}/{day}/chl.nc`
+    #[arg(long)]
+    datasets: String,
+    #[arg(long, default_value = "time")]
+    time_var: String,
+    /// Variable to render
+    #[arg(long)]
+    var: String,
+    /// Band-math expression to render instead of the variable
+    #[arg(long)]
+    expr: Option<String>,
+    /// `min_lng,min_lat,max_lng,max_lat`
+    #[arg(long)]
+    bbox: String,
+    /// First date, e.g. `2023-04-01`. The start of the archive by default.
+    #[arg(long)]
+    start: Option<String>,
+    /// Last date, inclusive. The end of the archive by default.
+    #[arg(long)]
+    end: Option<String>,
+    /// A `.gif`, `.apng` or `.zip` file
+    #[arg(long)]
+    output: PathBuf,
+    #[arg(long, default_value_t = 800)]
+    width: usize,
+    /// In proportion to the bbox by default
+    #[arg(long)]
+    height: Option<usize>,
+    /// Milliseconds per frame
+    #[arg(long, default_value_t = 500)]
+    delay: u16,
+    /// Style as a query string, the same as the tile endpoint takes, e.g. `gradient=turbo&max_value=5`
+    #[arg(long, default_value = "")]
+    style: String,
+    /// Leave out the date and legend
+    #[arg(long)]
+    no_overlay: bool,
+    #[arg(long, default_value = "lat")]
+    lat_dim: String,
+    #[arg(long, default_value = "lon")]
+    lon_dim: String,
+}
+
+fn run_animate(args: AnimateArgs) -> anyhow::Result<()> {
+    let format = AnimationFormat::from_path(&args.output)?;
+    let bounds =
+        BboxParam::parse(&args.bbox).ok_or_else(|| anyhow::anyhow!("--bbox is not min_lng,min_lat,max_lng,max_lat"))?;
+    let style = Form::<StyleParams>::parse(&args.style).map_err(|e| anyhow::anyhow!("Invalid style: {}", e))?;
+    let expr = match &args.expr {
+        Some(expr) => Expr::parse(expr)?,
+        None => Expr::variable(&args.var),
+    };
+    let start = args.start.as_deref().map(tiler::time::parse).transpose()?;
+    let end = args.end.as_deref().map(tiler::time::parse_end).transpose()?;
+
+    let datasets = Aggregation::discover(&args.datasets, &args.time_var)?;
+    let steps = datasets.between(start, end)?;
+    if steps.is_empty() {
+        anyhow::bail!("No files from {:?} in the date range", args.datasets);
+    }
+    let (width, height) = animation::frame_size(&bounds, args.width, args.height, steps.len())?;
+
+    let options = AnimationOptions {
+        bounds,
+        width,
+        height,
+        expr,
+        lat_name: args.lat_dim,
+        lon_name: args.lon_dim,
+        colormap: style.colormap(),
+        overlay: !args.no_overlay,
+    };
+    let frames = animation::render_frames(&steps, &options)?;
+    std::fs::write(&args.output, animation::encode(frames, format, args.delay)?)?;
+    println!("Rendered {} frames to {:?}", steps.len(), args.output);
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    match Cli::parse().command {
+        Command::Seed(args) => run_seed(args),
+        Command::Animate(args) => run_animate(args),
+    }
+}
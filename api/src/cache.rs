@@ -0,0 +1,363 @@
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// `[default.tile_cache]` in Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CacheConfig {
+    /// Number of tiles kept in memory
+    pub memory_entries: usize,
+    /// Where to keep rendered tiles on disk, if anywhere
+    pub disk_dir: Option<PathBuf>,
+    /// Default `Cache-Control` max-age in seconds
+    pub max_age: u32,
+    /// max-age overrides by variable, e.g. for variables that are updated more often than others
+    pub variables: HashMap<String, u32>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            memory_entries: 1024,
+            disk_dir: None,
+            max_age: 3600,
+            variables: HashMap::new(),
+        }
+    }
+}
+
+/// A rendered tile and its strong ETag
+#[derive(Debug, Clone)]
+pub struct CachedTile {
+    pub bytes: Arc<[u8]>,
+    pub content_type: ContentType,
+    pub etag: String,
+}
+
+impl CachedTile {
+    pub fn new(bytes: Vec<u8>, content_type: ContentType) -> Self {
+        let etag = format!("\"{:016x}\"", fnv1a(&bytes));
+        Self {
+            bytes: bytes.into(),
+            content_type,
+            etag,
+        }
+    }
+}
+
+// FNV-1a, which unlike the std hasher is stable across builds, so ETags survive restarts
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+struct MemoryEntry {
+    tile: CachedTile,
+    source_mtime: SystemTime,
+    last_used: u64,
+}
+
+// Least recently used eviction, with `order` mapping use counters back to keys
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<String, MemoryEntry>,
+    order: BTreeMap<u64, String>,
+    counter: u64,
+}
+
+impl Lru {
+    fn get(&mut self, key: &str, source_mtime: SystemTime) -> Option<CachedTile> {
+        let entry = self.entries.get_mut(key)?;
+        if entry.source_mtime != source_mtime {
+            let last_used = entry.last_used;
+            self.entries.remove(key);
+            self.order.remove(&last_used);
+            return None;
+        }
+        self.counter += 1;
+        self.order.remove(&entry.last_used);
+        self.order.insert(self.counter, key.to_string());
+        entry.last_used = self.counter;
+        Some(entry.tile.clone())
+    }
+
+    fn insert(&mut self, key: String, tile: CachedTile, source_mtime: SystemTime, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        self.counter += 1;
+        if let Some(old) = self.entries.remove(&key) {
+            self.order.remove(&old.last_used);
+        }
+        while self.entries.len() >= capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        self.order.insert(self.counter, key.clone());
+        self.entries.insert(
+            key,
+            MemoryEntry {
+                tile,
+                source_mtime,
+                last_used: self.counter,
+            },
+        );
+    }
+}
+
+/// Rendered tiles keyed by request, in memory and optionally on disk.
+/// Entries are dropped when the source file has been modified since they were rendered.
+pub struct TileCache {
+    config: CacheConfig,
+    memory: Mutex<Lru>,
+}
+
+impl TileCache {
+    pub fn new(config: CacheConfig) -> Self {
+        if let Some(dir) = &config.disk_dir {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                println!("Error: can't create tile cache dir {:?}: {}", dir, e);
+            }
+        }
+        Self {
+            config,
+            memory: Mutex::new(Lru::default()),
+        }
+    }
+
+    /// A key for the request, which covers the dataset, time, variable, style params and z/x/y.
+    /// The format is added since the same URI can be negotiated to different formats.
+    pub fn key(uri: &str, format: &str) -> String {
+        format!("{} {}", format, uri)
+    }
+
+    /// The `Cache-Control` max-age for tiles of a variable
+    pub fn max_age(&self, var: &str) -> u32 {
+        self.config.variables.get(var).copied().unwrap_or(self.config.max_age)
+    }
+
+    /// The cached tile for the key, or else the rendered one. Errors aren't cached.
+    pub fn get_or_render<E>(
+        &self,
+        key: &str,
+        source: &Path,
+        render: impl FnOnce() -> Result<CachedTile, E>,
+    ) -> Result<CachedTile, E> {
+        self.get_or_render_all(key, &[source], render)
+    }
+
+    /// Like `get_or_render`, for tiles made from several files, e.g. composites. Entries last until any file changes.
+    pub fn get_or_render_all<E>(
+        &self,
+        key: &str,
+        sources: &[&Path],
+        render: impl FnOnce() -> Result<CachedTile, E>,
+    ) -> Result<CachedTile, E> {
+        // Without source files there's nothing to invalidate against, so don't cache
+        let mtimes: Result<Vec<SystemTime>, _> =
+            sources.iter().map(|source| std::fs::metadata(source).and_then(|m| m.modified())).collect();
+        let source_mtime = match mtimes.map(|mtimes| mtimes.into_iter().max()) {
+            Ok(Some(mtime)) => mtime,
+            _ => return render(),
+        };
+
+        if let Some(tile) = self.memory.lock().unwrap().get(key, source_mtime) {
+            return Ok(tile);
+        }
+        if let Some(tile) = self.read_disk(key, source_mtime) {
+            self.insert_memory(key, &tile, source_mtime);
+            return Ok(tile);
+        }
+
+        let tile = render()?;
+        self.insert_memory(key, &tile, source_mtime);
+        self.write_disk(key, &tile);
+        Ok(tile)
+    }
+
+    fn insert_memory(&self, key: &str, tile: &CachedTile, source_mtime: SystemTime) {
+        self.memory
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), tile.clone(), source_mtime, self.config.memory_entries);
+    }
+
+    fn disk_path(&self, key: &str) -> Option<PathBuf> {
+        let dir = self.config.disk_dir.as_ref()?;
+        Some(dir.join(format!("{:016x}.tile", fnv1a(key.as_bytes()))))
+    }
+
+    // Disk entries hold the key and content type on the first two lines, then the tile.
+    // They're valid as long as they were written after the source was last modified.
+    fn read_disk(&self, key: &str, source_mtime: SystemTime) -> Option<CachedTile> {
+        let path = self.disk_path(key)?;
+        let written = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+        if written < source_mtime {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+
+        let data = std::fs::read(&path).ok()?;
+        let mut parts = data.splitn(3, |b| *b == b'\n');
+        let (stored_key, content_type, bytes) = (parts.next()?, parts.next()?, parts.next()?);
+        if stored_key != key.as_bytes() {
+            return None;
+        }
+        let content_type = ContentType::parse_flexible(std::str::from_utf8(content_type).ok()?)?;
+        Some(CachedTile::new(bytes.to_vec(), content_type))
+    }
+
+    fn write_disk(&self, key: &str, tile: &CachedTile) {
+        let Some(path) = self.disk_path(key) else {
+            return;
+        };
+        let mut data = format!("{}\n{}\n", key, tile.content_type).into_bytes();
+        data.extend_from_slice(&tile.bytes);
+
+        // Write then rename, so a concurrent read never sees half a tile. The temp name is unique to this write, so
+        // processes and threads writing the same tile don't rename each other's half written files.
+        static WRITES: AtomicUsize = AtomicUsize::new(0);
+        let tmp = path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        if let Err(e) = std::fs::write(&tmp, data).and_then(|_| std::fs::rename(&tmp, &path)) {
+            let _ = std::fs::remove_file(&tmp);
+            println!("Error: can't write tile cache entry {:?}: {}", path, e);
+        }
+    }
+}
+
+// Whether an If-None-Match header value matches the ETag
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// A tile response with caching headers, or 304 if the client already has it
+pub struct TileResponse {
+    pub tile: CachedTile,
+    pub max_age: u32,
+}
+
+impl<'r> Responder<'r, 'static> for TileResponse {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let not_modified = req
+            .headers()
+            .get("If-None-Match")
+            .any(|value| etag_matches(value, &self.tile.etag));
+
+        let mut builder = Response::build();
+        builder
+            .raw_header("ETag", self.tile.etag)
+            .raw_header("Cache-Control", format!("public, max-age={}", self.max_age))
+            // The same URL may be served as different formats depending on the Accept header
+            .raw_header("Vary", "Accept");
+        if not_modified {
+            builder.status(Status::NotModified);
+        } else {
+            builder
+                .header(self.tile.content_type)
+                .sized_body(self.tile.bytes.len(), Cursor::new(self.tile.bytes));
+        }
+        builder.ok()
+    }
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn tile(byte: u8) -> CachedTile {
+        CachedTile::new(vec![byte; 4], ContentType::PNG)
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let mtime = SystemTime::UNIX_EPOCH;
+        let mut lru = Lru::default();
+        lru.insert("a".to_string(), tile(1), mtime, 2);
+        lru.insert("b".to_string(), tile(2), mtime, 2);
+        // Using a makes b the oldest
+        assert!(lru.get("a", mtime).is_some());
+        lru.insert("c".to_string(), tile(3), mtime, 2);
+
+        assert!(lru.get("a", mtime).is_some());
+        assert!(lru.get("b", mtime).is_none());
+        assert!(lru.get("c", mtime).is_some());
+        assert_eq!(lru.entries.len(), lru.order.len());
+    }
+
+    #[test]
+    fn test_lru_mtime_invalidation() {
+        let mtime = SystemTime::UNIX_EPOCH;
+        let mut lru = Lru::default();
+        lru.insert("a".to_string(), tile(1), mtime, 2);
+        assert!(lru.get("a", mtime + Duration::from_secs(1)).is_none());
+        assert!(lru.get("a", mtime).is_none());
+        assert!(lru.order.is_empty());
+    }
+
+    #[test]
+    fn test_etag() {
+        assert_eq!(tile(1).etag, tile(1).etag);
+        assert_ne!(tile(1).etag, tile(2).etag);
+        assert!(tile(1).etag.starts_with('"'));
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches("\"abc\"", "\"abc\""));
+        assert!(etag_matches("\"xyz\", W/\"abc\"", "\"abc\""));
+        assert!(etag_matches("*", "\"abc\""));
+        assert!(!etag_matches("\"xyz\"", "\"abc\""));
+    }
+
+    #[test]
+    fn test_disk_cache() {
+        let dir = crate::testing::TempPath::dir("tile_cache");
+        let source = dir.join("source.nc");
+        std::fs::write(&source, b"data").unwrap();
+
+        let config = CacheConfig {
+            disk_dir: Some(dir.join("tiles")),
+            ..Default::default()
+        };
+        let rendered = TileCache::new(config.clone())
+            .get_or_render::<()>("key", &source, || Ok(tile(7)))
+            .unwrap();
+
+        // A fresh cache with an empty memory tier reads the tile back from disk
+        let cached = TileCache::new(config.clone())
+            .get_or_render::<()>("key", &source, || panic!("should be cached"))
+            .unwrap();
+        assert_eq!(cached.etag, rendered.etag);
+        assert_eq!(cached.content_type, ContentType::PNG);
+        assert_eq!(&*cached.bytes, &[7; 4]);
+
+        // Threads writing the same entry each use their own temp file, and leave only the entry behind
+        let cache = TileCache::new(config);
+        std::thread::scope(|scope| {
+            for byte in 0..8 {
+                let cache = &cache;
+                scope.spawn(move || cache.write_disk("key", &tile(byte)));
+            }
+        });
+        let names: Vec<_> = std::fs::read_dir(dir.join("tiles")).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names.len(), 1, "{:?}", names);
+        assert!(cache.read_disk("key", SystemTime::UNIX_EPOCH).is_some());
+    }
+}
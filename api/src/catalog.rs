@@ -0,0 +1,153 @@
+use crate::archive::{self, TileReader};
+use rocket::serde::Deserialize;
+use std::path::PathBuf;
+
+/// `[[default.archives]]` in Rocket.toml: a pre-seeded archive standing in for one variable on one day
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ArchiveConfig {
+    pub var: String,
+    /// `YYYY-MM-DD`
+    pub date: String,
+    pub path: PathBuf,
+    /// The style query string the archive was seeded with. Only requests with the same style are served from it.
+    #[serde(default)]
+    pub style: String,
+    /// Pixel size of the archived tiles, including any `@2x` scaling
+    #[serde(default = "default_tile_size")]
+    pub tile_size: usize,
+}
+
+fn default_tile_size() -> usize {
+    tiler::TILE_SIZE
+}
+
+struct MountedArchive {
+    var: String,
+    date: (u16, u8, u8),
+    style: Vec<String>,
+    tile_size: usize,
+    reader: Box<dyn TileReader>,
+}
+
+// Query parameters in a canonical order, so `a=1&b=2` matches `b=2&a=1`.
+// The tile size is matched on its own, and the row is already XYZ whatever the scheme, so they're left out.
+fn normalize_query(query: &str) -> Vec<String> {
+    let mut params: Vec<String> = query
+        .split('&')
+        .filter(|p| !p.is_empty() && !p.starts_with("tile_size=") && !p.starts_with("scheme="))
+        .map(str::to_string)
+        .collect();
+    params.sort();
+    params
+}
+
+fn parse_date(date: &str) -> Option<(u16, u8, u8)> {
+    let mut parts = date.splitn(3, '-');
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+/// Pre-seeded tile archives that are served ahead of live rendering
+#[derive(Default)]
+pub struct ArchiveCatalog {
+    archives: Vec<MountedArchive>,
+}
+
+impl ArchiveCatalog {
+    /// Open every configured archive. Ones that can't be opened are logged and left out,
+    /// so their tiles are rendered live instead.
+    pub fn open(configs: &[ArchiveConfig]) -> Self {
+        let mut archives = Vec::new();
+        for config in configs {
+            let Some(date) = parse_date(&config.date) else {
+                println!("Error: invalid date {:?} for archive {:?}", config.date, config.path);
+                continue;
+            };
+            match archive::open_reader(&config.path) {
+                Ok(reader) => archives.push(MountedArchive {
+                    var: config.var.clone(),
+                    date,
+                    style: normalize_query(&config.style),
+                    tile_size: config.tile_size,
+                    reader,
+                }),
+                Err(e) => println!("Error: can't open archive {:?}: {}", config.path, e),
+            }
+        }
+        Self { archives }
+    }
+
+    /// The archived tile for the request, if an archive was seeded for it with the same style, format and size
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_tile(
+        &self,
+        var: &str,
+        (year, month, day): (u16, u8, u8),
+        query: &str,
+        extension: &str,
+        tile_size: usize,
+        z: u8,
+        x: u32,
+        y: u32,
+    ) -> Option<Vec<u8>> {
+        let query = normalize_query(query);
+        self.archives
+            .iter()
+            .filter(|a| a.var == var && a.date == (year, month, day) && a.style == query && a.tile_size == tile_size)
+            .filter(|a| a.reader.format().is_none_or(|format| format == extension))
+            .find_map(|a| match a.reader.get_tile(z, x, y) {
+                Ok(tile) => tile,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    None
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod catalog_tests {
+    use super::*;
+    use crate::archive::{ArchiveMetadata, TileWriter};
+    use crate::mbtiles::MbtilesWriter;
+    use tiler::bounds::Bounds;
+
+    #[test]
+    fn test_catalog() {
+        let path = crate::testing::TempPath::new("catalog.mbtiles");
+        let mut writer = MbtilesWriter::open(&path).unwrap();
+        writer.write_tile(1, 0, 1, b"tile").unwrap();
+        writer
+            .finish(&ArchiveMetadata {
+                name: "chl".to_string(),
+                format: "png".to_string(),
+                bounds: Bounds::new(-180.0, -85.0, 180.0, 85.0),
+                min_zoom: 0,
+                max_zoom: 1,
+            })
+            .unwrap();
+        drop(writer);
+
+        let catalog = ArchiveCatalog::open(&[ArchiveConfig {
+            var: "chl".to_string(),
+            date: "2023-04-12".to_string(),
+            path: path.to_path_buf(),
+            style: "max_value=5&gradient=turbo".to_string(),
+            tile_size: 256,
+        }]);
+        let date = (2023, 4, 12);
+        let style = "gradient=turbo&max_value=5";
+        let style_512 = "gradient=turbo&tile_size=512&max_value=5";
+
+        assert_eq!(catalog.get_tile("chl", date, style, "png", 256, 1, 0, 1), Some(b"tile".to_vec()));
+        let tms_style = "scheme=tms&gradient=turbo&max_value=5";
+        assert_eq!(catalog.get_tile("chl", date, tms_style, "png", 256, 1, 0, 1), Some(b"tile".to_vec()));
+        // Missing tiles, other styles, formats, days and variables all fall through
+        assert_eq!(catalog.get_tile("chl", date, style, "png", 256, 1, 1, 1), None);
+        assert_eq!(catalog.get_tile("chl", date, "gradient=turbo", "png", 256, 1, 0, 1), None);
+        assert_eq!(catalog.get_tile("chl", date, style, "webp", 256, 1, 0, 1), None);
+        assert_eq!(catalog.get_tile("chl", date, style_512, "png", 512, 1, 0, 1), None);
+        assert_eq!(catalog.get_tile("chl", (2023, 4, 13), style, "png", 256, 1, 0, 1), None);
+        assert_eq!(catalog.get_tile("sst", date, style, "png", 256, 1, 0, 1), None);
+    }
+}
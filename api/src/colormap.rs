@@ -0,0 +1,485 @@
+
+use crate::format::{EncodeOptions, TileFormat};
+use image::RgbaImage;
+use rocket::form::{self, FromFormField, ValueField};
+use std::str::FromStr;
+
+// Palette entries reserved ahead of the gradient steps: nodata, below range, above range, clipped
+const PALETTE_RESERVED: usize = 4;
+// Transparent palette entry for pixels that aren't kept, unlike nodata which can have a color
+const PALETTE_CLIPPED: u8 = 3;
+// Number of gradient steps that fit in the rest of an 8-bit palette
+const PALETTE_STEPS: usize = 256 - PALETTE_RESERVED;
+
+const TRANSPARENT: Rgba = Rgba([0, 0, 0, 0]);
+
+/// An RGBA color, parsed from `rrggbb` or `rrggbbaa` hex with an optional leading `#`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgba(pub [u8; 4]);
+
+impl FromStr for Rgba {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim_start_matches('#');
+        if hex.len() != 6 && hex.len() != 8 {
+            return Err(anyhow::anyhow!("Invalid color: {}", s));
+        }
+        let mut rgba = [255; 4];
+        for (i, c) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+            *c = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+        }
+        Ok(Rgba(rgba))
+    }
+}
+
+/// How to draw values outside of `[min_value, max_value]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutOfRange {
+    /// Use the color at the nearest end of the gradient
+    Clamp,
+    Transparent,
+    Color(Rgba),
+}
+
+impl FromStr for OutOfRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(OutOfRange::Clamp),
+            "transparent" => Ok(OutOfRange::Transparent),
+            _ => Ok(OutOfRange::Color(s.parse()?)),
+        }
+    }
+}
+
+impl<'v> FromFormField<'v> for Rgba {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        field
+            .value
+            .parse()
+            .map_err(|e: anyhow::Error| form::Error::validation(e.to_string()).into())
+    }
+}
+
+impl<'v> FromFormField<'v> for OutOfRange {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        field
+            .value
+            .parse()
+            .map_err(|e: anyhow::Error| form::Error::validation(e.to_string()).into())
+    }
+}
+
+pub use style::StyleParams;
+
+// The FromForm derive emits a lint attribute that newer compilers have removed
+#[allow(renamed_and_removed_lints)]
+mod style {
+    use super::{Colormap, OutOfRange, Rgba};
+
+    /// Rendering query parameters shared by the image endpoints
+    #[derive(Debug, FromForm)]
+    pub struct StyleParams<'r> {
+        pub(super) min_value: Option<f64>,
+        pub(super) max_value: Option<f64>,
+        pub(super) log_scale: Option<bool>,
+        pub(super) gradient: Option<&'r str>,
+        /// Layer opacity from 0 to 1
+        pub(super) opacity: Option<f64>,
+        /// Color for missing data
+        pub(super) nodata_color: Option<Rgba>,
+        pub(super) below: Option<OutOfRange>,
+        pub(super) above: Option<OutOfRange>,
+        /// Fraction of the value range above `min_value` over which alpha fades in
+        pub(super) alpha_ramp: Option<f64>,
+        /// Run the gradient from its end to its start
+        pub(super) reverse: Option<bool>,
+    }
+
+    impl StyleParams<'_> {
+        pub fn colormap(&self) -> Colormap {
+            let gradient = Colormap::gradient_from_name(self.gradient);
+            let colormap = Colormap::new(
+                gradient,
+                self.min_value.unwrap_or(0.0),
+                self.max_value.unwrap_or(10.0),
+                self.log_scale.unwrap_or(false),
+            );
+            self.apply(colormap)
+        }
+
+        /// A colormap for anomalies, centred on no change: zero for differences, or one on a log scale for ratios.
+        /// Blue below and red above by default, and values past either end are clamped.
+        pub fn diverging_colormap(&self, ratio: bool) -> Colormap {
+            let gradient = match self.gradient {
+                Some(_) => Colormap::gradient_from_name(self.gradient),
+                None => colorous::RED_BLUE,
+            };
+            let (max_value, min_value) = match ratio {
+                true => {
+                    let max_value = self.max_value.unwrap_or(10.0);
+                    (max_value, self.min_value.unwrap_or(1.0 / max_value))
+                }
+                false => {
+                    let max_value = self.max_value.unwrap_or(1.0);
+                    (max_value, self.min_value.unwrap_or(-max_value))
+                }
+            };
+            let mut colormap = Colormap::new(gradient, min_value, max_value, ratio || self.log_scale.unwrap_or(false));
+            colormap.reverse = self.gradient.is_none();
+            colormap.below = OutOfRange::Clamp;
+            self.apply(colormap)
+        }
+
+        fn apply(&self, mut colormap: Colormap) -> Colormap {
+            if let Some(opacity) = self.opacity {
+                colormap.opacity = opacity.clamp(0.0, 1.0);
+            }
+            if let Some(nodata) = self.nodata_color {
+                colormap.nodata = nodata;
+            }
+            if let Some(below) = self.below {
+                colormap.below = below;
+            }
+            if let Some(above) = self.above {
+                colormap.above = above;
+            }
+            if let Some(alpha_ramp) = self.alpha_ramp {
+                colormap.alpha_ramp = alpha_ramp.max(0.0);
+            }
+            if let Some(reverse) = self.reverse {
+                colormap.reverse = reverse;
+            }
+            colormap
+        }
+    }
+}
+
+// Where a value falls relative to the colormap range
+enum Class {
+    Nodata,
+    Below,
+    Above,
+    // Position along the gradient from 0 to 1
+    Value(f64),
+}
+
+pub struct Colormap {
+    gradient: colorous::Gradient,
+    min_value: f64,
+    max_value: f64,
+    log_scale: bool,
+    pub opacity: f64,
+    pub nodata: Rgba,
+    pub below: OutOfRange,
+    pub above: OutOfRange,
+    pub alpha_ramp: f64,
+    pub reverse: bool,
+}
+
+impl Colormap {
+    pub fn new(gradient: colorous::Gradient, min_value: f64, max_value: f64, log_scale: bool) -> Self {
+        Self {
+            gradient,
+            min_value,
+            max_value,
+            log_scale,
+            opacity: 1.0,
+            nodata: TRANSPARENT,
+            below: OutOfRange::Transparent,
+            above: OutOfRange::Clamp,
+            alpha_ramp: 0.0,
+            reverse: false,
+        }
+    }
+
+    pub fn gradient_from_name(name: Option<&str>) -> colorous::Gradient {
+        // TODO: This is gross
+        match name {
+            Some("turbo") => colorous::TURBO,
+            Some("viridis") => colorous::VIRIDIS,
+            Some("inferno") => colorous::INFERNO,
+            Some("magma") => colorous::MAGMA,
+            Some("plasma") => colorous::PLASMA,
+            Some("cividis") => colorous::CIVIDIS,
+            Some("warm") => colorous::WARM,
+            Some("cool") => colorous::COOL,
+            Some("cubehelix") => colorous::CUBEHELIX,
+            Some("rainbow") => colorous::RAINBOW,
+            Some("sinebow") => colorous::SINEBOW,
+            Some("greens") => colorous::GREENS,
+            Some("bluegreen") => colorous::BLUE_GREEN,
+            Some("greys") => colorous::GREYS,
+            // Diverging
+            Some("redblue") => colorous::RED_BLUE,
+            Some("redyellowblue") => colorous::RED_YELLOW_BLUE,
+            Some("browngreen") => colorous::BROWN_GREEN,
+            Some("purpleorange") => colorous::PURPLE_ORANGE,
+            Some("spectral") => colorous::SPECTRAL,
+            _ => colorous::VIRIDIS,
+        }
+    }
+
+    pub fn min_value(&self) -> f64 {
+        self.min_value
+    }
+
+    pub fn max_value(&self) -> f64 {
+        self.max_value
+    }
+
+    /// Whether missing values get a visible color, so pixels outside the dataset need telling apart from them
+    pub fn paints_nodata(&self) -> bool {
+        self.nodata.0[3] > 0
+    }
+
+    /// Color at a fraction t along the gradient, e.g. for drawing a legend
+    pub fn ramp(&self, t: f64) -> [u8; 4] {
+        self.apply_opacity(self.ramp_color(t.clamp(0.0, 1.0)))
+    }
+
+    fn classify(&self, v: f64) -> Class {
+        if v.is_nan() {
+            return Class::Nodata;
+        }
+        if v <= self.min_value {
+            return Class::Below;
+        }
+        if v > self.max_value {
+            return Class::Above;
+        }
+        let t = if self.log_scale {
+            (v.log10() - self.min_value.log10()) / (self.max_value.log10() - self.min_value.log10())
+        } else {
+            (v - self.min_value) / (self.max_value - self.min_value)
+        };
+        Class::Value(t)
+    }
+
+    // Gradient color at t, faded in over the alpha ramp
+    fn ramp_color(&self, t: f64) -> Rgba {
+        let c = self.gradient.eval_continuous(if self.reverse { 1.0 - t } else { t });
+        let alpha = if self.alpha_ramp > 0.0 {
+            (t / self.alpha_ramp).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        Rgba([c.r, c.g, c.b, (alpha * 255.0).round() as u8])
+    }
+
+    fn out_of_range_color(&self, mode: OutOfRange, t: f64) -> Rgba {
+        match mode {
+            OutOfRange::Clamp => self.ramp_color(t),
+            OutOfRange::Transparent => TRANSPARENT,
+            OutOfRange::Color(c) => c,
+        }
+    }
+
+    fn apply_opacity(&self, c: Rgba) -> [u8; 4] {
+        let [r, g, b, a] = c.0;
+        [r, g, b, (a as f64 * self.opacity).round() as u8]
+    }
+
+    fn color(&self, v: f64) -> [u8; 4] {
+        let c = match self.classify(v) {
+            Class::Nodata => self.nodata,
+            Class::Below => self.out_of_range_color(self.below, 0.0),
+            Class::Above => self.out_of_range_color(self.above, 1.0),
+            Class::Value(t) => self.ramp_color(t),
+        };
+        self.apply_opacity(c)
+    }
+
+    pub fn render_rgba(&self, data: &[f64], width: usize, height: usize) -> RgbaImage {
+        let mut imgbuf = RgbaImage::new(width as u32, height as u32);
+
+        data.iter().enumerate().for_each(|(i, v)| {
+            let c = self.color(*v);
+            if c[3] > 0 {
+                let x = i % width;
+                let y = i / width;
+                imgbuf.put_pixel(x as u32, y as u32, image::Rgba(c));
+            }
+        });
+
+        imgbuf
+    }
+
+    /// Palette used by `render_indexed`
+    pub fn palette(&self) -> Vec<[u8; 4]> {
+        let mut palette = Vec::with_capacity(PALETTE_RESERVED + PALETTE_STEPS);
+        palette.push(self.apply_opacity(self.nodata));
+        palette.push(self.apply_opacity(self.out_of_range_color(self.below, 0.0)));
+        palette.push(self.apply_opacity(self.out_of_range_color(self.above, 1.0)));
+        palette.push(TRANSPARENT.0);
+        for i in 0..PALETTE_STEPS {
+            let t = i as f64 / (PALETTE_STEPS - 1) as f64;
+            palette.push(self.apply_opacity(self.ramp_color(t)));
+        }
+        palette
+    }
+
+    /// Map each value to an index into `palette`
+    pub fn render_indexed(&self, data: &[f64]) -> Vec<u8> {
+        data.iter()
+            .map(|v| match self.classify(*v) {
+                Class::Nodata => 0,
+                Class::Below => 1,
+                Class::Above => 2,
+                Class::Value(t) => {
+                    let step = (t.clamp(0.0, 1.0) * (PALETTE_STEPS - 1) as f64).round() as usize;
+                    (PALETTE_RESERVED + step) as u8
+                }
+            })
+            .collect()
+    }
+
+    /// Colormap a `size` x `size` tile of values and encode it as an image
+    pub fn encode_tile(
+        &self,
+        data: &[f64],
+        size: usize,
+        format: TileFormat,
+        options: &EncodeOptions,
+    ) -> anyhow::Result<Vec<u8>> {
+        if format.is_indexed() {
+            let indices = self.render_indexed(data);
+            format.encode_indexed(&indices, &self.palette(), size as u32, size as u32, options)
+        } else {
+            let imgbuf = self.render_rgba(data, size, size);
+            format.encode_rgba(&imgbuf, options)
+        }
+    }
+
+    /// Like `encode_tile`, but pixels that aren't kept are transparent, even with a nodata color
+    pub fn encode_clipped_tile(
+        &self,
+        data: &[f64],
+        kept: &[bool],
+        size: usize,
+        format: TileFormat,
+        options: &EncodeOptions,
+    ) -> anyhow::Result<Vec<u8>> {
+        if format.is_indexed() {
+            let mut indices = self.render_indexed(data);
+            for (index, kept) in indices.iter_mut().zip(kept) {
+                if !kept {
+                    *index = PALETTE_CLIPPED;
+                }
+            }
+            format.encode_indexed(&indices, &self.palette(), size as u32, size as u32, options)
+        } else {
+            let mut imgbuf = self.render_rgba(data, size, size);
+            clip_rgba(&mut imgbuf, kept);
+            format.encode_rgba(&imgbuf, options)
+        }
+    }
+}
+
+/// Make the pixels that aren't kept transparent, with `kept` in row order
+pub fn clip_rgba(imgbuf: &mut RgbaImage, kept: &[bool]) {
+    for (pixel, kept) in imgbuf.pixels_mut().zip(kept) {
+        if !kept {
+            *pixel = image::Rgba(TRANSPARENT.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod colormap_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_colors() {
+        assert_eq!("ff0000".parse::<Rgba>().unwrap(), Rgba([255, 0, 0, 255]));
+        assert_eq!("#00ff0080".parse::<Rgba>().unwrap(), Rgba([0, 255, 0, 128]));
+        assert!("fff".parse::<Rgba>().is_err());
+        assert!("gg0000".parse::<Rgba>().is_err());
+
+        assert_eq!("clamp".parse::<OutOfRange>().unwrap(), OutOfRange::Clamp);
+        assert_eq!("transparent".parse::<OutOfRange>().unwrap(), OutOfRange::Transparent);
+        assert_eq!(
+            "000000".parse::<OutOfRange>().unwrap(),
+            OutOfRange::Color(Rgba([0, 0, 0, 255]))
+        );
+    }
+
+    #[test]
+    fn test_render_indexed() {
+        let cmap = Colormap::new(colorous::VIRIDIS, 0.0, 10.0, false);
+        let indices = cmap.render_indexed(&[-1.0, 0.0, f64::NAN, 5.0, 10.0, 20.0]);
+        assert_eq!(indices, vec![1, 1, 0, 130, 255, 2]);
+    }
+
+    #[test]
+    fn test_palette_matches_rgba() {
+        let mut cmap = Colormap::new(colorous::VIRIDIS, 0.0, 10.0, false);
+        cmap.below = OutOfRange::Color(Rgba([1, 2, 3, 255]));
+        cmap.opacity = 0.5;
+        let palette = cmap.palette();
+        assert_eq!(palette.len(), 256);
+
+        // The last value falls exactly on a gradient step
+        let values = [f64::NAN, -1.0, 20.0, 10.0, 1000.0 / (PALETTE_STEPS - 1) as f64];
+        let img = cmap.render_rgba(&values, values.len(), 1);
+        for (i, index) in cmap.render_indexed(&values).iter().enumerate() {
+            assert_eq!(img.get_pixel(i as u32, 0).0, palette[*index as usize]);
+        }
+    }
+
+    #[test]
+    fn test_encode_clipped_tile() {
+        let mut cmap = Colormap::new(colorous::VIRIDIS, 0.0, 10.0, false);
+        cmap.nodata = Rgba([255, 0, 0, 255]);
+        let (values, kept) = ([f64::NAN, 5.0, 5.0, f64::NAN], [true, true, false, false]);
+        let options = EncodeOptions::new(None, None);
+
+        let png = cmap.encode_clipped_tile(&values, &kept, 2, TileFormat::Png, &options).unwrap();
+        let alphas: Vec<u8> = image::load_from_memory(&png).unwrap().to_rgba8().pixels().map(|p| p.0[3]).collect();
+        assert_eq!(alphas, [255, 255, 0, 0]);
+
+        let png8 = cmap.encode_clipped_tile(&values, &kept, 2, TileFormat::IndexedPng, &options).unwrap();
+        let alphas: Vec<u8> = image::load_from_memory(&png8).unwrap().to_rgba8().pixels().map(|p| p.0[3]).collect();
+        assert_eq!(alphas, [255, 255, 0, 0]);
+    }
+
+    #[test]
+    fn test_alpha_ramp() {
+        let mut cmap = Colormap::new(colorous::VIRIDIS, 0.0, 10.0, false);
+        cmap.alpha_ramp = 0.5;
+        assert_eq!(cmap.color(1.0)[3], 51);
+        assert_eq!(cmap.color(5.0)[3], 255);
+        assert_eq!(cmap.color(0.0)[3], 0);
+    }
+
+    #[test]
+    fn test_diverging() {
+        let style = StyleParams {
+            min_value: None,
+            max_value: Some(2.0),
+            log_scale: None,
+            gradient: None,
+            opacity: None,
+            nodata_color: None,
+            below: None,
+            above: None,
+            alpha_ramp: None,
+            reverse: None,
+        };
+        let middle = colorous::RED_BLUE.eval_continuous(0.5);
+        let (red, blue) = (colorous::RED_BLUE.eval_continuous(0.0), colorous::RED_BLUE.eval_continuous(1.0));
+
+        // Differences are centred on zero, with negative values blue and values past the range clamped
+        let cmap = style.diverging_colormap(false);
+        assert_eq!(cmap.color(0.0), [middle.r, middle.g, middle.b, 255]);
+        assert_eq!(cmap.color(-5.0), [blue.r, blue.g, blue.b, 255]);
+        assert_eq!(cmap.color(2.0), [red.r, red.g, red.b, 255]);
+
+        // Ratios are centred on one, between 1/2 and 2
+        let cmap = style.diverging_colormap(true);
+        assert_eq!(cmap.color(1.0), [middle.r, middle.g, middle.b, 255]);
+        assert_eq!(cmap.color(0.5), [blue.r, blue.g, blue.b, 255]);
+    }
+}
@@ -0,0 +1,284 @@
+use anyhow::anyhow;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::{ImageEncoder, RgbaImage};
+use rocket::http::{Accept, ContentType};
+use rocket::request::FromParam;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileFormat {
+    Png,
+    /// 8-bit palette PNG with one entry per gradient step
+    IndexedPng,
+    /// Lossless WebP
+    WebP,
+    /// JPEG has no alpha channel, so transparent pixels come out black
+    Jpeg,
+}
+
+impl TileFormat {
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "png" => Some(TileFormat::Png),
+            "png8" => Some(TileFormat::IndexedPng),
+            "webp" => Some(TileFormat::WebP),
+            "jpg" | "jpeg" => Some(TileFormat::Jpeg),
+            _ => None,
+        }
+    }
+
+    /// Pick the first supported format in the client's order of preference. Wildcards get PNG.
+    pub fn from_accept(accept: &Accept) -> Option<Self> {
+        let mut media_types: Vec<_> = accept.iter().collect();
+        media_types.sort_by(|a, b| b.weight_or(1.0).total_cmp(&a.weight_or(1.0)));
+
+        media_types.into_iter().find_map(|mt| {
+            if mt.top() != "image" && mt.top() != "*" {
+                return None;
+            }
+            match mt.sub().as_str() {
+                "png" | "*" => Some(TileFormat::Png),
+                "webp" => Some(TileFormat::WebP),
+                "jpeg" => Some(TileFormat::Jpeg),
+                _ => None,
+            }
+        })
+    }
+
+    /// An explicit extension wins over content negotiation, and PNG is the default
+    pub fn negotiate(extension: Option<TileFormat>, accept: Option<&Accept>) -> Self {
+        extension
+            .or_else(|| accept.and_then(TileFormat::from_accept))
+            .unwrap_or(TileFormat::Png)
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            TileFormat::Png | TileFormat::IndexedPng => ContentType::PNG,
+            TileFormat::WebP => ContentType::WEBP,
+            TileFormat::Jpeg => ContentType::JPEG,
+        }
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        matches!(self, TileFormat::IndexedPng)
+    }
+
+    pub fn encode_rgba(&self, img: &RgbaImage, options: &EncodeOptions) -> anyhow::Result<Vec<u8>> {
+        let (width, height) = img.dimensions();
+        let mut bytes = Vec::new();
+
+        match self {
+            TileFormat::Png => {
+                PngEncoder::new_with_quality(&mut bytes, options.compression.into(), FilterType::Adaptive)
+                    .write_image(img.as_raw(), width, height, image::ColorType::Rgba8)?;
+            }
+            TileFormat::WebP => {
+                WebPEncoder::new_lossless(&mut bytes)
+                    .write_image(img.as_raw(), width, height, image::ColorType::Rgba8)?;
+            }
+            TileFormat::Jpeg => {
+                let rgb = image::DynamicImage::ImageRgba8(img.clone()).into_rgb8();
+                JpegEncoder::new_with_quality(&mut bytes, options.quality)
+                    .write_image(rgb.as_raw(), width, height, image::ColorType::Rgb8)?;
+            }
+            TileFormat::IndexedPng => {
+                return Err(anyhow!("Indexed PNG must be encoded from palette indices"));
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn encode_indexed(
+        &self,
+        indices: &[u8],
+        palette: &[[u8; 4]],
+        width: u32,
+        height: u32,
+        options: &EncodeOptions,
+    ) -> anyhow::Result<Vec<u8>> {
+        if !self.is_indexed() {
+            return Err(anyhow!("{:?} does not support palette images", self));
+        }
+
+        let rgb: Vec<u8> = palette.iter().flat_map(|c| [c[0], c[1], c[2]]).collect();
+        let trns: Vec<u8> = palette.iter().map(|c| c[3]).collect();
+
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, width, height);
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_palette(rgb);
+            encoder.set_trns(trns);
+            encoder.set_compression(options.compression.into());
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(indices)?;
+        }
+
+        Ok(bytes)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum Compression {
+    Fast,
+    Default,
+    Best,
+}
+
+impl From<Compression> for CompressionType {
+    fn from(c: Compression) -> Self {
+        match c {
+            Compression::Fast => CompressionType::Fast,
+            Compression::Default => CompressionType::Default,
+            Compression::Best => CompressionType::Best,
+        }
+    }
+}
+
+impl From<Compression> for png::Compression {
+    fn from(c: Compression) -> Self {
+        match c {
+            Compression::Fast => png::Compression::Fast,
+            Compression::Default => png::Compression::Default,
+            Compression::Best => png::Compression::Best,
+        }
+    }
+}
+
+pub struct EncodeOptions {
+    /// Deflate effort for PNG output
+    pub compression: Compression,
+    /// JPEG quality, 1-100
+    pub quality: u8,
+}
+
+impl EncodeOptions {
+    pub fn new(compression: Option<Compression>, quality: Option<u8>) -> Self {
+        let mut options = Self::default();
+        if let Some(compression) = compression {
+            options.compression = compression;
+        }
+        if let Some(quality) = quality {
+            options.quality = quality.clamp(1, 100);
+        }
+        options
+    }
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            compression: Compression::Default,
+            quality: 85,
+        }
+    }
+}
+
+/// Tile sizes in pixels that can be asked for with `tile_size`, before any `@2x` scaling
+pub const TILE_SIZES: [usize; 2] = [256, 512];
+
+/// Last path segment of a tile URL, e.g. `7`, `7.webp` or `7@2x.png`
+pub struct ZoomParam {
+    pub zoom: u32,
+    pub format: Option<TileFormat>,
+    /// 2 for `@2x` high-DPI tiles
+    pub scale: u32,
+}
+
+impl ZoomParam {
+    /// Pixel size of the tile to render, or None if the requested size isn't supported
+    pub fn tile_size(&self, tile_size: Option<usize>) -> Option<usize> {
+        let tile_size = tile_size.unwrap_or(tiler::TILE_SIZE);
+        TILE_SIZES.contains(&tile_size).then_some(tile_size * self.scale as usize)
+    }
+}
+
+impl<'a> FromParam<'a> for ZoomParam {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        let (zoom, format) = match param.split_once('.') {
+            Some((zoom, ext)) => (zoom, Some(TileFormat::from_extension(ext).ok_or(param)?)),
+            None => (param, None),
+        };
+        let (zoom, scale) = match zoom.split_once('@') {
+            Some((zoom, "1x")) => (zoom, 1),
+            Some((zoom, "2x")) => (zoom, 2),
+            Some(_) => return Err(param),
+            None => (zoom, 1),
+        };
+        let zoom = zoom.parse().map_err(|_| param)?;
+        Ok(Self { zoom, format, scale })
+    }
+}
+
+#[cfg(test)]
+mod format_tests {
+    use super::*;
+
+    #[test]
+    fn test_zoom_param() {
+        let p = ZoomParam::from_param("7").unwrap();
+        assert_eq!(p.zoom, 7);
+        assert_eq!(p.format, None);
+
+        let p = ZoomParam::from_param("12.webp").unwrap();
+        assert_eq!(p.zoom, 12);
+        assert_eq!(p.format, Some(TileFormat::WebP));
+
+        let p = ZoomParam::from_param("3.png8").unwrap();
+        assert_eq!(p.format, Some(TileFormat::IndexedPng));
+
+        assert!(ZoomParam::from_param("3.gif").is_err());
+        assert!(ZoomParam::from_param("x.png").is_err());
+
+        let p = ZoomParam::from_param("5@2x.webp").unwrap();
+        assert_eq!((p.zoom, p.scale, p.format), (5, 2, Some(TileFormat::WebP)));
+        let p = ZoomParam::from_param("5@2x").unwrap();
+        assert_eq!((p.zoom, p.scale, p.format), (5, 2, None));
+        assert!(ZoomParam::from_param("5@3x.png").is_err());
+    }
+
+    #[test]
+    fn test_tile_size() {
+        let p = ZoomParam::from_param("4.png").unwrap();
+        assert_eq!(p.tile_size(None), Some(256));
+        assert_eq!(p.tile_size(Some(512)), Some(512));
+        assert_eq!(p.tile_size(Some(300)), None);
+
+        let p = ZoomParam::from_param("4@2x.png").unwrap();
+        assert_eq!(p.tile_size(None), Some(512));
+        assert_eq!(p.tile_size(Some(512)), Some(1024));
+    }
+
+    #[test]
+    fn test_from_accept() {
+        let accept: Accept = "image/avif,image/webp,image/apng,image/*,*/*;q=0.8".parse().unwrap();
+        assert_eq!(TileFormat::from_accept(&accept), Some(TileFormat::WebP));
+
+        let accept: Accept = "image/png;q=0.5, image/jpeg".parse().unwrap();
+        assert_eq!(TileFormat::from_accept(&accept), Some(TileFormat::Jpeg));
+
+        let accept: Accept = "*/*".parse().unwrap();
+        assert_eq!(TileFormat::from_accept(&accept), Some(TileFormat::Png));
+
+        let accept: Accept = "text/html".parse().unwrap();
+        assert_eq!(TileFormat::from_accept(&accept), None);
+    }
+
+    #[test]
+    fn test_encode_indexed() {
+        let palette = [[0, 0, 0, 0], [255, 0, 0, 255]];
+        let bytes = TileFormat::IndexedPng
+            .encode_indexed(&[0, 1, 1, 0], &palette, 2, 2, &EncodeOptions::default())
+            .unwrap();
+
+        let img = image::load_from_memory(&bytes).unwrap().into_rgba8();
+        assert_eq!(img.get_pixel(0, 0).0, [0, 0, 0, 0]);
+        assert_eq!(img.get_pixel(1, 0).0, [255, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,114 @@
+//! Text for image overlays, e.g. the date on animation frames, drawn with a small built-in bitmap font
+
+use image::{Rgba, RgbaImage};
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+// Space between glyphs, and around text on its background
+const SPACING: u32 = 1;
+
+// Rows from the top, with the leftmost pixel in bit 4. Dates, times and numbers only.
+fn glyph(c: char) -> Option<[u8; 7]> {
+    Some(match c {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '/' => [0x01, 0x01, 0x02, 0x04, 0x08, 0x10, 0x10],
+        'e' => [0x00, 0x00, 0x0E, 0x11, 0x1F, 0x10, 0x0E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        _ => return None,
+    })
+}
+
+/// Size of text drawn at a scale, without its background
+pub fn text_size(text: &str, scale: u32) -> (u32, u32) {
+    let chars = text.chars().count() as u32;
+    let width = (chars * (GLYPH_WIDTH + SPACING)).saturating_sub(SPACING);
+    (width * scale, GLYPH_HEIGHT * scale)
+}
+
+/// Draw text with its top left corner at x, y. Characters the font doesn't have are left blank.
+pub fn draw_text(img: &mut RgbaImage, x: i64, y: i64, text: &str, scale: u32, color: Rgba<u8>) {
+    let advance = ((GLYPH_WIDTH + SPACING) * scale) as i64;
+    for (i, c) in text.chars().enumerate() {
+        let Some(rows) = glyph(c) else { continue };
+        let left = x + i as i64 * advance;
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0x10 >> col) != 0 {
+                    let (px, py) = (left + (col * scale) as i64, y + row as i64 * scale as i64);
+                    fill_rect(img, px, py, scale, scale, color);
+                }
+            }
+        }
+    }
+}
+
+/// Draw text on a translucent dark box, so it reads over any colormap
+pub fn draw_label(img: &mut RgbaImage, x: i64, y: i64, text: &str, scale: u32) {
+    let (width, height) = text_size(text, scale);
+    let pad = SPACING * scale * 2;
+    blend_rect(img, x, y, width + 2 * pad, height + 2 * pad, Rgba([0, 0, 0, 160]));
+    draw_text(img, x + pad as i64, y + pad as i64, text, scale, Rgba([255, 255, 255, 255]));
+}
+
+/// Fill a rectangle, clipped to the image
+pub fn fill_rect(img: &mut RgbaImage, x: i64, y: i64, width: u32, height: u32, color: Rgba<u8>) {
+    for_each_pixel(img, x, y, width, height, |pixel| *pixel = color);
+}
+
+/// Alpha blend a color over a rectangle, clipped to the image
+pub fn blend_rect(img: &mut RgbaImage, x: i64, y: i64, width: u32, height: u32, color: Rgba<u8>) {
+    let alpha = color[3] as f64 / 255.0;
+    for_each_pixel(img, x, y, width, height, |pixel| {
+        for i in 0..3 {
+            pixel[i] = (pixel[i] as f64 * (1.0 - alpha) + color[i] as f64 * alpha).round() as u8;
+        }
+        pixel[3] = pixel[3].max(color[3]);
+    });
+}
+
+fn for_each_pixel(img: &mut RgbaImage, x: i64, y: i64, width: u32, height: u32, mut f: impl FnMut(&mut Rgba<u8>)) {
+    let (x0, y0) = (x.max(0) as u32, y.max(0) as u32);
+    let x1 = (x + width as i64).clamp(0, img.width() as i64) as u32;
+    let y1 = (y + height as i64).clamp(0, img.height() as i64) as u32;
+    for py in y0..y1 {
+        for px in x0..x1 {
+            f(img.get_pixel_mut(px, py));
+        }
+    }
+}
+
+#[cfg(test)]
+mod label_tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_text() {
+        assert_eq!(text_size("2023-04-12", 2), (118, 14));
+        assert_eq!(text_size("", 2), (0, 14));
+
+        let white = Rgba([255, 255, 255, 255]);
+        let mut img = RgbaImage::new(20, 10);
+        // Partly off the edge, which is clipped
+        draw_text(&mut img, -2, 1, "1-", 1, white);
+        // The foot of the 1 runs along its bottom row
+        assert_eq!(*img.get_pixel(0, 7), white);
+        assert_eq!(img.get_pixel(2, 7)[3], 0);
+        // The middle bar of the dash
+        assert_eq!(*img.get_pixel(4, 4), white);
+        assert_eq!(img.get_pixel(4, 3)[3], 0);
+    }
+}
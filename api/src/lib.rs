@@ -0,0 +1,21 @@
+#[macro_use]
+extern crate rocket;
+
+pub mod animation;
+pub mod archive;
+pub mod cache;
+pub mod catalog;
+pub mod colormap;
+pub mod format;
+pub mod label;
+pub mod mask;
+pub mod mbtiles;
+pub mod params;
+pub mod pmtiles;
+pub mod section;
+pub mod seed;
+pub mod shade;
+// Test helpers, public so the server's tests can use them too
+#[doc(hidden)]
+pub mod testing;
+pub mod vector;
@@ -1,20 +1,157 @@
-use rocket::response::status::NoContent;
-use std::io::Cursor;
-use std::path::Path;
+use api::animation::AnimationOptions;
+use api::cache::{CacheConfig, CachedTile, TileCache, TileResponse};
+use api::catalog::{ArchiveCatalog, ArchiveConfig};
+use api::colormap::{Colormap, Rgba, StyleParams};
+use api::format::{Compression, EncodeOptions, TileFormat, ZoomParam};
+use api::mask::{MaskConfig, Masks};
+use api::params::{
+    AggParam, AnimationParam, AnomalyParam, BaselineParam, BboxParam, ListParam, MvtZoomParam, PathParam,
+    QuadkeyParam, SectionParam, SubsetParam, TileScheme,
+};
+use api::section::{SectionJson, MAX_PLOT_SIZE};
+use api::shade::{self, RenderMode};
+use api::vector::{self, SymbolStyle, VectorMode};
+use rocket::http::uri::Origin;
+use rocket::http::{Accept, ContentType, Header};
+use rocket::response::status::{BadRequest, NoContent, NotFound};
+use rocket::response::Redirect;
+use rocket::serde::json::{Json, Value};
+use rocket::serde::Serialize;
+use rocket::{Build, Rocket, State};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tiler::aggregate::Aggregation;
+use tiler::anomaly::{Anomaly, Climatology};
+use tiler::composite::Composite;
+use tiler::contour::{ContourOptions, Levels, MAX_LEVELS};
+use tiler::dataset::DatasetPath;
+use tiler::expr::Expr;
+use tiler::mask::Mask;
+use tiler::overview::OverviewCache;
+use tiler::section::Profile;
+use tiler::tms::{CustomMatrixSet, Projection, TileMatrixSet, TileMatrixSets};
+use tiler::stats::Stats;
+use tiler::subset::{SubsetFormat, SubsetOptions};
+use tiler::region::Region;
+use tiler::zonal::{ZonalStats, DEFAULT_PERCENTILES};
 
-// TODO: Needs to be able to configure with env variables
-
-const BASE_PATH: &str = "./testfiles/6_bin8_data";
+// The daily archive, unless the `datasets` config key says otherwise
+const DATASET_TEMPLATE: &str = "./testfiles/6_bin8_data/{year}/{month}/{day}/mosaic_bin8_output.nc";
 
 #[macro_use]
 extern crate rocket;
 
 #[derive(Responder)]
-#[response(status = 200, content_type = "image/png")]
-struct ImageTile(Vec<u8>);
+#[response(status = 200)]
+struct DataResponse(Vec<u8>, ContentType);
+
+// A file to save, named by Content-Disposition
+#[derive(Responder)]
+#[response(status = 200)]
+struct Download(Vec<u8>, ContentType, Header<'static>);
+
+#[derive(Responder)]
+enum ApiError {
+    NoContent(NoContent),
+    BadRequest(BadRequest<String>),
+    NotFound(NotFound<String>),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct PointValue {
+    value: Option<f64>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct SeriesValue {
+    time: String,
+    value: Option<f64>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ZonalValue {
+    time: String,
+    #[serde(flatten)]
+    stats: ZonalStats,
+}
+
+// The file and time step for a day of the archive, at any time of day
+fn find_day(datasets: &Aggregation, year: u16, month: u8, day: u8) -> Option<DatasetPath> {
+    let day_start = tiler::time::from_ymd(year as i64, month as u32, day as u32);
+    datasets.find_within(day_start, tiler::time::add_days(day_start, 1) - 1)
+}
+
+// The file and time step for a day of the archive, or 204 if there isn't one
+fn dataset_path(datasets: &Aggregation, year: u16, month: u8, day: u8) -> Result<DatasetPath, ApiError> {
+    find_day(datasets, year, month, day).ok_or(ApiError::NoContent(NoContent))
+}
+
+// What an image tile is read from: one time step, or a composite of several
+struct TileSource {
+    steps: Vec<DatasetPath>,
+    composite: Option<Composite>,
+}
+
+impl TileSource {
+    fn step(dset_path: DatasetPath) -> Self {
+        Self {
+            steps: vec![dset_path],
+            composite: None,
+        }
+    }
+
+    // Every step from start to end, inclusive
+    fn composite(datasets: &Aggregation, start: i64, end: i64, agg: Option<AggParam>) -> Result<Self, ApiError> {
+        let steps = datasets
+            .between(Some(start), Some(end))
+            .map_err(|e| ApiError::BadRequest(BadRequest(Some(e.to_string()))))?;
+        let steps: Vec<DatasetPath> = steps.into_iter().map(|s| s.path).collect();
+        if steps.is_empty() {
+            return Err(ApiError::NoContent(NoContent));
+        }
+        Ok(Self {
+            steps,
+            composite: Some(agg.unwrap_or(AggParam::Mean).into()),
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn read(
+        &self,
+        tms: &dyn TileMatrixSet,
+        x: u32,
+        y: u32,
+        zoom: u32,
+        size: usize,
+        expr: &Expr,
+        lat_name: &str,
+        lon_name: &str,
+        overviews: &OverviewCache,
+    ) -> anyhow::Result<Option<Vec<f64>>> {
+        match self.composite {
+            Some(composite) => tiler::composite::get_composite_tile(
+                &self.steps, composite, tms, x, y, zoom, size, expr, lat_name, lon_name,
+            ),
+            None => tiler::get_overview_tile(&self.steps[0], tms, x, y, zoom, size, expr, lat_name, lon_name, overviews),
+        }
+    }
+}
+
+// Use the expr query param if there is one, otherwise just read the path variable
+fn parse_expr(var: &str, expr: Option<&str>) -> Result<Expr, ApiError> {
+    match expr {
+        Some(expr) => Expr::parse(expr)
+            .map_err(|e| ApiError::BadRequest(BadRequest(Some(format!("Invalid expression: {}", e))))),
+        None => Ok(Expr::variable(var)),
+    }
+}
 
 // Responds with image tile if there is one, otherwise 204
-#[get("/<var>/<year>/<month>/<day>/<x>/<y>/<z>?<min_value>&<max_value>&<log_scale>&<lat_dim>&<lon_dim>&<gradient>")]
+#[get("/<var>/<year>/<month>/<day>/<x>/<y>/<z>?<expr>&<render>&<azimuth>&<altitude>&<z_factor>&<shade_strength>&<tile_size>&<tms>&<scheme>&<start>&<end>&<agg>&<baseline>&<baseline_agg>&<anomaly>&<clip>&<lat_dim>&<lon_dim>&<compression>&<quality>&<style..>")]
+#[allow(clippy::too_many_arguments)]
 fn index(
     var: &str,
     year: u16,
@@ -22,89 +159,1100 @@ fn index(
     day: u8,
     x: u32,
     y: u32,
-    z: u32,
-    min_value: Option<f64>,
-    max_value: Option<f64>,
-    log_scale: Option<bool>,
+    z: ZoomParam,
+    expr: Option<&str>,
+    render: Option<RenderMode>,
+    azimuth: Option<f64>,
+    altitude: Option<f64>,
+    z_factor: Option<f64>,
+    shade_strength: Option<f64>,
+    tile_size: Option<usize>,
+    tms: Option<&str>,
+    scheme: Option<TileScheme>,
+    start: Option<&str>,
+    end: Option<&str>,
+    agg: Option<AggParam>,
+    baseline: Option<BaselineParam>,
+    baseline_agg: Option<AggParam>,
+    anomaly: Option<AnomalyParam>,
+    clip: Option<&str>,
+    lat_dim: Option<&str>,
+    lon_dim: Option<&str>,
+    compression: Option<Compression>,
+    quality: Option<u8>,
+    style: StyleParams<'_>,
+    accept: Option<&Accept>,
+    datasets: &State<Aggregation>,
+    climatology: &State<Option<Climatology>>,
+    cache: &State<TileCache>,
+    archives: &State<ArchiveCatalog>,
+    overviews: &State<OverviewCache>,
+    tile_matrix_sets: &State<TileMatrixSets>,
+    masks: &State<Masks>,
+    uri: &Origin<'_>,
+) -> Result<TileResponse, ApiError> {
+    // Handle optional query params
+    let expr = parse_expr(var, expr)?;
+    let lat_name = lat_dim.unwrap_or("lat");
+    let lon_name = lon_dim.unwrap_or("lon");
+    let anomaly: Option<Anomaly> = match (baseline, anomaly) {
+        (Some(_), anomaly) => Some(anomaly.unwrap_or(AnomalyParam::Difference).into()),
+        (None, Some(_)) => {
+            return Err(ApiError::BadRequest(BadRequest(Some(
+                "Anomaly tiles need a baseline".to_string(),
+            ))))
+        }
+        (None, None) => None,
+    };
+    let colormap = match anomaly {
+        Some(anomaly) => style.diverging_colormap(anomaly == Anomaly::Ratio),
+        None => style.colormap(),
+    };
+    let mask = mask_param(masks, clip)?;
+
+    let format = TileFormat::negotiate(z.format, accept);
+    let options = EncodeOptions::new(compression, quality);
+    let size = pixel_size(&z, tile_size)?;
+
+    let tms = tile_matrix_set(tile_matrix_sets, tms)?;
+    let y = tile_row(scheme, tms, z.zoom, y)?;
+
+    let render = render.unwrap_or(RenderMode::Color);
+    let hillshade_options = shade::hillshade_options(azimuth, altitude, z_factor);
+    if render == RenderMode::Shaded && format.is_indexed() {
+        return Err(ApiError::BadRequest(BadRequest(Some(
+            "Shaded tiles need full color output".to_string(),
+        ))));
+    }
+
+    // Pre-seeded tiles win, and anything missing from the archive is rendered live
+    let archived = || {
+        let query = uri.query().map_or("", |q| q.as_str());
+        let extension = api::seed::tile_extension(format);
+        let bytes = archives.get_tile(var, (year, month, day), query, extension, size, z.zoom as u8, x, y)?;
+        Some(CachedTile::new(bytes, format.content_type()))
+    };
+
+    // Composites combine every step from start to end, which default to the day in the path
+    let day_start = tiler::time::from_ymd(year as i64, month as u32, day as u32);
+    let source = if start.is_some() || end.is_some() || agg.is_some() {
+        let start = time_param(start)?.unwrap_or(day_start);
+        let end = end_param(end)?.unwrap_or(tiler::time::add_days(day_start, 1) - 1);
+        TileSource::composite(datasets, start, end, agg)?
+    } else {
+        match find_day(datasets, year, month, day) {
+            Some(dset_path) => TileSource::step(dset_path),
+            // Days that are only in an archive have nothing to render live
+            None => {
+                let tile = archived().ok_or(ApiError::NoContent(NoContent))?;
+                return Ok(TileResponse {
+                    tile,
+                    max_age: cache.max_age(var),
+                });
+            }
+        }
+    };
+    // Coverage and surfaces come from the first step, the only one for surfaces
+    let dset_path = source.steps[0].clone();
+    let baseline = match baseline {
+        Some(BaselineParam::Date(time)) => {
+            Some(TileSource::step(datasets.find(time).ok_or(ApiError::NoContent(NoContent))?))
+        }
+        Some(BaselineParam::Range(start, end)) => Some(TileSource::composite(datasets, start, end, baseline_agg)?),
+        Some(BaselineParam::Climatology) => {
+            let climatology = climatology.as_ref().ok_or_else(|| {
+                ApiError::BadRequest(BadRequest(Some("No climatology is configured".to_string())))
+            })?;
+            Some(TileSource::step(climatology.step(day_start).ok_or(ApiError::NoContent(NoContent))?))
+        }
+        None => None,
+    };
+    if render != RenderMode::Color && (source.composite.is_some() || baseline.is_some()) {
+        return Err(ApiError::BadRequest(BadRequest(Some(format!(
+            "{:?} tiles can't be composited or compared to a baseline",
+            render
+        )))));
+    }
+
+    let steps: Vec<&DatasetPath> = source.steps.iter().chain(baseline.iter().flat_map(|b| &b.steps)).collect();
+    let sources: Vec<&Path> = steps.iter().map(|s| s.path.as_path()).collect();
+
+    // New files can widen a composite, so its step count is part of the key
+    let key = TileCache::key(&format!("{}#{}", uri, steps.len()), &format!("{:?}", format));
+    let tile = cache.get_or_render_all(&key, &sources, || {
+        if let Some(tile) = archived() {
+            return Ok(tile);
+        }
+
+        // Pixels the mask clips are transparent, which is only worked out once there is data to draw
+        let read_kept = || {
+            kept_pixels(mask, &colormap, &dset_path, tms, x, y, z.zoom, size, lat_name, lon_name).map_err(|e| {
+                println!("Error: {}", e);
+                ApiError::NoContent(NoContent)
+            })
+        };
+
+        if let Some(surface) = render.surface(hillshade_options) {
+            let tile =
+                tiler::get_surface_tile(&dset_path, tms, x, y, z.zoom, size, &expr, &surface, lat_name, lon_name);
+            let (data, surface) = match tile {
+                Ok(Some(data)) => data,
+                Ok(None) => return Err(ApiError::NoContent(NoContent)),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return Err(ApiError::NoContent(NoContent));
+                }
+            };
+            let kept = read_kept()?;
+
+            return match render {
+                RenderMode::Hillshade => {
+                    // The greys gradient runs from white to black, so colormap the shadow
+                    let mut shade_map = Colormap::new(colorous::GREYS, 0.0, 1.0, false);
+                    shade_map.opacity = colormap.opacity;
+                    let shadow: Vec<f64> = surface.iter().map(|s| 1.0 - s).collect();
+                    encode_tile(&shadow, kept.as_deref(), size, &shade_map, format, &options)
+                }
+                RenderMode::Shaded => {
+                    let mut imgbuf = colormap.render_rgba(&data, size, size);
+                    shade::blend_hillshade(&mut imgbuf, &surface, shade_strength.unwrap_or(0.6));
+                    if let Some(kept) = &kept {
+                        api::colormap::clip_rgba(&mut imgbuf, kept);
+                    }
+                    image_response(format.encode_rgba(&imgbuf, &options), format)
+                }
+                _ => encode_tile(&surface, kept.as_deref(), size, &colormap, format, &options),
+            };
+        }
+
+        // Get tile
+        let mut data = match source.read(tms, x, y, z.zoom, size, &expr, lat_name, lon_name, overviews) {
+            Ok(Some(data)) => data,
+            Ok(None) => return Err(ApiError::NoContent(NoContent)),
+            Err(e) => {
+                println!("Error: {}", e);
+                return Err(ApiError::NoContent(NoContent));
+            }
+        };
+        if let (Some(baseline), Some(anomaly)) = (&baseline, anomaly) {
+            let baseline = baseline.read(tms, x, y, z.zoom, size, &expr, lat_name, lon_name, overviews);
+            let baseline = match baseline {
+                Ok(Some(baseline)) => baseline,
+                Ok(None) => return Err(ApiError::NoContent(NoContent)),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return Err(ApiError::NoContent(NoContent));
+                }
+            };
+            anomaly.apply(&mut data, &baseline);
+        }
+
+        let kept = read_kept()?;
+        encode_tile(&data, kept.as_deref(), size, &colormap, format, &options)
+    })?;
+    Ok(TileResponse {
+        tile,
+        max_age: cache.max_age(var),
+    })
+}
+
+// The pixels of a tile to draw: the ones a mask keeps, and only those inside the dataset when missing values get a
+// color, so the area around a regional grid stays transparent. None draws them all.
+#[allow(clippy::too_many_arguments)]
+fn kept_pixels(
+    mask: Option<&Mask>,
+    colormap: &Colormap,
+    dset_path: &DatasetPath,
+    tms: &dyn TileMatrixSet,
+    x: u32,
+    y: u32,
+    zoom: u32,
+    size: usize,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Option<Vec<bool>>> {
+    let mut kept = match mask {
+        Some(mask) => Some(mask.kept_pixels(dset_path, tms, x, y, zoom, size, lat_name, lon_name)?),
+        None => None,
+    };
+    if colormap.paints_nodata() {
+        let inside = tiler::get_tile_coverage(dset_path, tms, x, y, zoom, size, lat_name, lon_name)?;
+        kept = Some(match kept {
+            Some(kept) => kept.into_iter().zip(inside).map(|(kept, inside)| kept && inside).collect(),
+            None => inside,
+        });
+    }
+    Ok(kept)
+}
+
+// Colormap tile values and encode them as an image, with the pixels a mask doesn't keep transparent
+fn encode_tile(
+    data: &[f64],
+    kept: Option<&[bool]>,
+    size: usize,
+    colormap: &Colormap,
+    format: TileFormat,
+    options: &EncodeOptions,
+) -> Result<CachedTile, ApiError> {
+    let bytes = match kept {
+        Some(kept) => colormap.encode_clipped_tile(data, kept, size, format, options),
+        None => colormap.encode_tile(data, size, format, options),
+    };
+    image_response(bytes, format)
+}
+
+// The named mask to clip a tile to, if there is one
+fn mask_param<'a>(masks: &'a Masks, clip: Option<&str>) -> Result<Option<&'a Mask>, ApiError> {
+    match clip {
+        Some(name) => masks
+            .get(name)
+            .map(Some)
+            .ok_or_else(|| ApiError::BadRequest(BadRequest(Some(format!("Unknown mask {:?}", name))))),
+        None => Ok(None),
+    }
+}
+
+// The requested tile matrix set, WebMercatorQuad by default
+fn tile_matrix_set<'a>(sets: &'a TileMatrixSets, id: Option<&str>) -> Result<&'a dyn TileMatrixSet, ApiError> {
+    let id = id.unwrap_or("WebMercatorQuad");
+    sets.get(id)
+        .ok_or_else(|| ApiError::BadRequest(BadRequest(Some(format!("Unknown tile matrix set {}", id)))))
+}
+
+// The XYZ row of a requested tile, flipping TMS rows with the height of the zoom level, or 404 for zoom levels the
+// tile matrix set doesn't have
+fn tile_row(scheme: Option<TileScheme>, tms: &dyn TileMatrixSet, zoom: u32, y: u32) -> Result<u32, ApiError> {
+    let matrix = u8::try_from(zoom)
+        .ok()
+        .and_then(|zoom| tms.matrix(zoom))
+        .ok_or_else(|| ApiError::NotFound(NotFound(format!("No zoom level {} in {}", zoom, tms.id()))))?;
+    match scheme.unwrap_or_default() {
+        TileScheme::Xyz => Ok(y),
+        TileScheme::Tms => y
+            .checked_add(1)
+            .and_then(|y| matrix.matrix_height.checked_sub(y))
+            .ok_or_else(|| ApiError::BadRequest(BadRequest(Some(format!("No row {} at zoom {}", y, zoom))))),
+    }
+}
+
+// Pixel size of the requested tile, from `tile_size` and any `@2x` suffix
+fn pixel_size(z: &ZoomParam, tile_size: Option<usize>) -> Result<usize, ApiError> {
+    z.tile_size(tile_size).ok_or_else(|| {
+        ApiError::BadRequest(BadRequest(Some(format!(
+            "tile_size must be one of {:?}",
+            api::format::TILE_SIZES
+        ))))
+    })
+}
+
+fn image_response(bytes: anyhow::Result<Vec<u8>>, format: TileFormat) -> Result<CachedTile, ApiError> {
+    match bytes {
+        Ok(bytes) => Ok(CachedTile::new(bytes, format.content_type())),
+        Err(e) => {
+            println!("Error: {}", e);
+            Err(ApiError::NoContent(NoContent))
+        }
+    }
+}
+
+// Responds with a vector field tile from a pair of u/v component variables, otherwise 204
+#[get("/vector/<u>/<v>/<year>/<month>/<day>/<x>/<y>/<z>?<mode>&<spacing>&<symbol_color>&<uv_range>&<tile_size>&<tms>&<scheme>&<clip>&<lat_dim>&<lon_dim>&<compression>&<quality>&<style..>")]
+#[allow(clippy::too_many_arguments)]
+fn vector_tile(
+    u: &str,
+    v: &str,
+    year: u16,
+    month: u8,
+    day: u8,
+    x: u32,
+    y: u32,
+    z: ZoomParam,
+    mode: Option<VectorMode>,
+    spacing: Option<u32>,
+    symbol_color: Option<Rgba>,
+    uv_range: Option<f64>,
+    tile_size: Option<usize>,
+    tms: Option<&str>,
+    scheme: Option<TileScheme>,
+    clip: Option<&str>,
     lat_dim: Option<&str>,
     lon_dim: Option<&str>,
-    gradient: Option<&str>,
-) -> Result<ImageTile, NoContent> {
+    compression: Option<Compression>,
+    quality: Option<u8>,
+    style: StyleParams<'_>,
+    accept: Option<&Accept>,
+    datasets: &State<Aggregation>,
+    cache: &State<TileCache>,
+    tile_matrix_sets: &State<TileMatrixSets>,
+    masks: &State<Masks>,
+    uri: &Origin<'_>,
+) -> Result<TileResponse, ApiError> {
     // Handle optional query params
-    let min_value = min_value.unwrap_or(0.0);
-    let max_value = max_value.unwrap_or(10.0);
-    let log_scale = log_scale.unwrap_or(false);
+    let mode = mode.unwrap_or(VectorMode::Arrows);
+    let mask = mask_param(masks, clip)?;
+    let lat_name = lat_dim.unwrap_or("lat");
+    let lon_name = lon_dim.unwrap_or("lon");
+    let colormap = style.colormap();
+    let format = TileFormat::negotiate(z.format, accept);
+    let options = EncodeOptions::new(compression, quality);
+    let size = pixel_size(&z, tile_size)?;
+    let tms = tile_matrix_set(tile_matrix_sets, tms)?;
+    let y = tile_row(scheme, tms, z.zoom, y)?;
+
+    // Symbols point along u east and v north, which is only up and right in sets with north at the top
+    let north_up = matches!(tms.projection(), Projection::Geographic | Projection::WebMercator);
+    if !north_up && matches!(mode, VectorMode::Arrows | VectorMode::Barbs) {
+        return Err(ApiError::BadRequest(BadRequest(Some(format!(
+            "{:?} are only drawn in tile matrix sets with north up",
+            mode
+        )))));
+    }
+
+    // Packed u/v has to survive encoding exactly, and symbols need full color
+    let lossless_rgba = matches!(format, TileFormat::Png | TileFormat::WebP);
+    if !lossless_rgba && (mode == VectorMode::Uv || (mode != VectorMode::Magnitude && format.is_indexed())) {
+        return Err(ApiError::BadRequest(BadRequest(Some(format!(
+            "{:?} output is not supported for {:?} tiles",
+            format, mode
+        )))));
+    }
+
+    let dset_path = dataset_path(datasets, year, month, day)?;
+
+    let key = TileCache::key(&uri.to_string(), &format!("{:?}", format));
+    let tile = cache.get_or_render(&key, &dset_path.path, || {
+        let (u, v) = match tiler::get_vector_tile(&dset_path, tms, x, y, z.zoom, size, (u, v), lat_name, lon_name) {
+            Ok(Some(uv)) => uv,
+            Ok(None) => return Err(ApiError::NoContent(NoContent)),
+            Err(e) => {
+                println!("Error: {}", e);
+                return Err(ApiError::NoContent(NoContent));
+            }
+        };
+
+        let kept = kept_pixels(mask, &colormap, &dset_path, tms, x, y, z.zoom, size, lat_name, lon_name);
+        let kept = match kept {
+            Ok(kept) => kept,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Err(ApiError::NoContent(NoContent));
+            }
+        };
+
+        // Clipped pixels are transparent, like missing values
+        if mode == VectorMode::Uv {
+            let range = uv_range.unwrap_or(colormap.max_value());
+            let mut imgbuf = vector::encode_uv(&u, &v, size as u32, size as u32, range);
+            if let Some(kept) = &kept {
+                api::colormap::clip_rgba(&mut imgbuf, kept);
+            }
+            return image_response(format.encode_rgba(&imgbuf, &options), format);
+        }
+
+        let magnitude = vector::magnitude(&u, &v);
+        if mode == VectorMode::Magnitude {
+            return encode_tile(&magnitude, kept.as_deref(), size, &colormap, format, &options);
+        }
+
+        let mut imgbuf = colormap.render_rgba(&magnitude, size, size);
+        if let Some(kept) = &kept {
+            api::colormap::clip_rgba(&mut imgbuf, kept);
+        }
+        // Spacing is in CSS pixels, so @2x tiles get the same number of symbols
+        let symbol_style = SymbolStyle {
+            spacing: spacing.unwrap_or(32).max(8) * z.scale,
+            color: symbol_color.unwrap_or(Rgba([255, 255, 255, 255])),
+            max_speed: colormap.max_value(),
+        };
+        match mode {
+            VectorMode::Barbs => vector::draw_barbs(&mut imgbuf, &u, &v, &symbol_style),
+            _ => vector::draw_arrows(&mut imgbuf, &u, &v, &symbol_style),
+        }
+        image_response(format.encode_rgba(&imgbuf, &options), format)
+    })?;
+    Ok(TileResponse {
+        tile,
+        max_age: cache.max_age(u),
+    })
+}
+
+// Responds with the value at a point, which is null outside the dataset
+#[get("/point/<var>/<year>/<month>/<day>?<lat>&<lng>&<expr>&<lat_dim>&<lon_dim>")]
+#[allow(clippy::too_many_arguments)]
+fn point(
+    var: &str,
+    year: u16,
+    month: u8,
+    day: u8,
+    lat: f64,
+    lng: f64,
+    expr: Option<&str>,
+    lat_dim: Option<&str>,
+    lon_dim: Option<&str>,
+    datasets: &State<Aggregation>,
+) -> Result<Json<PointValue>, ApiError> {
+    let expr = parse_expr(var, expr)?;
+    let lat_name = lat_dim.unwrap_or("lat");
+    let lon_name = lon_dim.unwrap_or("lon");
+    let dset_path = dataset_path(datasets, year, month, day)?;
+
+    match tiler::get_point(&dset_path, lat, lng, &expr, lat_name, lon_name) {
+        Ok(value) => Ok(Json(PointValue { value })),
+        Err(e) => {
+            println!("Error: {}", e);
+            Err(ApiError::NoContent(NoContent))
+        }
+    }
+}
+
+// Parse an ISO date or date and time query param
+fn time_param(value: Option<&str>) -> Result<Option<i64>, ApiError> {
+    value
+        .map(tiler::time::parse)
+        .transpose()
+        .map_err(|e| ApiError::BadRequest(BadRequest(Some(e.to_string()))))
+}
+
+// Like `time_param`, for the end of an inclusive range, where a date alone takes in the whole day
+fn end_param(value: Option<&str>) -> Result<Option<i64>, ApiError> {
+    value
+        .map(tiler::time::parse_end)
+        .transpose()
+        .map_err(|e| ApiError::BadRequest(BadRequest(Some(e.to_string()))))
+}
+
+// Responds with the value at a point for every time step in the archive from start to end, inclusive
+#[get("/timeseries/<var>?<lat>&<lng>&<start>&<end>&<expr>&<lat_dim>&<lon_dim>")]
+#[allow(clippy::too_many_arguments)]
+fn time_series(
+    var: &str,
+    lat: f64,
+    lng: f64,
+    start: Option<&str>,
+    end: Option<&str>,
+    expr: Option<&str>,
+    lat_dim: Option<&str>,
+    lon_dim: Option<&str>,
+    datasets: &State<Aggregation>,
+) -> Result<Json<Vec<SeriesValue>>, ApiError> {
+    let expr = parse_expr(var, expr)?;
+    let lat_name = lat_dim.unwrap_or("lat");
+    let lon_name = lon_dim.unwrap_or("lon");
+    let (start, end) = (time_param(start)?, end_param(end)?);
+    // Too many steps is the request's fault, unlike a failed read
+    datasets.between(start, end).map_err(|e| ApiError::BadRequest(BadRequest(Some(e.to_string()))))?;
+
+    match datasets.get_time_series(start, end, lat, lng, &expr, lat_name, lon_name) {
+        Ok(series) => Ok(Json(
+            series
+                .into_iter()
+                .map(|(time, value)| SeriesValue {
+                    time: tiler::time::format(time),
+                    value,
+                })
+                .collect(),
+        )),
+        Err(e) => {
+            println!("Error: {}", e);
+            Err(ApiError::NoContent(NoContent))
+        }
+    }
+}
+
+// Lists the times in the archive
+#[get("/times")]
+fn times(datasets: &State<Aggregation>) -> Json<Vec<String>> {
+    Json(datasets.steps().iter().map(|step| tiler::time::format(step.time)).collect())
+}
+
+// Lists the tile matrix sets tiles can be requested in with `tms=`
+#[get("/tileMatrixSets")]
+fn tile_matrix_sets(sets: &State<TileMatrixSets>) -> DataResponse {
+    let sets: Vec<_> = sets
+        .iter()
+        .map(|tms| {
+            rocket::serde::json::json!({
+                "id": tms.id(),
+                "crs": tms.crs(),
+                "links": [{"rel": "self", "href": format!("/tileMatrixSets/{}", tms.id())}],
+            })
+        })
+        .collect();
+    let body = rocket::serde::json::json!({ "tileMatrixSets": sets });
+    DataResponse(body.to_string().into_bytes(), ContentType::JSON)
+}
+
+// Responds with a tile matrix set definition as OGC TileMatrixSet JSON
+#[get("/tileMatrixSets/<id>")]
+fn tile_matrix_set_definition(id: &str, sets: &State<TileMatrixSets>) -> Result<DataResponse, ApiError> {
+    let tms = tile_matrix_set(sets, Some(id))?;
+    Ok(DataResponse(tms.to_json().to_string().into_bytes(), ContentType::JSON))
+}
+
+// Responds with statistics over a bounding box, or the whole dataset if there isn't one
+#[get("/stats/<var>/<year>/<month>/<day>?<bbox>&<expr>&<lat_dim>&<lon_dim>")]
+#[allow(clippy::too_many_arguments)]
+fn stats(
+    var: &str,
+    year: u16,
+    month: u8,
+    day: u8,
+    bbox: Option<BboxParam>,
+    expr: Option<&str>,
+    lat_dim: Option<&str>,
+    lon_dim: Option<&str>,
+    datasets: &State<Aggregation>,
+) -> Result<Json<Stats>, ApiError> {
+    let expr = parse_expr(var, expr)?;
     let lat_name = lat_dim.unwrap_or("lat");
     let lon_name = lon_dim.unwrap_or("lon");
-    // TODO: This is gross
-    let gradient = match gradient {
-        Some("turbo") => colorous::TURBO,
-        Some("viridis") => colorous::VIRIDIS,
-        Some("inferno") => colorous::INFERNO,
-        Some("magma") => colorous::MAGMA,
-        Some("plasma") => colorous::PLASMA,
-        Some("cividis") => colorous::CIVIDIS,
-        Some("warm") => colorous::WARM,
-        Some("cool") => colorous::COOL,
-        Some("cubehelix") => colorous::CUBEHELIX,
-        Some("rainbow") => colorous::RAINBOW,
-        Some("sinebow") => colorous::SINEBOW,
-        Some("greens") => colorous::GREENS,
-        Some("bluegreen") => colorous::BLUE_GREEN,
-        _ => colorous::VIRIDIS,
+    let dset_path = dataset_path(datasets, year, month, day)?;
+
+    match tiler::get_stats(&dset_path, bbox.map(|b| b.0), &expr, lat_name, lon_name) {
+        Ok(stats) => Ok(Json(stats)),
+        Err(e) => {
+            println!("Error: {}", e);
+            Err(ApiError::NoContent(NoContent))
+        }
+    }
+}
+
+// Responds with an animation of a bounding box over every time step from start to end, inclusive
+#[get("/animation/<var>?<bbox>&<start>&<end>&<width>&<height>&<format>&<delay>&<overlay>&<expr>&<lat_dim>&<lon_dim>&<style..>")]
+#[allow(clippy::too_many_arguments)]
+fn animation(
+    var: &str,
+    bbox: BboxParam,
+    start: Option<&str>,
+    end: Option<&str>,
+    width: Option<usize>,
+    height: Option<usize>,
+    format: Option<AnimationParam>,
+    delay: Option<u16>,
+    overlay: Option<bool>,
+    expr: Option<&str>,
+    lat_dim: Option<&str>,
+    lon_dim: Option<&str>,
+    style: StyleParams<'_>,
+    datasets: &State<Aggregation>,
+) -> Result<DataResponse, ApiError> {
+    let expr = parse_expr(var, expr)?;
+    let steps = datasets
+        .between(time_param(start)?, end_param(end)?)
+        .map_err(|e| ApiError::BadRequest(BadRequest(Some(e.to_string()))))?;
+    if steps.is_empty() {
+        return Err(ApiError::NoContent(NoContent));
+    }
+    let (width, height) = api::animation::frame_size(&bbox.0, width.unwrap_or(800), height, steps.len())
+        .map_err(|e| ApiError::BadRequest(BadRequest(Some(e.to_string()))))?;
+
+    let options = AnimationOptions {
+        bounds: bbox.0,
+        width,
+        height,
+        expr,
+        lat_name: lat_dim.unwrap_or("lat").to_string(),
+        lon_name: lon_dim.unwrap_or("lon").to_string(),
+        colormap: style.colormap(),
+        overlay: overlay.unwrap_or(true),
     };
+    let format = format.unwrap_or(AnimationParam::Gif).into();
+    let frames = api::animation::render_frames(&steps, &options);
+    match frames.and_then(|frames| api::animation::encode(frames, format, delay.unwrap_or(500))) {
+        Ok(bytes) => Ok(DataResponse(bytes, format.content_type())),
+        Err(e) => {
+            println!("Error: {}", e);
+            Err(ApiError::NoContent(NoContent))
+        }
+    }
+}
+
+// Responds with the values of comma separated variables over a bounding box and the time steps from start to end,
+// inclusive, as a NetCDF, GeoTIFF or CSV download
+#[get("/subset/<vars>?<bbox>&<start>&<end>&<stride>&<format>&<lat_dim>&<lon_dim>")]
+#[allow(clippy::too_many_arguments)]
+fn subset(
+    vars: &str,
+    bbox: BboxParam,
+    start: Option<&str>,
+    end: Option<&str>,
+    stride: Option<usize>,
+    format: Option<SubsetParam>,
+    lat_dim: Option<&str>,
+    lon_dim: Option<&str>,
+    datasets: &State<Aggregation>,
+) -> Result<Download, ApiError> {
+    let bad_request = |message: String| ApiError::BadRequest(BadRequest(Some(message)));
+    let variables: Vec<String> = vars.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect();
+    if variables.is_empty() {
+        return Err(bad_request("No variables to subset".to_string()));
+    }
+    if stride == Some(0) {
+        return Err(bad_request("Stride must be at least 1".to_string()));
+    }
+    let steps = datasets.between(time_param(start)?, end_param(end)?).map_err(|e| bad_request(e.to_string()))?;
+    let options = SubsetOptions {
+        bounds: bbox.0,
+        variables,
+        stride: stride.unwrap_or(1),
+        lat_name: lat_dim.unwrap_or("lat").to_string(),
+        lon_name: lon_dim.unwrap_or("lon").to_string(),
+    };
+
+    let size = tiler::subset::subset_size(&steps, &options).map_err(|e| bad_request(e.to_string()))?;
+    if size == 0 {
+        return Err(ApiError::NoContent(NoContent));
+    }
+    if size > tiler::subset::MAX_VALUES {
+        return Err(bad_request(format!(
+            "{} values is more than the {} value limit, use a smaller box, fewer steps or a stride",
+            size,
+            tiler::subset::MAX_VALUES
+        )));
+    }
+
+    let format: SubsetFormat = format.unwrap_or(SubsetParam::NetCdf).into();
+    let subset = match tiler::subset::read_subset(&steps, &options) {
+        Ok(Some(subset)) => subset,
+        Ok(None) => return Err(ApiError::NoContent(NoContent)),
+        Err(e) => return Err(bad_request(e.to_string())),
+    };
+    let bytes = subset.encode(format).map_err(|e| bad_request(e.to_string()))?;
+    let content_type = match format {
+        SubsetFormat::NetCdf => ContentType::new("application", "x-netcdf"),
+        SubsetFormat::GeoTiff => ContentType::new("image", "tiff"),
+        SubsetFormat::Csv => ContentType::CSV,
+    };
+    let disposition = format!("attachment; filename=\"subset.{}\"", format.extension());
+    Ok(Download(bytes, content_type, Header::new("Content-Disposition", disposition)))
+}
+
+// Responds with the values at every level of the column nearest to a point, e.g. a temperature profile
+#[get("/profile/<var>/<year>/<month>/<day>?<lat>&<lng>&<expr>&<lat_dim>&<lon_dim>")]
+#[allow(clippy::too_many_arguments)]
+fn profile(
+    var: &str,
+    year: u16,
+    month: u8,
+    day: u8,
+    lat: f64,
+    lng: f64,
+    expr: Option<&str>,
+    lat_dim: Option<&str>,
+    lon_dim: Option<&str>,
+    datasets: &State<Aggregation>,
+) -> Result<Json<Profile>, ApiError> {
+    let expr = parse_expr(var, expr)?;
+    let lat_name = lat_dim.unwrap_or("lat");
+    let lon_name = lon_dim.unwrap_or("lon");
+    let dset_path = dataset_path(datasets, year, month, day)?;
+
+    match tiler::get_profile(&dset_path, lat, lng, &expr, lat_name, lon_name) {
+        Ok(Some(profile)) => Ok(Json(profile)),
+        Ok(None) => Err(ApiError::NoContent(NoContent)),
+        Err(e) => {
+            println!("Error: {}", e);
+            Err(ApiError::NoContent(NoContent))
+        }
+    }
+}
+
+// Responds with a depth x distance section along a path as JSON, or as a plot colored like tiles
+#[get("/section/<var>/<year>/<month>/<day>?<path>&<spacing>&<format>&<width>&<height>&<expr>&<lat_dim>&<lon_dim>&<style..>")]
+#[allow(clippy::too_many_arguments)]
+fn section(
+    var: &str,
+    year: u16,
+    month: u8,
+    day: u8,
+    path: PathParam,
+    spacing: Option<f64>,
+    format: Option<SectionParam>,
+    width: Option<usize>,
+    height: Option<usize>,
+    expr: Option<&str>,
+    lat_dim: Option<&str>,
+    lon_dim: Option<&str>,
+    style: StyleParams<'_>,
+    datasets: &State<Aggregation>,
+) -> Result<DataResponse, ApiError> {
+    let expr = parse_expr(var, expr)?;
+    let lat_name = lat_dim.unwrap_or("lat");
+    let lon_name = lon_dim.unwrap_or("lon");
+    let (width, height) = (width.unwrap_or(800), height.unwrap_or(400));
+    if width == 0 || height == 0 || width > MAX_PLOT_SIZE || height > MAX_PLOT_SIZE {
+        return Err(ApiError::BadRequest(BadRequest(Some(format!(
+            "Plots can be at most {} pixels wide or high",
+            MAX_PLOT_SIZE
+        )))));
+    }
+    if matches!(spacing, Some(spacing) if !spacing.is_finite() || spacing <= 0.0) {
+        return Err(ApiError::BadRequest(BadRequest(Some("Transect spacing must be positive".to_string()))));
+    }
+    let dset_path = dataset_path(datasets, year, month, day)?;
+
+    let section = tiler::get_section(&dset_path, &path.0, spacing, &expr, lat_name, lon_name)
+        .map_err(|e| ApiError::BadRequest(BadRequest(Some(e.to_string()))))?;
+    match format.unwrap_or(SectionParam::Json) {
+        SectionParam::Json => {
+            let json = rocket::serde::json::to_string(&SectionJson::from(&section))
+                .map_err(|e| ApiError::BadRequest(BadRequest(Some(e.to_string()))))?;
+            Ok(DataResponse(json.into_bytes(), ContentType::JSON))
+        }
+        SectionParam::Png => {
+            let img = api::section::render(&section, width, height, &style.colormap());
+            match TileFormat::Png.encode_rgba(&img, &EncodeOptions::default()) {
+                Ok(bytes) => Ok(DataResponse(bytes, ContentType::PNG)),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    Err(ApiError::NoContent(NoContent))
+                }
+            }
+        }
+    }
+}
+
+// Responds with area weighted statistics inside a posted GeoJSON polygon or multipolygon for every time step in the
+// archive from start to end, inclusive
+#[post("/zonal/<var>?<start>&<end>&<expr>&<percentiles>&<lat_dim>&<lon_dim>", format = "json", data = "<geojson>")]
+#[allow(clippy::too_many_arguments)]
+fn zonal(
+    var: &str,
+    start: Option<&str>,
+    end: Option<&str>,
+    expr: Option<&str>,
+    percentiles: Option<ListParam>,
+    lat_dim: Option<&str>,
+    lon_dim: Option<&str>,
+    geojson: Json<Value>,
+    datasets: &State<Aggregation>,
+) -> Result<Json<Vec<ZonalValue>>, ApiError> {
+    let expr = parse_expr(var, expr)?;
+    let lat_name = lat_dim.unwrap_or("lat");
+    let lon_name = lon_dim.unwrap_or("lon");
+    let (start, end) = (time_param(start)?, end_param(end)?);
+    // Too many steps is the request's fault, unlike a failed read
+    datasets.between(start, end).map_err(|e| ApiError::BadRequest(BadRequest(Some(e.to_string()))))?;
+    let region = Region::from_geojson(&geojson).map_err(|e| ApiError::BadRequest(BadRequest(Some(e.to_string()))))?;
+    let percents = percentiles.map_or(DEFAULT_PERCENTILES.to_vec(), |p| p.0);
+    if percents.iter().any(|p| !(0.0..=100.0).contains(p)) {
+        return Err(ApiError::BadRequest(BadRequest(Some("Percentiles must be from 0 to 100".to_string()))));
+    }
 
-    let dset_path = format!(
-        "{}/{}/{:02}/{:02}/mosaic_bin8_output.nc",
-        BASE_PATH, year, month, day
-    );
-    let dset_path = Path::new(&dset_path[..]);
-    println!("dset_path: {:?}", dset_path);
-
-    // Get tile
-    let data = match tiler::get_tile(dset_path, x, y, z, var, lat_name, lon_name) {
-        Ok(Some(data)) => data,
-        Ok(None) => return Err(NoContent),
+    match datasets.get_zonal_stats(start, end, &region, &expr, &percents, lat_name, lon_name) {
+        Ok(series) => Ok(Json(
+            series
+                .into_iter()
+                .map(|(time, stats)| ZonalValue {
+                    time: tiler::time::format(time),
+                    stats,
+                })
+                .collect(),
+        )),
         Err(e) => {
             println!("Error: {}", e);
-            return Err(NoContent);
+            Err(ApiError::NoContent(NoContent))
         }
+    }
+}
+
+// Explicit levels win over an interval, which defaults to 1
+fn contour_options(
+    interval: Option<f64>,
+    base: Option<f64>,
+    levels: Option<ListParam>,
+    smoothing: Option<usize>,
+    major_every: Option<usize>,
+    precision: Option<usize>,
+) -> Result<ContourOptions, ApiError> {
+    if matches!(interval, Some(interval) if interval.is_nan() || interval <= 0.0) {
+        return Err(ApiError::BadRequest(BadRequest(Some("Contour interval must be positive".to_string()))));
+    }
+    if levels.as_ref().is_some_and(|levels| levels.0.len() > MAX_LEVELS) {
+        let message = format!("Contours can have at most {} levels", MAX_LEVELS);
+        return Err(ApiError::BadRequest(BadRequest(Some(message))));
+    }
+    let defaults = ContourOptions::default();
+    let levels = match levels {
+        Some(levels) => Levels::Explicit(levels.0),
+        None => Levels::Interval {
+            interval: interval.unwrap_or(1.0),
+            base: base.unwrap_or(0.0),
+        },
     };
+    Ok(ContourOptions {
+        levels,
+        // More iterations than this only multiplies the point count
+        smoothing: smoothing.unwrap_or(defaults.smoothing).min(5),
+        major_every: major_every.unwrap_or(defaults.major_every),
+        label_precision: precision.unwrap_or(defaults.label_precision).min(10),
+    })
+}
 
-    // Convert to image
-    let mut imgbuf = image::RgbaImage::new(tiler::TILE_SIZE as u32, tiler::TILE_SIZE as u32);
-
-    data.iter().enumerate().for_each(|(i, v)| {
-        if *v > min_value {
-            let x = i % tiler::TILE_SIZE;
-            let y = i / tiler::TILE_SIZE;
-
-            let c = if log_scale {
-                let v = (*v).log10();
-                let v = (v - min_value.log10()) / (max_value.log10() - min_value.log10());
-                gradient.eval_continuous(v)
-            } else {
-                let v = (*v - min_value) / (max_value - min_value);
-                gradient.eval_continuous(v)
-            };
-            imgbuf.put_pixel(x as u32, y as u32, image::Rgba([c.r, c.g, c.b, 255]));
+// Responds with contour lines over a bounding box as GeoJSON
+#[get("/contours/<var>/<year>/<month>/<day>?<bbox>&<interval>&<base>&<levels>&<smoothing>&<major_every>&<precision>&<expr>&<tms>&<lat_dim>&<lon_dim>")]
+#[allow(clippy::too_many_arguments)]
+fn contours(
+    var: &str,
+    year: u16,
+    month: u8,
+    day: u8,
+    bbox: Option<BboxParam>,
+    interval: Option<f64>,
+    base: Option<f64>,
+    levels: Option<ListParam>,
+    smoothing: Option<usize>,
+    major_every: Option<usize>,
+    precision: Option<usize>,
+    expr: Option<&str>,
+    tms: Option<&str>,
+    lat_dim: Option<&str>,
+    lon_dim: Option<&str>,
+    datasets: &State<Aggregation>,
+) -> Result<DataResponse, ApiError> {
+    // GeoJSON is always lng/lat, so there are no tiles to lay out
+    if tms.is_some() {
+        return Err(ApiError::BadRequest(BadRequest(Some(
+            "GeoJSON contours are in lng/lat, use contour tiles for a tile matrix set".to_string(),
+        ))));
+    }
+    let expr = parse_expr(var, expr)?;
+    let lat_name = lat_dim.unwrap_or("lat");
+    let lon_name = lon_dim.unwrap_or("lon");
+    let options = contour_options(interval, base, levels, smoothing, major_every, precision)?;
+    let dset_path = dataset_path(datasets, year, month, day)?;
+
+    match tiler::get_contours(&dset_path, bbox.map(|b| b.0), &expr, &options, lat_name, lon_name) {
+        Ok(geojson) => Ok(DataResponse(
+            geojson.to_string().into_bytes(),
+            ContentType::new("application", "geo+json"),
+        )),
+        Err(e) => {
+            println!("Error: {}", e);
+            Err(ApiError::NoContent(NoContent))
         }
-    });
+    }
+}
 
-    // Write bytes
-    let mut bytes = Cursor::new(Vec::new());
-    imgbuf
-        .write_to(
-            &mut bytes,
-            image::ImageOutputFormat::from(image::ImageFormat::Png),
-        )
-        .unwrap();
+// Responds with contour lines for a tile as a Mapbox vector tile
+#[get("/contours/<var>/<year>/<month>/<day>/<x>/<y>/<z>?<interval>&<base>&<levels>&<smoothing>&<major_every>&<precision>&<expr>&<tms>&<scheme>&<lat_dim>&<lon_dim>")]
+#[allow(clippy::too_many_arguments)]
+fn contour_tile(
+    var: &str,
+    year: u16,
+    month: u8,
+    day: u8,
+    x: u32,
+    y: u32,
+    z: MvtZoomParam,
+    interval: Option<f64>,
+    base: Option<f64>,
+    levels: Option<ListParam>,
+    smoothing: Option<usize>,
+    major_every: Option<usize>,
+    precision: Option<usize>,
+    expr: Option<&str>,
+    tms: Option<&str>,
+    scheme: Option<TileScheme>,
+    lat_dim: Option<&str>,
+    lon_dim: Option<&str>,
+    datasets: &State<Aggregation>,
+    cache: &State<TileCache>,
+    tile_matrix_sets: &State<TileMatrixSets>,
+    uri: &Origin<'_>,
+) -> Result<TileResponse, ApiError> {
+    let expr = parse_expr(var, expr)?;
+    let lat_name = lat_dim.unwrap_or("lat");
+    let lon_name = lon_dim.unwrap_or("lon");
+    let options = contour_options(interval, base, levels, smoothing, major_every, precision)?;
+    let tms = tile_matrix_set(tile_matrix_sets, tms)?;
+    let y = tile_row(scheme, tms, z.0, y)?;
+    let dset_path = dataset_path(datasets, year, month, day)?;
+
+    let key = TileCache::key(&uri.to_string(), "mvt");
+    let tile = cache.get_or_render(&key, &dset_path.path, || {
+        match tiler::get_contour_tile(&dset_path, tms, x, y, z.0, &expr, &options, lat_name, lon_name) {
+            Ok(bytes) => Ok(CachedTile::new(bytes, ContentType::new("application", "vnd.mapbox-vector-tile"))),
+            Err(e) => {
+                println!("Error: {}", e);
+                Err(ApiError::NoContent(NoContent))
+            }
+        }
+    })?;
+    Ok(TileResponse {
+        tile,
+        max_age: cache.max_age(var),
+    })
+}
 
-    let bytes = bytes.into_inner();
-    Ok(ImageTile(bytes))
+// Redirects a Bing Maps style quadkey tile, e.g. `/quadkey/chl/2023/4/12/0231@2x.png`, to its XYZ tile
+#[get("/quadkey/<var>/<year>/<month>/<day>/<key>")]
+fn quadkey_tile(var: &str, year: u16, month: u8, day: u8, key: QuadkeyParam<'_>, uri: &Origin<'_>) -> Redirect {
+    let tile = key.tile;
+    // Quadkey rows are always counted from the top
+    let query: Vec<&str> = uri
+        .query()
+        .map_or("", |q| q.as_str())
+        .split('&')
+        .filter(|p| !p.is_empty() && !p.starts_with("scheme="))
+        .collect();
+    let query = if query.is_empty() { String::new() } else { format!("?{}", query.join("&")) };
+    Redirect::permanent(format!(
+        "/{}/{}/{}/{}/{}/{}/{}{}{}",
+        var,
+        year,
+        month,
+        day,
+        tile.x(),
+        tile.y(),
+        tile.zoom(),
+        key.suffix,
+        query
+    ))
 }
 
 #[launch]
 fn rocket() -> _ {
-    rocket::build().mount("/", routes![index])
+    build(rocket::build())
+}
+
+// The server, with state loaded from the config in its figment
+fn build(rocket: Rocket<Build>) -> Rocket<Build> {
+    let cache_config: CacheConfig = rocket.figment().extract_inner("tile_cache").unwrap_or_default();
+    let archive_configs: Vec<ArchiveConfig> = rocket.figment().extract_inner("archives").unwrap_or_default();
+    let overview_capacity: usize = rocket.figment().extract_inner("overviews").unwrap_or(8);
+    let tms_paths: Vec<PathBuf> = rocket.figment().extract_inner("tile_matrix_sets").unwrap_or_default();
+    let dataset_template: String = rocket.figment().extract_inner("datasets").unwrap_or(DATASET_TEMPLATE.to_string());
+    let time_var: String = rocket.figment().extract_inner("time_var").unwrap_or("time".to_string());
+    let datasets = Aggregation::discover(&dataset_template, &time_var).expect("Invalid datasets template");
+    let mask_configs: HashMap<String, MaskConfig> = rocket.figment().extract_inner("masks").unwrap_or_default();
+    let climatology_path: Option<PathBuf> = rocket.figment().extract_inner("climatology").ok();
+    let climatology = climatology_path.and_then(|path| match Climatology::open(&path) {
+        Ok(climatology) => Some(climatology),
+        Err(e) => {
+            println!("Error: can't open climatology {:?}: {}", path, e);
+            None
+        }
+    });
+
+    let mut tile_matrix_sets = TileMatrixSets::default();
+    for path in tms_paths {
+        match std::fs::read_to_string(&path).map_err(anyhow::Error::from).and_then(|json| CustomMatrixSet::from_json(&json)) {
+            Ok(tms) => tile_matrix_sets.add(Box::new(tms)),
+            Err(e) => println!("Error: can't load tile matrix set {:?}: {}", path, e),
+        }
+    }
+
+    rocket
+        .manage(TileCache::new(cache_config))
+        .manage(ArchiveCatalog::open(&archive_configs))
+        .manage(OverviewCache::new(overview_capacity))
+        .manage(tile_matrix_sets)
+        .manage(datasets)
+        .manage(climatology)
+        .manage(Masks::load(&mask_configs))
+        .mount(
+            "/",
+            routes![
+                index,
+                point,
+                time_series,
+                times,
+                stats,
+                animation,
+                subset,
+                zonal,
+                profile,
+                section,
+                vector_tile,
+                contours,
+                contour_tile,
+                quadkey_tile,
+                tile_matrix_sets,
+                tile_matrix_set_definition
+            ],
+        )
+}
+
+#[cfg(test)]
+mod main_tests {
+    use super::*;
+    use api::archive::{ArchiveMetadata, TileWriter};
+    use api::mbtiles::MbtilesWriter;
+    use api::testing::TempPath;
+    use rocket::figment::Figment;
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+    use rocket::serde::json::json;
+    use tiler::bounds::Bounds;
+
+    // A server with the figment's config, and datasets in an empty directory
+    fn client(dir: &Path, figment: Figment) -> Client {
+        let figment = figment.merge(("datasets", format!("{}/{{year}}/{{month}}/{{day}}/mosaic.nc", dir.display())));
+        Client::tracked(build(rocket::custom(figment))).unwrap()
+    }
+
+    #[test]
+    fn test_archive_only_day() {
+        // A day seeded into an archive, with no datasets at all
+        let dir = TempPath::dir("archive_only");
+        let archive = dir.join("chl.mbtiles");
+        let mut writer = MbtilesWriter::open(&archive).unwrap();
+        writer.write_tile(1, 0, 1, b"tile").unwrap();
+        let metadata = ArchiveMetadata {
+            name: "chl".to_string(),
+            format: "png".to_string(),
+            bounds: Bounds::new(-180.0, -85.0, 180.0, 85.0),
+            min_zoom: 0,
+            max_zoom: 1,
+        };
+        writer.finish(&metadata).unwrap();
+        drop(writer);
+
+        let archives = json!([{"var": "chl", "date": "2023-04-12", "path": archive, "style": "max_value=5"}]);
+        let client = client(&dir, Figment::from(rocket::Config::default()).merge(("archives", archives)));
+
+        let response = client.get("/chl/2023/4/12/0/1/1.png?max_value=5").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_bytes().unwrap(), b"tile");
+        // Other tiles, styles and days have nothing to fall back on
+        assert_eq!(client.get("/chl/2023/4/12/1/1/1.png?max_value=5").dispatch().status(), Status::NoContent);
+        assert_eq!(client.get("/chl/2023/4/12/0/1/1.png?max_value=6").dispatch().status(), Status::NoContent);
+        assert_eq!(client.get("/chl/2023/4/13/0/1/1.png?max_value=5").dispatch().status(), Status::NoContent);
+
+        // Zoom levels past the tile matrix set's are missing whatever the scheme, rather than wrapping around
+        for zoom in [25, 40, 256] {
+            let response = client.get(format!("/chl/2023/4/12/0/0/{}.png?max_value=5", zoom)).dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+            let response = client.get(format!("/chl/2023/4/12/0/0/{}.png?scheme=tms", zoom)).dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+        }
+    }
+
+    #[test]
+    fn test_section_spacing() {
+        let dir = TempPath::dir("section_spacing");
+        let client = client(&dir, Figment::from(rocket::Config::default()));
+        // Rejected before looking for the day, which has no dataset
+        for spacing in ["0", "-5", "inf", "NaN"] {
+            let uri = format!("/section/temp/2023/7/1?path=150,-34,152,-34&spacing={}", spacing);
+            assert_eq!(client.get(uri).dispatch().status(), Status::BadRequest, "{}", spacing);
+        }
+        let uri = "/section/temp/2023/7/1?path=150,-34,152,-34&spacing=5";
+        assert_eq!(client.get(uri).dispatch().status(), Status::NoContent);
+    }
+
+    #[test]
+    fn test_time_range() {
+        let dir = TempPath::dir("time_range");
+        let client = client(&dir, Figment::from(rocket::Config::default()));
+        for start in ["99999999999-01-01", "0000-01-01", "2023-04-12T99:00"] {
+            let uri = format!("/timeseries/chl?lat=0&lng=0&start={}", start);
+            assert_eq!(client.get(uri).dispatch().status(), Status::BadRequest, "{}", start);
+            let uri = format!("/chl/2023/4/12/0/0/0.png?start={}", start);
+            assert_eq!(client.get(uri).dispatch().status(), Status::BadRequest, "{}", start);
+        }
+        let uri = "/timeseries/chl?lat=0&lng=0&start=2023-04-12&end=9999-12-31";
+        assert_eq!(client.get(uri).dispatch().status(), Status::Ok);
+    }
 }
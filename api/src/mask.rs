@@ -0,0 +1,114 @@
+use rocket::serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tiler::mask::{Mask, MaskKind};
+use tiler::region::Region;
+
+/// A named mask under `[default.masks.<name>]` in Rocket.toml, from one of `geojson`, `shapefile` or `variable`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MaskConfig {
+    /// A GeoJSON file of polygons or multipolygons
+    pub geojson: Option<PathBuf>,
+    /// The .shp file of a polygon shapefile in WGS 84
+    pub shapefile: Option<PathBuf>,
+    /// A variable that is non-zero where pixels are kept, e.g. a land/sea mask
+    pub variable: Option<String>,
+    /// The file to read `variable` from, otherwise it's read from the dataset being rendered
+    pub path: Option<PathBuf>,
+    /// Keep the pixels outside the polygons, or where the variable is zero, instead
+    pub invert: bool,
+}
+
+impl MaskConfig {
+    /// The mask, with the polygons of GeoJSON and shapefile masks read in
+    pub fn load(&self) -> anyhow::Result<Mask> {
+        let kind = match (&self.geojson, &self.shapefile, &self.variable) {
+            (Some(path), None, None) => {
+                let geojson = rocket::serde::json::from_str(&std::fs::read_to_string(path)?)?;
+                MaskKind::Region(Region::from_geojson(&geojson)?)
+            }
+            (None, Some(path), None) => MaskKind::Region(tiler::shapefile::read_region(path)?),
+            (None, None, Some(name)) => MaskKind::Variable {
+                name: name.clone(),
+                path: self.path.clone(),
+            },
+            _ => return Err(anyhow::anyhow!("A mask needs exactly one of geojson, shapefile or variable")),
+        };
+        Ok(Mask {
+            kind,
+            invert: self.invert,
+        })
+    }
+}
+
+/// The masks tiles can be clipped by with `clip=<name>`, loaded at startup
+#[derive(Default)]
+pub struct Masks {
+    masks: HashMap<String, Mask>,
+}
+
+impl Masks {
+    /// Load every configured mask. Ones that can't be loaded are logged and left out.
+    pub fn load(configs: &HashMap<String, MaskConfig>) -> Self {
+        let mut masks = HashMap::new();
+        for (name, config) in configs {
+            match config.load() {
+                Ok(mask) => {
+                    masks.insert(name.clone(), mask);
+                }
+                Err(e) => println!("Error: can't load mask {:?}: {}", name, e),
+            }
+        }
+        Self { masks }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Mask> {
+        self.masks.get(name)
+    }
+}
+
+#[cfg(test)]
+mod mask_tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let geojson = r#"{"type": "Polygon", "coordinates": [[[0, 0], [10, 0], [10, 10], [0, 0]]]}"#;
+        let path = crate::testing::TempPath::write("mask.geojson", geojson);
+
+        let configs = HashMap::from([
+            (
+                "survey".to_string(),
+                MaskConfig {
+                    geojson: Some(path.to_path_buf()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "sea".to_string(),
+                MaskConfig {
+                    variable: Some("landmask".to_string()),
+                    invert: true,
+                    ..Default::default()
+                },
+            ),
+            (
+                "missing".to_string(),
+                MaskConfig {
+                    shapefile: Some(PathBuf::from("missing.shp")),
+                    ..Default::default()
+                },
+            ),
+            ("empty".to_string(), MaskConfig::default()),
+        ]);
+        let masks = Masks::load(&configs);
+        match &masks.get("survey").unwrap().kind {
+            MaskKind::Region(region) => assert!(region.contains((8.0, 2.0)) && !region.contains((2.0, 8.0))),
+            kind => panic!("Unexpected mask {:?}", kind),
+        }
+        assert!(masks.get("sea").unwrap().invert);
+        assert!(masks.get("missing").is_none());
+        assert!(masks.get("empty").is_none());
+    }
+}
@@ -0,0 +1,231 @@
+use crate::archive::{ArchiveMetadata, TileReader, TileWriter};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+// MBTiles rows count up from the bottom, like TMS
+fn tms_row(z: u8, y: u32) -> u32 {
+    (1u32 << z) - 1 - y
+}
+
+// Tiles written per transaction
+const BATCH_SIZE: usize = 256;
+
+/// Tiles in an MBTiles SQLite file, see https://github.com/mapbox/mbtiles-spec
+pub struct MbtilesWriter {
+    conn: Connection,
+    /// Tiles waiting to be inserted in one transaction, with None for empty tiles
+    pending: HashMap<(u8, u32, u32), Option<Vec<u8>>>,
+}
+
+impl MbtilesWriter {
+    /// Open or create the file. Tiles already in it are kept, so seeding can pick up where it left off.
+    /// Tiles without data are listed in an extra `empty_tiles` table for the same reason.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS metadata (name TEXT PRIMARY KEY, value TEXT);
+             CREATE TABLE IF NOT EXISTS tiles (
+                 zoom_level INTEGER,
+                 tile_column INTEGER,
+                 tile_row INTEGER,
+                 tile_data BLOB
+             );
+             CREATE UNIQUE INDEX IF NOT EXISTS tile_index ON tiles (zoom_level, tile_column, tile_row);
+             CREATE TABLE IF NOT EXISTS empty_tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER);
+             CREATE UNIQUE INDEX IF NOT EXISTS empty_tile_index ON empty_tiles (zoom_level, tile_column, tile_row);",
+        )?;
+        Ok(Self {
+            conn,
+            pending: HashMap::new(),
+        })
+    }
+
+    fn write(&mut self, z: u8, x: u32, y: u32, bytes: Option<&[u8]>) -> anyhow::Result<()> {
+        self.pending.insert((z, x, y), bytes.map(<[u8]>::to_vec));
+        if self.pending.len() >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Insert the pending tiles
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        let tx = self.conn.transaction()?;
+        for ((z, x, y), bytes) in self.pending.drain() {
+            match bytes {
+                Some(bytes) => tx.execute(
+                    "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![z, x, tms_row(z, y), bytes],
+                )?,
+                None => tx.execute(
+                    "INSERT OR REPLACE INTO empty_tiles (zoom_level, tile_column, tile_row) VALUES (?1, ?2, ?3)",
+                    params![z, x, tms_row(z, y)],
+                )?,
+            };
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+impl Drop for MbtilesWriter {
+    // Keep what's been rendered when seeding stops on an error
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            println!("Error: can't write tiles: {}", e);
+        }
+    }
+}
+
+impl TileWriter for MbtilesWriter {
+    fn contains(&self, z: u8, x: u32, y: u32) -> anyhow::Result<bool> {
+        if self.pending.contains_key(&(z, x, y)) {
+            return Ok(true);
+        }
+        let found = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3
+                 UNION ALL SELECT 1 FROM empty_tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                params![z, x, tms_row(z, y)],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    fn write_tile(&mut self, z: u8, x: u32, y: u32, bytes: &[u8]) -> anyhow::Result<()> {
+        self.write(z, x, y, Some(bytes))
+    }
+
+    fn write_empty(&mut self, z: u8, x: u32, y: u32) -> anyhow::Result<()> {
+        self.write(z, x, y, None)
+    }
+
+    fn finish(&mut self, metadata: &ArchiveMetadata) -> anyhow::Result<()> {
+        let b = &metadata.bounds;
+        let center_zoom = (metadata.min_zoom + metadata.max_zoom) / 2;
+        let values = [
+            ("name", metadata.name.clone()),
+            ("format", metadata.format.clone()),
+            ("type", "overlay".to_string()),
+            ("version", "1.0".to_string()),
+            ("bounds", format!("{},{},{},{}", b.min_x, b.min_y, b.max_x, b.max_y)),
+            (
+                "center",
+                format!("{},{},{}", (b.min_x + b.max_x) / 2.0, (b.min_y + b.max_y) / 2.0, center_zoom),
+            ),
+            ("minzoom", metadata.min_zoom.to_string()),
+            ("maxzoom", metadata.max_zoom.to_string()),
+        ];
+
+        self.flush()?;
+        let tx = self.conn.transaction()?;
+        for (name, value) in values {
+            tx.execute(
+                "INSERT OR REPLACE INTO metadata (name, value) VALUES (?1, ?2)",
+                params![name, value],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Read tiles out of an MBTiles file
+pub struct MbtilesReader {
+    // Connections can't be shared between threads
+    conn: Mutex<Connection>,
+    format: Option<String>,
+}
+
+impl MbtilesReader {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let format = conn
+            .query_row("SELECT value FROM metadata WHERE name = 'format'", [], |r| r.get(0))
+            .optional()?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            format,
+        })
+    }
+}
+
+impl TileReader for MbtilesReader {
+    fn get_tile(&self, z: u8, x: u32, y: u32) -> anyhow::Result<Option<Vec<u8>>> {
+        if 1u32.checked_shl(z as u32).is_none_or(|size| y >= size) {
+            return Ok(None);
+        }
+        let tile = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                params![z, x, tms_row(z, y)],
+                |r| r.get(0),
+            )
+            .optional()?;
+        Ok(tile)
+    }
+
+    fn format(&self) -> Option<&str> {
+        self.format.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod mbtiles_tests {
+    use super::*;
+    use tiler::bounds::Bounds;
+
+    #[test]
+    fn test_mbtiles_writer() {
+        let path = crate::testing::TempPath::new("mbtiles_writer.mbtiles");
+        let mut writer = MbtilesWriter::open(&path).unwrap();
+        writer.write_tile(2, 1, 0, b"tile").unwrap();
+        writer.write_empty(2, 2, 0).unwrap();
+        assert!(writer.contains(2, 1, 0).unwrap());
+        assert!(!writer.contains(2, 1, 3).unwrap());
+
+        let metadata = ArchiveMetadata {
+            name: "test".to_string(),
+            format: "png".to_string(),
+            bounds: Bounds::new(-10.0, -5.0, 10.0, 5.0),
+            min_zoom: 0,
+            max_zoom: 2,
+        };
+        writer.finish(&metadata).unwrap();
+
+        // Rows are stored flipped
+        let row: u32 = writer
+            .conn
+            .query_row("SELECT tile_row FROM tiles WHERE zoom_level = 2", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(row, 3);
+        let format: String = writer
+            .conn
+            .query_row("SELECT value FROM metadata WHERE name = 'format'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(format, "png");
+        drop(writer);
+
+        // Both kinds of tile are still there when seeding resumes
+        let writer = MbtilesWriter::open(&path).unwrap();
+        assert!(writer.contains(2, 1, 0).unwrap() && writer.contains(2, 2, 0).unwrap());
+        drop(writer);
+
+        let reader = MbtilesReader::open(&path).unwrap();
+        assert_eq!(reader.format(), Some("png"));
+        assert_eq!(reader.get_tile(2, 1, 0).unwrap(), Some(b"tile".to_vec()));
+        assert_eq!(reader.get_tile(2, 1, 3).unwrap(), None);
+        assert_eq!(reader.get_tile(2, 2, 0).unwrap(), None);
+        assert_eq!(reader.get_tile(40, 0, 0).unwrap(), None);
+    }
+}
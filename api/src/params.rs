@@ -0,0 +1,288 @@
+use crate::animation::AnimationFormat;
+use rocket::form::{self, FromFormField, ValueField};
+use rocket::request::FromParam;
+use tiler::anomaly::Anomaly;
+use tiler::bounds::Bounds;
+use tiler::composite::Composite;
+use tiler::coordinates::TileCoord;
+use tiler::subset::SubsetFormat;
+
+/// A `min_lng,min_lat,max_lng,max_lat` bounding box
+#[derive(Debug, Clone, Copy)]
+pub struct BboxParam(pub Bounds);
+
+impl BboxParam {
+    pub fn parse(s: &str) -> Option<Bounds> {
+        let values: Vec<f64> = s.split(',').map(|v| v.trim().parse()).collect::<Result<_, _>>().ok()?;
+        match values[..] {
+            [min_x, min_y, max_x, max_y] if min_x <= max_x && min_y <= max_y => {
+                Some(Bounds::new(min_x, min_y, max_x, max_y))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'v> FromFormField<'v> for BboxParam {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        match BboxParam::parse(field.value) {
+            Some(bounds) => Ok(BboxParam(bounds)),
+            None => Err(form::Error::validation("expected min_lng,min_lat,max_lng,max_lat").into()),
+        }
+    }
+}
+
+/// A `lng,lat,lng,lat,...` polyline of two or more points, e.g. a transect
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathParam(pub Vec<(f64, f64)>);
+
+impl PathParam {
+    pub fn parse(s: &str) -> Option<Vec<(f64, f64)>> {
+        let values: Vec<f64> = s.split(',').map(|v| v.trim().parse()).collect::<Result<_, _>>().ok()?;
+        match values.len() >= 4 && values.len().is_multiple_of(2) {
+            true => Some(values.chunks(2).map(|point| (point[0], point[1])).collect()),
+            false => None,
+        }
+    }
+}
+
+impl<'v> FromFormField<'v> for PathParam {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        match PathParam::parse(field.value) {
+            Some(points) => Ok(PathParam(points)),
+            None => Err(form::Error::validation("expected lng,lat,lng,lat with two or more points").into()),
+        }
+    }
+}
+
+/// How tile rows are counted in a tile URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, FromFormField)]
+pub enum TileScheme {
+    /// Down from the top, as in web maps
+    #[default]
+    Xyz,
+    /// Up from the bottom
+    Tms,
+}
+
+/// How the days of a composite are combined, with `agg=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum AggParam {
+    Mean,
+    Median,
+    Max,
+    Min,
+    Count,
+    #[field(value = "latest_valid")]
+    LatestValid,
+}
+
+impl From<AggParam> for Composite {
+    fn from(agg: AggParam) -> Self {
+        match agg {
+            AggParam::Mean => Composite::Mean,
+            AggParam::Median => Composite::Median,
+            AggParam::Max => Composite::Max,
+            AggParam::Min => Composite::Min,
+            AggParam::Count => Composite::Count,
+            AggParam::LatestValid => Composite::LatestValid,
+        }
+    }
+}
+
+/// What anomaly tiles are compared against, with `baseline=`: a date, a `start/end` composite, or `climatology`.
+/// A date alone as the end takes in the whole day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaselineParam {
+    Date(i64),
+    Range(i64, i64),
+    Climatology,
+}
+
+impl BaselineParam {
+    fn parse(s: &str) -> anyhow::Result<Self> {
+        if s == "climatology" {
+            return Ok(BaselineParam::Climatology);
+        }
+        match s.split_once('/') {
+            Some((start, end)) => Ok(BaselineParam::Range(tiler::time::parse(start)?, tiler::time::parse_end(end)?)),
+            None => Ok(BaselineParam::Date(tiler::time::parse(s)?)),
+        }
+    }
+}
+
+impl<'v> FromFormField<'v> for BaselineParam {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        BaselineParam::parse(field.value).map_err(|e| form::Error::validation(e.to_string()).into())
+    }
+}
+
+/// How anomaly tiles compare values to the baseline, with `anomaly=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum AnomalyParam {
+    Difference,
+    Ratio,
+}
+
+impl From<AnomalyParam> for Anomaly {
+    fn from(anomaly: AnomalyParam) -> Self {
+        match anomaly {
+            AnomalyParam::Difference => Anomaly::Difference,
+            AnomalyParam::Ratio => Anomaly::Ratio,
+        }
+    }
+}
+
+/// How an animation is encoded, with `format=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum AnimationParam {
+    Gif,
+    Apng,
+    Zip,
+}
+
+impl From<AnimationParam> for AnimationFormat {
+    fn from(format: AnimationParam) -> Self {
+        match format {
+            AnimationParam::Gif => AnimationFormat::Gif,
+            AnimationParam::Apng => AnimationFormat::Apng,
+            AnimationParam::Zip => AnimationFormat::Zip,
+        }
+    }
+}
+
+/// What a subset download is written as, with `format=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum SubsetParam {
+    #[field(value = "netcdf")]
+    #[field(value = "nc")]
+    NetCdf,
+    #[field(value = "geotiff")]
+    #[field(value = "tif")]
+    GeoTiff,
+    Csv,
+}
+
+impl From<SubsetParam> for SubsetFormat {
+    fn from(format: SubsetParam) -> Self {
+        match format {
+            SubsetParam::NetCdf => SubsetFormat::NetCdf,
+            SubsetParam::GeoTiff => SubsetFormat::GeoTiff,
+            SubsetParam::Csv => SubsetFormat::Csv,
+        }
+    }
+}
+
+/// What a vertical section is returned as, with `format=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum SectionParam {
+    Json,
+    /// A depth x distance plot colored like tiles
+    Png,
+}
+
+/// A quadkey tile URL segment, e.g. `0231`, `0231.webp` or `0231@2x.png`, split into the key and the
+/// suffix that goes after the zoom of the equivalent XYZ URL
+pub struct QuadkeyParam<'a> {
+    pub tile: TileCoord,
+    pub suffix: &'a str,
+}
+
+impl<'a> FromParam<'a> for QuadkeyParam<'a> {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        let (key, suffix) = param.split_at(param.find(['.', '@']).unwrap_or(param.len()));
+        let tile = TileCoord::from_quadkey(key).map_err(|_| param)?;
+        Ok(QuadkeyParam { tile, suffix })
+    }
+}
+
+/// A comma separated list of numbers, e.g. contour levels
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListParam(pub Vec<f64>);
+
+impl<'v> FromFormField<'v> for ListParam {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        field
+            .value
+            .split(',')
+            .map(|v| v.trim().parse())
+            .collect::<Result<_, _>>()
+            .map(ListParam)
+            .map_err(|_| form::Error::validation("expected a comma separated list of numbers").into())
+    }
+}
+
+/// Last path segment of a vector tile URL, e.g. `7`, `7.mvt` or `7.pbf`
+pub struct MvtZoomParam(pub u32);
+
+impl<'a> FromParam<'a> for MvtZoomParam {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        let zoom = param
+            .strip_suffix(".mvt")
+            .or_else(|| param.strip_suffix(".pbf"))
+            .unwrap_or(param);
+        zoom.parse().map(MvtZoomParam).map_err(|_| param)
+    }
+}
+
+#[cfg(test)]
+mod params_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bbox() {
+        let bounds = BboxParam::parse("-130, 48.5,-122,55").unwrap();
+        assert_eq!(bounds.min_x, -130.0);
+        assert_eq!(bounds.min_y, 48.5);
+        assert_eq!(bounds.max_x, -122.0);
+        assert_eq!(bounds.max_y, 55.0);
+
+        assert!(BboxParam::parse("1,2,3").is_none());
+        assert!(BboxParam::parse("1,2,0,3").is_none());
+        assert!(BboxParam::parse("a,b,c,d").is_none());
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(PathParam::parse("-130,48.5, -125,50").unwrap(), [(-130.0, 48.5), (-125.0, 50.0)]);
+        assert_eq!(PathParam::parse("0,0,1,1,2,0").unwrap().len(), 3);
+        assert!(PathParam::parse("-130,48.5").is_none());
+        assert!(PathParam::parse("1,2,3,4,5").is_none());
+    }
+
+    #[test]
+    fn test_mvt_zoom_param() {
+        assert_eq!(MvtZoomParam::from_param("7").unwrap().0, 7);
+        assert_eq!(MvtZoomParam::from_param("7.mvt").unwrap().0, 7);
+        assert_eq!(MvtZoomParam::from_param("12.pbf").unwrap().0, 12);
+        assert!(MvtZoomParam::from_param("7.png").is_err());
+    }
+
+    #[test]
+    fn test_quadkey_param() {
+        let param = QuadkeyParam::from_param("213").unwrap();
+        assert_eq!(param.tile, TileCoord::new(3, 5, 3));
+        assert_eq!(param.suffix, "");
+        let param = QuadkeyParam::from_param("213@2x.webp").unwrap();
+        assert_eq!(param.tile, TileCoord::new(3, 5, 3));
+        assert_eq!(param.suffix, "@2x.webp");
+        assert!(QuadkeyParam::from_param("214.png").is_err());
+    }
+
+    #[test]
+    fn test_parse_baseline() {
+        let day = tiler::time::from_ymd(2023, 4, 1);
+        assert_eq!(BaselineParam::parse("2023-04-01").unwrap(), BaselineParam::Date(day));
+        assert_eq!(
+            BaselineParam::parse("2023-04-01/2023-04-07").unwrap(),
+            BaselineParam::Range(day, tiler::time::add_days(day, 7) - 1)
+        );
+        assert_eq!(BaselineParam::parse("climatology").unwrap(), BaselineParam::Climatology);
+        assert!(BaselineParam::parse("last week").is_err());
+        assert!(BaselineParam::parse("2023-04-01/").is_err());
+    }
+}
@@ -0,0 +1,474 @@
+//! PMTiles v3 archives, see https://github.com/protomaps/PMTiles/blob/main/spec/v3/spec.md
+
+use crate::archive::{ArchiveMetadata, DirectoryWriter, TileReader, TileWriter};
+use anyhow::anyhow;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const HEADER_LEN: usize = 127;
+// The header and root directory have to fit in the first 16 KiB
+const MAX_ROOT_LEN: usize = 16384 - HEADER_LEN;
+const LEAF_ENTRIES: usize = 4096;
+
+const COMPRESSION_NONE: u8 = 1;
+const COMPRESSION_GZIP: u8 = 2;
+// Root, leaf, and at most one more level of leaves
+const MAX_DEPTH: usize = 3;
+
+/// A tile, or a run of identical tiles, or a leaf directory when `run_length` is 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub tile_id: u64,
+    pub offset: u64,
+    pub length: u32,
+    pub run_length: u32,
+}
+
+fn rotate(n: u64, x: &mut u64, y: &mut u64, rx: u64, ry: u64) {
+    if ry == 0 {
+        if rx == 1 {
+            *x = n - 1 - *x;
+            *y = n - 1 - *y;
+        }
+        std::mem::swap(x, y);
+    }
+}
+
+/// Position of a tile along the Hilbert curves of all zoom levels up to its own
+pub fn tile_id(z: u8, x: u32, y: u32) -> u64 {
+    // Number of tiles in all the lower zoom levels
+    let acc = ((1u64 << (2 * z as u64)) - 1) / 3;
+    let n = 1u64 << z;
+    let (mut x, mut y) = (x as u64, y as u64);
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = ((x & s) > 0) as u64;
+        let ry = ((y & s) > 0) as u64;
+        d += s * s * ((3 * rx) ^ ry);
+        rotate(n, &mut x, &mut y, rx, ry);
+        s /= 2;
+    }
+    acc + d
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> anyhow::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let b = bytes.next().ok_or_else(|| anyhow!("Truncated PMTiles directory"))?;
+        value |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("Invalid varint in PMTiles directory"))
+}
+
+/// Serialize a directory, uncompressed
+pub fn encode_directory(entries: &[Entry]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_varint(&mut buf, entries.len() as u64);
+
+    let mut last_id = 0;
+    for e in entries {
+        write_varint(&mut buf, e.tile_id - last_id);
+        last_id = e.tile_id;
+    }
+    for e in entries {
+        write_varint(&mut buf, e.run_length as u64);
+    }
+    for e in entries {
+        write_varint(&mut buf, e.length as u64);
+    }
+    for (i, e) in entries.iter().enumerate() {
+        // 0 means the tile follows straight on from the previous one
+        if i > 0 && e.offset == entries[i - 1].offset + entries[i - 1].length as u64 {
+            write_varint(&mut buf, 0);
+        } else {
+            write_varint(&mut buf, e.offset + 1);
+        }
+    }
+    buf
+}
+
+/// Deserialize an uncompressed directory
+pub fn decode_directory(bytes: &[u8]) -> anyhow::Result<Vec<Entry>> {
+    let mut bytes = bytes.iter().copied();
+    let count = read_varint(&mut bytes)? as usize;
+    let mut entries = vec![
+        Entry {
+            tile_id: 0,
+            offset: 0,
+            length: 0,
+            run_length: 0,
+        };
+        count
+    ];
+
+    let mut last_id = 0;
+    for e in entries.iter_mut() {
+        last_id += read_varint(&mut bytes)?;
+        e.tile_id = last_id;
+    }
+    for e in entries.iter_mut() {
+        e.run_length = read_varint(&mut bytes)? as u32;
+    }
+    for e in entries.iter_mut() {
+        e.length = read_varint(&mut bytes)? as u32;
+    }
+    for i in 0..count {
+        let offset = read_varint(&mut bytes)?;
+        entries[i].offset = if offset == 0 && i > 0 {
+            entries[i - 1].offset + entries[i - 1].length as u64
+        } else {
+            offset.saturating_sub(1)
+        };
+    }
+    Ok(entries)
+}
+
+// The entry covering the tile id: a tile run that contains it, or a leaf directory that might
+fn find_entry(entries: &[Entry], tile_id: u64) -> Option<Entry> {
+    let i = entries.partition_point(|e| e.tile_id <= tile_id).checked_sub(1)?;
+    let e = entries[i];
+    if e.run_length == 0 || tile_id < e.tile_id + e.run_length as u64 {
+        Some(e)
+    } else {
+        None
+    }
+}
+
+/// The root directory, and the leaf directories it points into if the entries don't fit in the root
+fn build_directories(entries: &[Entry]) -> (Vec<u8>, Vec<u8>) {
+    let root = encode_directory(entries);
+    if root.len() <= MAX_ROOT_LEN {
+        return (root, Vec::new());
+    }
+
+    let mut leaves = Vec::new();
+    let mut root_entries = Vec::new();
+    for chunk in entries.chunks(LEAF_ENTRIES) {
+        let leaf = encode_directory(chunk);
+        root_entries.push(Entry {
+            tile_id: chunk[0].tile_id,
+            offset: leaves.len() as u64,
+            length: leaf.len() as u32,
+            run_length: 0,
+        });
+        leaves.extend(leaf);
+    }
+    (encode_directory(&root_entries), leaves)
+}
+
+fn tile_type(format: &str) -> u8 {
+    match format {
+        "mvt" | "pbf" => 1,
+        "png" | "png8" => 2,
+        "jpg" | "jpeg" => 3,
+        "webp" => 4,
+        _ => 0,
+    }
+}
+
+fn e7(degrees: f64) -> i32 {
+    (degrees * 1e7).round() as i32
+}
+
+/// A PMTiles archive is written in one go at the end, so tiles are staged in a directory next to it
+/// until then. The staged tiles also let an interrupted run resume.
+pub struct PmtilesWriter {
+    path: PathBuf,
+    staging: DirectoryWriter,
+}
+
+impl PmtilesWriter {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let staging = DirectoryWriter::open(&path.with_extension("pmtiles-staging"), "tile")?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            staging,
+        })
+    }
+}
+
+impl TileWriter for PmtilesWriter {
+    fn contains(&self, z: u8, x: u32, y: u32) -> anyhow::Result<bool> {
+        self.staging.contains(z, x, y)
+    }
+
+    fn write_tile(&mut self, z: u8, x: u32, y: u32, bytes: &[u8]) -> anyhow::Result<()> {
+        self.staging.write_tile(z, x, y, bytes)
+    }
+
+    fn write_empty(&mut self, z: u8, x: u32, y: u32) -> anyhow::Result<()> {
+        self.staging.write_empty(z, x, y)
+    }
+
+    fn finish(&mut self, metadata: &ArchiveMetadata) -> anyhow::Result<()> {
+        let mut tiles: Vec<(u64, PathBuf)> = self
+            .staging
+            .tiles()?
+            .into_iter()
+            .map(|(z, x, y, path)| (tile_id(z, x, y), path))
+            .collect();
+        tiles.sort_by_key(|(id, _)| *id);
+
+        // Tile data goes in a temporary file first, since the directories in front of it depend on its layout
+        let data_path = self.path.with_extension("pmtiles-data");
+        let mut data = std::io::BufWriter::new(std::fs::File::create(&data_path)?);
+        let mut entries: Vec<Entry> = Vec::with_capacity(tiles.len());
+        let mut offset = 0;
+        for (id, path) in &tiles {
+            let bytes = std::fs::read(path)?;
+            let length = u32::try_from(bytes.len()).map_err(|_| anyhow!("Tile {:?} is too large", path))?;
+            data.write_all(&bytes)?;
+            entries.push(Entry {
+                tile_id: *id,
+                offset,
+                length,
+                run_length: 1,
+            });
+            offset += length as u64;
+        }
+        data.flush()?;
+        drop(data);
+
+        let (root, leaves) = build_directories(&entries);
+        let json = rocket::serde::json::serde_json::json!({
+            "name": metadata.name,
+            "format": metadata.format,
+        })
+        .to_string()
+        .into_bytes();
+
+        let root_offset = HEADER_LEN as u64;
+        let metadata_offset = root_offset + root.len() as u64;
+        let leaves_offset = metadata_offset + json.len() as u64;
+        let data_offset = leaves_offset + leaves.len() as u64;
+
+        let b = &metadata.bounds;
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(b"PMTiles");
+        header.push(3);
+        for value in [
+            root_offset,
+            root.len() as u64,
+            metadata_offset,
+            json.len() as u64,
+            leaves_offset,
+            leaves.len() as u64,
+            data_offset,
+            offset,
+            entries.len() as u64,
+            entries.len() as u64,
+            entries.len() as u64,
+        ] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        header.extend_from_slice(&[
+            1, // clustered
+            COMPRESSION_NONE,
+            COMPRESSION_NONE,
+            tile_type(&metadata.format),
+            metadata.min_zoom,
+            metadata.max_zoom,
+        ]);
+        for degrees in [b.min_x, b.min_y, b.max_x, b.max_y] {
+            header.extend_from_slice(&e7(degrees).to_le_bytes());
+        }
+        header.push((metadata.min_zoom + metadata.max_zoom) / 2);
+        header.extend_from_slice(&e7((b.min_x + b.max_x) / 2.0).to_le_bytes());
+        header.extend_from_slice(&e7((b.min_y + b.max_y) / 2.0).to_le_bytes());
+        debug_assert_eq!(header.len(), HEADER_LEN);
+
+        let mut out = std::io::BufWriter::new(std::fs::File::create(&self.path)?);
+        out.write_all(&header)?;
+        out.write_all(&root)?;
+        out.write_all(&json)?;
+        out.write_all(&leaves)?;
+        std::io::copy(&mut std::fs::File::open(&data_path)?, &mut out)?;
+        out.flush()?;
+
+        std::fs::remove_file(&data_path)?;
+        std::fs::remove_dir_all(self.path.with_extension("pmtiles-staging"))?;
+        Ok(())
+    }
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Read tiles out of a PMTiles archive. The root directory is kept in memory and leaves are read as needed.
+pub struct PmtilesReader {
+    file: Mutex<std::fs::File>,
+    root: Vec<Entry>,
+    leaves_offset: u64,
+    data_offset: u64,
+    internal_compression: u8,
+    format: Option<&'static str>,
+}
+
+impl PmtilesReader {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+        let mut header = [0; HEADER_LEN];
+        file.read_exact(&mut header)?;
+        if &header[..7] != b"PMTiles" || header[7] != 3 {
+            return Err(anyhow!("{:?} is not a PMTiles v3 archive", path));
+        }
+
+        let internal_compression = header[97];
+        let format = match header[99] {
+            1 => Some("mvt"),
+            2 => Some("png"),
+            3 => Some("jpg"),
+            4 => Some("webp"),
+            _ => None,
+        };
+        let mut reader = Self {
+            file: Mutex::new(file),
+            root: Vec::new(),
+            leaves_offset: u64_at(&header, 40),
+            data_offset: u64_at(&header, 56),
+            internal_compression,
+            format,
+        };
+        reader.root = reader.read_directory(u64_at(&header, 8), u64_at(&header, 16))?;
+        Ok(reader)
+    }
+
+    fn read_bytes(&self, offset: u64, length: u64) -> anyhow::Result<Vec<u8>> {
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))?;
+        let mut bytes = vec![0; length as usize];
+        file.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_directory(&self, offset: u64, length: u64) -> anyhow::Result<Vec<Entry>> {
+        let bytes = self.read_bytes(offset, length)?;
+        match self.internal_compression {
+            COMPRESSION_NONE => decode_directory(&bytes),
+            COMPRESSION_GZIP => {
+                let mut decompressed = Vec::new();
+                flate2::read::GzDecoder::new(&bytes[..]).read_to_end(&mut decompressed)?;
+                decode_directory(&decompressed)
+            }
+            c => Err(anyhow!("Unsupported PMTiles directory compression {}", c)),
+        }
+    }
+}
+
+impl TileReader for PmtilesReader {
+    fn get_tile(&self, z: u8, x: u32, y: u32) -> anyhow::Result<Option<Vec<u8>>> {
+        if 1u32.checked_shl(z as u32).is_none_or(|size| x >= size || y >= size) {
+            return Ok(None);
+        }
+        let id = tile_id(z, x, y);
+
+        let mut leaf;
+        let mut entries = &self.root;
+        for _ in 0..MAX_DEPTH {
+            let Some(entry) = find_entry(entries, id) else {
+                return Ok(None);
+            };
+            if entry.run_length > 0 {
+                return Ok(Some(self.read_bytes(self.data_offset + entry.offset, entry.length as u64)?));
+            }
+            leaf = self.read_directory(self.leaves_offset + entry.offset, entry.length as u64)?;
+            entries = &leaf;
+        }
+        Err(anyhow!("PMTiles directories are nested too deep"))
+    }
+
+    fn format(&self) -> Option<&str> {
+        self.format
+    }
+}
+
+#[cfg(test)]
+mod pmtiles_tests {
+    use super::*;
+
+    #[test]
+    fn test_tile_id() {
+        // From the spec
+        assert_eq!(tile_id(0, 0, 0), 0);
+        assert_eq!(tile_id(1, 0, 0), 1);
+        assert_eq!(tile_id(1, 0, 1), 2);
+        assert_eq!(tile_id(1, 1, 1), 3);
+        assert_eq!(tile_id(1, 1, 0), 4);
+        assert_eq!(tile_id(2, 0, 0), 5);
+    }
+
+    #[test]
+    fn test_encode_directory() {
+        let entries = [
+            Entry { tile_id: 1, offset: 0, length: 10, run_length: 1 },
+            Entry { tile_id: 3, offset: 10, length: 5, run_length: 1 },
+            Entry { tile_id: 4, offset: 100, length: 5, run_length: 2 },
+        ];
+        let bytes = encode_directory(&entries);
+        assert_eq!(bytes, vec![3, 1, 2, 1, 1, 1, 2, 10, 5, 5, 1, 0, 101]);
+    }
+
+    #[test]
+    fn test_decode_directory() {
+        let entries = vec![
+            Entry { tile_id: 1, offset: 0, length: 10, run_length: 1 },
+            Entry { tile_id: 3, offset: 10, length: 5, run_length: 1 },
+            Entry { tile_id: 4, offset: 100, length: 5, run_length: 2 },
+        ];
+        assert_eq!(decode_directory(&encode_directory(&entries)).unwrap(), entries);
+
+        assert_eq!(find_entry(&entries, 5), Some(entries[2]));
+        assert_eq!(find_entry(&entries, 2), None);
+        assert_eq!(find_entry(&entries, 6), None);
+        assert_eq!(find_entry(&entries, 0), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = crate::testing::TempPath::new("round_trip.pmtiles");
+        let mut writer = PmtilesWriter::open(&path).unwrap();
+        writer.write_tile(0, 0, 0, b"zero").unwrap();
+        writer.write_tile(2, 3, 1, b"two").unwrap();
+        let metadata = ArchiveMetadata {
+            name: "test".to_string(),
+            format: "webp".to_string(),
+            bounds: tiler::bounds::Bounds::new(-180.0, -85.0, 180.0, 85.0),
+            min_zoom: 0,
+            max_zoom: 2,
+        };
+        writer.finish(&metadata).unwrap();
+
+        let reader = PmtilesReader::open(&path).unwrap();
+        assert_eq!(reader.format(), Some("webp"));
+        assert_eq!(reader.get_tile(0, 0, 0).unwrap(), Some(b"zero".to_vec()));
+        assert_eq!(reader.get_tile(2, 3, 1).unwrap(), Some(b"two".to_vec()));
+        assert_eq!(reader.get_tile(2, 1, 1).unwrap(), None);
+        assert_eq!(reader.get_tile(40, 0, 0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_leaf_directories() {
+        let entries: Vec<Entry> = (0..20000)
+            .map(|i| Entry { tile_id: i * 2, offset: i * 100, length: 100, run_length: 1 })
+            .collect();
+        let (root, leaves) = build_directories(&entries);
+        assert!(root.len() <= MAX_ROOT_LEN);
+        assert!(!leaves.is_empty());
+        // Five leaves of up to 4096 entries
+        assert_eq!(root[0], 5);
+    }
+}
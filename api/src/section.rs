@@ -0,0 +1,106 @@
+//! Vertical sections as JSON, or as depth x distance plots colored like tiles
+
+use crate::animation::legend_value;
+use crate::colormap::Colormap;
+use crate::label;
+use image::RgbaImage;
+use rocket::serde::Serialize;
+use tiler::dataset::VerticalAxis;
+use tiler::section::Section;
+
+/// Largest plot width or height
+pub const MAX_PLOT_SIZE: usize = 2048;
+
+/// A section as JSON, with a row of values per level and missing values as null
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SectionJson<'a> {
+    pub axis: &'a VerticalAxis,
+    pub distances: &'a [f64],
+    pub points: Vec<[f64; 2]>,
+    pub values: Vec<Vec<f64>>,
+}
+
+impl<'a> From<&'a Section> for SectionJson<'a> {
+    fn from(section: &'a Section) -> Self {
+        Self {
+            axis: &section.axis,
+            distances: &section.distances,
+            points: section.points.iter().map(|(lng, lat)| [*lng, *lat]).collect(),
+            values: section.values.outer_iter().map(|row| row.to_vec()).collect(),
+        }
+    }
+}
+
+// Index of the value nearest to v
+fn nearest(values: &[f64], v: f64) -> usize {
+    (0..values.len()).min_by(|a, b| (values[*a] - v).abs().total_cmp(&(values[*b] - v).abs())).unwrap_or(0)
+}
+
+/// Plot a section with distance to the right and the surface or top level at the top, with each pixel taking the
+/// nearest level and point. The top and bottom levels are labelled on the left and the path length on the right.
+pub fn render(section: &Section, width: usize, height: usize, colormap: &Colormap) -> RgbaImage {
+    let levels = &section.axis.values;
+    let (min, max) = levels
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(*v), max.max(*v)));
+    let (top, bottom) = if section.axis.positive_up { (max, min) } else { (min, max) };
+    let length = section.distances.last().copied().unwrap_or(0.0);
+
+    let rows: Vec<usize> = (0..height)
+        .map(|row| nearest(levels, top + (row as f64 + 0.5) / height as f64 * (bottom - top)))
+        .collect();
+    let cols: Vec<usize> = (0..width)
+        .map(|col| nearest(&section.distances, (col as f64 + 0.5) / width as f64 * length))
+        .collect();
+    let data: Vec<f64> = rows
+        .iter()
+        .flat_map(|level| cols.iter().map(move |point| section.values[[*level, *point]]))
+        .collect();
+    let mut img = colormap.render_rgba(&data, width, height);
+
+    let scale = (img.width() / 400).max(1);
+    let margin = 4 * scale as i64;
+    let (_, text_height) = label::text_size("0", scale);
+    let label_height = text_height as i64 + 4 * scale as i64;
+    let bottom_y = img.height() as i64 - margin - label_height;
+    label::draw_label(&mut img, margin, margin, &legend_value(top), scale);
+    label::draw_label(&mut img, margin, bottom_y, &legend_value(bottom), scale);
+    let length_label = legend_value(length);
+    let length_width = label::text_size(&length_label, scale).0 as i64 + 4 * scale as i64;
+    let right_x = img.width() as i64 - margin - length_width;
+    label::draw_label(&mut img, right_x, bottom_y, &length_label, scale);
+    img
+}
+
+#[cfg(test)]
+mod section_tests {
+    use super::*;
+    use ndarray::Array2;
+
+    #[test]
+    fn test_render() {
+        let section = Section {
+            axis: VerticalAxis {
+                name: "depth".to_string(),
+                values: vec![0.0, 10.0, 100.0],
+                units: Some("m".to_string()),
+                positive_up: false,
+            },
+            distances: vec![0.0, 50.0, 100.0],
+            points: vec![(0.0, 0.0), (0.5, 0.0), (0.9, 0.0)],
+            values: Array2::from_shape_vec((3, 3), vec![0.0, 1.0, 2.0, 3.0, 4.0, f64::NAN, 6.0, 7.0, 8.0]).unwrap(),
+        };
+        let colormap = Colormap::new(colorous::VIRIDIS, 0.0, 8.0, false);
+        let img = render(&section, 300, 100, &colormap);
+        assert_eq!(img.dimensions(), (300, 100));
+        // Rows nearest 10m run down to 55m, and the last point takes the right sixth
+        assert_eq!(img.get_pixel(150, 20).0, colormap.ramp(4.0 / 8.0));
+        assert_eq!(img.get_pixel(280, 20)[3], 0);
+        assert_eq!(img.get_pixel(150, 80).0, colormap.ramp(7.0 / 8.0));
+
+        let json = rocket::serde::json::to_string(&SectionJson::from(&section)).unwrap();
+        assert!(json.contains(r#""values":[[0.0,1.0,2.0],[3.0,4.0,null],[6.0,7.0,8.0]]"#));
+        assert!(json.contains(r#""positive_up":false"#));
+    }
+}
@@ -0,0 +1,205 @@
+use crate::archive::{ArchiveMetadata, TileWriter};
+use crate::colormap::Colormap;
+use crate::format::{EncodeOptions, TileFormat};
+use rayon::prelude::*;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tiler::coordinates::tiles_for_bounds;
+use tiler::dataset::Dataset;
+use tiler::expr::Expr;
+use tiler::overview::OverviewCache;
+use tiler::tms::WebMercatorQuad;
+
+/// Everything needed to render one layer
+pub struct SeedJob {
+    pub dset_path: PathBuf,
+    pub expr: Expr,
+    pub lat_name: String,
+    pub lon_name: String,
+    pub colormap: Colormap,
+    pub format: TileFormat,
+    pub options: EncodeOptions,
+    /// Tile size in pixels
+    pub tile_size: usize,
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SeedSummary {
+    pub rendered: usize,
+    /// Already in the output from an earlier run, or recorded there as empty
+    pub skipped: usize,
+    /// No data in the tile
+    pub empty: usize,
+}
+
+/// The file extension tiles are stored under
+pub fn tile_extension(format: TileFormat) -> &'static str {
+    match format {
+        TileFormat::Png | TileFormat::IndexedPng => "png",
+        TileFormat::WebP => "webp",
+        TileFormat::Jpeg => "jpg",
+    }
+}
+
+// Colormap a tile, leaving pixels outside the dataset transparent if missing values get a color
+fn encode_tile(job: &SeedJob, data: &[f64], x: u32, y: u32, zoom: u8) -> anyhow::Result<Vec<u8>> {
+    if !job.colormap.paints_nodata() {
+        return job.colormap.encode_tile(data, job.tile_size, job.format, &job.options);
+    }
+    let inside = tiler::get_tile_coverage(
+        &job.dset_path,
+        &WebMercatorQuad,
+        x,
+        y,
+        zoom as u32,
+        job.tile_size,
+        &job.lat_name,
+        &job.lon_name,
+    )?;
+    job.colormap.encode_clipped_tile(data, &inside, job.tile_size, job.format, &job.options)
+}
+
+/// Render every tile of the job over the dataset bounds into the writer, in parallel.
+/// Tiles the writer already has, or has recorded as empty, are skipped, so an interrupted run can be resumed.
+pub fn seed(job: &SeedJob, writer: Box<dyn TileWriter>, name: &str) -> anyhow::Result<SeedSummary> {
+    // Tiles are found from lng/lat, so projected grids are seeded over the box around them
+    let bounds = Dataset::new(&job.dset_path, &job.lat_name, &job.lon_name)?
+        .lng_lat_bounds()
+        .ok_or_else(|| anyhow::anyhow!("The dataset's projection doesn't reach any of its grid"))?;
+    let writer = Mutex::new(writer);
+    let mut summary = SeedSummary::default();
+    // Low zooms all come from one set of overviews instead of rereading the full grid per tile
+    let overviews = OverviewCache::new(1);
+
+    for zoom in job.min_zoom..=job.max_zoom {
+        let tiles: Vec<(u32, u32)> = tiles_for_bounds(&bounds, zoom..=zoom).map(|t| (t.x(), t.y())).collect();
+        let done = AtomicUsize::new(0);
+        let rendered = AtomicUsize::new(0);
+        let skipped = AtomicUsize::new(0);
+        let empty = AtomicUsize::new(0);
+        // Report roughly every 5%
+        let report_every = (tiles.len() / 20).max(1);
+
+        tiles.par_iter().try_for_each(|(x, y)| -> anyhow::Result<()> {
+            if writer.lock().unwrap().contains(zoom, *x, *y)? {
+                skipped.fetch_add(1, Ordering::Relaxed);
+            } else {
+                let tile = tiler::get_overview_tile(
+                    &job.dset_path,
+                    &WebMercatorQuad,
+                    *x,
+                    *y,
+                    zoom as u32,
+                    job.tile_size,
+                    &job.expr,
+                    &job.lat_name,
+                    &job.lon_name,
+                    &overviews,
+                )?;
+                match tile {
+                    Some(data) => {
+                        let bytes = encode_tile(job, &data, *x, *y, zoom)?;
+                        writer.lock().unwrap().write_tile(zoom, *x, *y, &bytes)?;
+                        rendered.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => {
+                        writer.lock().unwrap().write_empty(zoom, *x, *y)?;
+                        empty.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+
+            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+            if done.is_multiple_of(report_every) || done == tiles.len() {
+                println!("z{}: {}/{} tiles", zoom, done, tiles.len());
+            }
+            Ok(())
+        })?;
+
+        summary.rendered += rendered.into_inner();
+        summary.skipped += skipped.into_inner();
+        summary.empty += empty.into_inner();
+    }
+
+    let metadata = ArchiveMetadata {
+        name: name.to_string(),
+        format: tile_extension(job.format).to_string(),
+        bounds,
+        min_zoom: job.min_zoom,
+        max_zoom: job.max_zoom,
+    };
+    writer.into_inner().unwrap().finish(&metadata)?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod seed_tests {
+    use super::*;
+    use crate::archive::DirectoryWriter;
+    use crate::testing::TempPath;
+    use std::path::Path;
+
+    // An uncompressed little-endian float Zarr v2 array in one chunk, or a scalar with only attributes
+    fn write_array(store: &Path, name: &str, dims: &[(&str, usize)], attrs: &str, values: &[f32]) {
+        let dir = store.join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        let shape: Vec<String> = dims.iter().map(|(_, len)| len.to_string()).collect();
+        let names: Vec<String> = dims.iter().map(|(name, _)| format!("\"{}\"", name)).collect();
+        let zarray = format!(
+            r#"{{"zarr_format": 2, "shape": [{0}], "chunks": [{0}], "dtype": "<f4", "compressor": null,
+                "fill_value": "NaN", "order": "C", "filters": null}}"#,
+            shape.join(", ")
+        );
+        std::fs::write(dir.join(".zarray"), zarray).unwrap();
+        let zattrs = format!(r#"{{"_ARRAY_DIMENSIONS": [{}]{}}}"#, names.join(", "), attrs);
+        std::fs::write(dir.join(".zattrs"), zattrs).unwrap();
+        if !dims.is_empty() {
+            let chunk = vec!["0"; dims.len()].join(".");
+            std::fs::write(dir.join(chunk), values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_seed_projected() {
+        // A 5 x 5 grid of 50 km cells in UTM zone 33, around 15E 50N
+        let store = TempPath::dir("utm.zarr");
+        std::fs::write(store.join(".zgroup"), r#"{"zarr_format": 2}"#).unwrap();
+        let coord = |axis: &str, origin: f32| {
+            let attrs = format!(r#", "standard_name": "projection_{}_coordinate", "units": "m""#, axis);
+            let values: Vec<f32> = (0..5).map(|i| origin + 50000.0 * i as f32).collect();
+            write_array(&store, axis, &[(axis, 5)], &attrs, &values);
+        };
+        coord("x", 400000.0);
+        coord("y", 5440000.0);
+        write_array(&store, "crs", &[], r#", "proj4": "+proj=utm +zone=33 +ellps=WGS84 +units=m""#, &[]);
+        write_array(&store, "chl", &[("y", 5), ("x", 5)], r#", "grid_mapping": "crs""#, &[1.0; 25]);
+
+        let job = SeedJob {
+            dset_path: store.to_path_buf(),
+            expr: Expr::variable("chl"),
+            lat_name: "lat".to_string(),
+            lon_name: "lon".to_string(),
+            colormap: Colormap::new(colorous::VIRIDIS, 0.0, 2.0, false),
+            format: TileFormat::Png,
+            options: EncodeOptions::default(),
+            tile_size: 64,
+            min_zoom: 4,
+            max_zoom: 6,
+        };
+        let tiles = TempPath::dir("utm-tiles");
+        let summary = seed(&job, Box::new(DirectoryWriter::open(&tiles, "png").unwrap()), "chl").unwrap();
+        assert_eq!(summary, SeedSummary { rendered: 3, skipped: 0, empty: 0 });
+        let mut written: Vec<(u8, u32, u32)> = DirectoryWriter::open(&tiles, "png")
+            .unwrap()
+            .tiles()
+            .unwrap()
+            .into_iter()
+            .map(|(z, x, y, _)| (z, x, y))
+            .collect();
+        written.sort();
+        assert_eq!(written, [(4, 8, 5), (5, 17, 10), (6, 34, 21)]);
+    }
+}
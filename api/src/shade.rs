@@ -0,0 +1,80 @@
+use image::RgbaImage;
+use tiler::terrain::HillshadeOptions;
+use tiler::Surface;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum RenderMode {
+    /// Colormapped values
+    Color,
+    /// Grayscale hillshade
+    Hillshade,
+    /// Colormapped gradient magnitude, per kilometer
+    Gradient,
+    /// Colormapped values with a hillshade multiplied over them
+    Shaded,
+}
+
+impl RenderMode {
+    /// The derived surface the mode needs, if any
+    pub fn surface(&self, options: HillshadeOptions) -> Option<Surface> {
+        match self {
+            RenderMode::Color => None,
+            RenderMode::Hillshade | RenderMode::Shaded => Some(Surface::Hillshade(options)),
+            RenderMode::Gradient => Some(Surface::Gradient),
+        }
+    }
+}
+
+pub fn hillshade_options(azimuth: Option<f64>, altitude: Option<f64>, z_factor: Option<f64>) -> HillshadeOptions {
+    let mut options = HillshadeOptions::default();
+    if let Some(azimuth) = azimuth {
+        options.azimuth = azimuth.rem_euclid(360.0);
+    }
+    if let Some(altitude) = altitude {
+        options.altitude = altitude.clamp(0.0, 90.0);
+    }
+    if let Some(z_factor) = z_factor {
+        options.z_factor = z_factor;
+    }
+    options
+}
+
+/// Darken the image by the hillshade. `strength` 0 leaves it alone and 1 is a plain multiply.
+pub fn blend_hillshade(img: &mut RgbaImage, shade: &[f64], strength: f64) {
+    let strength = strength.clamp(0.0, 1.0);
+    for (pixel, shade) in img.pixels_mut().zip(shade) {
+        if shade.is_nan() {
+            continue;
+        }
+        let factor = 1.0 - strength + strength * shade;
+        for c in &mut pixel.0[..3] {
+            *c = (*c as f64 * factor).round() as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod shade_tests {
+    use super::*;
+
+    #[test]
+    fn test_blend_hillshade() {
+        let mut img = RgbaImage::from_pixel(3, 1, image::Rgba([200, 100, 50, 255]));
+        blend_hillshade(&mut img, &[0.5, 1.0, f64::NAN], 1.0);
+        assert_eq!(img.get_pixel(0, 0).0, [100, 50, 25, 255]);
+        assert_eq!(img.get_pixel(1, 0).0, [200, 100, 50, 255]);
+        assert_eq!(img.get_pixel(2, 0).0, [200, 100, 50, 255]);
+
+        let mut img = RgbaImage::from_pixel(1, 1, image::Rgba([200, 100, 50, 255]));
+        blend_hillshade(&mut img, &[0.0], 0.5);
+        assert_eq!(img.get_pixel(0, 0).0, [100, 50, 25, 255]);
+    }
+
+    #[test]
+    fn test_hillshade_options() {
+        let options = hillshade_options(Some(-45.0), Some(120.0), None);
+        assert_eq!(options.azimuth, 315.0);
+        assert_eq!(options.altitude, 90.0);
+        assert_eq!(options.z_factor, 1.0);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::colormap::Rgba;
+use image::RgbaImage;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromFormField)]
+pub enum VectorMode {
+    /// Magnitude colored with arrows on top
+    Arrows,
+    /// Magnitude colored with wind barbs on top
+    Barbs,
+    /// Just the magnitude
+    Magnitude,
+    /// u and v packed into the red and green channels, for WebGL particle layers
+    Uv,
+}
+
+pub struct SymbolStyle {
+    /// Pixels between symbols
+    pub spacing: u32,
+    pub color: Rgba,
+    /// Speed at which arrows reach their full length
+    pub max_speed: f64,
+}
+
+pub fn magnitude(u: &[f64], v: &[f64]) -> Vec<f64> {
+    u.iter().zip(v).map(|(u, v)| u.hypot(*v)).collect()
+}
+
+// Iterate the center pixel of every symbol cell, with the u and v values there
+fn symbol_cells<'a>(
+    u: &'a [f64],
+    v: &'a [f64],
+    width: u32,
+    height: u32,
+    spacing: u32,
+) -> impl Iterator<Item = (f64, f64, f64, f64)> + 'a {
+    let spacing = spacing.max(1);
+    (spacing / 2..height).step_by(spacing as usize).flat_map(move |cy| {
+        (spacing / 2..width).step_by(spacing as usize).filter_map(move |cx| {
+            let i = (cy * width + cx) as usize;
+            if u[i].is_nan() || v[i].is_nan() {
+                None
+            } else {
+                Some((cx as f64, cy as f64, u[i], v[i]))
+            }
+        })
+    })
+}
+
+fn draw_line(img: &mut RgbaImage, (x0, y0): (f64, f64), (x1, y1): (f64, f64), color: Rgba) {
+    let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as usize;
+    for i in 0..=steps {
+        let t = i as f64 / steps as f64;
+        let x = (x0 + (x1 - x0) * t).round();
+        let y = (y0 + (y1 - y0) * t).round();
+        if x >= 0.0 && y >= 0.0 && (x as u32) < img.width() && (y as u32) < img.height() {
+            img.put_pixel(x as u32, y as u32, image::Rgba(color.0));
+        }
+    }
+}
+
+// Rotate a screen space direction by an angle in radians
+fn rotate((x, y): (f64, f64), angle: f64) -> (f64, f64) {
+    let (sin, cos) = angle.sin_cos();
+    (x * cos - y * sin, x * sin + y * cos)
+}
+
+/// Draw an arrow centered in every cell, pointing with the flow and scaled by speed
+pub fn draw_arrows(img: &mut RgbaImage, u: &[f64], v: &[f64], style: &SymbolStyle) {
+    let (width, height) = img.dimensions();
+
+    for (cx, cy, u, v) in symbol_cells(u, v, width, height, style.spacing) {
+        let speed = u.hypot(v);
+        if speed == 0.0 {
+            continue;
+        }
+        // Image y is down, v is north
+        let dir = (u / speed, -v / speed);
+        let length = style.spacing as f64 * 0.9 * (speed / style.max_speed).min(1.0);
+        let half = length / 2.0;
+        let tail = (cx - dir.0 * half, cy - dir.1 * half);
+        let head = (cx + dir.0 * half, cy + dir.1 * half);
+        draw_line(img, tail, head, style.color);
+
+        let wing_length = (length * 0.3).max(2.0);
+        for angle in [2.6, -2.6] {
+            let wing = rotate(dir, angle);
+            draw_line(img, head, (head.0 + wing.0 * wing_length, head.1 + wing.1 * wing_length), style.color);
+        }
+    }
+}
+
+/// Speed rounded to the nearest 5 as (pennants of 50, full barbs of 10, half barbs of 5)
+pub fn barb_counts(speed: f64) -> (u32, u32, u32) {
+    let rounded = (speed / 5.0).round() as u32 * 5;
+    (rounded / 50, rounded % 50 / 10, rounded % 10 / 5)
+}
+
+/// Draw a wind barb in every cell. The staff points into the flow, so it shows where the wind comes from,
+/// and speeds are counted in the units of the data.
+pub fn draw_barbs(img: &mut RgbaImage, u: &[f64], v: &[f64], style: &SymbolStyle) {
+    let (width, height) = img.dimensions();
+    let staff_length = style.spacing as f64 * 0.8;
+    let barb_length = staff_length * 0.4;
+    let step = staff_length / 7.0;
+
+    for (cx, cy, u, v) in symbol_cells(u, v, width, height, style.spacing) {
+        let speed = u.hypot(v);
+        let (pennants, full, half) = barb_counts(speed);
+
+        // Calm is drawn as a small circle
+        if pennants + full + half == 0 {
+            for i in 0..16 {
+                let a = i as f64 / 16.0 * std::f64::consts::TAU;
+                let b = (i + 1) as f64 / 16.0 * std::f64::consts::TAU;
+                draw_line(img, (cx + 3.0 * a.cos(), cy + 3.0 * a.sin()), (cx + 3.0 * b.cos(), cy + 3.0 * b.sin()), style.color);
+            }
+            continue;
+        }
+
+        // Unit vector from the station out along the staff
+        let staff = (-u / speed, v / speed);
+        let barb = rotate(staff, -1.2);
+        let end = (cx + staff.0 * staff_length, cy + staff.1 * staff_length);
+        draw_line(img, (cx, cy), end, style.color);
+
+        // Walk inwards from the end of the staff
+        let mut pos = 0.0;
+        let at = |pos: f64| (end.0 - staff.0 * pos, end.1 - staff.1 * pos);
+        for _ in 0..pennants {
+            let base0 = at(pos);
+            let base1 = at(pos + step * 2.0);
+            let tip = (base0.0 + barb.0 * barb_length, base0.1 + barb.1 * barb_length);
+            // Fill the triangle with lines from the tip to points along its base
+            for i in 0..=8 {
+                let t = i as f64 / 8.0;
+                let base = (base0.0 + (base1.0 - base0.0) * t, base0.1 + (base1.1 - base0.1) * t);
+                draw_line(img, base, tip, style.color);
+            }
+            pos += step * 2.5;
+        }
+        for _ in 0..full {
+            let base = at(pos);
+            draw_line(img, base, (base.0 + barb.0 * barb_length, base.1 + barb.1 * barb_length), style.color);
+            pos += step;
+        }
+        if half > 0 {
+            // A lone half barb is set in from the end so it isn't mistaken for a full one
+            if pos == 0.0 {
+                pos = step;
+            }
+            let base = at(pos);
+            let length = barb_length / 2.0;
+            draw_line(img, base, (base.0 + barb.0 * length, base.1 + barb.1 * length), style.color);
+        }
+    }
+}
+
+/// Pack u and v into red and green, mapping `[-range, range]` to `[0, 255]`.
+/// Missing data is fully transparent.
+pub fn encode_uv(u: &[f64], v: &[f64], width: u32, height: u32, range: f64) -> RgbaImage {
+    let scale = |x: f64| ((x + range) / (2.0 * range) * 255.0).round().clamp(0.0, 255.0) as u8;
+
+    let mut imgbuf = RgbaImage::new(width, height);
+    for (i, (u, v)) in u.iter().zip(v).enumerate() {
+        if u.is_nan() || v.is_nan() {
+            continue;
+        }
+        let x = i as u32 % width;
+        let y = i as u32 / width;
+        imgbuf.put_pixel(x, y, image::Rgba([scale(*u), scale(*v), 0, 255]));
+    }
+    imgbuf
+}
+
+#[cfg(test)]
+mod vector_tests {
+    use super::*;
+
+    const WHITE: Rgba = Rgba([255, 255, 255, 255]);
+
+    #[test]
+    fn test_barb_counts() {
+        assert_eq!(barb_counts(0.0), (0, 0, 0));
+        assert_eq!(barb_counts(4.0), (0, 0, 1));
+        assert_eq!(barb_counts(15.0), (0, 1, 1));
+        assert_eq!(barb_counts(64.0), (1, 1, 1));
+        assert_eq!(barb_counts(100.0), (2, 0, 0));
+    }
+
+    #[test]
+    fn test_encode_uv() {
+        let img = encode_uv(&[-10.0, 0.0, 10.0, f64::NAN], &[10.0, 0.0, -20.0, 0.0], 2, 2, 10.0);
+        assert_eq!(img.get_pixel(0, 0).0, [0, 255, 0, 255]);
+        assert_eq!(img.get_pixel(1, 0).0, [128, 128, 0, 255]);
+        assert_eq!(img.get_pixel(0, 1).0, [255, 0, 0, 255]);
+        assert_eq!(img.get_pixel(1, 1).0, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_draw_arrows() {
+        // One cell, flowing east at full speed
+        let mut img = RgbaImage::new(20, 20);
+        let u = vec![5.0; 400];
+        let v = vec![0.0; 400];
+        let style = SymbolStyle {
+            spacing: 20,
+            color: WHITE,
+            max_speed: 5.0,
+        };
+        draw_arrows(&mut img, &u, &v, &style);
+
+        // The shaft runs through the center along the row
+        assert_eq!(img.get_pixel(3, 10).0, WHITE.0);
+        assert_eq!(img.get_pixel(17, 10).0, WHITE.0);
+        assert_eq!(img.get_pixel(10, 3).0, [0, 0, 0, 0]);
+        // The head points east, so the wings are behind the tip
+        assert_eq!(img.get_pixel(14, 7).0, WHITE.0);
+        assert_eq!(img.get_pixel(14, 13).0, WHITE.0);
+        assert_eq!(img.get_pixel(6, 7).0, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_draw_barbs_staff_points_upwind() {
+        // Wind blowing north comes from the south, so the staff points down
+        let mut img = RgbaImage::new(40, 40);
+        let u = vec![0.0; 1600];
+        let v = vec![10.0; 1600];
+        let style = SymbolStyle {
+            spacing: 40,
+            color: WHITE,
+            max_speed: 10.0,
+        };
+        draw_barbs(&mut img, &u, &v, &style);
+
+        assert_eq!(img.get_pixel(20, 30).0, WHITE.0);
+        assert_eq!(img.get_pixel(20, 10).0, [0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,405 @@
+use crate::dataset::{read_times, DatasetPath};
+use crate::expr::Expr;
+use crate::time;
+use crate::region::Region;
+use crate::zonal::ZonalStats;
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Lookups that miss rescan the files, but no more often than this
+const RESCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The most time steps one request can read, ten years of daily files
+pub const MAX_STEPS: usize = 3660;
+
+/// A date or time part of a path template
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Year,
+    Month,
+    Day,
+    DayOfYear,
+    Hour,
+}
+
+impl Field {
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "year" => Ok(Field::Year),
+            "month" => Ok(Field::Month),
+            "day" => Ok(Field::Day),
+            "doy" => Ok(Field::DayOfYear),
+            "hour" => Ok(Field::Hour),
+            name => Err(anyhow!("Unknown template field {{{}}}", name)),
+        }
+    }
+
+    // Fields are zero padded to a fixed width, so they can sit next to each other, e.g. `{year}{month}{day}`
+    fn width(self) -> usize {
+        match self {
+            Field::Year => 4,
+            Field::DayOfYear => 3,
+            Field::Month | Field::Day | Field::Hour => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    Field(Field),
+    /// `*`, any run of characters
+    AnyRun,
+    /// `?`, any one character
+    AnyChar,
+}
+
+// Match one path component against template tokens, collecting the field values
+fn match_tokens(tokens: &[Token], s: &str, fields: &mut Vec<(Field, u32)>) -> bool {
+    let Some(token) = tokens.first() else {
+        return s.is_empty();
+    };
+    let rest = &tokens[1..];
+    match token {
+        Token::Literal(c) => s.strip_prefix(*c).is_some_and(|s| match_tokens(rest, s, fields)),
+        Token::AnyChar => s.chars().next().is_some_and(|c| match_tokens(rest, &s[c.len_utf8()..], fields)),
+        Token::AnyRun => s
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([s.len()])
+            .any(|i| match_tokens(rest, &s[i..], fields)),
+        Token::Field(field) => {
+            let width = field.width();
+            let digits = s.get(..width).filter(|d| d.len() == width && d.bytes().all(|b| b.is_ascii_digit()));
+            let Some(digits) = digits else {
+                return false;
+            };
+            fields.push((*field, digits.parse().unwrap()));
+            if match_tokens(rest, &s[width..], fields) {
+                return true;
+            }
+            fields.pop();
+            false
+        }
+    }
+}
+
+/// A path template like `/data/{year}/{month}/{day}/chl.nc` or `/data/*/chl_{year}{doy}.nc`. Fields are zero padded
+/// numbers: `{year}`, `{month}`, `{day}`, `{doy}` (day of year) and `{hour}`. `*` and `?` match as in a glob.
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    components: Vec<Vec<Token>>,
+    glob: String,
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> anyhow::Result<Self> {
+        let mut components = Vec::new();
+        let mut glob = Vec::new();
+        for component in template.split('/') {
+            let mut tokens = Vec::new();
+            let mut pattern = String::new();
+            let mut chars = component.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '{' => {
+                        let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                        let field = Field::parse(&name)?;
+                        pattern.push_str(&"[0-9]".repeat(field.width()));
+                        tokens.push(Token::Field(field));
+                    }
+                    '*' => {
+                        pattern.push('*');
+                        tokens.push(Token::AnyRun);
+                    }
+                    '?' => {
+                        pattern.push('?');
+                        tokens.push(Token::AnyChar);
+                    }
+                    c => {
+                        pattern.push_str(&glob::Pattern::escape(&c.to_string()));
+                        tokens.push(Token::Literal(c));
+                    }
+                }
+            }
+            components.push(tokens);
+            glob.push(pattern);
+        }
+        Ok(Self {
+            components,
+            glob: glob.join("/"),
+        })
+    }
+
+    /// Whether the times come from the paths, rather than the files' time coordinates
+    pub fn has_time(&self) -> bool {
+        self.components.iter().flatten().any(|t| matches!(t, Token::Field(_)))
+    }
+
+    /// The fields in a path, or None if it doesn't match. Components are matched from the end, since glob
+    /// results can drop a leading `./`.
+    fn match_path(&self, path: &Path) -> Option<Vec<(Field, u32)>> {
+        let path = path.to_string_lossy();
+        let parts: Vec<&str> = path.split('/').collect();
+        let mut fields = Vec::new();
+        for (tokens, part) in self.components.iter().rev().zip(parts.iter().rev()) {
+            if !match_tokens(tokens, part, &mut fields) {
+                return None;
+            }
+        }
+        Some(fields)
+    }
+
+    /// The time a path stands for, from its fields
+    pub fn time(&self, path: &Path) -> Option<i64> {
+        let fields = self.match_path(path)?;
+        let field = |f: Field| fields.iter().find(|(name, _)| *name == f).map(|(_, v)| *v);
+        let year = field(Field::Year)? as i64;
+        let date = match field(Field::DayOfYear) {
+            Some(doy) if (1..=366).contains(&doy) => time::add_days(time::from_ymd(year, 1, 1), doy as i64 - 1),
+            Some(_) => return None,
+            None => {
+                let (month, day) = (field(Field::Month).unwrap_or(1), field(Field::Day).unwrap_or(1));
+                if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+                    return None;
+                }
+                time::from_ymd(year, month, day)
+            }
+        };
+        Some(date + field(Field::Hour).unwrap_or(0) as i64 * 3600)
+    }
+
+    /// Every existing file the template matches
+    pub fn paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let paths = glob::glob(&self.glob)?.filter_map(Result::ok);
+        Ok(paths.filter(|path| path.is_file() && self.match_path(path).is_some()).collect())
+    }
+}
+
+/// One time step of an aggregation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeStep {
+    pub time: i64,
+    pub path: DatasetPath,
+}
+
+/// Files matching a path template, joined along time into one dataset. Times come from the path when the
+/// template has date fields, and otherwise from each file's time coordinate, so files can hold several steps.
+pub struct Aggregation {
+    template: PathTemplate,
+    time_name: String,
+    steps: RwLock<Vec<TimeStep>>,
+    scanned: Mutex<Instant>,
+}
+
+impl Aggregation {
+    pub fn discover(template: &str, time_name: &str) -> anyhow::Result<Self> {
+        let aggregation = Self {
+            template: PathTemplate::parse(template)?,
+            time_name: time_name.to_string(),
+            steps: RwLock::new(Vec::new()),
+            scanned: Mutex::new(Instant::now()),
+        };
+        aggregation.rescan()?;
+        Ok(aggregation)
+    }
+
+    /// Look for new or removed files
+    pub fn rescan(&self) -> anyhow::Result<()> {
+        // Files keep their times, so only new ones are opened
+        let mut known: HashMap<PathBuf, Vec<TimeStep>> = HashMap::new();
+        for step in self.steps.read().unwrap().iter() {
+            known.entry(step.path.path.clone()).or_default().push(step.clone());
+        }
+
+        let mut steps = Vec::new();
+        for path in self.template.paths()? {
+            if let Some(file_steps) = known.get(&path) {
+                steps.extend(file_steps.iter().cloned());
+            } else if let Some(time) = self.template.time(&path) {
+                steps.push(TimeStep {
+                    time,
+                    path: DatasetPath::new(path, 0).with_time_name(&self.time_name),
+                });
+            } else {
+                match crate::source::open(&path).and_then(|source| read_times(&*source, &self.time_name)) {
+                    Ok(Some(times)) => steps.extend(times.into_iter().enumerate().map(|(i, time)| TimeStep {
+                        time,
+                        path: DatasetPath::new(path.clone(), i).with_time_name(&self.time_name),
+                    })),
+                    Ok(None) => println!("Error: no {} coordinate in {:?}", self.time_name, path),
+                    Err(e) => println!("Error: can't read times from {:?}: {}", path, e),
+                }
+            }
+        }
+        // The first file wins when two have the same time
+        steps.sort_by_key(|step| step.time);
+        steps.dedup_by_key(|step| step.time);
+
+        *self.steps.write().unwrap() = steps;
+        *self.scanned.lock().unwrap() = Instant::now();
+        Ok(())
+    }
+
+    /// Every step in time order
+    pub fn steps(&self) -> Vec<TimeStep> {
+        self.steps.read().unwrap().clone()
+    }
+
+    /// Steps from start to end inclusive, where either can be left open. More than `MAX_STEPS` is an error.
+    pub fn between(&self, start: Option<i64>, end: Option<i64>) -> anyhow::Result<Vec<TimeStep>> {
+        let steps = self.steps.read().unwrap();
+        let from = start.map_or(0, |start| steps.partition_point(|s| s.time < start));
+        let to = end.map_or(steps.len(), |end| steps.partition_point(|s| s.time <= end)).max(from);
+        if to - from > MAX_STEPS {
+            return Err(anyhow!("{} time steps is more than the {} step limit", to - from, MAX_STEPS));
+        }
+        Ok(steps[from..to].to_vec())
+    }
+
+    fn lookup(&self, start: i64, end: i64) -> Option<DatasetPath> {
+        let steps = self.steps.read().unwrap();
+        let i = steps.partition_point(|s| s.time < start);
+        steps.get(i).filter(|step| step.time <= end).map(|step| step.path.clone())
+    }
+
+    /// The step at exactly this time. Files added since the last scan are picked up on a miss.
+    pub fn find(&self, time: i64) -> Option<DatasetPath> {
+        self.find_within(time, time)
+    }
+
+    /// The first step from start to end inclusive, e.g. a product stamped at noon on a day
+    pub fn find_within(&self, start: i64, end: i64) -> Option<DatasetPath> {
+        if let Some(path) = self.lookup(start, end) {
+            return Some(path);
+        }
+        if self.scanned.lock().unwrap().elapsed() < RESCAN_INTERVAL {
+            return None;
+        }
+        if let Err(e) = self.rescan() {
+            println!("Error: {}", e);
+        }
+        self.lookup(start, end)
+    }
+
+    /// Values at a lat/lng point for every step from start to end, where values are None outside the dataset
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_time_series(
+        &self,
+        start: Option<i64>,
+        end: Option<i64>,
+        lat: f64,
+        lng: f64,
+        expr: &Expr,
+        lat_name: &str,
+        lon_name: &str,
+    ) -> anyhow::Result<Vec<(i64, Option<f64>)>> {
+        self.between(start, end)?
+            .into_iter()
+            .map(|step| Ok((step.time, crate::get_point(&step.path, lat, lng, expr, lat_name, lon_name)?)))
+            .collect()
+    }
+
+    /// Zonal statistics over a region for every time step between `start` and `end`
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_zonal_stats(
+        &self,
+        start: Option<i64>,
+        end: Option<i64>,
+        region: &Region,
+        expr: &Expr,
+        percents: &[f64],
+        lat_name: &str,
+        lon_name: &str,
+    ) -> anyhow::Result<Vec<(i64, ZonalStats)>> {
+        self.between(start, end)?
+            .into_iter()
+            .map(|step| {
+                let stats = crate::get_zonal_stats(&step.path, region, expr, percents, lat_name, lon_name)?;
+                Ok((step.time, stats))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod aggregate_tests {
+    use super::*;
+
+    #[test]
+    fn test_template_time() {
+        let template = PathTemplate::parse("./data/{year}/{month}/{day}/mosaic.nc").unwrap();
+        assert!(template.has_time());
+        assert_eq!(template.time(Path::new("data/2023/04/12/mosaic.nc")), Some(time::from_ymd(2023, 4, 12)));
+        assert_eq!(template.time(Path::new("./data/2023/04/12/mosaic.nc")), Some(time::from_ymd(2023, 4, 12)));
+        assert_eq!(template.time(Path::new("data/2023/4/12/mosaic.nc")), None);
+        assert_eq!(template.time(Path::new("data/2023/13/12/mosaic.nc")), None);
+        assert_eq!(template.time(Path::new("data/2023/04/12/other.nc")), None);
+
+        let template = PathTemplate::parse("/archive/*/A{year}{doy}{hour}_*.nc").unwrap();
+        let path = Path::new("/archive/modis/A202310206_chl.nc");
+        assert_eq!(template.time(path), Some(time::from_ymd(2023, 4, 12) + 6 * 3600));
+
+        assert!(!PathTemplate::parse("/archive/*.nc").unwrap().has_time());
+        assert!(PathTemplate::parse("/archive/{week}.nc").is_err());
+    }
+
+    #[test]
+    fn test_discover() {
+        let dir = crate::testing::TempPath::dir("aggregate");
+        for day in ["2023/04/12", "2023/04/14", "2023/05/01"] {
+            let day_dir = dir.join(day);
+            std::fs::create_dir_all(&day_dir).unwrap();
+            std::fs::write(day_dir.join("mosaic.nc"), b"").unwrap();
+        }
+        std::fs::write(dir.join("2023/04/12/notes.txt"), b"").unwrap();
+
+        let template = format!("{}/{{year}}/{{month}}/{{day}}/mosaic.nc", dir.display());
+        let aggregation = Aggregation::discover(&template, "time").unwrap();
+        let times: Vec<i64> = aggregation.steps().iter().map(|s| s.time).collect();
+        assert_eq!(times, vec![time::from_ymd(2023, 4, 12), time::from_ymd(2023, 4, 14), time::from_ymd(2023, 5, 1)]);
+
+        let path = aggregation.find(time::from_ymd(2023, 4, 14)).unwrap();
+        assert_eq!(path, DatasetPath::new(dir.join("2023/04/14/mosaic.nc"), 0));
+        assert!(aggregation.find(time::from_ymd(2023, 4, 13)).is_none());
+        let noon = time::from_ymd(2023, 4, 14) + 12 * 3600;
+        assert!(aggregation.find(noon).is_none());
+        assert_eq!(aggregation.find_within(time::from_ymd(2023, 4, 14), noon), Some(path.clone()));
+        assert!(aggregation.find_within(time::from_ymd(2023, 4, 13), time::from_ymd(2023, 4, 13) + 3600).is_none());
+
+        let april = aggregation.between(Some(time::from_ymd(2023, 4, 13)), Some(time::from_ymd(2023, 4, 30))).unwrap();
+        assert_eq!(april.len(), 1);
+        assert_eq!(aggregation.between(None, Some(time::from_ymd(2023, 4, 14))).unwrap().len(), 2);
+        assert_eq!(aggregation.between(Some(time::from_ymd(2024, 1, 1)), None).unwrap().len(), 0);
+
+        // New days show up after a rescan
+        std::fs::create_dir_all(dir.join("2023/05/02")).unwrap();
+        std::fs::write(dir.join("2023/05/02/mosaic.nc"), b"").unwrap();
+        aggregation.rescan().unwrap();
+        assert!(aggregation.find(time::from_ymd(2023, 5, 2)).is_some());
+
+    }
+
+    #[test]
+    fn test_max_steps() {
+        // An hourly file for one step more than the limit
+        let dir = crate::testing::TempPath::dir("aggregate_max_steps");
+        for i in 0..=MAX_STEPS {
+            std::fs::write(dir.join(format!("A2023{:03}{:02}.nc", 1 + i / 24, i % 24)), b"").unwrap();
+        }
+        let template = format!("{}/A{{year}}{{doy}}{{hour}}.nc", dir.display());
+        let aggregation = Aggregation::discover(&template, "time").unwrap();
+
+        let err = aggregation.between(None, None).unwrap_err();
+        assert!(err.to_string().contains("step limit"), "{}", err);
+        let start = time::from_ymd(2023, 1, 1);
+        let end = start + (MAX_STEPS as i64 - 1) * 3600;
+        assert_eq!(aggregation.between(Some(start), Some(end)).unwrap().len(), MAX_STEPS);
+        // Refused before reading any of the files
+        let series = aggregation.get_time_series(None, None, 0.0, 0.0, &Expr::variable("chl"), "lat", "lon");
+        assert!(series.unwrap_err().to_string().contains("step limit"));
+    }
+}
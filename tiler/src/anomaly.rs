@@ -0,0 +1,146 @@
+use crate::dataset::DatasetPath;
+use anyhow::anyhow;
+use std::path::{Path, PathBuf};
+
+/// How a value is compared against its baseline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anomaly {
+    /// `value - baseline`, zero where they're the same
+    Difference,
+    /// `value / baseline`, one where they're the same
+    Ratio,
+}
+
+impl Anomaly {
+    /// Replace values with their anomaly from the baseline. Nodata in either is nodata, as is a ratio to zero.
+    pub fn apply(&self, values: &mut [f64], baseline: &[f64]) {
+        for (v, b) in values.iter_mut().zip(baseline) {
+            *v = match self {
+                Anomaly::Difference => *v - b,
+                Anomaly::Ratio if *b == 0.0 => f64::NAN,
+                Anomaly::Ratio => *v / b,
+            };
+        }
+    }
+}
+
+/// What the leading dimension of a climatology steps through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClimatologyAxis {
+    /// 1 to 366
+    DayOfYear,
+    /// 1 to 12
+    Month,
+}
+
+impl ClimatologyAxis {
+    // Coordinate variable names, as used by common climatology products
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "dayofyear" | "day_of_year" | "doy" => Some(ClimatologyAxis::DayOfYear),
+            "month" => Some(ClimatologyAxis::Month),
+            _ => None,
+        }
+    }
+
+    fn position(&self, time: i64) -> i64 {
+        match self {
+            ClimatologyAxis::DayOfYear => crate::time::day_of_year(time) as i64,
+            ClimatologyAxis::Month => crate::time::to_ymd(time).1 as i64,
+        }
+    }
+}
+
+/// A file of long-term means, with variables on a leading day of year or month dimension
+#[derive(Debug, Clone)]
+pub struct Climatology {
+    path: PathBuf,
+    axis: ClimatologyAxis,
+    /// Name of the day of year or month coordinate
+    axis_name: String,
+    /// Coordinate value of each step
+    positions: Vec<i64>,
+}
+
+impl Climatology {
+    /// Open a climatology with a `dayofyear`, `day_of_year`, `doy` or `month` coordinate variable
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let source = crate::source::open(path)?;
+        let (axis, var) = source
+            .variables()
+            .into_iter()
+            .filter(|var| source.dimensions(var).is_some_and(|dims| dims.len() == 1))
+            .find_map(|var| Some((ClimatologyAxis::from_name(&var)?, var)))
+            .ok_or_else(|| anyhow!("{:?} has no day of year or month coordinate", path))?;
+        let positions = source.read_all(&var)?.iter().map(|v| v.round() as i64).collect();
+        Ok(Self {
+            path: path.to_path_buf(),
+            axis,
+            axis_name: var,
+            positions,
+        })
+    }
+
+    pub fn axis(&self) -> ClimatologyAxis {
+        self.axis
+    }
+
+    /// The step for the day or month a time falls in. Day 366 falls back to day 365 in climatologies without it.
+    pub fn step(&self, time: i64) -> Option<DatasetPath> {
+        let position = self.axis.position(time);
+        let index = self.positions.iter().position(|p| *p == position).or_else(|| match (self.axis, position) {
+            (ClimatologyAxis::DayOfYear, 366) => self.positions.iter().position(|p| *p == 365),
+            _ => None,
+        })?;
+        Some(DatasetPath::new(self.path.clone(), index).with_time_name(&self.axis_name))
+    }
+}
+
+#[cfg(test)]
+mod anomaly_tests {
+    use super::*;
+    use crate::time::from_ymd;
+
+    #[test]
+    fn test_apply() {
+        let nan = f64::NAN;
+        let mut values = [3.0, 2.0, nan, 1.0];
+        Anomaly::Difference.apply(&mut values, &[1.0, 2.0, 1.0, nan]);
+        assert_eq!(&values[..2], &[2.0, 0.0]);
+        assert!(values[2].is_nan() && values[3].is_nan());
+
+        let mut values = [3.0, 2.0, 1.0];
+        Anomaly::Ratio.apply(&mut values, &[1.5, 4.0, 0.0]);
+        assert_eq!(&values[..2], &[2.0, 0.5]);
+        assert!(values[2].is_nan());
+    }
+
+    #[test]
+    fn test_climatology_step() {
+        let monthly = Climatology {
+            path: PathBuf::from("clim.nc"),
+            axis: ClimatologyAxis::Month,
+            axis_name: "month".to_string(),
+            positions: (1..=12).collect(),
+        };
+        let step = monthly.step(from_ymd(2023, 4, 12)).unwrap();
+        assert_eq!((step.time_index, step.time_name.as_str()), (3, "month"));
+
+        let daily = Climatology {
+            path: PathBuf::from("clim.nc"),
+            axis: ClimatologyAxis::DayOfYear,
+            axis_name: "dayofyear".to_string(),
+            positions: (1..=365).collect(),
+        };
+        assert_eq!(daily.step(from_ymd(2023, 4, 12)).unwrap().time_index, 101);
+        assert_eq!(daily.step(from_ymd(2024, 12, 31)).unwrap().time_index, 364);
+
+        let partial = Climatology {
+            path: PathBuf::from("clim.nc"),
+            axis: ClimatologyAxis::Month,
+            axis_name: "month".to_string(),
+            positions: vec![6, 7, 8],
+        };
+        assert!(partial.step(from_ymd(2023, 4, 12)).is_none());
+    }
+}
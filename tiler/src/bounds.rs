@@ -73,6 +73,11 @@ impl Bounds {
         )
     }
 
+    /// Grow the bounds by `dx` on the left and right and `dy` on the top and bottom
+    pub fn expand(&self, dx: f64, dy: f64) -> Self {
+        Self::new(self.min_x - dx, self.min_y - dy, self.max_x + dx, self.max_y + dy)
+    }
+
     pub fn xy_len(&self) -> (usize, usize) {
         let x_len = (self.max_x - self.min_x).round() as usize;
         let y_len = (self.max_y - self.min_y).round() as usize;
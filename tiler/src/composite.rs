@@ -0,0 +1,149 @@
+use crate::coordinates::TileCoord;
+use crate::dataset::{Dataset, DatasetPath};
+use crate::expr::Expr;
+use crate::tms::TileMatrixSet;
+
+/// How values from several time steps are combined into one, pixel by pixel. Nodata is skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Composite {
+    Mean,
+    Median,
+    Max,
+    Min,
+    /// Number of steps with data
+    Count,
+    /// The value from the last step with data
+    LatestValid,
+}
+
+/// Combines layers one at a time, so only medians need to keep every value
+pub struct Accumulator {
+    composite: Composite,
+    values: Vec<f64>,
+    counts: Vec<u32>,
+    samples: Vec<Vec<f64>>,
+}
+
+impl Accumulator {
+    pub fn new(composite: Composite, len: usize) -> Self {
+        let samples = match composite {
+            Composite::Median => vec![Vec::new(); len],
+            _ => Vec::new(),
+        };
+        Self {
+            composite,
+            values: vec![f64::NAN; len],
+            counts: vec![0; len],
+            samples,
+        }
+    }
+
+    /// Add a layer, which is expected to be in time order
+    pub fn add(&mut self, layer: &[f64]) {
+        for (i, v) in layer.iter().enumerate().filter(|(_, v)| !v.is_nan()) {
+            let (value, count) = (&mut self.values[i], &mut self.counts[i]);
+            *value = match (self.composite, *count) {
+                (Composite::Median, _) => {
+                    self.samples[i].push(*v);
+                    f64::NAN
+                }
+                (_, 0) | (Composite::LatestValid, _) => *v,
+                (Composite::Mean, n) => *value + (v - *value) / (n + 1) as f64,
+                (Composite::Max, _) => value.max(*v),
+                (Composite::Min, _) => value.min(*v),
+                (Composite::Count, _) => *value,
+            };
+            *count += 1;
+        }
+    }
+
+    /// The combined values, NaN where no layer had data
+    pub fn finish(self) -> Vec<f64> {
+        match self.composite {
+            Composite::Count => self.counts.iter().map(|c| if *c == 0 { f64::NAN } else { *c as f64 }).collect(),
+            Composite::Median => self.samples.into_iter().map(median).collect(),
+            _ => self.values,
+        }
+    }
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Like `get_overview_tile`, but combining the tile from every step, e.g. the days of a weekly composite.
+/// Steps are read straight from their grids, since a pyramid per step would cost more than it saves.
+/// None if no step has data in the tile.
+#[allow(clippy::too_many_arguments)]
+pub fn get_composite_tile(
+    steps: &[DatasetPath],
+    composite: Composite,
+    tms: &dyn TileMatrixSet,
+    tx: u32,
+    ty: u32,
+    zoom: u32,
+    tile_size: usize,
+    expr: &Expr,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Option<Vec<f64>>> {
+    let tile_coord = TileCoord::new(tx, ty, zoom as u8);
+    let mut accumulator = Accumulator::new(composite, tile_size * tile_size);
+    let mut any = false;
+    for step in steps {
+        let dset = Dataset::open(step, lat_name, lon_name)?;
+        let layer = crate::read_tms_tile(&dset, tms, &tile_coord, tile_size, expr)?;
+        if let Some(layer) = layer {
+            accumulator.add(&layer);
+            any = true;
+        }
+    }
+    Ok(any.then(|| accumulator.finish()))
+}
+
+#[cfg(test)]
+mod composite_tests {
+    use super::*;
+
+    fn combine(composite: Composite, layers: &[[f64; 3]]) -> Vec<f64> {
+        let mut accumulator = Accumulator::new(composite, 3);
+        for layer in layers {
+            accumulator.add(layer);
+        }
+        accumulator.finish()
+    }
+
+    #[test]
+    fn test_composites() {
+        let nan = f64::NAN;
+        let layers = [[1.0, nan, nan], [4.0, 2.0, nan], [nan, 6.0, nan], [7.0, 5.0, nan]];
+
+        let mean = combine(Composite::Mean, &layers);
+        assert_relative_eq!(mean[0], 4.0);
+        assert_relative_eq!(mean[1], 13.0 / 3.0);
+        assert!(mean[2].is_nan());
+
+        let median = combine(Composite::Median, &layers);
+        assert_eq!(&median[..2], &[4.0, 5.0]);
+        assert!(median[2].is_nan());
+        assert_eq!(combine(Composite::Median, &[[1.0, 0.0, 0.0], [2.0, 0.0, 0.0]])[0], 1.5);
+
+        assert_eq!(&combine(Composite::Max, &layers)[..2], &[7.0, 6.0]);
+        assert_eq!(&combine(Composite::Min, &layers)[..2], &[1.0, 2.0]);
+        assert_eq!(&combine(Composite::Count, &layers)[..2], &[3.0, 3.0]);
+        assert!(combine(Composite::Count, &layers)[2].is_nan());
+
+        // The last layer with data wins, even if later layers are empty there
+        let latest = combine(Composite::LatestValid, &[[1.0, 2.0, nan], [3.0, nan, nan]]);
+        assert_eq!(&latest[..2], &[3.0, 2.0]);
+    }
+}
@@ -0,0 +1,390 @@
+use ndarray::ArrayView2;
+use std::collections::HashMap;
+
+/// Which values to draw contour lines at
+#[derive(Debug, Clone, PartialEq)]
+pub enum Levels {
+    /// Every multiple of `interval` offset by `base`, within the range of the data
+    Interval { interval: f64, base: f64 },
+    Explicit(Vec<f64>),
+}
+
+/// Keeps a typo in the levels from producing millions of lines
+pub const MAX_LEVELS: usize = 1000;
+
+impl Levels {
+    pub fn resolve(&self, min: f64, max: f64) -> anyhow::Result<Vec<f64>> {
+        let too_many = || anyhow::anyhow!("Contours can have at most {} levels", MAX_LEVELS);
+        match self {
+            Levels::Explicit(levels) if levels.len() > MAX_LEVELS => Err(too_many()),
+            Levels::Explicit(levels) => Ok(levels.clone()),
+            Levels::Interval { interval, base } => {
+                if interval.is_nan() || *interval <= 0.0 {
+                    return Err(anyhow::anyhow!("Contour interval must be positive"));
+                }
+                if !min.is_finite() || !max.is_finite() {
+                    return Ok(vec![]);
+                }
+                let first = ((min - base) / interval).ceil() as i64;
+                let last = ((max - base) / interval).floor() as i64;
+                if last.saturating_sub(first) >= MAX_LEVELS as i64 {
+                    return Err(too_many());
+                }
+                Ok((first..=last).map(|k| base + k as f64 * interval).collect())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ContourOptions {
+    pub levels: Levels,
+    /// Iterations of Chaikin corner cutting
+    pub smoothing: usize,
+    /// Every nth level is flagged as major, counted from the first level
+    pub major_every: usize,
+    /// Decimal places in the label text
+    pub label_precision: usize,
+}
+
+impl Default for ContourOptions {
+    fn default() -> Self {
+        Self {
+            levels: Levels::Interval {
+                interval: 1.0,
+                base: 0.0,
+            },
+            smoothing: 0,
+            major_every: 5,
+            label_precision: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContourLine {
+    pub level: f64,
+    pub label: String,
+    pub major: bool,
+    /// (x, y) points, e.g. lon/lat
+    pub points: Vec<(f64, f64)>,
+}
+
+// A grid cell edge, identified by the corner it starts at and its direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Edge {
+    // From (row, col) to (row, col + 1)
+    Horizontal(usize, usize),
+    // From (row, col) to (row + 1, col)
+    Vertical(usize, usize),
+}
+
+/// Marching squares isolines at one level, as polylines in fractional (col, row) grid coordinates.
+/// Cells with a NaN corner are skipped.
+pub fn isolines(values: &ArrayView2<f64>, level: f64) -> Vec<Vec<(f64, f64)>> {
+    let (rows, cols) = values.dim();
+    let mut segments: Vec<(Edge, Edge)> = Vec::new();
+
+    for r in 0..rows.saturating_sub(1) {
+        for c in 0..cols.saturating_sub(1) {
+            let bl = values[[r, c]];
+            let br = values[[r, c + 1]];
+            let tr = values[[r + 1, c + 1]];
+            let tl = values[[r + 1, c]];
+            if bl.is_nan() || br.is_nan() || tr.is_nan() || tl.is_nan() {
+                continue;
+            }
+
+            let case = ((tl >= level) as u8) << 3
+                | ((tr >= level) as u8) << 2
+                | ((br >= level) as u8) << 1
+                | (bl >= level) as u8;
+
+            let bottom = Edge::Horizontal(r, c);
+            let top = Edge::Horizontal(r + 1, c);
+            let left = Edge::Vertical(r, c);
+            let right = Edge::Vertical(r, c + 1);
+
+            match case {
+                0 | 15 => {}
+                1 | 14 => segments.push((left, bottom)),
+                2 | 13 => segments.push((bottom, right)),
+                3 | 12 => segments.push((left, right)),
+                4 | 11 => segments.push((top, right)),
+                6 | 9 => segments.push((bottom, top)),
+                7 | 8 => segments.push((left, top)),
+                // Saddles, resolved with the average of the corners
+                5 | 10 => {
+                    let center_high = (bl + br + tr + tl) / 4.0 >= level;
+                    if (case == 5) == center_high {
+                        segments.push((left, top));
+                        segments.push((bottom, right));
+                    } else {
+                        segments.push((left, bottom));
+                        segments.push((top, right));
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    let crossing = |edge: Edge| -> (f64, f64) {
+        let ((r0, c0), (r1, c1)) = match edge {
+            Edge::Horizontal(r, c) => ((r, c), (r, c + 1)),
+            Edge::Vertical(r, c) => ((r, c), (r + 1, c)),
+        };
+        let v0 = values[[r0, c0]];
+        let v1 = values[[r1, c1]];
+        let t = if v1 == v0 { 0.5 } else { (level - v0) / (v1 - v0) };
+        (c0 as f64 + t * (c1 - c0) as f64, r0 as f64 + t * (r1 - r0) as f64)
+    };
+
+    join_segments(&segments)
+        .into_iter()
+        .map(|edges| edges.into_iter().map(crossing).collect())
+        .collect()
+}
+
+// Chain segments that share an edge into polylines
+fn join_segments(segments: &[(Edge, Edge)]) -> Vec<Vec<Edge>> {
+    let mut by_edge: HashMap<Edge, Vec<usize>> = HashMap::new();
+    for (i, (a, b)) in segments.iter().enumerate() {
+        by_edge.entry(*a).or_default().push(i);
+        by_edge.entry(*b).or_default().push(i);
+    }
+
+    let mut used = vec![false; segments.len()];
+    let mut lines = Vec::new();
+
+    // The other segment touching an edge, if it hasn't been used yet
+    let next_segment = |edge: Edge, used: &[bool]| -> Option<usize> {
+        by_edge[&edge].iter().copied().find(|i| !used[*i])
+    };
+
+    for start in 0..segments.len() {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+        let (a, b) = segments[start];
+        let mut line = std::collections::VecDeque::from([a, b]);
+
+        // Extend forwards from the end, then backwards from the start
+        let mut tail = b;
+        while let Some(i) = next_segment(tail, &used) {
+            used[i] = true;
+            let (a, b) = segments[i];
+            tail = if a == tail { b } else { a };
+            line.push_back(tail);
+        }
+        let mut head = a;
+        while let Some(i) = next_segment(head, &used) {
+            used[i] = true;
+            let (a, b) = segments[i];
+            head = if a == head { b } else { a };
+            line.push_front(head);
+        }
+
+        lines.push(line.into_iter().collect());
+    }
+
+    lines
+}
+
+/// Chaikin corner cutting. Endpoints are kept so lines still meet the edge of the data.
+pub fn smooth(points: &[(f64, f64)], iterations: usize) -> Vec<(f64, f64)> {
+    let mut points = points.to_vec();
+    for _ in 0..iterations {
+        if points.len() < 3 {
+            break;
+        }
+        let closed = points.first() == points.last();
+        let mut smoothed = Vec::with_capacity(points.len() * 2);
+        if !closed {
+            smoothed.push(points[0]);
+        }
+        for w in points.windows(2) {
+            let (p, q) = (w[0], w[1]);
+            smoothed.push((0.75 * p.0 + 0.25 * q.0, 0.75 * p.1 + 0.25 * q.1));
+            smoothed.push((0.25 * p.0 + 0.75 * q.0, 0.25 * p.1 + 0.75 * q.1));
+        }
+        if closed {
+            smoothed.push(smoothed[0]);
+        } else {
+            smoothed.push(*points.last().unwrap());
+        }
+        points = smoothed;
+    }
+    points
+}
+
+// Linear interpolation into a coordinate array at a fractional index
+fn interp_coord(coords: &[f64], i: f64) -> f64 {
+    let i0 = (i.floor() as usize).min(coords.len() - 1);
+    let i1 = (i0 + 1).min(coords.len() - 1);
+    coords[i0] + (i - i0 as f64) * (coords[i1] - coords[i0])
+}
+
+/// Contour lines for a grid of values with its x and y coordinates, e.g. the output of
+/// `Dataset::get_values` and `Dataset::get_coords`
+pub fn contour_lines(
+    values: &ArrayView2<f64>,
+    xs: &[f64],
+    ys: &[f64],
+    options: &ContourOptions,
+) -> anyhow::Result<Vec<ContourLine>> {
+    let (min, max) = values
+        .iter()
+        .filter(|v| !v.is_nan())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(*v), max.max(*v)));
+    let levels = options.levels.resolve(min, max)?;
+
+    let mut lines = Vec::new();
+    for (i, level) in levels.iter().enumerate() {
+        let major = options.major_every > 0 && i % options.major_every == 0;
+        let label = format!("{:.*}", options.label_precision, level);
+
+        for points in isolines(values, *level) {
+            let points: Vec<(f64, f64)> = points
+                .into_iter()
+                .map(|(c, r)| (interp_coord(xs, c), interp_coord(ys, r)))
+                .collect();
+            lines.push(ContourLine {
+                level: *level,
+                label: label.clone(),
+                major,
+                points: smooth(&points, options.smoothing),
+            });
+        }
+    }
+    Ok(lines)
+}
+
+/// GeoJSON FeatureCollection of LineStrings, with `level`, `label` and `major` properties
+pub fn to_geojson(lines: &[ContourLine]) -> serde_json::Value {
+    let features: Vec<serde_json::Value> = lines
+        .iter()
+        .map(|line| {
+            let coordinates: Vec<[f64; 2]> = line.points.iter().map(|(x, y)| [*x, *y]).collect();
+            serde_json::json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": coordinates,
+                },
+                "properties": {
+                    "level": line.level,
+                    "label": line.label,
+                    "major": line.major,
+                },
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+#[cfg(test)]
+mod contour_tests {
+    use super::*;
+    use ndarray::arr2;
+
+    #[test]
+    fn test_resolve_levels() {
+        let levels = Levels::Interval { interval: 0.5, base: 0.25 };
+        assert_eq!(levels.resolve(0.0, 1.5).unwrap(), vec![0.25, 0.75, 1.25]);
+
+        let levels = Levels::Interval { interval: 0.0, base: 0.0 };
+        assert!(levels.resolve(0.0, 1.0).is_err());
+
+        let levels = Levels::Interval { interval: 1e-9, base: 0.0 };
+        assert!(levels.resolve(0.0, 1.0).is_err());
+        let levels = Levels::Interval { interval: 1.0, base: 0.0 };
+        assert!(levels.resolve(-1e300, 1e300).is_err());
+
+        // Listed levels have the same limit
+        let levels = Levels::Explicit((0..MAX_LEVELS).map(|i| i as f64).collect());
+        assert_eq!(levels.resolve(0.0, 1.0).unwrap().len(), MAX_LEVELS);
+        let levels = Levels::Explicit((0..=MAX_LEVELS).map(|i| i as f64).collect());
+        assert!(levels.resolve(0.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_isolines_closed_ring() {
+        // A peak in the middle gives one closed ring around it
+        let values = arr2(&[
+            [0.0, 0.0, 0.0],
+            [0.0, 2.0, 0.0],
+            [0.0, 0.0, 0.0],
+        ]);
+        let lines = isolines(&values.view(), 1.0);
+        assert_eq!(lines.len(), 1);
+        let line = &lines[0];
+        assert_eq!(line.len(), 5);
+        assert_eq!(line.first(), line.last());
+        for (x, y) in line {
+            // Every crossing is halfway between the peak and its neighbours
+            assert_relative_eq!((x - 1.0).abs() + (y - 1.0).abs(), 0.5);
+        }
+    }
+
+    #[test]
+    fn test_isolines_open_line() {
+        // Values increase left to right, so the 1.5 line runs straight up the middle
+        let values = arr2(&[
+            [0.0, 1.0, 2.0, 3.0],
+            [0.0, 1.0, 2.0, 3.0],
+            [0.0, 1.0, 2.0, 3.0],
+        ]);
+        let lines = isolines(&values.view(), 1.5);
+        assert_eq!(lines.len(), 1);
+        let mut ys: Vec<f64> = lines[0].iter().map(|(x, y)| {
+            assert_relative_eq!(*x, 1.5);
+            *y
+        }).collect();
+        ys.sort_by(f64::total_cmp);
+        assert_eq!(ys, vec![0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_isolines_skip_nan() {
+        let values = arr2(&[
+            [0.0, 2.0],
+            [f64::NAN, 2.0],
+        ]);
+        assert!(isolines(&values.view(), 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_smooth_keeps_endpoints() {
+        let points = vec![(0.0, 0.0), (1.0, 1.0), (2.0, 0.0)];
+        let smoothed = smooth(&points, 2);
+        assert_eq!(smoothed.first(), Some(&(0.0, 0.0)));
+        assert_eq!(smoothed.last(), Some(&(2.0, 0.0)));
+        assert!(smoothed.len() > points.len());
+        assert!(smoothed.iter().all(|(_, y)| *y < 1.0));
+    }
+
+    #[test]
+    fn test_contour_lines_coords() {
+        let values = arr2(&[
+            [0.0, 2.0],
+            [0.0, 2.0],
+        ]);
+        let options = ContourOptions {
+            levels: Levels::Explicit(vec![1.0]),
+            ..Default::default()
+        };
+        let lines = contour_lines(&values.view(), &[-130.0, -120.0], &[50.0, 52.0], &options).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].label, "1.00");
+        assert!(lines[0].major);
+        let mut points = lines[0].points.clone();
+        points.sort_by(|a, b| a.1.total_cmp(&b.1));
+        assert_eq!(points, vec![(-125.0, 50.0), (-125.0, 52.0)]);
+    }
+}
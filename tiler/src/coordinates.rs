@@ -1,8 +1,13 @@
 use std::f64::consts::PI;
+use std::ops::RangeInclusive;
+use anyhow::anyhow;
 use crate::bounds::Bounds;
 use crate::TILE_SIZE;
+use crate::region::Region;
 
 
+/// An XYZ tile, with y counted down from the top as in web maps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct TileCoord {
     x: u32,
     y: u32,
@@ -22,6 +27,87 @@ impl TileCoord {
     pub fn zoom(&self) -> u8 {
         self.zoom
     }
+
+    /// Number of tiles across the zoom level, in both directions
+    fn matrix_size(zoom: u8) -> u32 {
+        1 << zoom
+    }
+
+    /// A tile addressed in the TMS scheme, with y counted up from the bottom
+    pub fn from_tms(x: u32, tms_y: u32, zoom: u8) -> Option<Self> {
+        let size = Self::matrix_size(zoom);
+        (x < size && tms_y < size).then(|| Self::new(x, size - 1 - tms_y, zoom))
+    }
+
+    /// The TMS y, counted up from the bottom, or None if y is past the bottom of the zoom level
+    pub fn tms_y(&self) -> Option<u32> {
+        Self::matrix_size(self.zoom).checked_sub(1)?.checked_sub(self.y)
+    }
+
+    /// A tile from a Bing Maps quadkey, one digit per zoom level
+    pub fn from_quadkey(quadkey: &str) -> anyhow::Result<Self> {
+        if quadkey.len() > 31 {
+            return Err(anyhow!("Quadkey {:?} is longer than 31 digits", quadkey));
+        }
+        let (mut x, mut y) = (0, 0);
+        for digit in quadkey.chars() {
+            let digit = digit
+                .to_digit(4)
+                .ok_or_else(|| anyhow!("Invalid quadkey {:?}, digits must be 0 to 3", quadkey))?;
+            x = (x << 1) | (digit & 1);
+            y = (y << 1) | (digit >> 1);
+        }
+        Ok(Self::new(x, y, quadkey.len() as u8))
+    }
+
+    /// The Bing Maps quadkey, which is empty at zoom 0
+    pub fn quadkey(&self) -> String {
+        (1..=self.zoom)
+            .rev()
+            .map(|i| {
+                let mask = 1 << (i - 1);
+                let digit = u8::from(self.x & mask != 0) + 2 * u8::from(self.y & mask != 0);
+                char::from(b'0' + digit)
+            })
+            .collect()
+    }
+
+    /// The tile one zoom level up that contains this one, or None at zoom 0
+    pub fn parent(&self) -> Option<Self> {
+        (self.zoom > 0).then(|| Self::new(self.x >> 1, self.y >> 1, self.zoom - 1))
+    }
+
+    /// The four tiles one zoom level down, in quadkey order: top left, top right, bottom left, bottom right
+    pub fn children(&self) -> [Self; 4] {
+        let (x, y, zoom) = (self.x * 2, self.y * 2, self.zoom + 1);
+        [
+            Self::new(x, y, zoom),
+            Self::new(x + 1, y, zoom),
+            Self::new(x, y + 1, zoom),
+            Self::new(x + 1, y + 1, zoom),
+        ]
+    }
+
+    /// The up to 8 tiles around this one. x wraps around the antimeridian, but there's nothing past the poles.
+    pub fn neighbours(&self) -> Vec<Self> {
+        let size = Self::matrix_size(self.zoom) as i64;
+        let mut neighbours = Vec::new();
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let y = self.y as i64 + dy;
+                if (dx, dy) == (0, 0) || y < 0 || y >= size {
+                    continue;
+                }
+                let x = (self.x as i64 + dx).rem_euclid(size);
+                let tile = Self::new(x as u32, y as u32, self.zoom);
+                // Low zoom levels wrap onto themselves
+                if tile != *self && !neighbours.contains(&tile) {
+                    neighbours.push(tile);
+                }
+            }
+        }
+        neighbours
+    }
 }
 
 pub struct Point {
@@ -60,24 +146,26 @@ impl LatLng {
     }
 }
 
-pub fn from_lat_lng_to_point(lat_lng: &LatLng) -> Point {
+/// Pixel position in the zoom 0 tile, for tiles `tile_size` pixels across
+pub fn from_lat_lng_to_point(lat_lng: &LatLng, tile_size: usize) -> Point {
     let mercator = -f64::ln(f64::tan((0.25 + lat_lng.lat() / 360.0) * PI));
     Point::new(
-        (TILE_SIZE as f64) * (lat_lng.lng() / 360.0 + 0.5),
-        (TILE_SIZE as f64) / 2.0 * (1.0 + mercator / PI),
+        (tile_size as f64) * (lat_lng.lng() / 360.0 + 0.5),
+        (tile_size as f64) / 2.0 * (1.0 + mercator / PI),
     )
 }
 
-pub fn from_point_to_lat_lng(point: &Point) -> LatLng {
-    let lng = (point.x / TILE_SIZE as f64 - 0.5) * 360.0;
-    let mercator = ((point.y * 2.0 / (TILE_SIZE as f64)) - 1.0) * PI;
+pub fn from_point_to_lat_lng(point: &Point, tile_size: usize) -> LatLng {
+    let lng = (point.x / tile_size as f64 - 0.5) * 360.0;
+    let mercator = ((point.y * 2.0 / (tile_size as f64)) - 1.0) * PI;
     let lat = ((f64::atan(f64::exp(-mercator)) / PI) - 0.25) * 360.0;
     LatLng::new(lat, lng)
 }
 
+/// Tiles cover the same area whatever their size in pixels, so these don't take one
 pub fn from_lat_lng_to_tile_coord(lat_lng: &LatLng, zoom: u8) -> TileCoord {
     let scale = f64::powi(2.0, zoom as i32);
-    let point = from_lat_lng_to_point(lat_lng);
+    let point = from_lat_lng_to_point(lat_lng, TILE_SIZE);
 
     TileCoord::new(
         f64::floor(point.x * scale / (TILE_SIZE as f64)) as u32,
@@ -97,8 +185,8 @@ pub fn from_tile_coord_to_lat_lng_bounds(tile_coord: &TileCoord) -> Bounds {
         (tile_coord.y() as f64 + 1.0) * (TILE_SIZE as f64) / scale,
     );
 
-    let min_lat_lng = from_point_to_lat_lng(&min_point);
-    let max_lat_lng = from_point_to_lat_lng(&max_point);
+    let min_lat_lng = from_point_to_lat_lng(&min_point, TILE_SIZE);
+    let max_lat_lng = from_point_to_lat_lng(&max_point, TILE_SIZE);
 
     let min_lng = min_lat_lng.lng();
     let max_lng = max_lat_lng.lng();
@@ -109,6 +197,174 @@ pub fn from_tile_coord_to_lat_lng_bounds(tile_coord: &TileCoord) -> Bounds {
     Bounds::new(min_lng, min_lat, max_lng, max_lat)
 }
 
+/// Web Mercator stops short of the poles
+pub const MAX_LAT: f64 = 85.051_128_78;
+
+// Position of a point in tile units at the zoom level, e.g. (2.5, 0.5) is the middle of the top row's third tile
+fn tile_fraction(lat: f64, lng: f64, zoom: u8) -> (f64, f64) {
+    let scale = f64::powi(2.0, zoom as i32);
+    let point = from_lat_lng_to_point(&LatLng::new(lat.clamp(-MAX_LAT, MAX_LAT), lng), TILE_SIZE);
+    (point.x * scale / TILE_SIZE as f64, point.y * scale / TILE_SIZE as f64)
+}
+
+// First and last tiles of a span in tile units. Tiles that only share an edge with the span's far end are left out.
+fn tile_span(start: f64, end: f64, zoom: u8) -> (u32, u32) {
+    let last = ((1u64 << zoom) - 1) as f64;
+    let first = start.floor().clamp(0.0, last);
+    let end = (end.ceil() - 1.0).clamp(first, last);
+    (first as u32, end as u32)
+}
+
+// West to east longitude spans in -180..180, split in two if they cross the antimeridian.
+// A span crosses it if it runs past 180, or runs west to east with min_x greater than max_x.
+fn lng_spans(min_x: f64, max_x: f64) -> Vec<(f64, f64)> {
+    let width = if min_x <= max_x { max_x - min_x } else { max_x - min_x + 360.0 };
+    if width >= 360.0 {
+        return vec![(-180.0, 180.0)];
+    }
+    let west = (min_x + 180.0).rem_euclid(360.0) - 180.0;
+    let east = west + width;
+    if east > 180.0 {
+        vec![(-180.0, east - 360.0), (west, 180.0)]
+    } else {
+        vec![(west, east)]
+    }
+}
+
+// Tile column ranges covering the longitude spans, merged where they meet
+fn column_ranges(spans: &[(f64, f64)], zoom: u8) -> Vec<(u32, u32)> {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for (west, east) in spans {
+        let range = tile_span(tile_fraction(0.0, *west, zoom).0, tile_fraction(0.0, *east, zoom).0, zoom);
+        match ranges.last_mut() {
+            Some(previous) if range.0 <= previous.1 + 1 => previous.1 = previous.1.max(range.1),
+            _ => ranges.push(range),
+        }
+    }
+    ranges
+}
+
+/// Every tile that covers part of a lat/lng box, zoom by zoom and row by row. Boxes with `min_x` greater than
+/// `max_x`, or past 180, wrap across the antimeridian. Latitudes are clamped to the Web Mercator limits.
+pub fn tiles_for_bounds(bounds: &Bounds, zooms: RangeInclusive<u8>) -> impl Iterator<Item = TileCoord> {
+    let spans = lng_spans(bounds.min_x, bounds.max_x);
+    let (min_lat, max_lat) = (bounds.min_y, bounds.max_y);
+    zooms.flat_map(move |zoom| {
+        let (min_y, max_y) = tile_span(tile_fraction(max_lat, 0.0, zoom).1, tile_fraction(min_lat, 0.0, zoom).1, zoom);
+        let columns = column_ranges(&spans, zoom);
+        (min_y..=max_y).flat_map(move |y| {
+            columns
+                .clone()
+                .into_iter()
+                .flat_map(move |(min_x, max_x)| (min_x..=max_x).map(move |x| TileCoord::new(x, y, zoom)))
+        })
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Coverage {
+    Outside,
+    /// The polygon's edge runs through the tile
+    Partial,
+    Inside,
+}
+
+// Whether any part of the segment is strictly inside the box, by clipping it (Liang-Barsky).
+// Segments along an edge or through a corner don't count, so tiles that only touch a polygon are left out.
+fn segment_crosses(from: (f64, f64), to: (f64, f64), bounds: &Bounds) -> bool {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let (mut t0, mut t1) = (0.0, 1.0);
+    for (p, q) in [
+        (-dx, from.0 - bounds.min_x),
+        (dx, bounds.max_x - from.0),
+        (-dy, from.1 - bounds.min_y),
+        (dy, bounds.max_y - from.1),
+    ] {
+        if p == 0.0 {
+            if q <= 0.0 {
+                return false;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = f64::max(t0, t);
+            } else {
+                t1 = f64::min(t1, t);
+            }
+        }
+    }
+    t0 < t1
+}
+
+fn coverage(region: &Region, bounds: &Bounds) -> Coverage {
+    let crosses = region
+        .rings()
+        .any(|ring| ring.iter().zip(ring.iter().cycle().skip(1)).any(|(a, b)| segment_crosses(*a, *b, bounds)));
+    let center = ((bounds.min_x + bounds.max_x) / 2.0, (bounds.min_y + bounds.max_y) / 2.0);
+    if crosses {
+        Coverage::Partial
+    } else if region.contains(center) {
+        Coverage::Inside
+    } else {
+        Coverage::Outside
+    }
+}
+
+// How much of a tile the region covers. Polygons written with longitudes past 180 are matched a world away.
+fn tile_coverage(region: &Region, tile: &TileCoord) -> Coverage {
+    let bounds = from_tile_coord_to_lat_lng_bounds(tile);
+    let mut result = Coverage::Outside;
+    for shift in [0.0, -360.0, 360.0] {
+        match coverage(region, &bounds.xy_shift(shift, 0.0)) {
+            Coverage::Inside => return Coverage::Inside,
+            Coverage::Partial => result = Coverage::Partial,
+            Coverage::Outside => (),
+        }
+    }
+    result
+}
+
+/// Every tile that covers part of a GeoJSON Polygon or MultiPolygon, or a Feature or FeatureCollection of them,
+/// zoom by zoom. Each level is found by testing the children of the tiles at the level above, so tiles wholly
+/// inside the polygon aren't tested again. Polygons can cross the antimeridian by running past 180.
+pub fn tiles_for_polygon(
+    geojson: &serde_json::Value,
+    zooms: RangeInclusive<u8>,
+) -> anyhow::Result<impl Iterator<Item = TileCoord>> {
+    let region = Region::from_geojson(geojson)?;
+
+    let (min_zoom, max_zoom) = (*zooms.start(), *zooms.end());
+    let root = TileCoord::new(0, 0, 0);
+    // Tiles outside the polygon have no children inside it, so levels stop as soon as one is empty
+    let first = match tile_coverage(&region, &root) {
+        Coverage::Outside => None,
+        coverage => Some(vec![(root, coverage)]),
+    };
+    let levels = std::iter::successors(first, move |level: &Vec<(TileCoord, Coverage)>| {
+        if level[0].0.zoom() >= max_zoom {
+            return None;
+        }
+        let next: Vec<(TileCoord, Coverage)> = level
+            .iter()
+            .flat_map(|(tile, coverage)| tile.children().map(|child| (child, *coverage)))
+            .map(|(child, coverage)| match coverage {
+                Coverage::Inside => (child, Coverage::Inside),
+                _ => (child, tile_coverage(&region, &child)),
+            })
+            .filter(|(_, coverage)| *coverage != Coverage::Outside)
+            .collect();
+        (!next.is_empty()).then_some(next)
+    });
+
+    Ok(levels
+        .filter(move |level| level[0].0.zoom() >= min_zoom)
+        .flat_map(|level| {
+            let mut tiles: Vec<TileCoord> = level.into_iter().map(|(tile, _)| tile).collect();
+            tiles.sort_by_key(|tile| (tile.y(), tile.x()));
+            tiles
+        }))
+}
+
 
 #[cfg(test)]
 mod coordinate_transforms_tests {
@@ -117,27 +373,27 @@ mod coordinate_transforms_tests {
     #[test]
     fn test_from_lat_lng_to_point() {
         let lat_lng = LatLng::new(0.0, 0.0);
-        let point = from_lat_lng_to_point(&lat_lng);
+        let point = from_lat_lng_to_point(&lat_lng, 256);
         assert_eq!(point.x(), 128.0);
         assert_eq!(point.y(), 128.0);
 
         let lat_lng = LatLng::new(0.0, 180.0);
-        let point = from_lat_lng_to_point(&lat_lng);
+        let point = from_lat_lng_to_point(&lat_lng, 256);
         assert_eq!(point.x(), 256.0);
         assert_eq!(point.y(), 128.0);
 
         let lat_lng = LatLng::new(0.0, -180.0);
-        let point = from_lat_lng_to_point(&lat_lng);
+        let point = from_lat_lng_to_point(&lat_lng, 256);
         assert_eq!(point.x(), 0.0);
         assert_eq!(point.y(), 128.0);
 
         let lat_lng = LatLng::new(85.051129, 0.0);
-        let point = from_lat_lng_to_point(&lat_lng);
+        let point = from_lat_lng_to_point(&lat_lng, 256);
         assert_eq!(point.x(), 128.0);
         assert_abs_diff_eq!(point.y(), 0.0, epsilon=0.001);
 
         let lat_lng = LatLng::new(-85.051129, 0.0);
-        let point = from_lat_lng_to_point(&lat_lng);
+        let point = from_lat_lng_to_point(&lat_lng, 256);
         assert_eq!(point.x(), 128.0);
         assert_abs_diff_eq!(point.y(), 256.0, epsilon=0.001);
     }
@@ -145,27 +401,27 @@ mod coordinate_transforms_tests {
     #[test]
     fn test_from_point_to_lat_lng() {
         let point = Point::new(128.0, 128.0);
-        let lat_lng = from_point_to_lat_lng(&point);
+        let lat_lng = from_point_to_lat_lng(&point, 256);
         assert_eq!(lat_lng.lat(), 0.0);
         assert_eq!(lat_lng.lng(), 0.0);
 
         let point = Point::new(256.0, 128.0);
-        let lat_lng = from_point_to_lat_lng(&point);
+        let lat_lng = from_point_to_lat_lng(&point, 256);
         assert_eq!(lat_lng.lat(), 0.0);
         assert_eq!(lat_lng.lng(), 180.0);
 
         let point = Point::new(0.0, 128.0);
-        let lat_lng = from_point_to_lat_lng(&point);
+        let lat_lng = from_point_to_lat_lng(&point, 256);
         assert_eq!(lat_lng.lat(), 0.0);
         assert_eq!(lat_lng.lng(), -180.0);
 
         let point = Point::new(128.0, 0.0);
-        let lat_lng = from_point_to_lat_lng(&point);
+        let lat_lng = from_point_to_lat_lng(&point, 256);
         assert_relative_eq!(lat_lng.lat(), 85.051129, epsilon=0.001);
         assert_abs_diff_eq!(lat_lng.lng(),  0.0 , epsilon=0.001);
 
         let point = Point::new(128.0, 256.0);
-        let lat_lng = from_point_to_lat_lng(&point);
+        let lat_lng = from_point_to_lat_lng(&point, 256);
         assert_relative_eq!(lat_lng.lat(), -85.051129, epsilon=0.001);
         assert_abs_diff_eq!(lat_lng.lng(),  0.0 , epsilon=0.001);
     }
@@ -219,4 +475,177 @@ mod coordinate_transforms_tests {
         assert_relative_eq!(bounds.max_x(), 30.234373, epsilon=0.0001);
         assert_relative_eq!(bounds.max_y(), 7.013666, epsilon=0.0001);
     }
+
+    #[test]
+    fn test_512_tiles() {
+        let point = from_lat_lng_to_point(&LatLng::new(0.0, 0.0), 512);
+        assert_eq!(point.x(), 256.0);
+        assert_eq!(point.y(), 256.0);
+
+        let point = from_lat_lng_to_point(&LatLng::new(-85.051129, 180.0), 512);
+        assert_eq!(point.x(), 512.0);
+        assert_abs_diff_eq!(point.y(), 512.0, epsilon=0.001);
+
+        let lat_lng = from_point_to_lat_lng(&Point::new(512.0, 256.0), 512);
+        assert_eq!(lat_lng.lat(), 0.0);
+        assert_eq!(lat_lng.lng(), 180.0);
+
+        // The same point at either size lands on the same spot
+        let lat_lng = LatLng::new(6.5, 29.7);
+        let small = from_lat_lng_to_point(&lat_lng, 256);
+        let large = from_lat_lng_to_point(&lat_lng, 512);
+        assert_relative_eq!(large.x(), small.x() * 2.0);
+        assert_relative_eq!(large.y(), small.y() * 2.0);
+        let back = from_point_to_lat_lng(&large, 512);
+        assert_relative_eq!(back.lat(), 6.5, epsilon=1e-9);
+        assert_relative_eq!(back.lng(), 29.7, epsilon=1e-9);
+    }
+
+    #[test]
+    fn test_tms_y() {
+        let tile = TileCoord::new(298, 246, 9);
+        assert_eq!(tile.tms_y(), Some(265));
+        assert_eq!(TileCoord::new(0, 1, 1).tms_y(), Some(0));
+        assert_eq!(TileCoord::new(0, 2, 1).tms_y(), None);
+        assert_eq!(TileCoord::from_tms(298, 265, 9), Some(tile));
+        assert_eq!(TileCoord::from_tms(0, 0, 0), Some(TileCoord::new(0, 0, 0)));
+        assert_eq!(TileCoord::from_tms(0, 0, 1), Some(TileCoord::new(0, 1, 1)));
+        assert!(TileCoord::from_tms(0, 2, 1).is_none());
+    }
+
+    #[test]
+    fn test_quadkey() {
+        // From the Bing Maps tile system docs
+        let tile = TileCoord::new(3, 5, 3);
+        assert_eq!(tile.quadkey(), "213");
+        assert_eq!(TileCoord::from_quadkey("213").unwrap(), tile);
+
+        assert_eq!(TileCoord::new(0, 0, 0).quadkey(), "");
+        assert_eq!(TileCoord::from_quadkey("").unwrap(), TileCoord::new(0, 0, 0));
+        let tile = TileCoord::new(298, 246, 9);
+        assert_eq!(TileCoord::from_quadkey(&tile.quadkey()).unwrap(), tile);
+
+        assert!(TileCoord::from_quadkey("0124").is_err());
+        assert!(TileCoord::from_quadkey(&"0".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_parent_and_children() {
+        let tile = TileCoord::new(298, 246, 9);
+        assert_eq!(tile.parent(), Some(TileCoord::new(149, 123, 8)));
+        assert!(TileCoord::new(0, 0, 0).parent().is_none());
+
+        let children = tile.children();
+        assert_eq!(children[0], TileCoord::new(596, 492, 10));
+        assert_eq!(children[3], TileCoord::new(597, 493, 10));
+        for (i, child) in children.iter().enumerate() {
+            assert_eq!(child.parent(), Some(tile));
+            assert_eq!(child.quadkey(), format!("{}{}", tile.quadkey(), i));
+        }
+    }
+
+    #[test]
+    fn test_neighbours() {
+        assert_eq!(TileCoord::new(5, 5, 4).neighbours().len(), 8);
+        assert!(TileCoord::new(0, 0, 0).neighbours().is_empty());
+
+        // Wraps across the antimeridian, stops at the top
+        let neighbours = TileCoord::new(0, 0, 2).neighbours();
+        assert_eq!(neighbours.len(), 5);
+        assert!(neighbours.contains(&TileCoord::new(3, 0, 2)));
+        assert!(neighbours.contains(&TileCoord::new(3, 1, 2)));
+
+        // Zoom 1 has only 3 other tiles
+        assert_eq!(TileCoord::new(1, 1, 1).neighbours().len(), 3);
+    }
+
+    fn xyz(tiles: impl Iterator<Item = TileCoord>) -> Vec<(u32, u32, u8)> {
+        tiles.map(|t| (t.x(), t.y(), t.zoom())).collect()
+    }
+
+    #[test]
+    fn test_tiles_for_bounds() {
+        let world = Bounds::new(-180.0, -90.0, 180.0, 90.0);
+        assert_eq!(tiles_for_bounds(&world, 0..=0).count(), 1);
+        assert_eq!(tiles_for_bounds(&world, 2..=2).count(), 16);
+        assert_eq!(tiles_for_bounds(&world, 0..=3).count(), 1 + 4 + 16 + 64);
+
+        // A small box in the north east quadrant
+        let bounds = Bounds::new(10.0, 10.0, 20.0, 20.0);
+        assert_eq!(xyz(tiles_for_bounds(&bounds, 1..=1)), vec![(1, 0, 1)]);
+        assert_eq!(xyz(tiles_for_bounds(&bounds, 3..=3)), vec![(4, 3, 3)]);
+
+        // Edges on tile boundaries don't pull in the next tile
+        let quadrant = Bounds::new(0.0, 0.0, 180.0, 90.0);
+        assert_eq!(xyz(tiles_for_bounds(&quadrant, 1..=1)), vec![(1, 0, 1)]);
+        // A point still has a tile
+        let point = Bounds::new(29.7, 6.5, 29.7, 6.5);
+        assert_eq!(xyz(tiles_for_bounds(&point, 9..=9)), vec![(298, 246, 9)]);
+    }
+
+    #[test]
+    fn test_tiles_for_bounds_antimeridian() {
+        // Crossing with min_x > max_x, or running past 180, gives the tiles on both sides
+        let crossing = Bounds::new(170.0, -10.0, -170.0, 10.0);
+        let tiles = xyz(tiles_for_bounds(&crossing, 3..=3));
+        assert_eq!(tiles, vec![(0, 3, 3), (7, 3, 3), (0, 4, 3), (7, 4, 3)]);
+        assert_eq!(xyz(tiles_for_bounds(&Bounds::new(170.0, -10.0, 190.0, 10.0), 3..=3)), tiles);
+
+        // Low zooms don't repeat the tile on both sides
+        assert_eq!(xyz(tiles_for_bounds(&crossing, 0..=0)), vec![(0, 0, 0)]);
+        assert_eq!(tiles_for_bounds(&crossing, 1..=1).count(), 4);
+    }
+
+    fn polygon(ring: &[(f64, f64)]) -> serde_json::Value {
+        let ring: Vec<[f64; 2]> = ring.iter().map(|(x, y)| [*x, *y]).collect();
+        serde_json::json!({"type": "Polygon", "coordinates": [ring]})
+    }
+
+    #[test]
+    fn test_tiles_for_polygon() {
+        // A box gives the same tiles as its bounds
+        let bounds = Bounds::new(-20.0, -15.0, 33.0, 41.0);
+        let ring = [(-20.0, -15.0), (33.0, -15.0), (33.0, 41.0), (-20.0, 41.0), (-20.0, -15.0)];
+        let tiles = xyz(tiles_for_polygon(&polygon(&ring), 0..=6).unwrap());
+        assert_eq!(tiles, xyz(tiles_for_bounds(&bounds, 0..=6)));
+
+        // A triangle leaves out the corner of its bounding box
+        let triangle = polygon(&[(1.0, 1.0), (89.0, 1.0), (1.0, 66.0), (1.0, 1.0)]);
+        let tiles = xyz(tiles_for_polygon(&triangle, 3..=3).unwrap());
+        assert_eq!(tiles, vec![(4, 2, 3), (4, 3, 3), (5, 3, 3)]);
+
+        // The tiles inside a hole are left out
+        let holed = serde_json::json!({"type": "Feature", "properties": {}, "geometry": {"type": "Polygon", "coordinates": [
+            [[-170.0, -85.0], [170.0, -85.0], [170.0, 85.0], [-170.0, 85.0], [-170.0, -85.0]],
+            [[-1.0, -1.0], [46.0, -1.0], [46.0, 42.0], [-1.0, 42.0], [-1.0, -1.0]],
+        ]}});
+        let tiles = xyz(tiles_for_polygon(&holed, 4..=4).unwrap());
+        assert!(tiles.contains(&(7, 7, 4)));
+        assert!(!tiles.contains(&(9, 6, 4)));
+        assert_eq!(tiles.len(), 256 - 4);
+
+        // Overlapping features are a union, rather than cancelling out where they overlap
+        let overlapping = serde_json::json!({"type": "FeatureCollection", "features": [
+            {"type": "Feature", "geometry": polygon(&ring)},
+            {"type": "Feature", "geometry": polygon(&[(0.0, 0.0), (30.0, 0.0), (30.0, 30.0), (0.0, 30.0), (0.0, 0.0)])},
+        ]});
+        let tiles = xyz(tiles_for_polygon(&overlapping, 0..=6).unwrap());
+        assert_eq!(tiles, xyz(tiles_for_bounds(&bounds, 0..=6)));
+
+        assert!(tiles_for_polygon(&serde_json::json!({"type": "Point", "coordinates": [0.0, 0.0]}), 0..=1).is_err());
+    }
+
+    #[test]
+    fn test_tiles_for_polygon_antimeridian() {
+        let ring = [(170.0, -10.0), (190.0, -10.0), (190.0, 10.0), (170.0, 10.0), (170.0, -10.0)];
+        let tiles = xyz(tiles_for_polygon(&polygon(&ring), 3..=3).unwrap());
+        assert_eq!(tiles, vec![(0, 3, 3), (7, 3, 3), (0, 4, 3), (7, 4, 3)]);
+
+        // The same area split in two at the antimeridian
+        let split = serde_json::json!({"type": "MultiPolygon", "coordinates": [
+            [[[170.0, -10.0], [180.0, -10.0], [180.0, 10.0], [170.0, 10.0], [170.0, -10.0]]],
+            [[[-180.0, -10.0], [-170.0, -10.0], [-170.0, 10.0], [-180.0, 10.0], [-180.0, -10.0]]],
+        ]});
+        assert_eq!(xyz(tiles_for_polygon(&split, 3..=3).unwrap()), tiles);
+    }
 }
@@ -0,0 +1,329 @@
+use crate::bounds::Bounds;
+use crate::source::AttrValue;
+use anyhow::anyhow;
+use proj4rs::Proj;
+use std::collections::HashMap;
+
+/// A CF `grid_mapping` variable: the projection of a grid with x/y coordinates,
+/// see https://cfconventions.org/cf-conventions/cf-conventions.html#grid-mappings-and-projections
+#[derive(Debug, Clone, Default)]
+pub struct GridMapping {
+    pub name: String,
+    /// Numeric attributes, e.g. `standard_parallel`
+    pub params: HashMap<String, Vec<f64>>,
+    /// A proj string given by the file itself, which wins over the CF parameters
+    pub proj4: Option<String>,
+}
+
+// CF parameter lists and their proj equivalents, in proj string order
+const PARALLELS: [&str; 2] = ["lat_1", "lat_2"];
+
+impl GridMapping {
+    /// Read a grid mapping variable from its attributes, or None if it isn't one
+    pub fn from_attributes(attributes: &[(String, AttrValue)]) -> Option<Self> {
+        let mut mapping = GridMapping::default();
+        for (name, value) in attributes {
+            match (name.as_str(), value) {
+                ("grid_mapping_name", AttrValue::Str(name)) => mapping.name = name.clone(),
+                ("proj4" | "proj4text" | "proj4_params" | "proj_params", AttrValue::Str(proj4)) => {
+                    mapping.proj4 = Some(proj4.clone())
+                }
+                (name, AttrValue::Numbers(values)) => {
+                    mapping.params.insert(name.to_string(), values.clone());
+                }
+                _ => {}
+            }
+        }
+        (!mapping.name.is_empty() || mapping.proj4.is_some()).then_some(mapping)
+    }
+
+    fn param(&self, name: &str) -> Option<f64> {
+        self.params.get(name).and_then(|v| v.first().copied())
+    }
+
+    /// The equivalent proj string, or None for plain latitude_longitude grids
+    pub fn proj_string(&self) -> anyhow::Result<Option<String>> {
+        if let Some(proj4) = &self.proj4 {
+            return Ok(Some(proj4.clone()));
+        }
+
+        let p = |name: &str| self.param(name).unwrap_or(0.0);
+        let mut proj = match self.name.as_str() {
+            "latitude_longitude" => return Ok(None),
+            "lambert_conformal_conic" => format!(
+                "+proj=lcc {} +lat_0={} +lon_0={}",
+                self.parallels()?,
+                p("latitude_of_projection_origin"),
+                p("longitude_of_central_meridian")
+            ),
+            "albers_conical_equal_area" => format!(
+                "+proj=aea {} +lat_0={} +lon_0={}",
+                self.parallels()?,
+                p("latitude_of_projection_origin"),
+                p("longitude_of_central_meridian")
+            ),
+            "polar_stereographic" => {
+                let lon_0 = self
+                    .param("straight_vertical_longitude_from_pole")
+                    .or_else(|| self.param("longitude_of_projection_origin"))
+                    .unwrap_or(0.0);
+                let scale = match self.param("standard_parallel") {
+                    Some(lat_ts) => format!("+lat_ts={}", lat_ts),
+                    None => format!("+k={}", self.param("scale_factor_at_projection_origin").unwrap_or(1.0)),
+                };
+                format!("+proj=stere +lat_0={} +lon_0={} {}", p("latitude_of_projection_origin"), lon_0, scale)
+            }
+            "stereographic" => format!(
+                "+proj=stere +lat_0={} +lon_0={} +k={}",
+                p("latitude_of_projection_origin"),
+                p("longitude_of_projection_origin"),
+                self.param("scale_factor_at_projection_origin").unwrap_or(1.0)
+            ),
+            "transverse_mercator" => format!(
+                "+proj=tmerc +lat_0={} +lon_0={} +k={}",
+                p("latitude_of_projection_origin"),
+                p("longitude_of_central_meridian"),
+                self.param("scale_factor_at_central_meridian").unwrap_or(1.0)
+            ),
+            "mercator" => {
+                let scale = match self.param("standard_parallel") {
+                    Some(lat_ts) => format!("+lat_ts={}", lat_ts),
+                    None => format!("+k={}", self.param("scale_factor_at_projection_origin").unwrap_or(1.0)),
+                };
+                format!("+proj=merc +lon_0={} {}", p("longitude_of_projection_origin"), scale)
+            }
+            "lambert_azimuthal_equal_area" => format!(
+                "+proj=laea +lat_0={} +lon_0={}",
+                p("latitude_of_projection_origin"),
+                p("longitude_of_projection_origin")
+            ),
+            name => return Err(anyhow!("Unsupported grid mapping {:?}", name)),
+        };
+
+        proj.push_str(&format!(" +x_0={} +y_0={}", p("false_easting"), p("false_northing")));
+        // The earth shape, WGS84 unless the file says otherwise
+        if let Some(radius) = self.param("earth_radius") {
+            proj.push_str(&format!(" +R={}", radius));
+        } else if let Some(a) = self.param("semi_major_axis") {
+            proj.push_str(&format!(" +a={}", a));
+            match (self.param("semi_minor_axis"), self.param("inverse_flattening")) {
+                (Some(b), _) => proj.push_str(&format!(" +b={}", b)),
+                (None, Some(rf)) if rf != 0.0 => proj.push_str(&format!(" +rf={}", rf)),
+                // A sphere
+                _ => proj.push_str(&format!(" +b={}", a)),
+            }
+        } else {
+            proj.push_str(" +ellps=WGS84");
+        }
+        Ok(Some(proj))
+    }
+
+    // `standard_parallel` has one or two values, and one means the cone touches there
+    fn parallels(&self) -> anyhow::Result<String> {
+        let parallels = self
+            .params
+            .get("standard_parallel")
+            .filter(|p| !p.is_empty())
+            .ok_or_else(|| anyhow!("{} needs a standard_parallel", self.name))?;
+        Ok(PARALLELS
+            .iter()
+            .zip(parallels.iter().chain(std::iter::repeat(&parallels[0])))
+            .map(|(name, lat)| format!("+{}={}", name, lat))
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+}
+
+/// The projected CRS of a source grid, for moving between lng/lat and the grid's x/y coordinates
+pub struct SourceCrs {
+    proj: Proj,
+    geographic: Proj,
+    /// Meters per x/y coordinate unit, e.g. 1000 for grids in km
+    unit_scale: f64,
+}
+
+impl SourceCrs {
+    pub fn new(proj_string: &str, unit_scale: f64) -> anyhow::Result<Self> {
+        Ok(Self {
+            proj: Proj::from_proj_string(proj_string)
+                .map_err(|e| anyhow!("Invalid projection {:?}: {}", proj_string, e))?,
+            geographic: Proj::from_proj_string("+proj=longlat +ellps=WGS84").map_err(|e| anyhow!("{}", e))?,
+            unit_scale,
+        })
+    }
+
+    /// Project lng/lat points in degrees to grid x/y in place. Points the projection can't reach are NaN.
+    pub fn from_lng_lat(&self, points: &mut [(f64, f64)]) {
+        for point in points.iter_mut() {
+            let mut xy = (point.0.to_radians(), point.1.to_radians());
+            *point = match proj4rs::transform::transform(&self.geographic, &self.proj, &mut xy) {
+                Ok(()) if xy.0.is_finite() && xy.1.is_finite() => (xy.0 / self.unit_scale, xy.1 / self.unit_scale),
+                _ => (f64::NAN, f64::NAN),
+            };
+        }
+    }
+
+    /// Unproject grid x/y points to lng/lat in degrees in place
+    pub fn to_lng_lat(&self, points: &mut [(f64, f64)]) {
+        for point in points.iter_mut() {
+            let mut xy = (point.0 * self.unit_scale, point.1 * self.unit_scale);
+            *point = match proj4rs::transform::transform(&self.proj, &self.geographic, &mut xy) {
+                Ok(()) => (xy.0.to_degrees(), xy.1.to_degrees()),
+                _ => (f64::NAN, f64::NAN),
+            };
+        }
+    }
+
+    /// The x/y box around a lng/lat box, found by projecting a grid of points over it
+    pub fn envelope(&self, bounds: Bounds) -> Option<Bounds> {
+        let mut points = grid_points(bounds);
+        self.from_lng_lat(&mut points);
+        points_envelope(points)
+    }
+
+    /// The lng/lat box around an x/y box, the inverse of `envelope`
+    pub fn lng_lat_envelope(&self, bounds: Bounds) -> Option<Bounds> {
+        let mut points = grid_points(bounds);
+        self.to_lng_lat(&mut points);
+        points_envelope(points)
+    }
+}
+
+// A grid of points over a box, edges included
+fn grid_points(bounds: Bounds) -> Vec<(f64, f64)> {
+    const STEPS: usize = 20;
+    (0..=STEPS)
+        .flat_map(|i| {
+            (0..=STEPS).map(move |j| {
+                (
+                    bounds.min_x + (bounds.max_x - bounds.min_x) * i as f64 / STEPS as f64,
+                    bounds.min_y + (bounds.max_y - bounds.min_y) * j as f64 / STEPS as f64,
+                )
+            })
+        })
+        .collect()
+}
+
+// The box around the points that could be transformed
+fn points_envelope(points: Vec<(f64, f64)>) -> Option<Bounds> {
+    points.into_iter().filter(|(x, y)| x.is_finite() && y.is_finite()).fold(None, |envelope, (x, y)| {
+        Some(match envelope {
+            Some(b) => Bounds::new(b.min_x.min(x), b.min_y.min(y), b.max_x.max(x), b.max_y.max(y)),
+            None => Bounds::new(x, y, x, y),
+        })
+    })
+}
+
+/// Meters per unit for a CF `units` attribute on a projection coordinate
+pub fn unit_scale(units: Option<&str>) -> f64 {
+    match units {
+        Some("km" | "kilometer" | "kilometers" | "kilometre" | "kilometres") => 1000.0,
+        _ => 1.0,
+    }
+}
+
+#[cfg(test)]
+mod crs_tests {
+    use super::*;
+
+    fn mapping(name: &str, params: &[(&str, &[f64])]) -> GridMapping {
+        GridMapping {
+            name: name.to_string(),
+            params: params.iter().map(|(k, v)| (k.to_string(), v.to_vec())).collect(),
+            proj4: None,
+        }
+    }
+
+    #[test]
+    fn test_proj_string() {
+        let lcc = mapping(
+            "lambert_conformal_conic",
+            &[
+                ("standard_parallel", &[25.0]),
+                ("longitude_of_central_meridian", &[265.0]),
+                ("latitude_of_projection_origin", &[25.0]),
+                ("earth_radius", &[6371229.0]),
+            ],
+        );
+        assert_eq!(
+            lcc.proj_string().unwrap().unwrap(),
+            "+proj=lcc +lat_1=25 +lat_2=25 +lat_0=25 +lon_0=265 +x_0=0 +y_0=0 +R=6371229"
+        );
+
+        let polar = mapping(
+            "polar_stereographic",
+            &[
+                ("straight_vertical_longitude_from_pole", &[-45.0]),
+                ("latitude_of_projection_origin", &[90.0]),
+                ("standard_parallel", &[70.0]),
+                ("semi_major_axis", &[6378137.0]),
+                ("inverse_flattening", &[298.257223563]),
+            ],
+        );
+        assert_eq!(
+            polar.proj_string().unwrap().unwrap(),
+            "+proj=stere +lat_0=90 +lon_0=-45 +lat_ts=70 +x_0=0 +y_0=0 +a=6378137 +rf=298.257223563"
+        );
+
+        assert!(mapping("latitude_longitude", &[]).proj_string().unwrap().is_none());
+        assert!(mapping("lambert_conformal_conic", &[]).proj_string().is_err());
+        assert!(mapping("vertical_perspective", &[]).proj_string().is_err());
+
+        let given = GridMapping {
+            proj4: Some("+proj=laea +lat_0=90".to_string()),
+            ..mapping("lambert_azimuthal_equal_area", &[])
+        };
+        assert_eq!(given.proj_string().unwrap().unwrap(), "+proj=laea +lat_0=90");
+    }
+
+    #[test]
+    fn test_source_crs() {
+        // NSIDC sea ice polar stereographic north, EPSG:3413, with coordinates in km
+        let crs = SourceCrs::new("+proj=stere +lat_0=90 +lon_0=-45 +lat_ts=70 +ellps=WGS84", 1000.0).unwrap();
+        let mut points = [(0.0, 75.0), (-45.0, 90.0)];
+        crs.from_lng_lat(&mut points);
+        assert_relative_eq!(points[0].0, 1155.327, epsilon = 0.01);
+        assert_relative_eq!(points[0].1, -1155.327, epsilon = 0.01);
+        assert_abs_diff_eq!(points[1].0, 0.0, epsilon = 1e-6);
+
+        crs.to_lng_lat(&mut points);
+        assert_abs_diff_eq!(points[0].0, 0.0, epsilon = 1e-6);
+        assert_relative_eq!(points[0].1, 75.0, epsilon = 1e-6);
+
+        // A box around the pole covers it on every side
+        let envelope = crs.envelope(Bounds::new(-180.0, 80.0, 180.0, 90.0)).unwrap();
+        assert!(envelope.min_x < -1000.0 && envelope.max_x > 1000.0);
+        assert!(envelope.min_y < -1000.0 && envelope.max_y > 1000.0);
+        // and a grid around it reaches the pole and every longitude
+        let lng_lat = crs.lng_lat_envelope(Bounds::new(-500.0, -500.0, 500.0, 500.0)).unwrap();
+        assert_relative_eq!(lng_lat.max_y, 90.0, epsilon = 1e-6);
+        assert!(lng_lat.min_y > 83.0 && lng_lat.min_y < 84.0, "{:?}", lng_lat);
+        assert!(lng_lat.min_x < -170.0 && lng_lat.max_x > 170.0, "{:?}", lng_lat);
+
+        let lcc = SourceCrs::new(
+            &mapping(
+                "lambert_conformal_conic",
+                &[
+                    ("standard_parallel", &[33.0, 45.0]),
+                    ("longitude_of_central_meridian", &[-96.0]),
+                    ("latitude_of_projection_origin", &[23.0]),
+                ],
+            )
+            .proj_string()
+            .unwrap()
+            .unwrap(),
+            1.0,
+        )
+        .unwrap();
+        let mut points = [(-96.0, 23.0), (-120.0, 50.0)];
+        lcc.from_lng_lat(&mut points);
+        assert_abs_diff_eq!(points[0].0, 0.0, epsilon = 1e-6);
+        assert_abs_diff_eq!(points[0].1, 0.0, epsilon = 1e-6);
+        lcc.to_lng_lat(&mut points);
+        assert_relative_eq!(points[1].0, -120.0, epsilon = 1e-6);
+        assert_relative_eq!(points[1].1, 50.0, epsilon = 1e-6);
+
+        assert_eq!(unit_scale(Some("km")), 1000.0);
+        assert_eq!(unit_scale(Some("m")), 1.0);
+    }
+}
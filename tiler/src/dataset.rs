@@ -1,38 +1,243 @@
 use crate::bounds::Bounds;
-use std::path::Path;
+use crate::crs::{self, GridMapping, SourceCrs};
+use crate::expr::Expr;
+use crate::source::{self, AttrValue, GridSource};
+use crate::time::TimeUnits;
+use anyhow::anyhow;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
-pub struct Dataset {
+/// Name of the time coordinate of files that don't say otherwise
+pub const DEFAULT_TIME_NAME: &str = "time";
+
+/// A grid on disk: a NetCDF file, and the step to read from variables with a time dimension
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DatasetPath {
+    pub path: PathBuf,
+    pub time_index: usize,
+    /// The coordinate variable `time_index` steps along
+    pub time_name: String,
+}
+
+impl DatasetPath {
+    pub fn new(path: PathBuf, time_index: usize) -> Self {
+        Self {
+            path,
+            time_index,
+            time_name: DEFAULT_TIME_NAME.to_string(),
+        }
+    }
+
+    /// The same step along a differently named time coordinate, e.g. `month` in a climatology
+    pub fn with_time_name(mut self, time_name: &str) -> Self {
+        self.time_name = time_name.to_string();
+        self
+    }
+}
+
+impl From<PathBuf> for DatasetPath {
+    fn from(path: PathBuf) -> Self {
+        Self::new(path, 0)
+    }
+}
+
+impl From<&PathBuf> for DatasetPath {
+    fn from(path: &PathBuf) -> Self {
+        Self::new(path.clone(), 0)
+    }
+}
+
+impl From<&Path> for DatasetPath {
+    fn from(path: &Path) -> Self {
+        Self::new(path.to_path_buf(), 0)
+    }
+}
+
+impl From<&DatasetPath> for DatasetPath {
+    fn from(dset_path: &DatasetPath) -> Self {
+        dset_path.clone()
+    }
+}
+
+// Names and CF standard names of vertical coordinates without an `axis` or `positive` attribute
+const VERTICAL_NAMES: [&str; 8] = ["depth", "z", "lev", "level", "height", "altitude", "pressure", "plev"];
+const VERTICAL_STANDARD_NAMES: [&str; 5] = ["depth", "height", "altitude", "air_pressure", "sea_water_pressure"];
+
+/// The vertical coordinate of a variable with a level dimension before lat and lon, e.g. depth below the sea surface
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VerticalAxis {
+    pub name: String,
+    /// Coordinate values of each level, or level indices if there's no coordinate variable
+    pub values: Vec<f64>,
+    pub units: Option<String>,
+    /// Whether values increase upwards, like height, rather than downwards, like depth or pressure
+    pub positive_up: bool,
+}
+
+/// A grid with 1-D lat/lon coordinates, read from any source. NetCDF files and Zarr stores are opened with `new`.
+pub struct Dataset<S: GridSource = Box<dyn GridSource>> {
     lats: Vec<f64>,
     lons: Vec<f64>,
-    file: netcdf::File,
+    /// Names of the coordinate variables `lats` and `lons` were read from
+    lat_name: String,
+    lon_name: String,
+    source: S,
     inv_y: bool,
     inv_x: bool,
+    /// Set for grids in projected x/y coordinates, which `lats` and `lons` then hold
+    crs: Option<SourceCrs>,
+    /// Step read from variables with a time dimension before lat and lon
+    time_index: usize,
+    /// Name of the dimension `time_index` steps along. Other dimensions before lat and lon are read at their first
+    /// index, e.g. the surface of a depth dimension.
+    time_dim: String,
+    /// Cell centre lats and lons of each of the source's overviews, finest first
+    overviews: Vec<(Vec<f64>, Vec<f64>)>,
+}
+
+// Cell centres of a regular coordinate resampled to fewer cells over the same extent
+fn resample_coords(coords: &[f64], len: usize) -> Vec<f64> {
+    let n = coords.len();
+    let step = if n > 1 { (coords[n - 1] - coords[0]) / (n - 1) as f64 } else { 0.0 };
+    let edge = coords[0] - step / 2.0;
+    let step = step * n as f64 / len as f64;
+    (0..len).map(|i| edge + (i as f64 + 0.5) * step).collect()
+}
+
+// The name of the 1-D coordinate variable with a CF standard_name, e.g. `projection_x_coordinate`
+fn find_coordinate(source: &impl GridSource, standard_name: &str) -> Option<String> {
+    source.variables().into_iter().find(|var| {
+        source.dimensions(var).is_some_and(|dims| dims.len() == 1)
+            && source.attribute(var, "standard_name").as_ref().and_then(|name| name.as_str()) == Some(standard_name)
+    })
+}
+
+// The projection of a grid in CF projection x/y coordinates, from the grid_mapping variable named by its data
+// variables. Grids in lat/lon and mappings without a proj equivalent, e.g. rotated_latitude_longitude, have none.
+fn find_crs(source: &impl GridSource, x_name: &str) -> anyhow::Result<Option<SourceCrs>> {
+    let standard_name = source.attribute(x_name, "standard_name");
+    if standard_name.as_ref().and_then(|name| name.as_str()) != Some("projection_x_coordinate") {
+        return Ok(None);
+    }
+    let x_dim = source.dimensions(x_name).and_then(|dims| dims.first().map(|(name, _)| name.clone()));
+    let mapping = source
+        .variables()
+        .into_iter()
+        .filter(|var| {
+            let dims = source.dimensions(var).unwrap_or_default();
+            dims.iter().any(|(dim, _)| Some(dim) == x_dim.as_ref())
+        })
+        .find_map(|var| {
+            let grid_mapping = source.attribute(&var, "grid_mapping")?;
+            // The extended form names mappings and their coordinates, e.g. "crs: x y"
+            let name = grid_mapping.as_str()?.split_whitespace().next()?.trim_end_matches(':').to_string();
+            GridMapping::from_attributes(&source.attributes(&name))
+        });
+    let Some(Ok(Some(proj_string))) = mapping.map(|mapping| mapping.proj_string()) else {
+        return Ok(None);
+    };
+    let units = source.attribute(x_name, "units");
+    let units = units.as_ref().and_then(|units| units.as_str());
+    Ok(Some(SourceCrs::new(&proj_string, crs::unit_scale(units))?))
 }
 
 impl Dataset {
+    /// Open a NetCDF file or Zarr store with 1-D coordinate variables, see `from_source`
     pub fn new(path: &Path, lat_name: &str, lon_name: &str) -> anyhow::Result<Self> {
-        let file = netcdf::open(path)?;
+        Self::from_source(source::open(path)?, lat_name, lon_name)
+    }
 
-        let lats = &file.variable(lat_name).expect("No latitude variable in dataset");
-        let lats = lats.values_arr::<f64, _>(..)?;
-        let lats = lats.into_raw_vec();
+    /// Open a dataset at a time step
+    pub fn open(dset_path: &DatasetPath, lat_name: &str, lon_name: &str) -> anyhow::Result<Self> {
+        let mut dset = Self::new(&dset_path.path, lat_name, lon_name)?;
+        dset.time_index = dset_path.time_index;
+        // The dimension of the time coordinate, which is usually named the same
+        let time_dims = dset.source.dimensions(&dset_path.time_name).unwrap_or_default();
+        dset.time_dim = match time_dims.as_slice() {
+            [(dim, _)] => dim.clone(),
+            _ => dset_path.time_name.clone(),
+        };
+        Ok(dset)
+    }
+}
+
+impl<S: GridSource> Dataset<S> {
+    /// Grids on a CF `grid_mapping` projection are read through their projection x/y coordinates, which are
+    /// found by standard name if there's no `lat_name`/`lon_name`.
+    pub fn from_source(source: S, lat_name: &str, lon_name: &str) -> anyhow::Result<Self> {
+        let (lat_name, lon_name) = match (source.dimensions(lat_name), source.dimensions(lon_name)) {
+            (Some(_), Some(_)) => (lat_name.to_string(), lon_name.to_string()),
+            _ => (
+                find_coordinate(&source, "projection_y_coordinate").unwrap_or(lat_name.to_string()),
+                find_coordinate(&source, "projection_x_coordinate").unwrap_or(lon_name.to_string()),
+            ),
+        };
+        for name in [&lat_name, &lon_name] {
+            match source.dimensions(name) {
+                Some(dims) if dims.len() == 1 => {}
+                Some(dims) => {
+                    return Err(anyhow!("{} has {} dimensions, only 1-D coordinates are supported", name, dims.len()))
+                }
+                None => return Err(anyhow!("No coordinate variable {} in dataset", name)),
+            }
+        }
+        let crs = find_crs(&source, &lon_name)?;
 
-        let lons = &file.variable(lon_name).expect("No longitude variable in dataset");
-        let lons = lons.values_arr::<f64, _>(..)?;
-        let lons = lons.into_raw_vec();
+        let lats = source.read_all(&lat_name)?.into_raw_vec();
+        let lons = source.read_all(&lon_name)?.into_raw_vec();
 
         let inv_y = lats[0] > lats[1];
         let inv_x = lons[0] > lons[1];
+        let overviews = source
+            .overviews()
+            .into_iter()
+            .map(|(rows, cols)| (resample_coords(&lats, rows), resample_coords(&lons, cols)))
+            .collect();
 
         Ok(Self {
             lats,
             lons,
-            file,
+            lat_name,
+            lon_name,
+            source,
             inv_y,
             inv_x,
+            crs,
+            time_index: 0,
+            time_dim: DEFAULT_TIME_NAME.to_string(),
+            overviews,
         })
     }
 
+    /// Times of the steps along the time coordinate, or None if there isn't one
+    pub fn times(&self, time_name: &str) -> anyhow::Result<Option<Vec<i64>>> {
+        read_times(&self.source, time_name)
+    }
+
+    /// The grid's projection, if it's in projected x/y coordinates rather than lat/lon
+    pub fn crs(&self) -> Option<&SourceCrs> {
+        self.crs.as_ref()
+    }
+
+    /// Grid coordinates of a lng/lat point
+    pub fn source_point(&self, lng: f64, lat: f64) -> (f64, f64) {
+        let mut point = [(lng, lat)];
+        if let Some(crs) = &self.crs {
+            crs.from_lng_lat(&mut point);
+        }
+        point[0]
+    }
+
+    /// Grid coordinate bounds covering a lng/lat box
+    pub fn source_bounds(&self, bounds: Bounds) -> Option<Bounds> {
+        match &self.crs {
+            Some(crs) => crs.envelope(bounds),
+            None => Some(bounds),
+        }
+    }
+
     pub fn lats(&self) -> &[f64] {
         &self.lats
     }
@@ -41,6 +246,59 @@ impl Dataset {
         &self.lons
     }
 
+    pub fn lat_name(&self) -> &str {
+        &self.lat_name
+    }
+
+    pub fn lon_name(&self) -> &str {
+        &self.lon_name
+    }
+
+    /// A variable's attributes as the source has them, e.g. to copy into an export
+    pub fn attributes(&self, var_name: &str) -> Vec<(String, AttrValue)> {
+        self.source.attributes(var_name)
+    }
+
+    /// Whether the source has its own reduced resolution copies, e.g. a Cloud-Optimized GeoTIFF
+    pub fn has_overviews(&self) -> bool {
+        !self.overviews.is_empty()
+    }
+
+    // Coordinates at a level: 0 is the full grid, and the source's overviews follow
+    fn level_coords(&self, level: usize) -> (&[f64], &[f64]) {
+        match level.checked_sub(1).and_then(|i| self.overviews.get(i)) {
+            Some((lats, lons)) => (lats, lons),
+            None => (&self.lats, &self.lons),
+        }
+    }
+
+    /// Width and height of a cell at a level
+    pub fn cell_size(&self, level: usize) -> (f64, f64) {
+        let (lats, lons) = self.level_coords(level);
+        let step = |coords: &[f64]| if coords.len() > 1 { (coords[1] - coords[0]).abs() } else { 0.0 };
+        (step(lons), step(lats))
+    }
+
+    /// The coarsest level with cells no bigger than a pixel, so reads for an image of that pixel size
+    /// come from the smallest overview that doesn't lose detail. 0 is the full grid.
+    pub fn level_for(&self, (pixel_x, pixel_y): (f64, f64)) -> usize {
+        (1..=self.overviews.len())
+            .rev()
+            .find(|level| {
+                let (dx, dy) = self.cell_size(*level);
+                dx <= pixel_x && dy <= pixel_y
+            })
+            .unwrap_or(0)
+    }
+
+    /// The lng/lat box around the grid, or None if its projection can't reach any of it
+    pub fn lng_lat_bounds(&self) -> Option<Bounds> {
+        match &self.crs {
+            Some(crs) => crs.lng_lat_envelope(self.get_bounds()),
+            None => Some(self.get_bounds()),
+        }
+    }
+
     pub fn get_bounds(&self) -> Bounds {
         let (min_x, max_x) = match self.inv_x {
             true => (self.lons.last().unwrap(), self.lons.first().unwrap()),
@@ -69,38 +327,84 @@ impl Dataset {
         closest_i
     }
 
+    // Index ranges of the lat and lon dimensions covering the bounds at a level
+    fn get_index_ranges(&self, level: usize, bounds: Bounds) -> (Range<usize>, Range<usize>) {
+        let (lats, lons) = self.level_coords(level);
+        let (start_lat_i, end_lat_i) = if self.inv_y {
+            (self.get_dim_index(lats, bounds.max_y), self.get_dim_index(lats, bounds.min_y))
+        } else {
+            (self.get_dim_index(lats, bounds.min_y), self.get_dim_index(lats, bounds.max_y))
+        };
+        let (start_lon_i, end_lon_i) = if self.inv_x {
+            (self.get_dim_index(lons, bounds.max_x), self.get_dim_index(lons, bounds.min_x))
+        } else {
+            (self.get_dim_index(lons, bounds.min_x), self.get_dim_index(lons, bounds.max_x))
+        };
+
+        (start_lat_i..end_lat_i, start_lon_i..end_lon_i)
+    }
+
+    /// The lat and lon coordinates of the rows and columns `get_values` returns for the bounds
+    pub fn get_coords(&self, bounds: Bounds) -> (Vec<f64>, Vec<f64>) {
+        self.get_coords_at(0, bounds)
+    }
+
+    /// Like `get_coords`, for `get_values_at` a level
+    pub fn get_coords_at(&self, level: usize, bounds: Bounds) -> (Vec<f64>, Vec<f64>) {
+        let (lat_range, lon_range) = self.get_index_ranges(level, bounds);
+        let (lats, lons) = self.level_coords(level);
+        let mut lats = lats[lat_range].to_vec();
+        let mut lons = lons[lon_range].to_vec();
+
+        if self.inv_y {
+            lats.reverse();
+        }
+        if self.inv_x {
+            lons.reverse();
+        }
+        (lats, lons)
+    }
+
     pub fn get_values(
         &self,
         var_name: &str,
         bounds: Bounds,
     ) -> anyhow::Result<ndarray::ArrayD<f64>> {
-        let data = self.file.variable(var_name).unwrap();
+        self.get_values_at(var_name, 0, bounds)
+    }
+
+    // Start indices of the dimensions before lat and lon: the time step along the time dimension, and 0 for others
+    fn leading_start(&self, dims: &[(String, usize)]) -> Vec<usize> {
+        dims[..dims.len().saturating_sub(2)]
+            .iter()
+            .map(|(name, _)| if *name == self.time_dim { self.time_index } else { 0 })
+            .collect()
+    }
+
+    /// Like `get_values`, from a level picked with `level_for`
+    pub fn get_values_at(&self, var_name: &str, level: usize, bounds: Bounds) -> anyhow::Result<ndarray::ArrayD<f64>> {
+        let dims = self
+            .source
+            .dimensions(var_name)
+            .ok_or_else(|| anyhow!("No variable {} in dataset", var_name))?;
 
         // Get start and end indices for lat and lon
-        let (start_lat_i, end_lat_i) = if self.inv_y {
-            (
-                self.get_dim_index(&self.lats, bounds.max_y),
-                self.get_dim_index(&self.lats, bounds.min_y),
-            )
-        } else {
-            (
-                self.get_dim_index(&self.lats, bounds.min_y),
-                self.get_dim_index(&self.lats, bounds.max_y),
-            )
-        };
-        let (start_lon_i, end_lon_i) = if self.inv_x {
-            (
-                self.get_dim_index(&self.lons, bounds.max_x),
-                self.get_dim_index(&self.lons, bounds.min_x),
-            )
-        } else {
-            (
-                self.get_dim_index(&self.lons, bounds.min_x),
-                self.get_dim_index(&self.lons, bounds.max_x),
-            )
+        let (lat_range, lon_range) = self.get_index_ranges(level, bounds);
+        let read = |start: &[usize], count: &[usize]| match level {
+            0 => self.source.read(var_name, start, count),
+            _ => self.source.read_overview(var_name, level - 1, start, count),
         };
 
-        let mut result = data.values_arr::<f64, _>((start_lat_i..end_lat_i, start_lon_i..end_lon_i))?;
+        let mut start = self.leading_start(&dims);
+        let mut count = vec![1; start.len()];
+        start.extend([lat_range.start, lon_range.start]);
+        count.extend([lat_range.len(), lon_range.len()]);
+        let mut result = read(&start, &count)?.into_shape(ndarray::IxDyn(&[lat_range.len(), lon_range.len()]))?;
+
+        // Missing data is NaN from here on
+        if let Some(fill_value) = get_fill_value(&self.source, var_name) {
+            result.mapv_inplace(|v| if v == fill_value { f64::NAN } else { v });
+        }
 
         if self.inv_y {
             result.invert_axis(ndarray::Axis(0));
@@ -112,13 +416,350 @@ impl Dataset {
 
         Ok(result)
     }
+
+    /// Evaluate an expression over the variables it uses, read with `get_values`
+    pub fn get_expr_values(
+        &self,
+        expr: &Expr,
+        bounds: Bounds,
+    ) -> anyhow::Result<ndarray::ArrayD<f64>> {
+        self.get_expr_values_at(expr, 0, bounds)
+    }
+
+    /// Like `get_expr_values`, from a level picked with `level_for`
+    pub fn get_expr_values_at(
+        &self,
+        expr: &Expr,
+        level: usize,
+        bounds: Bounds,
+    ) -> anyhow::Result<ndarray::ArrayD<f64>> {
+        if let Expr::Variable(name) = expr {
+            return self.get_values_at(name, level, bounds);
+        }
+
+        let names = expr.variables();
+        if names.is_empty() {
+            return Err(anyhow!("Expression does not use any variables"));
+        }
+        let mut arrays = HashMap::new();
+        for name in names {
+            arrays.insert(name, self.get_values_at(name, level, bounds)?);
+        }
+        expr.eval(&arrays)
+    }
+
+    /// Value of the grid cell nearest to a point, or None if the point is outside the dataset
+    pub fn get_value(&self, var_name: &str, x: f64, y: f64) -> anyhow::Result<Option<f64>> {
+        let bounds = self.get_bounds();
+        if x < bounds.min_x || x > bounds.max_x || y < bounds.min_y || y > bounds.max_y {
+            return Ok(None);
+        }
+
+        let dims = self
+            .source
+            .dimensions(var_name)
+            .ok_or_else(|| anyhow!("No variable {} in dataset", var_name))?;
+        let lat_i = self.get_dim_index(&self.lats, y);
+        let lon_i = self.get_dim_index(&self.lons, x);
+        let mut start = self.leading_start(&dims);
+        start.extend([lat_i, lon_i]);
+        let values = self.source.read(var_name, &start, &vec![1; start.len()])?;
+        let value = values.iter().next().copied().unwrap_or(f64::NAN);
+
+        match get_fill_value(&self.source, var_name) {
+            Some(fill_value) if value == fill_value => Ok(Some(f64::NAN)),
+            _ => Ok(Some(value)),
+        }
+    }
+
+    /// The vertical axis of a variable with dimensions (level, lat, lon) or (time, level, lat, lon), or None if it
+    /// has no level dimension. Levels are found by a CF `axis` of Z, a `positive` attribute, or a depth-like name.
+    pub fn vertical_axis(&self, var_name: &str) -> anyhow::Result<Option<VerticalAxis>> {
+        let dims = self
+            .source
+            .dimensions(var_name)
+            .ok_or_else(|| anyhow!("No variable {} in dataset", var_name))?;
+        if !(3..=4).contains(&dims.len()) {
+            return Ok(None);
+        }
+        let (name, len) = &dims[dims.len() - 3];
+        let attribute = |attr: &str| {
+            let value = self.source.attribute(name, attr)?;
+            value.as_str().map(str::to_lowercase)
+        };
+        let positive = attribute("positive");
+        let standard_name = attribute("standard_name");
+        let vertical = attribute("axis").as_deref() == Some("z")
+            || positive.is_some()
+            || standard_name.as_deref().is_some_and(|s| VERTICAL_STANDARD_NAMES.contains(&s))
+            || VERTICAL_NAMES.contains(&name.to_lowercase().as_str());
+        if !vertical {
+            return Ok(None);
+        }
+
+        let values = match self.source.dimensions(name) {
+            Some(_) => self.source.read_all(name)?.into_raw_vec(),
+            None => (0..*len).map(|i| i as f64).collect(),
+        };
+        let positive_up = match positive.as_deref() {
+            Some(positive) => positive == "up",
+            None => matches!(standard_name.as_deref(), Some("height" | "altitude")),
+        };
+        Ok(Some(VerticalAxis {
+            name: name.clone(),
+            values,
+            units: self.source.attribute(name, "units").and_then(|u| u.as_str().map(str::to_string)),
+            positive_up,
+        }))
+    }
+
+    /// Values at every level of the grid cells nearest to points in grid coordinates, as a (level, point) array.
+    /// Points outside the dataset are NaN.
+    pub fn get_columns(&self, var_name: &str, points: &[(f64, f64)]) -> anyhow::Result<ndarray::Array2<f64>> {
+        let dims = self
+            .source
+            .dimensions(var_name)
+            .ok_or_else(|| anyhow!("No variable {} in dataset", var_name))?;
+        if self.vertical_axis(var_name)?.is_none() {
+            return Err(anyhow!("{} has no vertical dimension", var_name));
+        }
+        let levels = dims[dims.len() - 3].1;
+        let read = |rows: Range<usize>, cols: Range<usize>| -> anyhow::Result<ndarray::Array3<f64>> {
+            // The dimensions before the vertical one
+            let mut start = self.leading_start(&dims[..dims.len() - 1]);
+            let mut count = vec![1; start.len()];
+            start.extend([0, rows.start, cols.start]);
+            count.extend([levels, rows.len(), cols.len()]);
+            let values = self.source.read(var_name, &start, &count)?;
+            Ok(values.into_shape((levels, rows.len(), cols.len()))?)
+        };
+
+        let bounds = self.get_bounds();
+        let cells: Vec<Option<(usize, usize)>> = points
+            .iter()
+            .map(|(x, y)| {
+                let inside = *x >= bounds.min_x && *x <= bounds.max_x && *y >= bounds.min_y && *y <= bounds.max_y;
+                inside.then(|| (self.get_dim_index(&self.lats, *y), self.get_dim_index(&self.lons, *x)))
+            })
+            .collect();
+        let mut result = ndarray::Array2::from_elem((levels, points.len()), f64::NAN);
+        let ranges = cells.iter().flatten().fold(None, |ranges: Option<(Range<usize>, Range<usize>)>, (r, c)| {
+            Some(match ranges {
+                Some((rows, cols)) => {
+                    (rows.start.min(*r)..rows.end.max(r + 1), cols.start.min(*c)..cols.end.max(c + 1))
+                }
+                None => (*r..r + 1, *c..c + 1),
+            })
+        });
+        let Some((rows, cols)) = ranges else {
+            return Ok(result);
+        };
+
+        // One read of the cells around the points, unless they're sparse in it, e.g. along a long diagonal
+        if rows.len() * cols.len() <= 4 * points.len() {
+            let block = read(rows.clone(), cols.clone())?;
+            for (i, cell) in cells.iter().enumerate() {
+                if let Some((r, c)) = cell {
+                    let column = block.slice(ndarray::s![.., r - rows.start, c - cols.start]);
+                    result.column_mut(i).assign(&column);
+                }
+            }
+        } else {
+            // Otherwise a read for each row, over each run of nearby columns in it
+            let mut rows: BTreeMap<usize, Vec<(usize, usize)>> = BTreeMap::new();
+            for (i, cell) in cells.iter().enumerate() {
+                if let Some((r, c)) = cell {
+                    rows.entry(*r).or_default().push((*c, i));
+                }
+            }
+            for (r, mut row) in rows {
+                row.sort_unstable();
+                for run in row.chunk_by(|(c0, _), (c1, _)| c1 - c0 <= 4) {
+                    let cols = run[0].0..run[run.len() - 1].0 + 1;
+                    let block = read(r..r + 1, cols.clone())?;
+                    for (c, i) in run {
+                        result.column_mut(*i).assign(&block.slice(ndarray::s![.., 0, c - cols.start]));
+                    }
+                }
+            }
+        }
+
+        if let Some(fill_value) = get_fill_value(&self.source, var_name) {
+            result.mapv_inplace(|v| if v == fill_value { f64::NAN } else { v });
+        }
+        Ok(result)
+    }
+
+    /// Evaluate an expression over the `get_columns` of the variables it uses
+    pub fn get_expr_columns(&self, expr: &Expr, points: &[(f64, f64)]) -> anyhow::Result<ndarray::Array2<f64>> {
+        if let Expr::Variable(name) = expr {
+            return self.get_columns(name, points);
+        }
+
+        let names = expr.variables();
+        if names.is_empty() {
+            return Err(anyhow!("Expression does not use any variables"));
+        }
+        let mut arrays = HashMap::new();
+        for name in names {
+            arrays.insert(name, self.get_columns(name, points)?.into_dyn());
+        }
+        Ok(expr.eval(&arrays)?.into_dimensionality::<ndarray::Ix2>()?)
+    }
+
+    /// Evaluate an expression at the grid cell nearest to a point
+    pub fn get_expr_value(&self, expr: &Expr, x: f64, y: f64) -> anyhow::Result<Option<f64>> {
+        let mut values = HashMap::new();
+        for name in expr.variables() {
+            match self.get_value(name, x, y)? {
+                Some(v) => values.insert(name, v),
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(expr.eval_with(&|name| values[name])))
+    }
 }
 
+/// Times along a CF time coordinate in a file, or None if it doesn't have one
+pub fn read_times<S: GridSource + ?Sized>(source: &S, time_name: &str) -> anyhow::Result<Option<Vec<i64>>> {
+    if source.dimensions(time_name).is_none() {
+        return Ok(None);
+    }
+    let units = match source.attribute(time_name, "units") {
+        Some(AttrValue::Str(units)) => TimeUnits::parse(&units)?,
+        _ => return Err(anyhow!("Time coordinate {} has no units", time_name)),
+    };
+    let values = source.read_all(time_name)?;
+    Ok(Some(values.iter().map(|v| units.to_time(*v)).collect()))
+}
+
+// The CF _FillValue or missing_value of a variable
+fn get_fill_value(source: &impl GridSource, var_name: &str) -> Option<f64> {
+    ["_FillValue", "missing_value"]
+        .iter()
+        .find_map(|name| source.attribute(var_name, name)?.as_f64())
+}
 
 #[cfg(test)]
 mod dataset_test {
     use super::*;
-    use std::path::Path;
+    use ndarray::{ArrayD, Dimension, IxDyn, Slice};
+
+    struct TestVariable {
+        name: String,
+        dims: Vec<(String, usize)>,
+        attributes: Vec<(String, AttrValue)>,
+        values: ArrayD<f64>,
+    }
+
+    // An in-memory source of named variables with dimensions and attributes
+    #[derive(Default)]
+    struct TestSource {
+        vars: Vec<TestVariable>,
+    }
+
+    impl TestSource {
+        fn add(mut self, name: &str, dims: &[(&str, usize)], attributes: &[(&str, &str)]) -> Self {
+            let dims: Vec<(String, usize)> = dims.iter().map(|(dim, len)| (dim.to_string(), *len)).collect();
+            let shape: Vec<usize> = dims.iter().map(|(_, len)| *len).collect();
+            let values = ArrayD::from_shape_fn(IxDyn(&shape), |index| index.as_array_view().sum() as f64);
+            let attributes = attributes.iter().map(|(k, v)| (k.to_string(), AttrValue::Str(v.to_string()))).collect();
+            self.vars.push(TestVariable { name: name.to_string(), dims, attributes, values });
+            self
+        }
+
+        fn var(&self, name: &str) -> Option<&TestVariable> {
+            self.vars.iter().find(|var| var.name == name)
+        }
+    }
+
+    impl GridSource for TestSource {
+        fn variables(&self) -> Vec<String> {
+            self.vars.iter().map(|var| var.name.clone()).collect()
+        }
+
+        fn dimensions(&self, var: &str) -> Option<Vec<(String, usize)>> {
+            self.var(var).map(|var| var.dims.clone())
+        }
+
+        fn attributes(&self, var: &str) -> Vec<(String, AttrValue)> {
+            self.var(var).map(|var| var.attributes.clone()).unwrap_or_default()
+        }
+
+        fn read(&self, var: &str, start: &[usize], count: &[usize]) -> anyhow::Result<ArrayD<f64>> {
+            let values = &self.var(var).ok_or_else(|| anyhow!("No variable {}", var))?.values;
+            let range = |axis: usize| start[axis]..start[axis] + count[axis];
+            Ok(values.slice_each_axis(|ax| Slice::from(range(ax.axis.0))).to_owned())
+        }
+    }
+
+    const LCC: [(&str, &str); 1] = [("proj4", "+proj=lcc +lat_1=30 +lat_2=60 +lat_0=45 +lon_0=-100 +ellps=WGS84")];
+
+    #[test]
+    fn test_find_crs() {
+        let projected = |data_mapping: &str| {
+            TestSource::default()
+                .add("rotated", &[], &[("grid_mapping_name", "rotated_latitude_longitude")])
+                .add("lambert", &[], &LCC)
+                .add("y", &[("y", 3)], &[("standard_name", "projection_y_coordinate"), ("units", "km")])
+                .add("x", &[("x", 4)], &[("standard_name", "projection_x_coordinate"), ("units", "km")])
+                .add("chl", &[("y", 3), ("x", 4)], &[("grid_mapping", data_mapping)])
+        };
+        // The data variable's mapping, not the first one in the file
+        let dset = Dataset::from_source(projected("lambert"), "lat", "lon").unwrap();
+        assert_eq!((dset.lat_name(), dset.lon_name()), ("y", "x"));
+        assert!(dset.crs().is_some());
+        let dset = Dataset::from_source(projected("lambert: x y"), "lat", "lon").unwrap();
+        assert!(dset.crs().is_some());
+        // Unsupported mappings read as plain grids rather than failing
+        let dset = Dataset::from_source(projected("rotated"), "lat", "lon").unwrap();
+        assert!(dset.crs().is_none());
+
+        // A mapping alongside lat/lon coordinates doesn't apply to them
+        let source = TestSource::default()
+            .add("lat", &[("lat", 3)], &[("standard_name", "latitude")])
+            .add("lon", &[("lon", 4)], &[("standard_name", "longitude")])
+            .add("lambert", &[], &LCC)
+            .add("chl", &[("lat", 3), ("lon", 4)], &[("grid_mapping", "lambert")]);
+        let dset = Dataset::from_source(source, "lat", "lon").unwrap();
+        assert!(dset.crs().is_none());
+    }
+
+    #[test]
+    fn test_time_dimension() {
+        let source = TestSource::default()
+            .add("lat", &[("lat", 3)], &[])
+            .add("lon", &[("lon", 4)], &[])
+            .add("chl", &[("time", 2), ("lat", 3), ("lon", 4)], &[])
+            .add("sst", &[("depth", 3), ("lat", 3), ("lon", 4)], &[("units", "degC")])
+            .add("temp", &[("time", 2), ("depth", 3), ("lat", 3), ("lon", 4)], &[]);
+        let mut dset = Dataset::from_source(source, "lat", "lon").unwrap();
+        dset.time_index = 1;
+
+        // Values are the sum of their indices, so the time step adds 1 and other dimensions read index 0
+        assert_eq!(dset.get_value("chl", 2.0, 1.0).unwrap(), Some(4.0));
+        assert_eq!(dset.get_value("sst", 2.0, 1.0).unwrap(), Some(3.0));
+        assert_eq!(dset.get_value("temp", 2.0, 1.0).unwrap(), Some(4.0));
+        let values = dset.get_values("temp", Bounds::new(0.0, 0.0, 2.0, 2.0)).unwrap();
+        assert_eq!(values.shape(), &[2, 2]);
+        assert_eq!(values[[1, 1]], 3.0);
+        let columns = dset.get_columns("temp", &[(2.0, 1.0)]).unwrap();
+        assert_eq!(columns.column(0).to_vec(), vec![4.0, 5.0, 6.0]);
+
+        // A time step along another dimension, e.g. a climatology's month
+        dset.time_dim = "month".to_string();
+        assert_eq!(dset.get_value("chl", 2.0, 1.0).unwrap(), Some(3.0));
+    }
+
+    #[test]
+    fn test_2d_coordinates() {
+        let source = TestSource::default()
+            .add("lat", &[("y", 3), ("x", 4)], &[])
+            .add("lon", &[("y", 3), ("x", 4)], &[])
+            .add("chl", &[("y", 3), ("x", 4)], &[]);
+        let err = Dataset::from_source(source, "lat", "lon").err().unwrap();
+        assert!(err.to_string().contains("only 1-D coordinates"), "{}", err);
+    }
 
     // #[test]
     // fn test_dset_bounds() {
@@ -0,0 +1,652 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// A parsed band-math expression such as `sqrt(u*u + v*v)` or `where(sst > 273.15, sst - 273.15, nan)`.
+///
+/// Identifiers are dataset variable names, except for the constants `pi`, `e` and `nan`.
+/// Comparisons and logical operators return 1.0 or 0.0, and NaN propagates through everything
+/// so missing data stays missing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Variable(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Log10,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+    Hypot,
+    Floor,
+    Ceil,
+    Round,
+    Pow,
+    Min,
+    Max,
+    IsNan,
+    Where,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        use Function::*;
+
+        let f = match name {
+            "abs" => Abs,
+            "sqrt" => Sqrt,
+            "exp" => Exp,
+            "ln" | "log" => Ln,
+            "log10" => Log10,
+            "sin" => Sin,
+            "cos" => Cos,
+            "tan" => Tan,
+            "asin" => Asin,
+            "acos" => Acos,
+            "atan" => Atan,
+            "atan2" => Atan2,
+            "hypot" => Hypot,
+            "floor" => Floor,
+            "ceil" => Ceil,
+            "round" => Round,
+            "pow" => Pow,
+            "min" => Min,
+            "max" => Max,
+            "isnan" => IsNan,
+            "where" => Where,
+            _ => return None,
+        };
+        Some(f)
+    }
+
+    // Allowed number of arguments, as (min, max)
+    fn arity(&self) -> (usize, usize) {
+        use Function::*;
+
+        match self {
+            Atan2 | Hypot | Pow => (2, 2),
+            Min | Max => (2, usize::MAX),
+            Where => (3, 3),
+            _ => (1, 1),
+        }
+    }
+
+    fn apply(&self, args: &[f64]) -> f64 {
+        use Function::*;
+
+        let a = args[0];
+        match self {
+            Abs => a.abs(),
+            Sqrt => a.sqrt(),
+            Exp => a.exp(),
+            Ln => a.ln(),
+            Log10 => a.log10(),
+            Sin => a.sin(),
+            Cos => a.cos(),
+            Tan => a.tan(),
+            Asin => a.asin(),
+            Acos => a.acos(),
+            Atan => a.atan(),
+            Atan2 => a.atan2(args[1]),
+            Hypot => a.hypot(args[1]),
+            Floor => a.floor(),
+            Ceil => a.ceil(),
+            Round => a.round(),
+            Pow => a.powf(args[1]),
+            // f64::min ignores NaN, but missing data should stay missing
+            Min => args.iter().copied().fold(a, |m, v| if m.is_nan() || v.is_nan() { f64::NAN } else { m.min(v) }),
+            Max => args.iter().copied().fold(a, |m, v| if m.is_nan() || v.is_nan() { f64::NAN } else { m.max(v) }),
+            IsNan => bool_to_f64(a.is_nan()),
+            Where => {
+                if a.is_nan() {
+                    f64::NAN
+                } else if a != 0.0 {
+                    args[1]
+                } else {
+                    args[2]
+                }
+            }
+        }
+    }
+}
+
+fn bool_to_f64(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+impl BinaryOp {
+    fn apply(&self, a: f64, b: f64) -> f64 {
+        use BinaryOp::*;
+
+        if (a.is_nan() || b.is_nan()) && !matches!(self, Add | Sub | Mul | Div | Rem | Pow) {
+            return f64::NAN;
+        }
+        match self {
+            Add => a + b,
+            Sub => a - b,
+            Mul => a * b,
+            Div => a / b,
+            Rem => a % b,
+            Pow => a.powf(b),
+            Lt => bool_to_f64(a < b),
+            Le => bool_to_f64(a <= b),
+            Gt => bool_to_f64(a > b),
+            Ge => bool_to_f64(a >= b),
+            Eq => bool_to_f64(a == b),
+            Ne => bool_to_f64(a != b),
+            And => bool_to_f64(a != 0.0 && b != 0.0),
+            Or => bool_to_f64(a != 0.0 || b != 0.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset into the expression where the error was found
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Limits that keep parsing and evaluating from recursing deep enough to overflow the stack. Expressions are ASCII,
+// so the length is in characters.
+const MAX_LENGTH: usize = 1000;
+const MAX_DEPTH: usize = 32;
+
+impl Expr {
+    /// Parse an expression of at most 1000 characters, with brackets, calls and unary operators nested at most
+    /// 32 deep
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        if input.len() > MAX_LENGTH {
+            return Err(ParseError {
+                position: MAX_LENGTH,
+                message: format!("Expression is longer than {} characters", MAX_LENGTH),
+            });
+        }
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: input.len(),
+            depth: 0,
+        };
+        let expr = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(_) => Err(parser.error("Unexpected token")),
+        }
+    }
+
+    /// An expression that just reads one variable
+    pub fn variable(name: &str) -> Self {
+        Expr::Variable(name.to_string())
+    }
+
+    /// Names of the dataset variables used by the expression, without duplicates
+    pub fn variables(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        self.collect_variables(&mut names);
+        names
+    }
+
+    fn collect_variables<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Variable(name) => {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+            Expr::Unary(_, e) => e.collect_variables(names),
+            Expr::Binary(_, a, b) => {
+                a.collect_variables(names);
+                b.collect_variables(names);
+            }
+            Expr::Call(_, args) => args.iter().for_each(|a| a.collect_variables(names)),
+        }
+    }
+
+    /// Evaluate for a single cell, looking up variable values with `lookup`
+    pub fn eval_with(&self, lookup: &dyn Fn(&str) -> f64) -> f64 {
+        match self {
+            Expr::Number(v) => *v,
+            Expr::Variable(name) => lookup(name),
+            Expr::Unary(UnaryOp::Neg, e) => -e.eval_with(lookup),
+            Expr::Unary(UnaryOp::Not, e) => {
+                let v = e.eval_with(lookup);
+                if v.is_nan() {
+                    f64::NAN
+                } else {
+                    bool_to_f64(v == 0.0)
+                }
+            }
+            Expr::Binary(op, a, b) => op.apply(a.eval_with(lookup), b.eval_with(lookup)),
+            Expr::Call(f, args) => {
+                let args: Vec<f64> = args.iter().map(|a| a.eval_with(lookup)).collect();
+                f.apply(&args)
+            }
+        }
+    }
+
+    /// Evaluate over arrays of the same shape, one per variable
+    pub fn eval(&self, arrays: &HashMap<&str, ndarray::ArrayD<f64>>) -> anyhow::Result<ndarray::ArrayD<f64>> {
+        let shape = match arrays.values().next() {
+            Some(a) => a.raw_dim(),
+            None => ndarray::IxDyn(&[]),
+        };
+        for (name, a) in arrays {
+            if a.raw_dim() != shape {
+                return Err(anyhow::anyhow!("Variable {} has shape {:?}, expected {:?}", name, a.shape(), shape));
+            }
+        }
+        for name in self.variables() {
+            if !arrays.contains_key(name) {
+                return Err(anyhow::anyhow!("No values for variable {}", name));
+            }
+        }
+
+        Ok(ndarray::ArrayD::from_shape_fn(shape, |idx| {
+            self.eval_with(&|name| arrays[name][&idx])
+        }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+// Longest operators first so `<=` is not read as `<`
+const OPERATORS: [&str; 16] = [
+    "**", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "^", "<", ">", "!",
+];
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let bytes = input.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i] as char;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                i += 1;
+            }
+            // Exponent, e.g. 1e-3
+            if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+                let mut j = i + 1;
+                if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-') {
+                    j += 1;
+                }
+                if j < bytes.len() && bytes[j].is_ascii_digit() {
+                    i = j;
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let value = input[start..i].parse().map_err(|_| ParseError {
+                position: start,
+                message: format!("Invalid number '{}'", &input[start..i]),
+            })?;
+            tokens.push((start, Token::Number(value)));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            tokens.push((start, Token::Ident(input[start..i].to_string())));
+        } else if c == '(' {
+            tokens.push((i, Token::LParen));
+            i += 1;
+        } else if c == ')' {
+            tokens.push((i, Token::RParen));
+            i += 1;
+        } else if c == ',' {
+            tokens.push((i, Token::Comma));
+            i += 1;
+        } else if let Some(op) = OPERATORS.iter().find(|op| input[i..].starts_with(**op)) {
+            tokens.push((i, Token::Op(op)));
+            i += op.len();
+        } else {
+            return Err(ParseError {
+                position: i,
+                message: format!("Unexpected character '{}'", input[i..].chars().next().unwrap_or(c)),
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    // Length of the input, for errors at the end
+    end: usize,
+    // Brackets, calls and unary operators around the current token
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, t)| t.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        let position = self.tokens.get(self.pos).map(|(p, _)| *p).unwrap_or(self.end);
+        let message = match self.peek() {
+            None => format!("{}: unexpected end of expression", message),
+            Some(_) => message.to_string(),
+        };
+        ParseError { position, message }
+    }
+
+    // Parse one level further in, or fail past the nesting limit
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Expr, ParseError>) -> Result<Expr, ParseError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("Expression is nested too deeply"));
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    // Consume the operator if it is next
+    fn eat_op(&mut self, ops: &[&str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_and()?;
+        while self.eat_op(&["||"]).is_some() {
+            let rhs = self.parse_and()?;
+            lhs = Expr::Binary(BinaryOp::Or, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_comparison()?;
+        while self.eat_op(&["&&"]).is_some() {
+            let rhs = self.parse_comparison()?;
+            lhs = Expr::Binary(BinaryOp::And, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
+        let lhs = self.parse_additive()?;
+        let op = match self.eat_op(&["<", "<=", ">", ">=", "==", "!="]) {
+            Some("<") => BinaryOp::Lt,
+            Some("<=") => BinaryOp::Le,
+            Some(">") => BinaryOp::Gt,
+            Some(">=") => BinaryOp::Ge,
+            Some("==") => BinaryOp::Eq,
+            Some(_) => BinaryOp::Ne,
+            None => return Ok(lhs),
+        };
+        let rhs = self.parse_additive()?;
+        Ok(Expr::Binary(op, Box::new(lhs), Box::new(rhs)))
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_multiplicative()?;
+        while let Some(op) = self.eat_op(&["+", "-"]) {
+            let op = if op == "+" { BinaryOp::Add } else { BinaryOp::Sub };
+            let rhs = self.parse_multiplicative()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(op) = self.eat_op(&["*", "/", "%"]) {
+            let op = match op {
+                "*" => BinaryOp::Mul,
+                "/" => BinaryOp::Div,
+                _ => BinaryOp::Rem,
+            };
+            let rhs = self.parse_unary()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        match self.eat_op(&["-", "!"]) {
+            Some("-") => Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.nested(Self::parse_unary)?))),
+            Some(_) => Ok(Expr::Unary(UnaryOp::Not, Box::new(self.nested(Self::parse_unary)?))),
+            None => self.parse_power(),
+        }
+    }
+
+    // Right associative, and binds tighter than unary minus on its left: -2^2 == -4
+    fn parse_power(&mut self) -> Result<Expr, ParseError> {
+        let base = self.parse_atom()?;
+        if self.eat_op(&["^", "**"]).is_some() {
+            let exponent = self.nested(Self::parse_unary)?;
+            return Ok(Expr::Binary(BinaryOp::Pow, Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, ParseError> {
+        let start = self.pos;
+        match self.next() {
+            Some(Token::Number(v)) => Ok(Expr::Number(v)),
+            Some(Token::LParen) => {
+                let expr = self.nested(Self::parse_or)?;
+                self.expect_rparen()?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                if self.peek() == Some(&Token::LParen) {
+                    self.pos += 1;
+                    return self.parse_call(start, &name);
+                }
+                Ok(match name.as_str() {
+                    "pi" => Expr::Number(std::f64::consts::PI),
+                    "e" => Expr::Number(std::f64::consts::E),
+                    "nan" => Expr::Number(f64::NAN),
+                    _ => Expr::Variable(name),
+                })
+            }
+            _ => {
+                self.pos = start;
+                Err(self.error("Expected a number, variable or '('"))
+            }
+        }
+    }
+
+    fn parse_call(&mut self, start: usize, name: &str) -> Result<Expr, ParseError> {
+        let position = self.tokens[start].0;
+        let function = Function::from_name(name).ok_or_else(|| ParseError {
+            position,
+            message: format!("Unknown function '{}'", name),
+        })?;
+
+        let mut args = Vec::new();
+        if self.peek() != Some(&Token::RParen) {
+            loop {
+                args.push(self.nested(Self::parse_or)?);
+                if self.peek() == Some(&Token::Comma) {
+                    self.pos += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect_rparen()?;
+
+        let (min_args, max_args) = function.arity();
+        if args.len() < min_args || args.len() > max_args {
+            return Err(ParseError {
+                position,
+                message: format!("Wrong number of arguments to '{}'", name),
+            });
+        }
+        Ok(Expr::Call(function, args))
+    }
+
+    fn expect_rparen(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(Token::RParen) => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error("Expected ')'")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod expr_tests {
+    use super::*;
+
+    fn eval(input: &str, vars: &[(&str, f64)]) -> f64 {
+        let expr = Expr::parse(input).unwrap();
+        expr.eval_with(&|name| vars.iter().find(|(n, _)| *n == name).unwrap().1)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(eval("1 + 2 * 3", &[]), 7.0);
+        assert_eq!(eval("(1 + 2) * 3", &[]), 9.0);
+        assert_eq!(eval("2 ^ 3 ^ 2", &[]), 512.0);
+        assert_eq!(eval("-2 ** 2", &[]), -4.0);
+        assert_eq!(eval("7 % 4 - 1.5e1", &[]), -12.0);
+        assert_eq!(eval("sst - 273.15", &[("sst", 283.15)]), 10.0);
+        assert_eq!(eval("sqrt(u*u + v*v)", &[("u", 3.0), ("v", 4.0)]), 5.0);
+        assert_relative_eq!(eval("cos(pi)", &[]), -1.0);
+    }
+
+    #[test]
+    fn test_comparisons_and_where() {
+        assert_eq!(eval("1 < 2 && 2 <= 2", &[]), 1.0);
+        assert_eq!(eval("1 > 2 || !(1 == 1)", &[]), 0.0);
+        assert_eq!(eval("where(x > 0, x, -x)", &[("x", -3.0)]), 3.0);
+        assert_eq!(eval("max(1, x, 3)", &[("x", 5.0)]), 5.0);
+        assert!(eval("where(x > 0, 1, 0)", &[("x", f64::NAN)]).is_nan());
+        assert!(eval("min(x, 1)", &[("x", f64::NAN)]).is_nan());
+        assert_eq!(eval("isnan(x)", &[("x", f64::NAN)]), 1.0);
+    }
+
+    #[test]
+    fn test_variables() {
+        let expr = Expr::parse("chl_conc / chl_conc_climatology + chl_conc").unwrap();
+        assert_eq!(expr.variables(), vec!["chl_conc", "chl_conc_climatology"]);
+
+        let expr = Expr::parse("pi * e").unwrap();
+        assert!(expr.variables().is_empty());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Expr::parse("1 +").unwrap_err().position, 3);
+        assert_eq!(Expr::parse("(1 + 2").unwrap_err().position, 6);
+        assert_eq!(Expr::parse("foo(1)").unwrap_err().position, 0);
+        assert_eq!(Expr::parse("1 $ 2").unwrap_err().position, 2);
+        assert_eq!(Expr::parse("1 2").unwrap_err().position, 2);
+        assert!(Expr::parse("atan2(1)").is_err());
+        assert!(Expr::parse("where(1, 2)").is_err());
+
+        // Nesting and length limits
+        let nested = |open: &str, close: &str, depth: usize| format!("{}x{}", open.repeat(depth), close.repeat(depth));
+        assert!(Expr::parse(&nested("(", ")", MAX_DEPTH)).is_ok());
+        for (open, close) in [("(", ")"), ("abs(", ")"), ("-", ""), ("!", ""), ("2^", "")] {
+            let err = Expr::parse(&nested(open, close, MAX_DEPTH + 1)).unwrap_err();
+            assert!(err.message.contains("nested too deeply"), "{}: {}", open, err);
+        }
+        assert!(Expr::parse(&nested("(", ")", 100_000)).is_err());
+        let sum = vec!["1"; MAX_LENGTH / 2].join("+");
+        assert_eq!(Expr::parse(&sum).unwrap().eval_with(&|_| 0.0), (MAX_LENGTH / 2) as f64);
+        let err = Expr::parse(&format!("{}+1", sum)).unwrap_err();
+        assert_eq!(err.position, MAX_LENGTH);
+    }
+
+    #[test]
+    fn test_eval_arrays() {
+        let u = ndarray::arr2(&[[3.0, 0.0], [1.0, f64::NAN]]).into_dyn();
+        let v = ndarray::arr2(&[[4.0, 2.0], [0.0, 1.0]]).into_dyn();
+        let arrays = HashMap::from([("u", u), ("v", v)]);
+
+        let result = Expr::parse("hypot(u, v)").unwrap().eval(&arrays).unwrap();
+        assert_eq!(result[[0, 0]], 5.0);
+        assert_eq!(result[[0, 1]], 2.0);
+        assert_eq!(result[[1, 0]], 1.0);
+        assert!(result[[1, 1]].is_nan());
+
+        assert!(Expr::parse("w").unwrap().eval(&arrays).is_err());
+    }
+}
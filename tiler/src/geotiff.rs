@@ -0,0 +1,1261 @@
+//! GeoTIFF and Cloud-Optimized GeoTIFF sources, read from local files or with HTTP range requests.
+//! Each band is a variable on 1-D coordinates computed from the geotransform, see
+//! https://docs.ogc.org/is/19-008r4/19-008r4.html
+
+use crate::http::HttpRange;
+use crate::source::{AttrValue, GridSource};
+use crate::zarr::{self, DataType, NumberKind};
+use anyhow::anyhow;
+use ndarray::{Array1, Array2, ArrayD};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Mutex;
+
+// Read up front in one go, which covers the headers of a Cloud-Optimized GeoTIFF
+const HEAD_LEN: usize = 64 * 1024;
+// Guards against IFD chains that loop
+const MAX_IFDS: usize = 64;
+// Guard against corrupt counts, which BigTIFF stores in 64 bits: the most entries in an IFD, as classic TIFF allows,
+// and the largest tag value, which leaves room for the tile offsets of very large images
+const MAX_IFD_ENTRIES: usize = u16::MAX as usize;
+const MAX_TAG_BYTES: usize = 256 * 1024 * 1024;
+
+// Baseline TIFF tags
+const NEW_SUBFILE_TYPE: u16 = 254;
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const PLANAR_CONFIGURATION: u16 = 284;
+const PREDICTOR: u16 = 317;
+const TILE_WIDTH: u16 = 322;
+const TILE_LENGTH: u16 = 323;
+const TILE_OFFSETS: u16 = 324;
+const TILE_BYTE_COUNTS: u16 = 325;
+const SAMPLE_FORMAT: u16 = 339;
+// GeoTIFF and GDAL tags
+const MODEL_PIXEL_SCALE: u16 = 33550;
+const MODEL_TIEPOINT: u16 = 33922;
+const MODEL_TRANSFORMATION: u16 = 34264;
+const GEO_KEY_DIRECTORY: u16 = 34735;
+const GDAL_NODATA: u16 = 42113;
+
+// The only tags read, so remote files aren't asked for the values of any others
+const TAGS: [u16; 21] = [
+    NEW_SUBFILE_TYPE,
+    IMAGE_WIDTH,
+    IMAGE_LENGTH,
+    BITS_PER_SAMPLE,
+    COMPRESSION,
+    STRIP_OFFSETS,
+    SAMPLES_PER_PIXEL,
+    ROWS_PER_STRIP,
+    STRIP_BYTE_COUNTS,
+    PLANAR_CONFIGURATION,
+    PREDICTOR,
+    TILE_WIDTH,
+    TILE_LENGTH,
+    TILE_OFFSETS,
+    TILE_BYTE_COUNTS,
+    SAMPLE_FORMAT,
+    MODEL_PIXEL_SCALE,
+    MODEL_TIEPOINT,
+    MODEL_TRANSFORMATION,
+    GEO_KEY_DIRECTORY,
+    GDAL_NODATA,
+];
+
+// GeoKeys
+const GT_MODEL_TYPE: u16 = 1024;
+const GT_RASTER_TYPE: u16 = 1025;
+const PROJECTED_CS_TYPE: u16 = 3072;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+const MODEL_TYPE_PROJECTED: u16 = 1;
+const USER_DEFINED: u16 = 32767;
+
+type Tags = HashMap<u16, AttrValue>;
+
+fn number(tags: &Tags, tag: u16) -> Option<f64> {
+    tags.get(&tag).and_then(|value| value.as_f64())
+}
+
+fn numbers(tags: &Tags, tag: u16) -> Option<&[f64]> {
+    tags.get(&tag).and_then(|value| value.as_f64s())
+}
+
+fn read_uint(bytes: &[u8], little_endian: bool) -> u64 {
+    let fold = |acc: u64, b: &u8| (acc << 8) | *b as u64;
+    match little_endian {
+        true => bytes.iter().rev().fold(0, fold),
+        false => bytes.iter().fold(0, fold),
+    }
+}
+
+fn write_uint(bytes: &mut [u8], value: u64, little_endian: bool) {
+    let len = bytes.len();
+    for (i, b) in bytes.iter_mut().enumerate() {
+        let shift = match little_endian {
+            true => 8 * i,
+            false => 8 * (len - 1 - i),
+        };
+        *b = (value >> shift) as u8;
+    }
+}
+
+enum Bytes {
+    File(Mutex<File>),
+    Http(HttpRange),
+}
+
+// Byte order aware reads, served from the head of the file when they fall in it
+struct Reader {
+    bytes: Bytes,
+    head: Vec<u8>,
+    little_endian: bool,
+    big_tiff: bool,
+}
+
+impl Reader {
+    fn read(&self, offset: u64, len: usize) -> anyhow::Result<Vec<u8>> {
+        if let Some(bytes) = self.head.get(offset as usize..(offset as usize).saturating_add(len)) {
+            return Ok(bytes.to_vec());
+        }
+        let bytes = match &self.bytes {
+            Bytes::File(file) => {
+                let mut file = file.lock().map_err(|_| anyhow!("GeoTIFF file lock poisoned"))?;
+                file.seek(SeekFrom::Start(offset))?;
+                zarr::read_all((&mut *file).take(len as u64))?
+            }
+            Bytes::Http(http) => http.read(offset, len)?,
+        };
+        match bytes.len() == len {
+            true => Ok(bytes),
+            false => Err(anyhow!("Truncated GeoTIFF, expected {} bytes at {}", len, offset)),
+        }
+    }
+
+    fn uint(&self, bytes: &[u8]) -> u64 {
+        read_uint(bytes, self.little_endian)
+    }
+
+    // An IFD's tags, and the offset of the next IFD
+    fn read_ifd(&self, offset: u64) -> anyhow::Result<(Tags, u64)> {
+        let (count_len, entry_len, offset_len) = match self.big_tiff {
+            true => (8, 20, 8),
+            false => (2, 12, 4),
+        };
+        let count = self.uint(&self.read(offset, count_len)?);
+        let entries_len = usize::try_from(count)
+            .ok()
+            .filter(|count| *count <= MAX_IFD_ENTRIES)
+            .and_then(|count| count.checked_mul(entry_len))
+            .ok_or_else(|| anyhow!("Invalid GeoTIFF, IFD at {} has {} entries", offset, count))?;
+        let entries = self.read(offset + count_len as u64, entries_len + offset_len)?;
+
+        let mut tags = Tags::new();
+        for entry in entries.chunks_exact(entry_len) {
+            let tag = self.uint(&entry[..2]) as u16;
+            let field_type = self.uint(&entry[2..4]) as u16;
+            let (len, field) = match self.big_tiff {
+                true => (self.uint(&entry[4..12]) as usize, &entry[12..20]),
+                false => (self.uint(&entry[4..8]) as usize, &entry[8..12]),
+            };
+            let Some(size) = field_size(field_type) else { continue };
+            if !TAGS.contains(&tag) {
+                continue;
+            }
+            let data_len = len
+                .checked_mul(size)
+                .filter(|data_len| *data_len <= MAX_TAG_BYTES)
+                .ok_or_else(|| anyhow!("Invalid GeoTIFF, tag {} has {} values", tag, len))?;
+            let data = match data_len <= field.len() {
+                true => field[..data_len].to_vec(),
+                false => self.read(self.uint(field), data_len)?,
+            };
+            tags.insert(tag, self.field_value(field_type, size, &data));
+        }
+        let next = self.uint(&entries[entries_len..]);
+        Ok((tags, next))
+    }
+
+    fn field_value(&self, field_type: u16, size: usize, data: &[u8]) -> AttrValue {
+        if field_type == 2 {
+            let text = String::from_utf8_lossy(data);
+            return AttrValue::Str(text.trim_end_matches('\0').to_string());
+        }
+        let values = data.chunks_exact(size).map(|b| {
+            let half = |b: &[u8]| self.uint(b) as u32;
+            match field_type {
+                5 => half(&b[..4]) as f64 / half(&b[4..]) as f64,
+                10 => half(&b[..4]) as i32 as f64 / half(&b[4..]) as i32 as f64,
+                6 => self.uint(b) as u8 as i8 as f64,
+                8 => self.uint(b) as u16 as i16 as f64,
+                9 => self.uint(b) as u32 as i32 as f64,
+                17 => self.uint(b) as i64 as f64,
+                11 => f32::from_bits(self.uint(b) as u32) as f64,
+                12 => f64::from_bits(self.uint(b)),
+                _ => self.uint(b) as f64,
+            }
+        });
+        AttrValue::Numbers(values.collect())
+    }
+}
+
+// Bytes per value of a TIFF field type, or None for types that aren't known
+fn field_size(field_type: u16) -> Option<usize> {
+    match field_type {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 | 16 | 17 | 18 => Some(8),
+        _ => None,
+    }
+}
+
+/// One image of the file, the full resolution one or an overview, in tiles or strips
+#[derive(Debug, Clone)]
+struct Image {
+    width: usize,
+    height: usize,
+    /// Strips are blocks as wide as the image
+    block_width: usize,
+    block_height: usize,
+    offsets: Vec<u64>,
+    byte_counts: Vec<u64>,
+    compression: u16,
+    predictor: u16,
+    samples: usize,
+    /// Each band in its own blocks, rather than interleaved
+    planar: bool,
+    data_type: DataType,
+}
+
+impl Image {
+    fn from_tags(tags: &Tags, little_endian: bool) -> anyhow::Result<Self> {
+        let required = |tag: u16| number(tags, tag).ok_or_else(|| anyhow!("GeoTIFF image has no tag {}", tag));
+        let width = required(IMAGE_WIDTH)? as usize;
+        let height = required(IMAGE_LENGTH)? as usize;
+        let (block_width, block_height, offsets, byte_counts) = match number(tags, TILE_WIDTH) {
+            Some(tile_width) => (
+                tile_width as usize,
+                required(TILE_LENGTH)? as usize,
+                TILE_OFFSETS,
+                TILE_BYTE_COUNTS,
+            ),
+            None => (
+                width,
+                number(tags, ROWS_PER_STRIP).map_or(height, |rows| (rows as usize).min(height)),
+                STRIP_OFFSETS,
+                STRIP_BYTE_COUNTS,
+            ),
+        };
+        let list = |tag: u16| -> anyhow::Result<Vec<u64>> {
+            let values = numbers(tags, tag).ok_or_else(|| anyhow!("GeoTIFF image has no tag {}", tag))?;
+            Ok(values.iter().map(|v| *v as u64).collect())
+        };
+
+        let bits = number(tags, BITS_PER_SAMPLE).unwrap_or(1.0) as usize;
+        let kind = match number(tags, SAMPLE_FORMAT).unwrap_or(1.0) as u16 {
+            1 => NumberKind::Uint,
+            2 => NumberKind::Int,
+            3 => NumberKind::Float,
+            format => return Err(anyhow!("Unsupported GeoTIFF sample format {}", format)),
+        };
+        let data_type = match bits % 8 {
+            0 => DataType::new(kind, bits / 8, !little_endian),
+            _ => None,
+        };
+        let data_type = data_type.ok_or_else(|| anyhow!("Unsupported GeoTIFF sample of {} bits", bits))?;
+        let samples = number(tags, SAMPLES_PER_PIXEL).unwrap_or(1.0) as usize;
+        // Blocks and pixels are counted by dividing by these
+        if width == 0 || height == 0 || block_width == 0 || block_height == 0 || samples == 0 {
+            return Err(anyhow!(
+                "GeoTIFF image is {} x {} in {} x {} blocks of {} samples, and none can be zero",
+                width,
+                height,
+                block_width,
+                block_height,
+                samples
+            ));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            block_width,
+            block_height,
+            offsets: list(offsets)?,
+            byte_counts: list(byte_counts)?,
+            compression: number(tags, COMPRESSION).unwrap_or(1.0) as u16,
+            predictor: number(tags, PREDICTOR).unwrap_or(1.0) as u16,
+            samples,
+            planar: number(tags, PLANAR_CONFIGURATION) == Some(2.0),
+            data_type,
+        })
+    }
+
+    // Samples per pixel in a block
+    fn block_samples(&self) -> usize {
+        if self.planar {
+            1
+        } else {
+            self.samples
+        }
+    }
+}
+
+/// A GeoTIFF on a regular grid, in lat/lon or a projection with a known EPSG code
+pub struct GeoTiff {
+    reader: Reader,
+    /// The full resolution image, then its overviews from finest to coarsest
+    images: Vec<Image>,
+    /// Cell centres of the rows, top first, and of the columns
+    ys: Vec<f64>,
+    xs: Vec<f64>,
+    /// A proj string for projected files
+    proj_string: Option<String>,
+    nodata: Option<f64>,
+}
+
+impl GeoTiff {
+    /// Whether a path or `http://` URL names a GeoTIFF, by its extension
+    pub fn is_geotiff(path: &Path) -> bool {
+        let name = path.to_string_lossy().to_lowercase();
+        let name = name.split(['?', '#']).next().unwrap_or("");
+        name.ends_with(".tif") || name.ends_with(".tiff")
+    }
+
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let name = path.to_string_lossy();
+        let bytes = match HttpRange::is_url(&name) {
+            true => Bytes::Http(HttpRange::new(&name)?),
+            false => Bytes::File(Mutex::new(File::open(path).map_err(|e| anyhow!("{:?}: {}", path, e))?)),
+        };
+        let mut reader = Reader {
+            bytes,
+            head: Vec::new(),
+            little_endian: true,
+            big_tiff: false,
+        };
+        reader.head = match &reader.bytes {
+            Bytes::File(file) => {
+                let mut file = file.lock().map_err(|_| anyhow!("GeoTIFF file lock poisoned"))?;
+                zarr::read_all((&mut *file).take(HEAD_LEN as u64))?
+            }
+            Bytes::Http(http) => http.read(0, HEAD_LEN)?,
+        };
+
+        reader.little_endian = match reader.head.get(..2) {
+            Some(b"II") => true,
+            Some(b"MM") => false,
+            _ => return Err(anyhow!("{:?} is not a TIFF file", path)),
+        };
+        let header = reader.read(0, 8)?;
+        reader.big_tiff = match reader.uint(&header[2..4]) {
+            42 => false,
+            43 => true,
+            _ => return Err(anyhow!("{:?} is not a TIFF file", path)),
+        };
+        let mut offset = match reader.big_tiff {
+            true => reader.uint(reader.read(8, 8)?.as_slice()),
+            false => reader.uint(&header[4..8]),
+        };
+
+        let mut ifds = Vec::new();
+        while offset != 0 && ifds.len() < MAX_IFDS {
+            let (tags, next) = reader.read_ifd(offset)?;
+            ifds.push(tags);
+            offset = next;
+        }
+        let tags = ifds.first().ok_or_else(|| anyhow!("{:?} has no images", path))?;
+        let image = Image::from_tags(tags, reader.little_endian)?;
+
+        // Reduced resolution images that aren't masks
+        let mut images: Vec<Image> = ifds[1..]
+            .iter()
+            .filter(|tags| number(tags, NEW_SUBFILE_TYPE).is_some_and(|t| t as u32 & 5 == 1))
+            .map(|tags| Image::from_tags(tags, reader.little_endian))
+            .collect::<anyhow::Result<_>>()?;
+        images.retain(|overview| overview.samples == image.samples && overview.width < image.width);
+        images.sort_by_key(|overview| std::cmp::Reverse(overview.width));
+
+        let (x0, y0, dx, dy) = geotransform(tags)?;
+        let xs = (0..image.width).map(|col| x0 + (col as f64 + 0.5) * dx).collect();
+        let ys = (0..image.height).map(|row| y0 + (row as f64 + 0.5) * dy).collect();
+
+        let nodata = tags
+            .get(&GDAL_NODATA)
+            .and_then(|value| value.as_str()?.trim().parse::<f64>().ok())
+            .filter(|v| !v.is_nan())
+            // Compared with values read at their own precision
+            .map(|v| match number(tags, SAMPLE_FORMAT) == Some(3.0) && image.data_type.size == 4 {
+                true => v as f32 as f64,
+                false => v,
+            });
+
+        images.insert(0, image);
+        Ok(Self {
+            reader,
+            images,
+            ys,
+            xs,
+            proj_string: projection(tags)?,
+            nodata,
+        })
+    }
+
+    fn coordinate_names(&self) -> (&str, &str) {
+        match self.proj_string {
+            Some(_) => ("y", "x"),
+            None => ("lat", "lon"),
+        }
+    }
+
+    // The 0-based band of a `band_<n>` variable
+    fn band(&self, var: &str) -> Option<usize> {
+        let band: usize = var.strip_prefix("band_")?.parse().ok()?;
+        (1..=self.images[0].samples).contains(&band).then(|| band - 1)
+    }
+
+    // Values of the block at an index, or None if the file leaves it out
+    fn read_block(&self, image: &Image, index: usize) -> anyhow::Result<Option<Vec<f64>>> {
+        let missing = || anyhow!("GeoTIFF has no block {}", index);
+        let offset = *image.offsets.get(index).ok_or_else(missing)?;
+        let len = *image.byte_counts.get(index).ok_or_else(missing)? as usize;
+        if offset == 0 || len == 0 {
+            return Ok(None);
+        }
+        let mut data = decompress(image.compression, self.reader.read(offset, len)?)?;
+
+        let samples = image.block_samples();
+        let size = image.data_type.size;
+        let row_len = image.block_width * samples * size;
+        // The last strip may be short
+        let rows = (data.len() / row_len).min(image.block_height);
+        data.truncate(rows * row_len);
+        let mut data_type = image.data_type;
+        for row in data.chunks_exact_mut(row_len) {
+            match image.predictor {
+                1 => {}
+                2 => undo_horizontal_predictor(row, samples, size, self.reader.little_endian),
+                3 => undo_float_predictor(row, samples, size),
+                predictor => return Err(anyhow!("Unsupported GeoTIFF predictor {}", predictor)),
+            }
+        }
+        // The floating point predictor leaves values big-endian
+        if image.predictor == 3 {
+            data_type = DataType::new(NumberKind::Float, size, true).ok_or_else(|| anyhow!("Invalid float size"))?;
+        }
+        Ok(Some(data_type.decode(&data)))
+    }
+
+    // A window of one band of an image, reading only the blocks it touches
+    fn read_window(&self, image: &Image, band: usize, start: &[usize], count: &[usize]) -> anyhow::Result<ArrayD<f64>> {
+        let (&[row0, col0], &[rows, cols]) = (start, count) else {
+            return Err(anyhow!("GeoTIFF bands are 2-D"));
+        };
+        if row0 + rows > image.height || col0 + cols > image.width {
+            return Err(anyhow!("Read past the edge of a {} x {} GeoTIFF", image.width, image.height));
+        }
+        let mut values = Array2::from_elem((rows, cols), f64::NAN);
+        if rows == 0 || cols == 0 {
+            return Ok(values.into_dyn());
+        }
+
+        let (bw, bh) = (image.block_width, image.block_height);
+        let across = image.width.div_ceil(bw);
+        let down = image.height.div_ceil(bh);
+        let samples = image.block_samples();
+        let (first_block, sample) = match image.planar {
+            true => (band * across * down, 0),
+            false => (0, band),
+        };
+        for by in row0 / bh..=(row0 + rows - 1) / bh {
+            for bx in col0 / bw..=(col0 + cols - 1) / bw {
+                let block = self.read_block(image, first_block + by * across + bx)?;
+                let row_range = (by * bh).max(row0)..((by + 1) * bh).min(row0 + rows);
+                let col_range = (bx * bw).max(col0)..((bx + 1) * bw).min(col0 + cols);
+                for row in row_range {
+                    for col in col_range.clone() {
+                        let i = ((row - by * bh) * bw + col - bx * bw) * samples + sample;
+                        values[[row - row0, col - col0]] = match &block {
+                            Some(block) => *block.get(i).ok_or_else(|| anyhow!("Truncated GeoTIFF block"))?,
+                            // Blocks left out of sparse files are nodata
+                            None => self.nodata.unwrap_or(f64::NAN),
+                        };
+                    }
+                }
+            }
+        }
+        Ok(values.into_dyn())
+    }
+}
+
+// Origin and cell size: cell centres are at `x0 + (col + 0.5) * dx` and `y0 + (row + 0.5) * dy`
+fn geotransform(tags: &Tags) -> anyhow::Result<(f64, f64, f64, f64)> {
+    let (x0, y0, dx, dy) = if let Some(m) = numbers(tags, MODEL_TRANSFORMATION).filter(|m| m.len() >= 8) {
+        if m[1] != 0.0 || m[4] != 0.0 {
+            return Err(anyhow!("Rotated GeoTIFFs aren't supported"));
+        }
+        (m[3], m[7], m[0], m[5])
+    } else {
+        let scale = numbers(tags, MODEL_PIXEL_SCALE).filter(|s| s.len() >= 2);
+        let tiepoint = numbers(tags, MODEL_TIEPOINT).filter(|t| t.len() >= 6);
+        let (Some(scale), Some(tie)) = (scale, tiepoint) else {
+            return Err(anyhow!("GeoTIFF has no geotransform"));
+        };
+        let (dx, dy) = (scale[0], -scale[1]);
+        (tie[3] - tie[0] * dx, tie[4] - tie[1] * dy, dx, dy)
+    };
+    // The transform of a PixelIsPoint raster puts the centre of cells on whole pixels
+    match geo_key(tags, GT_RASTER_TYPE) {
+        Some(RASTER_PIXEL_IS_POINT) => Ok((x0 - dx / 2.0, y0 - dy / 2.0, dx, dy)),
+        _ => Ok((x0, y0, dx, dy)),
+    }
+}
+
+// A short GeoKey, stored in the directory itself
+fn geo_key(tags: &Tags, key: u16) -> Option<u16> {
+    let directory = numbers(tags, GEO_KEY_DIRECTORY)?;
+    directory
+        .get(4..)?
+        .chunks_exact(4)
+        .find(|entry| entry[0] as u16 == key && entry[1] == 0.0)
+        .map(|entry| entry[3] as u16)
+}
+
+// The proj string of a projected file, or None for lat/lon
+fn projection(tags: &Tags) -> anyhow::Result<Option<String>> {
+    let code = geo_key(tags, PROJECTED_CS_TYPE);
+    match (code, geo_key(tags, GT_MODEL_TYPE)) {
+        (Some(USER_DEFINED), _) => Err(anyhow!("User-defined GeoTIFF projections aren't supported")),
+        (Some(code), _) => epsg_proj_string(code)
+            .map(Some)
+            .ok_or_else(|| anyhow!("Unsupported GeoTIFF projection EPSG:{}", code)),
+        (None, Some(MODEL_TYPE_PROJECTED)) => Err(anyhow!("Projected GeoTIFF has no EPSG code")),
+        (None, _) => Ok(None),
+    }
+}
+
+/// proj strings of the projected EPSG codes partner products use: web mercator, WGS 84 UTM zones,
+/// and the NSIDC polar stereographic grids
+pub fn epsg_proj_string(code: u16) -> Option<String> {
+    let wgs84 = "+ellps=WGS84 +units=m";
+    Some(match code {
+        3857 | 3785 => "+proj=merc +a=6378137 +b=6378137 +lat_ts=0 +lon_0=0 +x_0=0 +y_0=0 +k=1 +units=m".to_string(),
+        32601..=32660 => format!("+proj=utm +zone={} {}", code - 32600, wgs84),
+        32701..=32760 => format!("+proj=utm +zone={} +south {}", code - 32700, wgs84),
+        3413 => format!("+proj=stere +lat_0=90 +lat_ts=70 +lon_0=-45 +x_0=0 +y_0=0 {}", wgs84),
+        3031 => format!("+proj=stere +lat_0=-90 +lat_ts=-71 +lon_0=0 +x_0=0 +y_0=0 {}", wgs84),
+        3976 => format!("+proj=stere +lat_0=-90 +lat_ts=-70 +lon_0=0 +x_0=0 +y_0=0 {}", wgs84),
+        _ => return None,
+    })
+}
+
+fn decompress(compression: u16, bytes: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    match compression {
+        1 => Ok(bytes),
+        5 => lzw_decompress(&bytes),
+        8 | 32946 => zarr::read_all(flate2::read::ZlibDecoder::new(&bytes[..])),
+        32773 => packbits_decompress(&bytes),
+        50000 => zarr::zstd_decompress(&bytes),
+        _ => Err(anyhow!("Unsupported GeoTIFF compression {}", compression)),
+    }
+}
+
+/// Decompress TIFF LZW: codes of 9 to 12 bits, most significant bit first, which widen a code early
+fn lzw_decompress(src: &[u8]) -> anyhow::Result<Vec<u8>> {
+    const CLEAR: usize = 256;
+    const END: usize = 257;
+    let invalid = || anyhow!("Invalid LZW data");
+
+    let mut table: Vec<Vec<u8>> = (0..=255u8).map(|b| vec![b]).chain([vec![], vec![]]).collect();
+    let mut out = Vec::new();
+    let mut prev: Option<Vec<u8>> = None;
+    let (mut bits, mut bit_count, mut width) = (0u32, 0, 9);
+    let mut bytes = src.iter();
+    loop {
+        while bit_count < width {
+            let Some(b) = bytes.next() else { return Ok(out) };
+            bits = (bits << 8) | *b as u32;
+            bit_count += 8;
+        }
+        let code = ((bits >> (bit_count - width)) & ((1 << width) - 1)) as usize;
+        bit_count -= width;
+
+        if code == CLEAR {
+            table.truncate(258);
+            width = 9;
+            prev = None;
+            continue;
+        }
+        if code == END {
+            return Ok(out);
+        }
+        let entry = match (table.get(code), &prev) {
+            (Some(entry), _) => entry.clone(),
+            (None, Some(prev)) if code == table.len() => [prev.as_slice(), &prev[..1]].concat(),
+            _ => return Err(invalid()),
+        };
+        out.extend_from_slice(&entry);
+        if let Some(prev) = prev {
+            if table.len() < 4096 {
+                table.push([prev.as_slice(), &entry[..1]].concat());
+            }
+        }
+        prev = Some(entry);
+        if table.len() + 1 >= 1 << width && width < 12 {
+            width += 1;
+        }
+    }
+}
+
+/// Decompress PackBits runs
+fn packbits_decompress(src: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let truncated = || anyhow!("Truncated PackBits data");
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < src.len() {
+        let n = src[pos] as i8;
+        pos += 1;
+        match n {
+            0..=127 => {
+                let len = n as usize + 1;
+                out.extend_from_slice(src.get(pos..pos + len).ok_or_else(truncated)?);
+                pos += len;
+            }
+            -127..=-1 => {
+                let b = *src.get(pos).ok_or_else(truncated)?;
+                out.extend(std::iter::repeat_n(b, 1 + (-n) as usize));
+                pos += 1;
+            }
+            -128 => {}
+        }
+    }
+    Ok(out)
+}
+
+// Undo predictor 2: each sample is stored as the difference from the one to its left
+fn undo_horizontal_predictor(row: &mut [u8], samples: usize, size: usize, little_endian: bool) {
+    let stride = samples * size;
+    for i in (stride..row.len()).step_by(size) {
+        let left = read_uint(&row[i - stride..i - stride + size], little_endian);
+        let value = read_uint(&row[i..i + size], little_endian);
+        write_uint(&mut row[i..i + size], value.wrapping_add(left), little_endian);
+    }
+}
+
+// Undo predictor 3: byte differences over the row, with the bytes of each value split into planes
+// from most to least significant
+fn undo_float_predictor(row: &mut [u8], samples: usize, size: usize) {
+    for i in samples..row.len() {
+        row[i] = row[i].wrapping_add(row[i - samples]);
+    }
+    let planes = row.to_vec();
+    let count = row.len() / size;
+    for value in 0..count {
+        for byte in 0..size {
+            row[value * size + byte] = planes[byte * count + value];
+        }
+    }
+}
+
+// Tags only written
+const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const EXTRA_SAMPLES: u16 = 338;
+const GDAL_METADATA: u16 = 42112;
+// Bytes per strip written, before compression
+const STRIP_LEN: usize = 64 * 1024;
+
+// Text that's safe inside an XML element or attribute
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// A deflated float32 GeoTIFF of bands on a lat/lon grid, with NaN as nodata. Bands are described by their
+/// names, and their rows run north to south from `top_left`, the corner of the first cell.
+pub fn encode(bands: &[(String, Array2<f64>)], top_left: (f64, f64), cell_size: (f64, f64)) -> anyhow::Result<Vec<u8>> {
+    use std::io::Write;
+
+    let (height, width) = bands.first().map(|(_, values)| values.dim()).ok_or_else(|| anyhow!("No bands to write"))?;
+    if height == 0 || width == 0 || bands.iter().any(|(_, values)| values.dim() != (height, width)) {
+        return Err(anyhow!("GeoTIFF bands must be the same size and not empty"));
+    }
+    let samples = u16::try_from(bands.len())
+        .map_err(|_| anyhow!("{} bands is more than a GeoTIFF can hold", bands.len()))? as u64;
+    let rows_per_strip = (STRIP_LEN / (width * 4)).clamp(1, height);
+
+    let mut out = b"II\x2a\0\0\0\0\0".to_vec();
+    let (mut offsets, mut counts) = (Vec::new(), Vec::new());
+    for (_, values) in bands {
+        for strip in values.axis_chunks_iter(ndarray::Axis(0), rows_per_strip) {
+            let bytes: Vec<u8> = strip.iter().flat_map(|v| (*v as f32).to_le_bytes()).collect();
+            let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&bytes)?;
+            let strip = encoder.finish()?;
+            offsets.push(out.len() as u64);
+            counts.push(strip.len() as u64);
+            out.extend(strip);
+        }
+    }
+
+    let descriptions: String = bands
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| {
+            let name = xml_escape(name);
+            format!("<Item name=\"DESCRIPTION\" sample=\"{}\" role=\"description\">{}</Item>", i, name)
+        })
+        .collect();
+    let shorts = |values: Vec<u64>| (3, values.iter().flat_map(|v| (*v as u16).to_le_bytes()).collect::<Vec<u8>>());
+    let longs = |values: Vec<u64>| (4, values.iter().flat_map(|v| (*v as u32).to_le_bytes()).collect::<Vec<u8>>());
+    let doubles = |values: Vec<f64>| (12, values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>());
+    let ascii = |text: String| (2, text.bytes().chain([0]).collect::<Vec<u8>>());
+    let mut tags = vec![
+        (IMAGE_WIDTH, longs(vec![width as u64])),
+        (IMAGE_LENGTH, longs(vec![height as u64])),
+        (BITS_PER_SAMPLE, shorts(vec![32; samples as usize])),
+        (COMPRESSION, shorts(vec![8])),
+        (PHOTOMETRIC_INTERPRETATION, shorts(vec![1])),
+        (STRIP_OFFSETS, longs(offsets)),
+        (SAMPLES_PER_PIXEL, shorts(vec![samples])),
+        (ROWS_PER_STRIP, longs(vec![rows_per_strip as u64])),
+        (STRIP_BYTE_COUNTS, longs(counts)),
+        (PLANAR_CONFIGURATION, shorts(vec![2])),
+        (SAMPLE_FORMAT, shorts(vec![3; samples as usize])),
+        (MODEL_PIXEL_SCALE, doubles(vec![cell_size.0, cell_size.1, 0.0])),
+        (MODEL_TIEPOINT, doubles(vec![0.0, 0.0, 0.0, top_left.0, top_left.1, 0.0])),
+        // GeoTIFF 1.1, with a lat/lon model in WGS 84 and cells that are areas
+        (GEO_KEY_DIRECTORY, shorts(vec![1, 1, 1, 3, 1024, 0, 1, 2, 1025, 0, 1, 1, 2048, 0, 1, 4326])),
+        (GDAL_METADATA, ascii(format!("<GDALMetadata>{}</GDALMetadata>", descriptions))),
+        (GDAL_NODATA, ascii("nan".to_string())),
+    ];
+    if samples > 1 {
+        tags.push((EXTRA_SAMPLES, shorts(vec![0; samples as usize - 1])));
+    }
+    tags.sort_by_key(|(tag, _)| *tag);
+
+    // The IFD goes after the strips, on a word boundary, and the values that don't fit in it after that
+    if out.len() % 2 == 1 {
+        out.push(0);
+    }
+    let ifd_offset = out.len();
+    let mut values_offset = ifd_offset + 2 + tags.len() * 12 + 4;
+    let mut values = Vec::new();
+    out[4..8].copy_from_slice(&(ifd_offset as u32).to_le_bytes());
+    out.extend((tags.len() as u16).to_le_bytes());
+    for (tag, (field_type, bytes)) in &tags {
+        let count = bytes.len() / field_size(*field_type).unwrap_or(1);
+        out.extend(tag.to_le_bytes());
+        out.extend(field_type.to_le_bytes());
+        out.extend((count as u32).to_le_bytes());
+        if bytes.len() <= 4 {
+            out.extend(bytes);
+            out.extend(vec![0; 4 - bytes.len()]);
+        } else {
+            out.extend((values_offset as u32).to_le_bytes());
+            values.extend(bytes);
+            if bytes.len() % 2 == 1 {
+                values.push(0);
+            }
+            values_offset = ifd_offset + 2 + tags.len() * 12 + 4 + values.len();
+        }
+    }
+    out.extend(0u32.to_le_bytes());
+    out.extend(values);
+    if out.len() > u32::MAX as usize {
+        return Err(anyhow!("GeoTIFF is too large to write"));
+    }
+    Ok(out)
+}
+
+impl GridSource for GeoTiff {
+    fn variables(&self) -> Vec<String> {
+        let (y, x) = self.coordinate_names();
+        let mut variables: Vec<String> = (1..=self.images[0].samples).map(|band| format!("band_{}", band)).collect();
+        variables.extend([y.to_string(), x.to_string()]);
+        if self.proj_string.is_some() {
+            variables.push("crs".to_string());
+        }
+        variables
+    }
+
+    fn dimensions(&self, var: &str) -> Option<Vec<(String, usize)>> {
+        let (y, x) = self.coordinate_names();
+        let (height, width) = (self.images[0].height, self.images[0].width);
+        match var {
+            _ if self.band(var).is_some() => Some(vec![(y.to_string(), height), (x.to_string(), width)]),
+            _ if var == y => Some(vec![(y.to_string(), height)]),
+            _ if var == x => Some(vec![(x.to_string(), width)]),
+            "crs" if self.proj_string.is_some() => Some(vec![]),
+            _ => None,
+        }
+    }
+
+    fn attributes(&self, var: &str) -> Vec<(String, AttrValue)> {
+        let str = |name: &str, value: &str| (name.to_string(), AttrValue::Str(value.to_string()));
+        match (var, &self.proj_string) {
+            ("lat", None) => vec![str("standard_name", "latitude"), str("units", "degrees_north")],
+            ("lon", None) => vec![str("standard_name", "longitude"), str("units", "degrees_east")],
+            ("y", Some(_)) => vec![str("standard_name", "projection_y_coordinate"), str("units", "m")],
+            ("x", Some(_)) => vec![str("standard_name", "projection_x_coordinate"), str("units", "m")],
+            ("crs", Some(proj_string)) => vec![str("proj4", proj_string)],
+            _ if self.band(var).is_some() => {
+                let mut attributes = Vec::new();
+                if let Some(nodata) = self.nodata {
+                    attributes.push(("_FillValue".to_string(), AttrValue::Numbers(vec![nodata])));
+                }
+                if self.proj_string.is_some() {
+                    attributes.push(str("grid_mapping", "crs"));
+                }
+                attributes
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn read(&self, var: &str, start: &[usize], count: &[usize]) -> anyhow::Result<ArrayD<f64>> {
+        let (y, x) = self.coordinate_names();
+        let coords = match var {
+            _ if var == y => &self.ys,
+            _ if var == x => &self.xs,
+            _ => {
+                let band = self.band(var).ok_or_else(|| anyhow!("No variable {} in dataset", var))?;
+                return self.read_window(&self.images[0], band, start, count);
+            }
+        };
+        let (&[start], &[count]) = (start, count) else {
+            return Err(anyhow!("{} is 1-D", var));
+        };
+        let values = coords.get(start..start + count).ok_or_else(|| anyhow!("Read past the end of {}", var))?;
+        Ok(Array1::from(values.to_vec()).into_dyn())
+    }
+
+    fn overviews(&self) -> Vec<(usize, usize)> {
+        self.images[1..].iter().map(|image| (image.height, image.width)).collect()
+    }
+
+    fn read_overview(
+        &self,
+        var: &str,
+        overview: usize,
+        start: &[usize],
+        count: &[usize],
+    ) -> anyhow::Result<ArrayD<f64>> {
+        let band = self.band(var).ok_or_else(|| anyhow!("No variable {} in dataset", var))?;
+        let image = self.images.get(overview + 1).ok_or_else(|| anyhow!("No overview {} of {}", overview, var))?;
+        self.read_window(image, band, start, count)
+    }
+}
+
+/// Writes a test GeoTIFF of a 6 x 8 grid of 1 degree cells from 0,0 to 8,6, with rows north to south, where
+/// `value` gives the single band's value at a row and column
+#[cfg(test)]
+pub(crate) fn write_test_grid(name: &str, value: impl Fn(usize, usize) -> f64) -> crate::testing::TempPath {
+    let values = Array2::from_shape_fn((6, 8), |(row, col)| value(row, col));
+    let bytes = encode(&[("band".to_string(), values)], (0.0, 6.0), (1.0, 1.0)).unwrap();
+    crate::testing::TempPath::write(&format!("{}.tif", name), bytes)
+}
+
+#[cfg(test)]
+mod geotiff_tests {
+    use super::*;
+    use crate::bounds::Bounds;
+    use crate::dataset::Dataset;
+    use crate::expr::Expr;
+    use crate::testing::TempPath;
+    use std::io::Write;
+
+    enum Field {
+        Short(Vec<u16>),
+        Long(Vec<u32>),
+        Double(Vec<f64>),
+        Ascii(&'static str),
+    }
+
+    struct TestImage {
+        tags: Vec<(u16, Field)>,
+        tiled: bool,
+        blocks: Vec<Vec<u8>>,
+    }
+
+    // A classic TIFF with each image's blocks, then its out of line tag values, then its IFD
+    fn write_tiff(images: Vec<TestImage>, little_endian: bool) -> Vec<u8> {
+        let put = |out: &mut Vec<u8>, value: u64, len: usize| {
+            let mut bytes = vec![0; len];
+            write_uint(&mut bytes, value, little_endian);
+            out.extend(bytes);
+        };
+        let mut out = match little_endian {
+            true => b"II".to_vec(),
+            false => b"MM".to_vec(),
+        };
+        put(&mut out, 42, 2);
+        let mut next_offset_at = out.len();
+        put(&mut out, 0, 4);
+
+        for image in images {
+            let mut offsets = Vec::new();
+            for block in &image.blocks {
+                offsets.push(out.len() as u32);
+                out.extend(block);
+            }
+            let counts = image.blocks.iter().map(|b| b.len() as u32).collect();
+            let (offsets_tag, counts_tag) = match image.tiled {
+                true => (TILE_OFFSETS, TILE_BYTE_COUNTS),
+                false => (STRIP_OFFSETS, STRIP_BYTE_COUNTS),
+            };
+            let mut tags = image.tags;
+            tags.push((offsets_tag, Field::Long(offsets)));
+            tags.push((counts_tag, Field::Long(counts)));
+            tags.sort_by_key(|(tag, _)| *tag);
+
+            let mut entries = Vec::new();
+            for (tag, field) in tags {
+                let mut data = Vec::new();
+                let (field_type, count) = match &field {
+                    Field::Short(v) => (3, v.iter().map(|v| put(&mut data, *v as u64, 2)).count()),
+                    Field::Long(v) => (4, v.iter().map(|v| put(&mut data, *v as u64, 4)).count()),
+                    Field::Double(v) => (12, v.iter().map(|v| put(&mut data, v.to_bits(), 8)).count()),
+                    Field::Ascii(s) => {
+                        data.extend(s.bytes().chain([0]));
+                        (2, data.len())
+                    }
+                };
+                let value = match data.len() <= 4 {
+                    true => {
+                        data.resize(4, 0);
+                        data
+                    }
+                    false => {
+                        let mut offset = Vec::new();
+                        put(&mut offset, out.len() as u64, 4);
+                        out.extend(data);
+                        offset
+                    }
+                };
+                entries.push((tag, field_type, count, value));
+            }
+
+            let ifd_offset = out.len() as u64;
+            write_uint(&mut out[next_offset_at..next_offset_at + 4], ifd_offset, little_endian);
+            put(&mut out, entries.len() as u64, 2);
+            for (tag, field_type, count, value) in entries {
+                put(&mut out, tag as u64, 2);
+                put(&mut out, field_type, 2);
+                put(&mut out, count as u64, 4);
+                out.extend(value);
+            }
+            next_offset_at = out.len();
+            put(&mut out, 0, 4);
+        }
+        out
+    }
+
+    fn deflate(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // TIFF LZW, widening codes one early like libtiff
+    fn lzw(bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (mut bits, mut bit_count) = (0u64, 0);
+        let mut emit = |code: usize, width: usize| {
+            bits = (bits << width) | code as u64;
+            bit_count += width;
+            while bit_count >= 8 {
+                out.push((bits >> (bit_count - 8)) as u8);
+                bit_count -= 8;
+            }
+        };
+        let mut table: HashMap<Vec<u8>, usize> = (0..=255u8).map(|b| (vec![b], b as usize)).collect();
+        let (mut next, mut width) = (258, 9);
+        emit(256, width);
+        let mut word = Vec::new();
+        for b in bytes {
+            let extended = [word.as_slice(), &[*b]].concat();
+            if table.contains_key(&extended) {
+                word = extended;
+                continue;
+            }
+            emit(table[&word], width);
+            table.insert(extended, next);
+            next += 1;
+            if next >= 1 << width {
+                width += 1;
+            }
+            word = vec![*b];
+        }
+        emit(table[&word], width);
+        emit(257, width);
+        emit(0, 7);
+        out
+    }
+
+    // A cell of the lat/lon test image, 20 x 18 at half a degree
+    fn cell(row: usize, col: usize) -> f32 {
+        match (row, col) {
+            (17, 19) => -9999.0,
+            _ => (100 * row + col) as f32,
+        }
+    }
+
+    // The predictor 3 encoding of a row: bytes split into planes, most significant first, then differenced
+    fn float_predict(values: &[f32]) -> Vec<u8> {
+        let count = values.len();
+        let mut row = vec![0; count * 4];
+        for (i, v) in values.iter().enumerate() {
+            for (byte, b) in v.to_be_bytes().iter().enumerate() {
+                row[byte * count + i] = *b;
+            }
+        }
+        for i in (1..row.len()).rev() {
+            row[i] = row[i].wrapping_sub(row[i - 1]);
+        }
+        row
+    }
+
+    // 16 x 16 tiles, deflated with the floating point predictor, and a 10 x 9 overview of every other cell
+    fn lat_lon_tiff() -> Vec<u8> {
+        // Rows of a tile starting at the top left of the ranges, padded with zeros past their ends
+        let tile = |rows: std::ops::Range<usize>, cols: std::ops::Range<usize>, value: &dyn Fn(usize, usize) -> f32| {
+            let (r0, c0) = (rows.start, cols.start);
+            let padded = |r, c| match rows.contains(&r) && cols.contains(&c) {
+                true => value(r, c),
+                false => 0.0,
+            };
+            (0..16).map(|r| (0..16).map(|c| padded(r0 + r, c0 + c)).collect::<Vec<f32>>()).collect::<Vec<_>>()
+        };
+        let blocks = [(0, 0), (0, 16), (16, 0), (16, 16)]
+            .iter()
+            .map(|(r0, c0)| {
+                let rows = tile(*r0..18, *c0..20, &cell);
+                deflate(&rows.iter().flat_map(|row| float_predict(row)).collect::<Vec<u8>>())
+            })
+            .collect();
+        let overview = tile(0..9, 0..10, &|r, c| cell(2 * r, 2 * c));
+        let overview = vec![overview.iter().flatten().flat_map(|v| v.to_le_bytes()).collect()];
+
+        let common = || {
+            vec![
+                (BITS_PER_SAMPLE, Field::Short(vec![32])),
+                (SAMPLE_FORMAT, Field::Short(vec![3])),
+                (TILE_WIDTH, Field::Short(vec![16])),
+                (TILE_LENGTH, Field::Short(vec![16])),
+                (GDAL_NODATA, Field::Ascii("-9999")),
+            ]
+        };
+        let mut full = common();
+        full.extend([
+            (IMAGE_WIDTH, Field::Short(vec![20])),
+            (IMAGE_LENGTH, Field::Short(vec![18])),
+            (COMPRESSION, Field::Short(vec![8])),
+            (PREDICTOR, Field::Short(vec![3])),
+            (MODEL_PIXEL_SCALE, Field::Double(vec![0.5, 0.5, 0.0])),
+            (MODEL_TIEPOINT, Field::Double(vec![0.0, 0.0, 0.0, -10.0, 5.0, 0.0])),
+            (GEO_KEY_DIRECTORY, Field::Short(vec![1, 1, 0, 2, 1024, 0, 1, 2, 2048, 0, 1, 4326])),
+        ]);
+        let mut reduced = common();
+        reduced.extend([
+            (NEW_SUBFILE_TYPE, Field::Long(vec![1])),
+            (IMAGE_WIDTH, Field::Short(vec![10])),
+            (IMAGE_LENGTH, Field::Short(vec![9])),
+        ]);
+        write_tiff(
+            vec![
+                TestImage {
+                    tags: full,
+                    tiled: true,
+                    blocks,
+                },
+                TestImage {
+                    tags: reduced,
+                    tiled: true,
+                    blocks: overview,
+                },
+            ],
+            true,
+        )
+    }
+
+    #[test]
+    fn test_tiled_overviews() {
+        let path = TempPath::write("cog.tif", lat_lon_tiff());
+        let tiff = GeoTiff::open(&path).unwrap();
+        assert_eq!(tiff.variables(), ["band_1", "lat", "lon"]);
+        assert_eq!(tiff.dimensions("band_1").unwrap(), [("lat".to_string(), 18), ("lon".to_string(), 20)]);
+        assert_eq!(tiff.overviews(), [(9, 10)]);
+        assert_eq!(tiff.read_all("lon").unwrap()[0], -9.75);
+        assert_eq!(tiff.read_all("lat").unwrap()[17], -3.75);
+
+        // Across all four tiles
+        let window = tiff.read("band_1", &[14, 13], &[4, 5]).unwrap();
+        assert_eq!(window[[0, 0]], 1413.0);
+        assert_eq!(window[[3, 4]], 1717.0);
+        assert_eq!(window[[2, 3]], 1616.0);
+        assert_eq!(tiff.read_overview("band_1", 0, &[8, 9], &[1, 1]).unwrap()[[0, 0]], 1618.0);
+        assert!(tiff.read("band_1", &[17, 19], &[2, 1]).is_err());
+
+        let dset = Dataset::new(&path, "lat", "lon").unwrap();
+        assert!(dset.has_overviews());
+        assert_eq!(dset.level_for((0.6, 0.6)), 0);
+        assert_eq!(dset.level_for((1.0, 1.0)), 1);
+        let bounds = Bounds::new(-10.0, -4.0, 0.0, 5.0);
+        let values = dset.get_values_at("band_1", 1, bounds).unwrap();
+        assert_eq!(values.shape(), [8, 9]);
+        // Rows run south to north
+        assert_eq!(values[[7, 0]], 0.0);
+        let (lats, lons) = dset.get_coords_at(1, bounds);
+        assert_eq!((lats[7], lons[0]), (4.5, -9.5));
+        assert!(dset.get_value("band_1", -0.25, -3.75).unwrap().unwrap().is_nan());
+        assert_eq!(dset.get_value("band_1", -9.75, 4.75).unwrap(), Some(0.0));
+    }
+
+    #[test]
+    fn test_http_range_reads() {
+        let url = crate::http::serve(lat_lon_tiff(), "cog.tif");
+        let path = std::path::PathBuf::from(&url);
+        let value = crate::get_point(&path, 4.25, -9.25, &Expr::variable("band_1"), "lat", "lon").unwrap();
+        assert_eq!(value, Some(101.0));
+        let bounds = Bounds::new(-10.0, 3.6, -8.6, 5.0);
+        let stats = crate::get_stats(&path, Some(bounds), &Expr::variable("band_1"), "lat", "lon").unwrap();
+        assert_eq!(stats.count, 4);
+    }
+
+    #[test]
+    fn test_encode_limits() {
+        let band = |name: &str| (name.to_string(), Array2::from_elem((1, 1), 1.0));
+        let bytes = encode(&[band("a<b & \"c\"")], (0.0, 1.0), (1.0, 1.0)).unwrap();
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(text.contains(">a&lt;b &amp; &quot;c&quot;</Item>"), "{}", text);
+
+        let bands: Vec<(String, Array2<f64>)> = (0..=u16::MAX as usize).map(|_| band("chl")).collect();
+        assert!(encode(&bands, (0.0, 1.0), (1.0, 1.0)).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_big_tiff() {
+        // A BigTIFF header, then an IFD at 16 claiming more entries than could ever be read
+        let mut bytes = b"II".to_vec();
+        bytes.extend(43u16.to_le_bytes());
+        bytes.extend(8u16.to_le_bytes());
+        bytes.extend(0u16.to_le_bytes());
+        bytes.extend(16u64.to_le_bytes());
+        bytes.extend((u64::MAX / 4).to_le_bytes());
+        let path = TempPath::write("corrupt.tif", bytes);
+        let err = GeoTiff::open(&path).err().unwrap();
+        assert!(err.to_string().contains("entries"), "{}", err);
+    }
+
+    #[test]
+    fn test_zero_dimensions() {
+        // A 2 x 2 float image in one strip, with each of its sizes in turn set to zero
+        let header = |width: u16, height: u16, rows: u16, samples: u16, tile: Option<u16>| {
+            let mut tags = vec![
+                (IMAGE_WIDTH, Field::Short(vec![width])),
+                (IMAGE_LENGTH, Field::Short(vec![height])),
+                (BITS_PER_SAMPLE, Field::Short(vec![32])),
+                (SAMPLE_FORMAT, Field::Short(vec![3])),
+                (SAMPLES_PER_PIXEL, Field::Short(vec![samples])),
+                (MODEL_PIXEL_SCALE, Field::Double(vec![1.0, 1.0, 0.0])),
+                (MODEL_TIEPOINT, Field::Double(vec![0.0, 0.0, 0.0, 0.0, 2.0, 0.0])),
+                (GEO_KEY_DIRECTORY, Field::Short(vec![1, 1, 0, 2, 1024, 0, 1, 2, 2048, 0, 1, 4326])),
+            ];
+            match tile {
+                Some(size) => {
+                    tags.extend([(TILE_WIDTH, Field::Short(vec![size])), (TILE_LENGTH, Field::Short(vec![2]))])
+                }
+                None => tags.push((ROWS_PER_STRIP, Field::Short(vec![rows]))),
+            }
+            let blocks = vec![vec![0; 16]];
+            write_tiff(vec![TestImage { tags, tiled: tile.is_some(), blocks }], true)
+        };
+        assert!(GeoTiff::open(&TempPath::write("sizes.tif", header(2, 2, 2, 1, None))).is_ok());
+        for (name, bytes) in [
+            ("width", header(0, 2, 2, 1, None)),
+            ("height", header(2, 0, 2, 1, None)),
+            ("rows_per_strip", header(2, 2, 0, 1, None)),
+            ("samples", header(2, 2, 2, 0, None)),
+            ("tile_width", header(2, 2, 2, 1, Some(0))),
+        ] {
+            let path = TempPath::write(&format!("zero_{}.tif", name), bytes);
+            let err = GeoTiff::open(&path).err().unwrap();
+            assert!(err.to_string().contains("can be zero"), "{}: {}", name, err);
+        }
+    }
+
+    #[test]
+    fn test_projected_strips() {
+        // 5 x 7 signed 16 bit cells, big-endian, in LZW compressed strips of 3 rows with horizontal differencing
+        let value = |row: usize, col: usize| (10 * row) as i16 - 3 * col as i16;
+        let blocks = (0..7)
+            .step_by(3)
+            .map(|r0| {
+                let mut bytes = Vec::new();
+                for row in r0..(r0 + 3).min(7) {
+                    let mut prev = 0i16;
+                    for col in 0..5 {
+                        bytes.extend(value(row, col).wrapping_sub(prev).to_be_bytes());
+                        prev = value(row, col);
+                    }
+                }
+                lzw(&bytes)
+            })
+            .collect();
+        let tags = vec![
+            (IMAGE_WIDTH, Field::Short(vec![5])),
+            (IMAGE_LENGTH, Field::Short(vec![7])),
+            (BITS_PER_SAMPLE, Field::Short(vec![16])),
+            (SAMPLE_FORMAT, Field::Short(vec![2])),
+            (ROWS_PER_STRIP, Field::Short(vec![3])),
+            (COMPRESSION, Field::Short(vec![5])),
+            (PREDICTOR, Field::Short(vec![2])),
+            (
+                MODEL_TRANSFORMATION,
+                Field::Double(vec![
+                    1000.0, 0.0, 0.0, 500000.0, 0.0, -1000.0, 0.0, 6000000.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+                ]),
+            ),
+            (GEO_KEY_DIRECTORY, Field::Short(vec![1, 1, 0, 2, 1024, 0, 1, 1, 3072, 0, 1, 32633])),
+        ];
+        let bytes = write_tiff(vec![TestImage { tags, tiled: false, blocks }], false);
+        let path = TempPath::write("utm.tif", bytes);
+
+        let tiff = GeoTiff::open(&path).unwrap();
+        assert_eq!(tiff.variables(), ["band_1", "y", "x", "crs"]);
+        assert_eq!(tiff.attribute("crs", "proj4").unwrap().as_str(), Some("+proj=utm +zone=33 +ellps=WGS84 +units=m"));
+        let window = tiff.read("band_1", &[1, 1], &[6, 3]).unwrap();
+        assert_eq!(window[[0, 0]], 7.0);
+        assert_eq!(window[[5, 2]], 51.0);
+
+        let dset = Dataset::new(&path, "lat", "lon").unwrap();
+        assert!(dset.crs().is_some());
+        assert!(!dset.has_overviews());
+        assert_eq!(dset.get_value("band_1", 501500.0, 5993500.0).unwrap(), Some(57.0));
+        let mut point = [(502500.0, 5996500.0)];
+        dset.crs().unwrap().to_lng_lat(&mut point);
+        let value = crate::get_point(&path, point[0].1, point[0].0, &Expr::variable("band_1"), "lat", "lon").unwrap();
+        assert_eq!(value, Some(24.0));
+    }
+
+    #[test]
+    fn test_decompress() {
+        let bytes: Vec<u8> = (0..3000u32).map(|i| (((i * 7919) % 251) ^ (i / 13)) as u8).collect();
+        assert_eq!(lzw_decompress(&lzw(&bytes)).unwrap(), bytes);
+
+        let packed = [0xFE, 0xAA, 0x02, 0x80, 0x00, 0x2A, 0xFD, 0xAA, 0x03, 0x80, 0x00, 0x2A, 0x22, 0xF7, 0xAA];
+        let unpacked = packbits_decompress(&packed).unwrap();
+        assert_eq!(unpacked.len(), 24);
+        assert_eq!(&unpacked[..6], &[0xAA, 0xAA, 0xAA, 0x80, 0x00, 0x2A]);
+        assert!(unpacked[14..].iter().all(|b| *b == 0xAA));
+        assert!(packbits_decompress(&[0x05, 0x01]).is_err());
+    }
+}
@@ -0,0 +1,275 @@
+//! Byte range reads over plain HTTP, e.g. of Cloud-Optimized GeoTIFFs on a file server
+
+use anyhow::anyhow;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A file at an `http://` URL, read with one `Range` request per read. Connections are kept alive and reused.
+#[derive(Debug, Clone)]
+pub struct HttpRange {
+    url: String,
+    host: String,
+    port: u16,
+    path: String,
+    /// Open connections between reads, shared by clones
+    idle: Arc<Mutex<Vec<BufReader<TcpStream>>>>,
+}
+
+// A response's status, and its body if it was read
+struct Response {
+    status: u16,
+    body: Option<Vec<u8>>,
+    keep_alive: bool,
+}
+
+impl HttpRange {
+    pub fn is_url(path: &str) -> bool {
+        path.starts_with("http://") || path.starts_with("https://")
+    }
+
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| anyhow!("Only http:// URLs can be read, not {}", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| anyhow!("Invalid port in {}", url))?),
+            None => (authority, 80),
+        };
+        Ok(Self {
+            url: url.to_string(),
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            idle: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    fn connect(&self) -> anyhow::Result<BufReader<TcpStream>> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port))
+            .map_err(|e| anyhow!("Could not connect to {}: {}", self.url, e))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        Ok(BufReader::new(stream))
+    }
+
+    /// `len` bytes from `offset`, or fewer if the file ends first
+    pub fn read(&self, offset: u64, len: usize) -> anyhow::Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let idle = self.idle.lock().unwrap().pop();
+        let reused = idle.is_some();
+        let mut stream = match idle {
+            Some(stream) => stream,
+            None => self.connect()?,
+        };
+        let mut response = self.request(&mut stream, offset, len);
+        // The server may have closed an idle connection since it was last used
+        if response.is_err() && reused {
+            stream = self.connect()?;
+            response = self.request(&mut stream, offset, len);
+        }
+        let response = response?;
+        if response.keep_alive {
+            self.idle.lock().unwrap().push(stream);
+        }
+
+        match (response.status, response.body) {
+            (206, Some(mut body)) => {
+                body.truncate(len);
+                Ok(body)
+            }
+            // Servers without range support send the whole file, which is only read if it's all that was asked for
+            (200, Some(body)) => Ok(body),
+            (200, None) => Err(anyhow!("{} doesn't support HTTP range requests", self.url)),
+            // The range starts past the end of the file
+            (416, _) => Ok(Vec::new()),
+            (status, _) => Err(anyhow!("{} returned HTTP {}", self.url, status)),
+        }
+    }
+
+    // Send a range request and read the response. Bodies are only read for ranges and whole files that fit in one.
+    fn request(&self, stream: &mut BufReader<TcpStream>, offset: u64, len: usize) -> anyhow::Result<Response> {
+        write!(
+            stream.get_mut(),
+            "GET {} HTTP/1.1\r\nHost: {}\r\nRange: bytes={}-{}\r\n\r\n",
+            self.path,
+            self.host,
+            offset,
+            offset + len as u64 - 1
+        )?;
+
+        let invalid = || anyhow!("Invalid HTTP response from {}", self.url);
+        let mut line = String::new();
+        stream.read_line(&mut line)?;
+        let status: u16 = line.split_whitespace().nth(1).and_then(|status| status.parse().ok()).ok_or_else(invalid)?;
+        let (mut content_length, mut chunked, mut keep_alive) = (None, false, true);
+        loop {
+            line.clear();
+            if stream.read_line(&mut line)? == 0 {
+                return Err(invalid());
+            }
+            let header = line.trim_end().to_ascii_lowercase();
+            if header.is_empty() {
+                break;
+            }
+            let Some((name, value)) = header.split_once(':') else {
+                continue;
+            };
+            match (name.trim(), value.trim()) {
+                ("content-length", value) => content_length = Some(value.parse::<u64>().map_err(|_| invalid())?),
+                ("transfer-encoding", value) => chunked = value.contains("chunked"),
+                ("connection", value) => keep_alive = !value.contains("close"),
+                _ => {}
+            }
+        }
+
+        let whole_file = offset == 0 && content_length.is_some_and(|length| length <= len as u64);
+        if status == 200 && !whole_file {
+            return Ok(Response { status, body: None, keep_alive: false });
+        }
+        let body = match (chunked, content_length) {
+            (true, _) => dechunk(stream)?,
+            (false, Some(length)) => {
+                let mut body = Vec::new();
+                stream.by_ref().take(length).read_to_end(&mut body)?;
+                if body.len() as u64 != length {
+                    return Err(invalid());
+                }
+                body
+            }
+            // The body runs until the server closes the connection
+            (false, None) => {
+                keep_alive = false;
+                let mut body = Vec::new();
+                stream.read_to_end(&mut body)?;
+                body
+            }
+        };
+        Ok(Response { status, body: Some(body), keep_alive })
+    }
+}
+
+// The body of a chunked transfer encoding
+fn dechunk(reader: &mut impl BufRead) -> anyhow::Result<Vec<u8>> {
+    let invalid = || anyhow!("Invalid chunked HTTP response");
+    let mut out = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid())?;
+        if size == 0 {
+            // Skip any trailer headers up to the blank line
+            loop {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                    return Ok(out);
+                }
+            }
+        }
+        let start = out.len();
+        out.resize(start + size, 0);
+        reader.read_exact(&mut out[start..]).map_err(|_| invalid())?;
+        let mut crlf = [0; 2];
+        reader.read_exact(&mut crlf).map_err(|_| invalid())?;
+    }
+}
+
+/// Serve bytes from a local server thread, which answers `Range` requests. Returns the URL of the file.
+#[cfg(test)]
+pub(crate) fn serve(bytes: Vec<u8>, name: &str) -> String {
+    serve_with(bytes, name, true).0
+}
+
+// Like `serve`, optionally ignoring ranges, and counting connections
+#[cfg(test)]
+fn serve_with(bytes: Vec<u8>, name: &str, ranges: bool) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/{}", listener.local_addr().unwrap(), name);
+    let bytes = Arc::new(bytes);
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = connections.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let bytes = bytes.clone();
+            counter.fetch_add(1, Ordering::SeqCst);
+            // Each connection answers requests until the client hangs up
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut range = None;
+                    let mut line = String::new();
+                    loop {
+                        line.clear();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                            return;
+                        }
+                        if line.trim_end().is_empty() {
+                            break;
+                        }
+                        if let Some(value) = line.trim_end().strip_prefix("Range: bytes=") {
+                            let (start, end) = value.split_once('-').unwrap();
+                            range = Some((start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap()));
+                        }
+                    }
+                    let (status, (start, end)) = match range.filter(|_| ranges) {
+                        Some(range) => ("206 Partial Content", range),
+                        None => ("200 OK", (0, bytes.len() - 1)),
+                    };
+                    let body = &bytes[start.min(bytes.len())..(end + 1).min(bytes.len())];
+                    let head = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n", status, body.len());
+                    if stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(body)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    (url, connections)
+}
+
+#[cfg(test)]
+mod http_tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_read_range() {
+        let (url, connections) = serve_with((0..100).collect(), "bytes.bin", true);
+        let file = HttpRange::new(&url).unwrap();
+        assert_eq!(file.read(10, 4).unwrap(), vec![10, 11, 12, 13]);
+        assert_eq!(file.read(98, 10).unwrap(), vec![98, 99]);
+        assert_eq!(file.clone().read(0, 2).unwrap(), vec![0, 1]);
+        // Every read went over the first connection
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert!(HttpRange::new("https://example.com/a.tif").is_err());
+    }
+
+    #[test]
+    fn test_no_ranges() {
+        let (url, _) = serve_with((0..100).collect(), "bytes.bin", false);
+        let file = HttpRange::new(&url).unwrap();
+        // Rather than downloading the whole file for every block
+        assert!(file.read(10, 4).is_err());
+        assert_eq!(file.read(0, 200).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_dechunk() {
+        assert_eq!(dechunk(&mut &b"3\r\nabc\r\n2;x=1\r\nde\r\n0\r\n\r\n"[..]).unwrap(), b"abcde");
+        assert!(dechunk(&mut &b"5\r\nab"[..]).is_err());
+    }
+}
@@ -1,35 +1,330 @@
-use dataset::Dataset;
-use std::path::Path;
-use crate::coordinates::{TileCoord, from_tile_coord_to_lat_lng_bounds};
+use dataset::{Dataset, DatasetPath};
+use crate::bounds::Bounds;
+use crate::contour::{contour_lines, ContourLine, ContourOptions};
+use crate::coordinates::TileCoord;
+use crate::expr::Expr;
+use crate::overview::OverviewCache;
+use crate::tms::{Projection, TileMatrixSet};
+use crate::section::{Profile, Section};
+use crate::stats::Stats;
+use crate::terrain::HillshadeOptions;
+use crate::region::Region;
+use crate::zonal::ZonalStats;
 
-mod bounds;
-mod dataset;
-mod coordinates;
+pub mod aggregate;
+pub mod anomaly;
+pub mod bounds;
+pub mod composite;
+pub mod contour;
+pub mod crs;
+pub mod dataset;
+pub mod coordinates;
+pub mod expr;
+pub mod geotiff;
+pub mod http;
+pub mod mask;
+pub mod mvt;
+pub mod overview;
+pub mod region;
+pub mod source;
+pub mod section;
+pub mod shapefile;
+pub mod stats;
+pub mod subset;
+pub mod terrain;
+#[cfg(test)]
+mod testing;
+pub mod time;
+pub mod tms;
+pub mod zarr;
+pub mod zonal;
 
 #[cfg(test)]
 #[macro_use]
 extern crate approx;
 
+/// The standard tile size in pixels. Tiles can also be rendered at 512, or at 2x either for high-DPI screens.
 pub const TILE_SIZE: usize = 256;
 
+/// Values for every pixel of a `tile_size` x `tile_size` tile of the tile matrix set, with row 0 at the top
+#[allow(clippy::too_many_arguments)]
 pub fn get_tile(
-    dset_path: &Path,
+    dset_path: impl Into<DatasetPath>,
+    tms: &dyn TileMatrixSet,
     tx: u32,
     ty: u32,
     zoom: u32,
+    tile_size: usize,
     var_name: &str,
     lat_name: &str,
     lon_name: &str,
 ) -> anyhow::Result<Option<Vec<f64>>> {
-    let dset = Dataset::new(dset_path, lat_name, lon_name)?;
+    get_expr_tile(dset_path, tms, tx, ty, zoom, tile_size, &Expr::variable(var_name), lat_name, lon_name)
+}
+
+/// Like `get_tile`, but with values computed from an expression over the dataset variables
+#[allow(clippy::too_many_arguments)]
+pub fn get_expr_tile(
+    dset_path: impl Into<DatasetPath>,
+    tms: &dyn TileMatrixSet,
+    tx: u32,
+    ty: u32,
+    zoom: u32,
+    tile_size: usize,
+    expr: &Expr,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Option<Vec<f64>>> {
+    let dset_path = dset_path.into();
+    let dset = Dataset::open(&dset_path, lat_name, lon_name)?;
+    read_tms_tile(&dset, tms, &TileCoord::new(tx, ty, zoom as u8), tile_size, expr)
+}
+
+/// Read the u and v components of a vector field, e.g. currents or wind, as two tiles
+#[allow(clippy::too_many_arguments)]
+pub fn get_vector_tile(
+    dset_path: impl Into<DatasetPath>,
+    tms: &dyn TileMatrixSet,
+    tx: u32,
+    ty: u32,
+    zoom: u32,
+    tile_size: usize,
+    (u_name, v_name): (&str, &str),
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Option<(Vec<f64>, Vec<f64>)>> {
+    let dset_path = dset_path.into();
+    let dset = Dataset::open(&dset_path, lat_name, lon_name)?;
+    let tile_coord = TileCoord::new(tx, ty, zoom as u8);
+
+    let u = read_tms_tile(&dset, tms, &tile_coord, tile_size, &Expr::variable(u_name))?;
+    let v = read_tms_tile(&dset, tms, &tile_coord, tile_size, &Expr::variable(v_name))?;
+    Ok(u.zip(v))
+}
+
+/// Whether each pixel of a tile is inside the dataset's grid, with row 0 at the top. Tiles are NaN both where values
+/// are missing and outside the grid, so this tells the two apart, e.g. to only paint missing values with a color.
+#[allow(clippy::too_many_arguments)]
+pub fn get_tile_coverage(
+    dset_path: impl Into<DatasetPath>,
+    tms: &dyn TileMatrixSet,
+    tx: u32,
+    ty: u32,
+    zoom: u32,
+    tile_size: usize,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Vec<bool>> {
+    let dset = Dataset::open(&dset_path.into(), lat_name, lon_name)?;
+    match mask::tile_pixels(tms, &TileCoord::new(tx, ty, zoom as u8), tile_size) {
+        Some(pixels) => Ok(coverage(&dset, pixels)),
+        None => Ok(vec![false; tile_size * tile_size]),
+    }
+}
+
+/// Like `get_tile_coverage`, for an image from `get_image`
+pub fn get_image_coverage(
+    dset_path: impl Into<DatasetPath>,
+    bounds: Bounds,
+    width: usize,
+    height: usize,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Vec<bool>> {
+    let dset = Dataset::open(&dset_path.into(), lat_name, lon_name)?;
+    Ok(coverage(&dset, mask::image_pixels(bounds, width, height)))
+}
+
+// Whether each lng/lat point is inside the dataset's grid
+fn coverage(dset: &Dataset, mut points: Vec<(f64, f64)>) -> Vec<bool> {
+    if let Some(crs) = dset.crs() {
+        crs.from_lng_lat(&mut points);
+    }
+    let bounds = dset.get_bounds();
+    points
+        .into_iter()
+        .map(|(x, y)| x >= bounds.min_x && x <= bounds.max_x && y >= bounds.min_y && y <= bounds.max_y)
+        .collect()
+}
+
+// Sample expression values for every pixel of a tile in a tile matrix set
+pub(crate) fn read_tms_tile(
+    dset: &Dataset,
+    tms: &dyn TileMatrixSet,
+    tile_coord: &TileCoord,
+    tile_size: usize,
+    expr: &Expr,
+) -> anyhow::Result<Option<Vec<f64>>> {
+    if let Some(tile_bounds) = tms.lat_lng_bounds(tile_coord) {
+        return read_grid(dset, tile_bounds, tile_size, tile_size, expr);
+    }
+    match tms.tile_bounds(tile_coord) {
+        Some(tile_bounds) => read_projected(dset, tms.projection(), tile_bounds, tile_size, expr),
+        None => Ok(None),
+    }
+}
+
+// Sample expression values at the lng/lat of every pixel center of a tile in projected coordinates
+fn read_projected(
+    dset: &Dataset,
+    projection: Projection,
+    tile_bounds: Bounds,
+    tile_size: usize,
+    expr: &Expr,
+) -> anyhow::Result<Option<Vec<f64>>> {
+    let (dx, dy) = tile_bounds.get_pixel_lengths(tile_size, tile_size);
+    let pixels: Vec<(f64, f64)> = (0..tile_size)
+        .flat_map(|row| {
+            (0..tile_size).map(move |col| {
+                let x = tile_bounds.min_x + (col as f64 + 0.5) * dx;
+                let y = tile_bounds.max_y - (row as f64 + 0.5) * dy;
+                projection.inverse(x, y)
+            })
+        })
+        .collect();
+    read_points(dset, pixels, (tile_size, tile_size), expr)
+}
+
+// Sample expression values at the grid cell nearest to each lng/lat point, or None if none are inside the dataset.
+// The points are the pixels of a width x height image, which sets the overview they're read from.
+fn read_points(
+    dset: &Dataset,
+    mut points: Vec<(f64, f64)>,
+    (width, height): (usize, usize),
+    expr: &Expr,
+) -> anyhow::Result<Option<Vec<f64>>> {
+    // Projected grids are sampled in their own x/y coordinates
+    if let Some(crs) = dset.crs() {
+        crs.from_lng_lat(&mut points);
+    }
     let dset_bounds = dset.get_bounds();
-    let tile_bounds = from_tile_coord_to_lat_lng_bounds(&TileCoord::new(tx, ty, zoom as u8));
+    let inside = |(x, y): (f64, f64)| {
+        x >= dset_bounds.min_x && x <= dset_bounds.max_x && y >= dset_bounds.min_y && y <= dset_bounds.max_y
+    };
+
+    // Only read the part of the dataset the points touch, plus a cell so every point has a neighbour
+    let envelope = points.iter().filter(|p| inside(**p)).fold(None, |envelope: Option<Bounds>, (x, y)| {
+        Some(match envelope {
+            Some(b) => Bounds::new(b.min_x.min(*x), b.min_y.min(*y), b.max_x.max(*x), b.max_y.max(*y)),
+            None => Bounds::new(*x, *y, *x, *y),
+        })
+    });
+    let Some(envelope) = envelope else {
+        return Ok(None);
+    };
+    let pixel_size = (
+        (envelope.max_x - envelope.min_x) / width as f64,
+        (envelope.max_y - envelope.min_y) / height as f64,
+    );
+    let level = dset.level_for(pixel_size);
+    let (dx, dy) = dset.cell_size(level);
+    let cell = dx.max(dy);
+    let envelope = match dset_bounds.intersect(&envelope.expand(cell, cell)) {
+        Some(envelope) => envelope,
+        None => return Ok(None),
+    };
+
+    let values = dset.get_expr_values_at(expr, level, envelope)?.into_dimensionality::<ndarray::Ix2>()?;
+    let (lats, lons) = dset.get_coords_at(level, envelope);
+    if values.is_empty() {
+        return Ok(Some(vec![f64::NAN; points.len()]));
+    }
+    let result = points
+        .into_iter()
+        .map(|(x, y)| {
+            if !inside((x, y)) {
+                return f64::NAN;
+            }
+            let row = overview::nearest_index(&lats, y).min(lats.len() - 1);
+            let col = overview::nearest_index(&lons, x).min(lons.len() - 1);
+            values[[row, col]]
+        })
+        .collect();
+    Ok(Some(result))
+}
+
+/// Like `get_expr_tile`, but low zoom tiles are sampled from the coarsest overview that is still
+/// at least as fine as the tile pixels, instead of the full resolution grid
+#[allow(clippy::too_many_arguments)]
+pub fn get_overview_tile(
+    dset_path: impl Into<DatasetPath>,
+    tms: &dyn TileMatrixSet,
+    tx: u32,
+    ty: u32,
+    zoom: u32,
+    tile_size: usize,
+    expr: &Expr,
+    lat_name: &str,
+    lon_name: &str,
+    overviews: &OverviewCache,
+) -> anyhow::Result<Option<Vec<f64>>> {
+    let dset_path = dset_path.into();
+    let dset = Dataset::open(&dset_path, lat_name, lon_name)?;
+    let tile_coord = TileCoord::new(tx, ty, zoom as u8);
+    // Overviews are lat/lon grids, so they only serve tiles that are lat/lon boxes of lat/lon datasets
+    let tile_bounds = match tms.lat_lng_bounds(&tile_coord) {
+        // Sources with overviews of their own are read through those instead
+        Some(tile_bounds) if overviews.enabled() && dset.crs().is_none() && !dset.has_overviews() => tile_bounds,
+        _ => return read_tms_tile(&dset, tms, &tile_coord, tile_size, expr),
+    };
 
-    // Create result array and image
-    let mut result = vec![0.0; TILE_SIZE * TILE_SIZE];
+    let pixel_size = tile_bounds.get_pixel_lengths(tile_size, tile_size);
+    // The first level is at half resolution, so tiles with finer pixels read the full grid without building or
+    // fetching a pyramid, as do grids that already fit in a tile
+    let (cell_x, cell_y) = dset.cell_size(0);
+    let fits = dset.lats().len() <= tile_size && dset.lons().len() <= tile_size;
+    if fits || pixel_size.0 < 2.0 * cell_x || pixel_size.1 < 2.0 * cell_y {
+        return read_grid(&dset, tile_bounds, tile_size, tile_size, expr);
+    }
+    // Built down to the tile size, so the coarsest level serves zoom 0
+    let pyramid = overviews.get_or_build(&dset_path, &dset, expr, lat_name, lon_name, tile_size)?;
+    match pyramid.level_for(pixel_size) {
+        Some(level) => sample_grid(level.get_bounds(), tile_bounds, tile_size, tile_size, |bounds| {
+            Ok(level.get_values(bounds))
+        }),
+        None => read_grid(&dset, tile_bounds, tile_size, tile_size, expr),
+    }
+}
+
+// Sample expression values onto a width x height image covering the bounds, with row 0 at the top
+fn read_grid(
+    dset: &Dataset,
+    grid_bounds: Bounds,
+    width: usize,
+    height: usize,
+    expr: &Expr,
+) -> anyhow::Result<Option<Vec<f64>>> {
+    if dset.crs().is_some() {
+        let (dx, dy) = grid_bounds.get_pixel_lengths(width, height);
+        let pixels = (0..height)
+            .flat_map(|row| {
+                (0..width).map(move |col| {
+                    (grid_bounds.min_x + (col as f64 + 0.5) * dx, grid_bounds.max_y - (row as f64 + 0.5) * dy)
+                })
+            })
+            .collect();
+        return read_points(dset, pixels, (width, height), expr);
+    }
+    let level = dset.level_for(grid_bounds.get_pixel_lengths(width, height));
+    sample_grid(dset.get_bounds(), grid_bounds, width, height, |bounds| {
+        dset.get_expr_values_at(expr, level, bounds)
+    })
+}
+
+// Sample a source grid covering `dset_bounds` onto a width x height image, reading only the overlap
+fn sample_grid(
+    dset_bounds: Bounds,
+    grid_bounds: Bounds,
+    width: usize,
+    height: usize,
+    read: impl FnOnce(Bounds) -> anyhow::Result<ndarray::ArrayD<f64>>,
+) -> anyhow::Result<Option<Vec<f64>>> {
+
+    // Create result array and image. Pixels outside the dataset are NaN.
+    let mut result = vec![f64::NAN; width * height];
 
     // Get bounds intersection
-    let intersect_bounds = dset_bounds.intersect(&tile_bounds);
+    let intersect_bounds = dset_bounds.intersect(&grid_bounds);
     let intersect_bounds = match intersect_bounds {
         Some(bounds) => bounds,
         None => {
@@ -39,15 +334,23 @@ pub fn get_tile(
     };
 
     // Read the intersection data
-    let values = dset.get_values(var_name, intersect_bounds)?;
+    let values = read(intersect_bounds)?;
+    if values.is_empty() {
+        return Ok(Some(result));
+    }
 
     // Get the meter distance between result pixels
-    let (tile_x_delta, tile_y_delta) = tile_bounds.get_pixel_lengths(TILE_SIZE, TILE_SIZE);
+    let (x_delta, y_delta) = grid_bounds.get_pixel_lengths(width, height);
 
-    // Get the pixel indices of the result tile
-    let pixel_bounds = intersect_bounds.xy_shift(-tile_bounds.min_x, -tile_bounds.min_y).xy_scale(1.0 / tile_x_delta, 1.0 / tile_y_delta);
+    // Get the pixel indices of the result grid
+    let pixel_bounds = intersect_bounds.xy_shift(-grid_bounds.min_x, -grid_bounds.min_y).xy_scale(1.0 / x_delta, 1.0 / y_delta);
     let (px_origin, py_origin) = (pixel_bounds.min_x as usize, pixel_bounds.min_y as usize);
     let (x_len, y_len) = pixel_bounds.xy_len();
+    // Rounding can put the last pixel one past the edge, or the whole window past it
+    let (x_len, y_len) = (x_len.min(width.saturating_sub(px_origin)), y_len.min(height.saturating_sub(py_origin)));
+    if x_len == 0 || y_len == 0 {
+        return Ok(Some(result));
+    }
 
     // Get decimation factors
     let dec_y: f64 = (values.shape()[0] as f64) / y_len as f64;
@@ -59,9 +362,9 @@ pub fn get_tile(
     for yi in 0..y_len {
         for xi in 0..x_len {
             let val = values[[yif as usize, xif as usize]];
-            let yi = (TILE_SIZE - 1) - (py_origin + yi);
+            let yi = (height - 1) - (py_origin + yi);
             let xi = px_origin + xi;
-            result[yi * TILE_SIZE + xi] = val;
+            result[yi * width + xi] = val;
             xif += dec_x;
         }
         yif += dec_y;
@@ -70,3 +373,347 @@ pub fn get_tile(
 
     Ok(Some(result))
 }
+
+/// A surface derived from the slope of the values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Surface {
+    Hillshade(HillshadeOptions),
+    /// Gradient magnitude per kilometer
+    Gradient,
+}
+
+/// Tile values along with a derived surface, e.g. a hillshade of bathymetry.
+/// Slopes are computed from a read one pixel past the tile, so there are no seams between tiles.
+#[allow(clippy::too_many_arguments)]
+pub fn get_surface_tile(
+    dset_path: impl Into<DatasetPath>,
+    tms: &dyn TileMatrixSet,
+    tx: u32,
+    ty: u32,
+    zoom: u32,
+    tile_size: usize,
+    expr: &Expr,
+    surface: &Surface,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Option<(Vec<f64>, Vec<f64>)>> {
+    let dset_path = dset_path.into();
+    let dset = Dataset::open(&dset_path, lat_name, lon_name)?;
+    let tile_coord = TileCoord::new(tx, ty, zoom as u8);
+    let size = tile_size + 2;
+
+    let (halo, cell_sizes) = if let Some(tile_bounds) = tms.lat_lng_bounds(&tile_coord) {
+        let (dx, dy) = tile_bounds.get_pixel_lengths(tile_size, tile_size);
+        // Cells shrink towards the poles, so work out their size row by row
+        let cell_sizes: Vec<(f64, f64)> = (0..tile_size)
+            .map(|row| terrain::cell_size(dx, dy, tile_bounds.max_y - (row as f64 + 0.5) * dy))
+            .collect();
+        (read_grid(&dset, tile_bounds.expand(dx, dy), size, size, expr)?, cell_sizes)
+    } else if let Some(tile_bounds) = tms.tile_bounds(&tile_coord) {
+        // Projected cells are taken at their size on the map, which is true to scale at the projection's
+        // standard parallel
+        let (dx, dy) = tile_bounds.get_pixel_lengths(tile_size, tile_size);
+        let halo = read_projected(&dset, tms.projection(), tile_bounds.expand(dx, dy), size, expr)?;
+        (halo, vec![(dx, dy); tile_size])
+    } else {
+        return Ok(None);
+    };
+    let Some(halo) = halo else {
+        return Ok(None);
+    };
+
+    let values: Vec<f64> = halo
+        .chunks(size)
+        .skip(1)
+        .take(tile_size)
+        .flat_map(|row| row[1..=tile_size].iter().copied())
+        .collect();
+
+    let gradients = terrain::gradients(&halo, size, size, &cell_sizes);
+
+    let surface = match surface {
+        Surface::Hillshade(options) => terrain::hillshade(&gradients, options),
+        Surface::Gradient => terrain::gradient_magnitude(&gradients),
+    };
+    Ok(Some((values, surface)))
+}
+
+/// Values over a lat/lng bounding box on a width x height equirectangular image, with row 0 at the top.
+/// None if the box is outside the dataset.
+pub fn get_image(
+    dset_path: impl Into<DatasetPath>,
+    bounds: Bounds,
+    width: usize,
+    height: usize,
+    expr: &Expr,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Option<Vec<f64>>> {
+    let dset = Dataset::open(&dset_path.into(), lat_name, lon_name)?;
+    read_grid(&dset, bounds, width, height, expr)
+}
+
+/// Value at a lat/lng point, or None if the point is outside the dataset
+pub fn get_point(
+    dset_path: impl Into<DatasetPath>,
+    lat: f64,
+    lng: f64,
+    expr: &Expr,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Option<f64>> {
+    let dset_path = dset_path.into();
+    let dset = Dataset::open(&dset_path, lat_name, lon_name)?;
+    let (x, y) = dset.source_point(lng, lat);
+    if x.is_nan() || y.is_nan() {
+        return Ok(None);
+    }
+    dset.get_expr_value(expr, x, y)
+}
+
+/// Statistics over a lat/lng bounding box, or the whole dataset if no bounds are given.
+/// Projected grids use the cells inside the box's x/y envelope.
+pub fn get_stats(
+    dset_path: impl Into<DatasetPath>,
+    bounds: Option<Bounds>,
+    expr: &Expr,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Stats> {
+    let dset_path = dset_path.into();
+    let dset = Dataset::open(&dset_path, lat_name, lon_name)?;
+    let dset_bounds = dset.get_bounds();
+    let bounds = match bounds.map(|bounds| dset.source_bounds(bounds)) {
+        Some(None) => return Ok(Stats::from_values([])),
+        Some(Some(bounds)) => match dset_bounds.intersect(&bounds) {
+            Some(bounds) => bounds,
+            None => return Ok(Stats::from_values([])),
+        },
+        None => dset_bounds,
+    };
+
+    let values = dset.get_expr_values(expr, bounds)?;
+    Ok(Stats::from_values(values.iter().copied()))
+}
+
+/// Area weighted statistics over a GeoJSON region, see `zonal::zonal_stats`
+pub fn get_zonal_stats(
+    dset_path: impl Into<DatasetPath>,
+    region: &Region,
+    expr: &Expr,
+    percents: &[f64],
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<ZonalStats> {
+    let dset_path = dset_path.into();
+    let dset = Dataset::open(&dset_path, lat_name, lon_name)?;
+    zonal::zonal_stats(&dset, region, expr, percents)
+}
+
+/// Values at every level of the column nearest to a lat/lng point, see `section::profile`
+pub fn get_profile(
+    dset_path: impl Into<DatasetPath>,
+    lat: f64,
+    lng: f64,
+    expr: &Expr,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Option<Profile>> {
+    let dset_path = dset_path.into();
+    let dset = Dataset::open(&dset_path, lat_name, lon_name)?;
+    section::profile(&dset, lng, lat, expr)
+}
+
+/// Values at every level along a lng/lat path, see `section::section`
+pub fn get_section(
+    dset_path: impl Into<DatasetPath>,
+    path: &[(f64, f64)],
+    spacing_km: Option<f64>,
+    expr: &Expr,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Section> {
+    let dset_path = dset_path.into();
+    let dset = Dataset::open(&dset_path, lat_name, lon_name)?;
+    section::section(&dset, path, spacing_km, expr)
+}
+
+// Contour lines over the part of the dataset inside the bounds
+fn read_contours(
+    dset: &Dataset,
+    bounds: Option<Bounds>,
+    expr: &Expr,
+    options: &ContourOptions,
+) -> anyhow::Result<Vec<ContourLine>> {
+    let dset_bounds = dset.get_bounds();
+    let bounds = match bounds.map(|bounds| dset.source_bounds(bounds)) {
+        Some(None) => return Ok(vec![]),
+        Some(Some(bounds)) => match dset_bounds.intersect(&bounds) {
+            Some(bounds) => bounds,
+            None => return Ok(vec![]),
+        },
+        None => dset_bounds,
+    };
+
+    let values = dset.get_expr_values(expr, bounds)?.into_dimensionality::<ndarray::Ix2>()?;
+    let (lats, lons) = dset.get_coords(bounds);
+    let mut lines = contour_lines(&values.view(), &lons, &lats, options)?;
+    // Lines on projected grids are traced in x/y, and handed out in lon/lat like the rest
+    if let Some(crs) = dset.crs() {
+        for line in lines.iter_mut() {
+            crs.to_lng_lat(&mut line.points);
+        }
+    }
+    Ok(lines)
+}
+
+/// Contour lines as a GeoJSON FeatureCollection, over a lat/lng bounding box or the whole dataset
+pub fn get_contours(
+    dset_path: impl Into<DatasetPath>,
+    bounds: Option<Bounds>,
+    expr: &Expr,
+    options: &ContourOptions,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<serde_json::Value> {
+    let dset_path = dset_path.into();
+    let dset = Dataset::open(&dset_path, lat_name, lon_name)?;
+    let lines = read_contours(&dset, bounds, expr, options)?;
+    Ok(contour::to_geojson(&lines))
+}
+
+/// Contour lines for one tile of a tile matrix set as a Mapbox vector tile with a `contours` layer
+#[allow(clippy::too_many_arguments)]
+pub fn get_contour_tile(
+    dset_path: impl Into<DatasetPath>,
+    tms: &dyn TileMatrixSet,
+    tx: u32,
+    ty: u32,
+    zoom: u32,
+    expr: &Expr,
+    options: &ContourOptions,
+    lat_name: &str,
+    lon_name: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let dset_path = dset_path.into();
+    let dset = Dataset::open(&dset_path, lat_name, lon_name)?;
+    let tile_coord = TileCoord::new(tx, ty, zoom as u8);
+    let tile_bounds = tms
+        .tile_bounds(&tile_coord)
+        .ok_or_else(|| anyhow::anyhow!("No tile {}/{}/{} in {}", zoom, tx, ty, tms.id()))?;
+
+    // Read a little past the tile so lines run cleanly across tile edges
+    let buffer = 8.0;
+    let read_bounds = match tms.lat_lng_bounds(&tile_coord) {
+        Some(bounds) => {
+            let (dx, dy) = bounds.get_pixel_lengths(TILE_SIZE, TILE_SIZE);
+            bounds.expand(dx * buffer, dy * buffer)
+        }
+        None => {
+            let (dx, dy) = tile_bounds.get_pixel_lengths(TILE_SIZE, TILE_SIZE);
+            tms.projection().lat_lng_envelope(tile_bounds.expand(dx * buffer, dy * buffer))
+        }
+    };
+    let lines = read_contours(&dset, Some(read_bounds), expr, options)?;
+
+    // Tile units run right and down from the top left corner
+    let projection = tms.projection();
+    let (width, height) = (tile_bounds.max_x - tile_bounds.min_x, tile_bounds.max_y - tile_bounds.min_y);
+    let to_tile = |(lng, lat): (f64, f64)| {
+        let (x, y) = projection.forward(lng, lat);
+        let extent = mvt::EXTENT as f64;
+        ((x - tile_bounds.min_x) / width * extent, (tile_bounds.max_y - y) / height * extent)
+    };
+
+    let tile_buffer = buffer * mvt::EXTENT as f64 / TILE_SIZE as f64;
+    let features: Vec<mvt::LineFeature> = lines
+        .into_iter()
+        .map(|line| {
+            let points: Vec<(f64, f64)> = line.points.iter().map(|point| to_tile(*point)).collect();
+            mvt::LineFeature {
+                lines: mvt::clip_line(&points, tile_buffer),
+                properties: vec![
+                    ("level".to_string(), mvt::PropertyValue::Double(line.level)),
+                    ("label".to_string(), mvt::PropertyValue::String(line.label)),
+                    ("major".to_string(), mvt::PropertyValue::Bool(line.major)),
+                ],
+            }
+        })
+        .collect();
+
+    Ok(mvt::encode_line_layer("contours", &features))
+}
+
+#[cfg(test)]
+mod tiler_tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_grid_edge() {
+        // A dataset that only touches the right edge of the image
+        let read = |_| Ok(ndarray::ArrayD::from_elem(vec![1, 1], 1.0));
+        let result = sample_grid(Bounds::new(10.0, 0.0, 20.0, 10.0), Bounds::new(0.0, 0.0, 10.0, 10.0), 10, 10, read);
+        assert!(result.unwrap().unwrap().iter().all(|v| v.is_nan()));
+
+        let read = |_| Ok(ndarray::ArrayD::from_elem(vec![5, 5], 1.0));
+        let result = sample_grid(Bounds::new(5.0, 0.0, 20.0, 5.0), Bounds::new(0.0, 0.0, 10.0, 10.0), 10, 10, read);
+        let result = result.unwrap().unwrap();
+        assert_eq!(result.iter().filter(|v| !v.is_nan()).count(), 25);
+        assert_eq!(result[99], 1.0);
+    }
+
+    #[test]
+    fn test_overview_tile() {
+        // A 4 degree box of 0.01 degree cells, so it takes a pyramid of two levels
+        let values = ndarray::Array2::from_shape_fn((400, 400), |(row, col)| (row + col) as f64);
+        let bytes = geotiff::encode(&[("band".to_string(), values)], (0.0, 4.0), (0.01, 0.01)).unwrap();
+        let path = crate::testing::TempPath::write("overview-tile.tif", bytes);
+        let (tms, expr) = (tms::WorldCrs84Quad, Expr::variable("band_1"));
+
+        // Pixels finer than two cells read the full grid and leave the cache alone
+        let overviews = OverviewCache::new(1);
+        let tile = get_overview_tile(&path, &tms, 256, 123, 8, TILE_SIZE, &expr, "lat", "lon", &overviews).unwrap();
+        assert!(tile.unwrap().iter().any(|v| !v.is_nan()));
+        assert_eq!(overviews.len(), 0);
+
+        let tile = get_overview_tile(&path, &tms, 1, 0, 0, TILE_SIZE, &expr, "lat", "lon", &overviews).unwrap();
+        assert!(tile.unwrap().iter().any(|v| !v.is_nan()));
+        assert_eq!(overviews.len(), 1);
+
+        // Pyramids go down to the tile size, and grids that fit in a tile are read whole
+        let overviews = OverviewCache::new(2);
+        let tile = get_overview_tile(&path, &tms, 1, 0, 0, 128, &expr, "lat", "lon", &overviews).unwrap();
+        assert_eq!(tile.unwrap().len(), 128 * 128);
+        let tile = get_overview_tile(&path, &tms, 1, 0, 0, TILE_SIZE, &expr, "lat", "lon", &overviews).unwrap();
+        assert!(tile.is_some());
+        assert_eq!(overviews.len(), 2);
+        let tile = get_overview_tile(&path, &tms, 1, 0, 0, 512, &expr, "lat", "lon", &overviews).unwrap();
+        assert!(tile.unwrap().iter().any(|v| !v.is_nan()));
+        assert_eq!(overviews.len(), 2);
+    }
+
+    #[test]
+    fn test_surface_tile_sets() {
+        // North of 60N, with values of one per degree of latitude, so slopes are about 1 / 111 per km everywhere
+        let values = ndarray::Array2::from_shape_fn((120, 1440), |(row, _)| 90.0 - (row as f64 + 0.5) / 4.0);
+        let bytes = geotiff::encode(&[("band".to_string(), values)], (-180.0, 90.0), (0.25, 0.25)).unwrap();
+        let path = crate::testing::TempPath::write("surface-sets.tif", bytes);
+        let expr = Expr::variable("band_1");
+
+        // Pixels don't line up with cells, so single slopes step up and down around the mean
+        let mean_slope = |tms: &dyn TileMatrixSet, (x, y, zoom): (u32, u32, u32)| {
+            let tile = get_surface_tile(&path, tms, x, y, zoom, 64, &expr, &Surface::Gradient, "lat", "lon");
+            let (_, surface) = tile.unwrap().unwrap();
+            let slopes: Vec<f64> = surface.into_iter().filter(|s| !s.is_nan()).collect();
+            slopes.iter().sum::<f64>() / slopes.len() as f64
+        };
+        let expected = 1.0 / 111.32;
+        for (name, slope) in [
+            ("mercator", mean_slope(&tms::WebMercatorQuad, (2, 0, 2))),
+            ("crs84", mean_slope(&tms::WorldCrs84Quad, (4, 0, 2))),
+            ("polar", mean_slope(&tms::PolarQuad::epsg3413(), (1, 1, 2))),
+        ] {
+            assert!((slope / expected - 1.0).abs() < 0.1, "{}: {}", name, slope);
+        }
+    }
+}
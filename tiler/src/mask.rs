@@ -0,0 +1,150 @@
+//! Masks that clip rendered tiles to a region, e.g. a survey area or the sea, so pixels outside are transparent
+
+use crate::bounds::Bounds;
+use crate::coordinates::TileCoord;
+use crate::dataset::DatasetPath;
+use crate::expr::Expr;
+use crate::tms::TileMatrixSet;
+use crate::region::Region;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
+pub enum MaskKind {
+    /// Pixels inside polygons, e.g. from GeoJSON or a shapefile
+    Region(Region),
+    /// Pixels where a variable is non-zero and not missing, e.g. a land/sea mask. The variable is read from its
+    /// own file if there is one, otherwise from the dataset being rendered.
+    Variable { name: String, path: Option<PathBuf> },
+}
+
+/// The pixels a tile keeps, with `invert` keeping the others instead, e.g. to clip land polygons out of ocean tiles
+#[derive(Debug, Clone)]
+pub struct Mask {
+    pub kind: MaskKind,
+    pub invert: bool,
+}
+
+/// The centre of every pixel of a width x height image covering the bounds, with row 0 at the top
+pub fn image_pixels(bounds: Bounds, width: usize, height: usize) -> Vec<(f64, f64)> {
+    let (dx, dy) = bounds.get_pixel_lengths(width, height);
+    (0..height)
+        .flat_map(|row| {
+            (0..width).map(move |col| (bounds.min_x + (col as f64 + 0.5) * dx, bounds.max_y - (row as f64 + 0.5) * dy))
+        })
+        .collect()
+}
+
+/// The lng/lat of every pixel centre of a tile, with row 0 at the top, sampled the same way as its values
+pub fn tile_pixels(tms: &dyn TileMatrixSet, tile_coord: &TileCoord, tile_size: usize) -> Option<Vec<(f64, f64)>> {
+    if let Some(bounds) = tms.lat_lng_bounds(tile_coord) {
+        return Some(image_pixels(bounds, tile_size, tile_size));
+    }
+    let projection = tms.projection();
+    let pixels = image_pixels(tms.tile_bounds(tile_coord)?, tile_size, tile_size);
+    Some(pixels.into_iter().map(|(x, y)| projection.inverse(x, y)).collect())
+}
+
+impl Mask {
+    /// Whether each pixel of a tile is kept, with row 0 at the top
+    #[allow(clippy::too_many_arguments)]
+    pub fn kept_pixels(
+        &self,
+        dset_path: &DatasetPath,
+        tms: &dyn TileMatrixSet,
+        tx: u32,
+        ty: u32,
+        zoom: u32,
+        tile_size: usize,
+        lat_name: &str,
+        lon_name: &str,
+    ) -> anyhow::Result<Vec<bool>> {
+        let inside = match &self.kind {
+            MaskKind::Region(region) => {
+                let tile_coord = TileCoord::new(tx, ty, zoom as u8);
+                // Tiles that are lat/lon boxes are filled row by row, others test each pixel's lng/lat
+                match tms.lat_lng_bounds(&tile_coord) {
+                    Some(bounds) => region.rasterize(bounds, tile_size, tile_size),
+                    None => match tile_pixels(tms, &tile_coord, tile_size) {
+                        Some(pixels) => region.contains_all(&pixels),
+                        None => vec![false; tile_size * tile_size],
+                    },
+                }
+            }
+            MaskKind::Variable { name, path } => {
+                let path = path.as_ref().map_or(dset_path.clone(), DatasetPath::from);
+                let expr = Expr::variable(name);
+                match crate::get_expr_tile(path, tms, tx, ty, zoom, tile_size, &expr, lat_name, lon_name)? {
+                    Some(values) => values.into_iter().map(|v| !v.is_nan() && v != 0.0).collect(),
+                    None => vec![false; tile_size * tile_size],
+                }
+            }
+        };
+        Ok(inside.into_iter().map(|inside| inside != self.invert).collect())
+    }
+}
+
+#[cfg(test)]
+mod mask_tests {
+    use super::*;
+    use crate::tms::{WebMercatorQuad, WorldCrs84Quad};
+    use serde_json::json;
+
+    #[test]
+    fn test_region_mask() {
+        // The eastern half of the northern hemisphere
+        let geojson = json!({
+            "type": "Polygon",
+            "coordinates": [[[0.0, 0.0], [180.0, 0.0], [180.0, 85.0], [0.0, 85.0], [0.0, 0.0]]]
+        });
+        let mut mask = Mask {
+            kind: MaskKind::Region(Region::from_geojson(&geojson).unwrap()),
+            invert: false,
+        };
+        let dset_path = DatasetPath::from(PathBuf::from("unused.nc"));
+        let kept = mask.kept_pixels(&dset_path, &WebMercatorQuad, 0, 0, 0, 4, "lat", "lon").unwrap();
+        let expected = [false, false, true, true, false, false, true, true, false, false, false, false];
+        assert_eq!(kept[..12], expected);
+        assert_eq!(kept[12..], [false; 4]);
+
+        mask.invert = true;
+        let kept = mask.kept_pixels(&dset_path, &WorldCrs84Quad, 1, 0, 0, 2, "lat", "lon").unwrap();
+        assert_eq!(kept, [false, false, true, true]);
+    }
+
+    #[test]
+    fn test_variable_mask() {
+        // A global mask of 1 degree cells that is 1 in the northern hemisphere, and missing east of 120E
+        let values = ndarray::Array2::from_shape_fn((180, 360), |(row, col)| match (row, col) {
+            (_, 300..) => f64::NAN,
+            (..90, _) => 1.0,
+            _ => 0.0,
+        });
+        let bytes = crate::geotiff::encode(&[("mask".to_string(), values)], (-180.0, 90.0), (1.0, 1.0)).unwrap();
+        let path = crate::testing::TempPath::write("mask.tif", bytes);
+
+        let mask = Mask {
+            kind: MaskKind::Variable {
+                name: "band_1".to_string(),
+                path: Some(path.to_path_buf()),
+            },
+            invert: false,
+        };
+        let dset_path = DatasetPath::from(PathBuf::from("unused.nc"));
+        let kept = mask.kept_pixels(&dset_path, &WorldCrs84Quad, 1, 0, 0, 4, "lat", "lon").unwrap();
+        let north = [true, true, true, false];
+        assert_eq!(kept.chunks(4).collect::<Vec<_>>(), [north, north, [false; 4], [false; 4]]);
+    }
+
+    #[test]
+    fn test_tile_coverage() {
+        // A 10 degree box of 1 degree cells north east of 0,0
+        let values = ndarray::Array2::from_elem((10, 10), f64::NAN);
+        let bytes = crate::geotiff::encode(&[("band".to_string(), values)], (0.0, 10.0), (1.0, 1.0)).unwrap();
+        let path = crate::testing::TempPath::write("coverage.tif", bytes);
+
+        // Pixels 5 degrees wide over the eastern hemisphere, so two columns and two rows are over the box
+        let inside = crate::get_tile_coverage(&path, &WorldCrs84Quad, 1, 0, 0, 36, "lat", "lon").unwrap();
+        let inside: Vec<usize> = inside.iter().enumerate().filter(|(_, inside)| **inside).map(|(i, _)| i).collect();
+        assert_eq!(inside, [16 * 36, 16 * 36 + 1, 17 * 36, 17 * 36 + 1]);
+    }
+}
@@ -0,0 +1,225 @@
+//! Minimal Mapbox Vector Tile encoder for line layers, see
+//! https://github.com/mapbox/vector-tile-spec/tree/master/2.1
+
+/// Tile coordinate range of the geometry
+pub const EXTENT: u32 = 4096;
+
+// Protobuf wire types
+const VARINT: u32 = 0;
+const LENGTH_DELIMITED: u32 = 2;
+
+// Geometry commands
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+
+const LINESTRING: u64 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    String(String),
+    Double(f64),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineFeature {
+    /// Lines in tile coordinates, 0..EXTENT
+    pub lines: Vec<Vec<(i32, i32)>>,
+    pub properties: Vec<(String, PropertyValue)>,
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_key(buf: &mut Vec<u8>, field: u32, wire_type: u32) {
+    write_varint(buf, ((field << 3) | wire_type) as u64);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_key(buf, field, LENGTH_DELIMITED);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_uint(buf: &mut Vec<u8>, field: u32, value: u64) {
+    write_key(buf, field, VARINT);
+    write_varint(buf, value);
+}
+
+fn write_packed(buf: &mut Vec<u8>, field: u32, values: &[u32]) {
+    let mut packed = Vec::new();
+    for v in values {
+        write_varint(&mut packed, *v as u64);
+    }
+    write_bytes(buf, field, &packed);
+}
+
+fn zigzag(n: i32) -> u32 {
+    ((n << 1) ^ (n >> 31)) as u32
+}
+
+fn command(id: u32, count: u32) -> u32 {
+    (id & 0x7) | (count << 3)
+}
+
+// Cursor relative MoveTo/LineTo commands for a set of lines
+fn encode_geometry(lines: &[Vec<(i32, i32)>]) -> Vec<u32> {
+    let mut geometry = Vec::new();
+    let mut cursor = (0, 0);
+    for line in lines.iter().filter(|line| line.len() >= 2) {
+        let mut delta = |(x, y): (i32, i32)| {
+            let d = [zigzag(x - cursor.0), zigzag(y - cursor.1)];
+            cursor = (x, y);
+            d
+        };
+        geometry.push(command(MOVE_TO, 1));
+        geometry.extend(delta(line[0]));
+        geometry.push(command(LINE_TO, line.len() as u32 - 1));
+        for p in &line[1..] {
+            geometry.extend(delta(*p));
+        }
+    }
+    geometry
+}
+
+fn encode_value(value: &PropertyValue) -> Vec<u8> {
+    let mut buf = Vec::new();
+    match value {
+        PropertyValue::String(s) => write_bytes(&mut buf, 1, s.as_bytes()),
+        PropertyValue::Double(d) => {
+            write_key(&mut buf, 3, 1);
+            buf.extend_from_slice(&d.to_le_bytes());
+        }
+        PropertyValue::Bool(b) => write_uint(&mut buf, 7, *b as u64),
+    }
+    buf
+}
+
+/// Encode a tile with a single layer of line features
+pub fn encode_line_layer(name: &str, features: &[LineFeature]) -> Vec<u8> {
+    let mut keys: Vec<&str> = Vec::new();
+    let mut values: Vec<&PropertyValue> = Vec::new();
+
+    let mut layer = Vec::new();
+    write_uint(&mut layer, 15, 2);
+    write_bytes(&mut layer, 1, name.as_bytes());
+
+    for feature in features {
+        let geometry = encode_geometry(&feature.lines);
+        if geometry.is_empty() {
+            continue;
+        }
+
+        let mut tags = Vec::new();
+        for (key, value) in &feature.properties {
+            let key_index = keys.iter().position(|k| k == key).unwrap_or_else(|| {
+                keys.push(key);
+                keys.len() - 1
+            });
+            let value_index = values.iter().position(|v| *v == value).unwrap_or_else(|| {
+                values.push(value);
+                values.len() - 1
+            });
+            tags.extend([key_index as u32, value_index as u32]);
+        }
+
+        let mut buf = Vec::new();
+        write_packed(&mut buf, 2, &tags);
+        write_uint(&mut buf, 3, LINESTRING);
+        write_packed(&mut buf, 4, &geometry);
+        write_bytes(&mut layer, 2, &buf);
+    }
+
+    for key in keys {
+        write_bytes(&mut layer, 3, key.as_bytes());
+    }
+    for value in values {
+        write_bytes(&mut layer, 4, &encode_value(value));
+    }
+    write_uint(&mut layer, 5, EXTENT as u64);
+
+    let mut tile = Vec::new();
+    write_bytes(&mut tile, 3, &layer);
+    tile
+}
+
+/// Split a line into the runs that are inside the tile plus `buffer` tile units.
+/// Points are kept whole rather than cut at the box edge, which the buffer hides.
+pub fn clip_line(points: &[(f64, f64)], buffer: f64) -> Vec<Vec<(i32, i32)>> {
+    let (lo, hi) = (-buffer, EXTENT as f64 + buffer);
+    let inside = |(x, y): (f64, f64)| x >= lo && x <= hi && y >= lo && y <= hi;
+
+    let mut runs = Vec::new();
+    let mut run: Vec<(i32, i32)> = Vec::new();
+    for (i, p) in points.iter().enumerate() {
+        // Keep the points either side of a run so the line reaches the buffer edge
+        let keep = inside(*p)
+            || (i > 0 && inside(points[i - 1]))
+            || (i + 1 < points.len() && inside(points[i + 1]));
+        if keep {
+            let p = (p.0.round() as i32, p.1.round() as i32);
+            if run.last() != Some(&p) {
+                run.push(p);
+            }
+        } else if !run.is_empty() {
+            runs.push(std::mem::take(&mut run));
+        }
+    }
+    runs.push(run);
+    runs.retain(|run| run.len() >= 2);
+    runs
+}
+
+#[cfg(test)]
+mod mvt_tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, 300);
+        assert_eq!(buf, vec![0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_zigzag() {
+        assert_eq!(zigzag(0), 0);
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+        assert_eq!(zigzag(-2), 3);
+    }
+
+    #[test]
+    fn test_encode_geometry() {
+        // The example from the spec: a line through (2,2), (2,10), (10,10)
+        let geometry = encode_geometry(&[vec![(2, 2), (2, 10), (10, 10)]]);
+        assert_eq!(geometry, vec![9, 4, 4, 18, 0, 16, 16, 0]);
+    }
+
+    #[test]
+    fn test_encode_line_layer() {
+        let feature = LineFeature {
+            lines: vec![vec![(0, 0), (10, 10)]],
+            properties: vec![("major".to_string(), PropertyValue::Bool(true))],
+        };
+        let tile = encode_line_layer("contours", &[feature.clone(), feature]);
+        // Tile field 3, then the layer length
+        assert_eq!(tile[0], 0x1a);
+        let name = b"contours";
+        assert!(tile.windows(name.len()).any(|w| w == name));
+        // Shared keys are only written once
+        assert_eq!(tile.windows(5).filter(|w| w == b"major").count(), 1);
+    }
+
+    #[test]
+    fn test_clip_line() {
+        let points = [(-500.0, 10.0), (-300.0, 10.0), (100.0, 10.0), (5000.0, 10.0), (6000.0, 10.0)];
+        let runs = clip_line(&points, 64.0);
+        assert_eq!(runs, vec![vec![(-300, 10), (100, 10), (5000, 10)]]);
+    }
+}
@@ -0,0 +1,292 @@
+use crate::bounds::Bounds;
+use crate::dataset::{Dataset, DatasetPath};
+use crate::expr::Expr;
+use ndarray::{Array2, ArrayView2};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// A reduced resolution copy of a grid, with rows and columns in ascending lat and lon order
+#[derive(Debug, Clone)]
+pub struct Overview {
+    lats: Vec<f64>,
+    lons: Vec<f64>,
+    values: Array2<f64>,
+}
+
+// Mean of each run of `factor` coordinates
+fn downsample_coords(coords: &[f64], factor: usize) -> Vec<f64> {
+    coords.chunks(factor).map(|c| c.iter().sum::<f64>() / c.len() as f64).collect()
+}
+
+/// Average `factor` x `factor` blocks of cells, skipping NaN. Blocks at the far edges may be partial.
+pub fn downsample(values: &ArrayView2<f64>, factor: usize) -> Array2<f64> {
+    let (height, width) = values.dim();
+    let (out_height, out_width) = (height.div_ceil(factor), width.div_ceil(factor));
+    Array2::from_shape_fn((out_height, out_width), |(row, col)| {
+        let (mut sum, mut count) = (0.0, 0);
+        for y in row * factor..((row + 1) * factor).min(height) {
+            for x in col * factor..((col + 1) * factor).min(width) {
+                let v = values[[y, x]];
+                if !v.is_nan() {
+                    sum += v;
+                    count += 1;
+                }
+            }
+        }
+        if count == 0 {
+            f64::NAN
+        } else {
+            sum / count as f64
+        }
+    })
+}
+
+// Index of the coordinate nearest to the value, in ascending coordinates
+pub(crate) fn nearest_index(coords: &[f64], value: f64) -> usize {
+    let i = coords.partition_point(|c| *c < value);
+    if i > 0 && (i == coords.len() || value - coords[i - 1] < coords[i] - value) {
+        i - 1
+    } else {
+        i
+    }
+}
+
+impl Overview {
+    pub fn new(lats: Vec<f64>, lons: Vec<f64>, values: Array2<f64>) -> Self {
+        Self { lats, lons, values }
+    }
+
+    pub fn get_bounds(&self) -> Bounds {
+        Bounds::new(self.lons[0], self.lats[0], *self.lons.last().unwrap(), *self.lats.last().unwrap())
+    }
+
+    /// Width and height of a cell in degrees
+    pub fn resolution(&self) -> (f64, f64) {
+        let step = |coords: &[f64]| match coords {
+            [first, .., last] => (last - first) / (coords.len() - 1) as f64,
+            _ => f64::INFINITY,
+        };
+        (step(&self.lons), step(&self.lats))
+    }
+
+    /// Values inside the bounds, selected the same way as `Dataset::get_values`
+    pub fn get_values(&self, bounds: Bounds) -> ndarray::ArrayD<f64> {
+        let lat_range = nearest_index(&self.lats, bounds.min_y)..nearest_index(&self.lats, bounds.max_y);
+        let lon_range = nearest_index(&self.lons, bounds.min_x)..nearest_index(&self.lons, bounds.max_x);
+        self.values.slice(ndarray::s![lat_range, lon_range]).to_owned().into_dyn()
+    }
+
+    fn downsample(&self, factor: usize) -> Self {
+        Self {
+            lats: downsample_coords(&self.lats, factor),
+            lons: downsample_coords(&self.lons, factor),
+            values: downsample(&self.values.view(), factor),
+        }
+    }
+}
+
+/// Overviews at 1/2, 1/4, 1/8, ... of the full resolution, down to the first one that fits in a tile
+#[derive(Debug, Clone, Default)]
+pub struct Pyramid {
+    levels: Vec<Overview>,
+}
+
+impl Pyramid {
+    /// Build every level from the full resolution grid
+    pub fn build(full: &Overview, tile_size: usize) -> Self {
+        let mut levels: Vec<Overview> = Vec::new();
+        loop {
+            let previous = levels.last().unwrap_or(full);
+            let (height, width) = previous.values.dim();
+            if (height <= tile_size && width <= tile_size) || height < 2 || width < 2 {
+                break;
+            }
+            levels.push(previous.downsample(2));
+        }
+        Self { levels }
+    }
+
+    pub fn levels(&self) -> &[Overview] {
+        &self.levels
+    }
+
+    /// The coarsest level with cells no larger than the target pixel size, in degrees.
+    /// None means only the full resolution data is fine enough.
+    pub fn level_for(&self, (dx, dy): (f64, f64)) -> Option<&Overview> {
+        self.levels.iter().rev().find(|level| {
+            let (x_res, y_res) = level.resolution();
+            x_res <= dx && y_res <= dy
+        })
+    }
+}
+
+/// Read the whole dataset at full resolution
+pub fn read_full(dset: &Dataset, expr: &Expr) -> anyhow::Result<Overview> {
+    let bounds = dset.get_bounds();
+    let values = dset.get_expr_values(expr, bounds)?.into_dimensionality::<ndarray::Ix2>()?;
+    let (lats, lons) = dset.get_coords(bounds);
+    Ok(Overview::new(lats, lons, values))
+}
+
+type PyramidKey = (PathBuf, usize, String, String, String, usize);
+
+struct CachedPyramid {
+    modified: Option<SystemTime>,
+    last_used: u64,
+    pyramid: Arc<Pyramid>,
+}
+
+/// Pyramids kept in memory by dataset file, expression and tile size. They're rebuilt when the file changes.
+pub struct OverviewCache {
+    capacity: usize,
+    entries: Mutex<(u64, HashMap<PyramidKey, CachedPyramid>)>,
+    // Held while building, so concurrent requests for a new dataset read it once
+    building: Mutex<()>,
+}
+
+impl OverviewCache {
+    /// Keep up to `capacity` pyramids. A capacity of 0 turns overviews off.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new((0, HashMap::new())),
+            building: Mutex::new(()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Number of pyramids held
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().1.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The cached pyramid, if it's still current
+    fn lookup(&self, key: &PyramidKey, modified: Option<SystemTime>) -> Option<Arc<Pyramid>> {
+        let mut entries = self.entries.lock().unwrap();
+        let (clock, map) = &mut *entries;
+        *clock += 1;
+        let entry = map.get_mut(key).filter(|entry| entry.modified == modified)?;
+        entry.last_used = *clock;
+        Some(entry.pyramid.clone())
+    }
+
+    /// The pyramid for the dataset and expression, building it on first use
+    pub fn get_or_build(
+        &self,
+        dset_path: &DatasetPath,
+        dset: &Dataset,
+        expr: &Expr,
+        lat_name: &str,
+        lon_name: &str,
+        tile_size: usize,
+    ) -> anyhow::Result<Arc<Pyramid>> {
+        let key = (
+            dset_path.path.clone(),
+            dset_path.time_index,
+            format!("{:?}", expr),
+            lat_name.to_string(),
+            lon_name.to_string(),
+            tile_size,
+        );
+        let modified = std::fs::metadata(&dset_path.path).and_then(|m| m.modified()).ok();
+
+        if let Some(pyramid) = self.lookup(&key, modified) {
+            return Ok(pyramid);
+        }
+        let _building = self.building.lock().unwrap();
+        if let Some(pyramid) = self.lookup(&key, modified) {
+            return Ok(pyramid);
+        }
+
+        // Built without holding the entries lock, so cached pyramids can still be served meanwhile
+        let pyramid = Arc::new(Pyramid::build(&read_full(dset, expr)?, tile_size));
+
+        let mut entries = self.entries.lock().unwrap();
+        let (clock, map) = &mut *entries;
+        if !map.contains_key(&key) && map.len() >= self.capacity {
+            let oldest = map.iter().min_by_key(|(_, e)| e.last_used).map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                map.remove(&oldest);
+            }
+        }
+        map.insert(
+            key,
+            CachedPyramid {
+                modified,
+                last_used: *clock,
+                pyramid: pyramid.clone(),
+            },
+        );
+        Ok(pyramid)
+    }
+}
+
+#[cfg(test)]
+mod overview_tests {
+    use super::*;
+
+    fn grid(width: usize, height: usize) -> Overview {
+        let lats = (0..height).map(|i| i as f64).collect();
+        let lons = (0..width).map(|i| i as f64).collect();
+        let values = Array2::from_shape_fn((height, width), |(y, x)| (y * width + x) as f64);
+        Overview::new(lats, lons, values)
+    }
+
+    #[test]
+    fn test_downsample() {
+        let values = ndarray::arr2(&[[1.0, 3.0, 5.0], [f64::NAN, 5.0, 7.0], [f64::NAN, f64::NAN, 9.0]]);
+        let result = downsample(&values.view(), 2);
+        assert_eq!(result.dim(), (2, 2));
+        assert_relative_eq!(result[[0, 0]], 3.0);
+        assert_relative_eq!(result[[0, 1]], 6.0);
+        assert!(result[[1, 0]].is_nan());
+        assert_relative_eq!(result[[1, 1]], 9.0);
+    }
+
+    #[test]
+    fn test_pyramid_levels() {
+        let full = grid(40, 20);
+        let pyramid = Pyramid::build(&full, 8);
+        let dims: Vec<_> = pyramid.levels().iter().map(|l| l.values.dim()).collect();
+        assert_eq!(dims, vec![(10, 20), (5, 10), (3, 5)]);
+
+        // Coordinates stay centered on the cells they cover
+        let level = &pyramid.levels()[0];
+        assert_relative_eq!(level.lons[0], 0.5);
+        assert_relative_eq!(level.resolution().0, 2.0);
+        let bounds = level.get_bounds();
+        assert_relative_eq!(bounds.min_x, 0.5);
+        assert_relative_eq!(bounds.max_x, 38.5);
+
+        // A grid that already fits needs no overviews
+        assert!(Pyramid::build(&grid(8, 8), 8).levels().is_empty());
+    }
+
+    #[test]
+    fn test_level_for() {
+        let pyramid = Pyramid::build(&grid(64, 64), 8);
+        assert!(pyramid.level_for((1.0, 1.0)).is_none());
+        assert_relative_eq!(pyramid.level_for((2.0, 2.0)).unwrap().resolution().0, 2.0);
+        assert_relative_eq!(pyramid.level_for((5.0, 5.0)).unwrap().resolution().0, 4.0);
+        assert_relative_eq!(pyramid.level_for((100.0, 100.0)).unwrap().resolution().0, 8.0);
+        // Both directions have to be fine enough
+        assert_relative_eq!(pyramid.level_for((100.0, 2.5)).unwrap().resolution().1, 2.0);
+    }
+
+    #[test]
+    fn test_get_values() {
+        let overview = grid(10, 10);
+        let values = overview.get_values(Bounds::new(2.0, 3.0, 5.0, 7.0));
+        assert_eq!(values.shape(), &[4, 3]);
+        assert_relative_eq!(values[[0, 0]], 32.0);
+    }
+}
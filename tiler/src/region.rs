@@ -0,0 +1,257 @@
+//! Polygons in lng/lat, from GeoJSON or shapefiles, for zonal statistics, masks and tile coverage
+
+use crate::bounds::Bounds;
+use anyhow::anyhow;
+use serde_json::Value;
+
+/// A closed line of lng/lat points, which needn't repeat the first point
+pub type Ring = Vec<(f64, f64)>;
+
+/// Polygons in lng/lat, each an outer ring followed by its holes. Points inside an odd number of a polygon's
+/// rings are inside it, so shapefile records with several parts work as polygons too.
+#[derive(Debug, Clone)]
+pub struct Region {
+    polygons: Vec<(Vec<Ring>, Bounds)>,
+}
+
+fn union(a: Bounds, b: Bounds) -> Bounds {
+    Bounds::new(a.min_x.min(b.min_x), a.min_y.min(b.min_y), a.max_x.max(b.max_x), a.max_y.max(b.max_y))
+}
+
+fn ring_bounds(ring: &[(f64, f64)]) -> Bounds {
+    ring.iter().fold(Bounds::new(f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY), |b, (x, y)| {
+        Bounds::new(b.min_x.min(*x), b.min_y.min(*y), b.max_x.max(*x), b.max_y.max(*y))
+    })
+}
+
+fn in_bounds(b: &Bounds, (x, y): (f64, f64)) -> bool {
+    x >= b.min_x && x <= b.max_x && y >= b.min_y && y <= b.max_y
+}
+
+// A line from one point of a ring to the next
+type Edge = ((f64, f64), (f64, f64));
+
+fn ring_edges(ring: &Ring) -> impl Iterator<Item = Edge> + '_ {
+    ring.iter().enumerate().map(|(i, point)| (ring[(i + ring.len() - 1) % ring.len()], *point))
+}
+
+// Where an edge crosses the parallel at y, if it does
+fn crossing(&((x0, y0), (x1, y1)): &Edge, y: f64) -> Option<f64> {
+    ((y1 > y) != (y0 > y)).then(|| x0 + (y - y0) / (y1 - y0) * (x1 - x0))
+}
+
+// Even-odd crossings of a ray from the point towards +x
+fn edges_contain(edges: impl Iterator<Item = Edge>, (x, y): (f64, f64)) -> bool {
+    edges.filter(|edge| crossing(edge, y).is_some_and(|cross| x < cross)).count() % 2 == 1
+}
+
+fn parse_ring(value: &Value) -> anyhow::Result<Ring> {
+    let invalid = || anyhow!("Invalid GeoJSON linear ring");
+    let ring: Ring = value
+        .as_array()
+        .ok_or_else(invalid)?
+        .iter()
+        .map(|position| {
+            let coord = |i: usize| position.get(i).and_then(Value::as_f64);
+            match (coord(0), coord(1)) {
+                (Some(x), Some(y)) => Ok((x, y)),
+                _ => Err(invalid()),
+            }
+        })
+        .collect::<anyhow::Result<_>>()?;
+    match ring.len() >= 3 {
+        true => Ok(ring),
+        false => Err(invalid()),
+    }
+}
+
+fn parse_polygon(value: &Value) -> anyhow::Result<Vec<Ring>> {
+    let rings = value.as_array().ok_or_else(|| anyhow!("Invalid GeoJSON polygon"))?;
+    let rings: Vec<Ring> = rings.iter().map(parse_ring).collect::<anyhow::Result<_>>()?;
+    match rings.is_empty() {
+        true => Err(anyhow!("GeoJSON polygon has no rings")),
+        false => Ok(rings),
+    }
+}
+
+impl Region {
+    /// The polygons of a GeoJSON Polygon, MultiPolygon, GeometryCollection, Feature or FeatureCollection
+    pub fn from_geojson(geojson: &Value) -> anyhow::Result<Self> {
+        let mut polygons = Vec::new();
+        collect_polygons(geojson, &mut polygons)?;
+        Self::from_polygons(polygons).ok_or_else(|| anyhow!("GeoJSON has no polygons"))
+    }
+
+    /// A region from polygon rings, or None if there are no rings
+    pub fn from_polygons(polygons: Vec<Vec<Ring>>) -> Option<Self> {
+        let polygons: Vec<(Vec<Ring>, Bounds)> = polygons
+            .into_iter()
+            .filter(|rings| !rings.is_empty())
+            .map(|rings| {
+                let bounds = rings.iter().map(|ring| ring_bounds(ring)).reduce(union).unwrap();
+                (rings, bounds)
+            })
+            .collect();
+        (!polygons.is_empty()).then_some(Self { polygons })
+    }
+
+    /// Every ring of every polygon, outer rings and holes alike
+    pub fn rings(&self) -> impl Iterator<Item = &Ring> {
+        self.polygons.iter().flat_map(|(rings, _)| rings)
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        self.polygons.iter().map(|(_, bounds)| *bounds).reduce(union).unwrap()
+    }
+
+    /// Whether a lng/lat point is inside any polygon, and not in one of its holes
+    pub fn contains(&self, point: (f64, f64)) -> bool {
+        self.polygons
+            .iter()
+            .any(|(rings, b)| in_bounds(b, point) && edges_contain(rings.iter().flat_map(ring_edges), point))
+    }
+
+    /// Whether each lng/lat point is inside, like `contains` but only testing the edges of the polygons near the
+    /// points
+    pub fn contains_all(&self, points: &[(f64, f64)]) -> Vec<bool> {
+        let polygons = self.edges_within(ring_bounds(points));
+        let contains = |point: &(f64, f64)| {
+            polygons.iter().any(|(edges, b)| in_bounds(b, *point) && edges_contain(edges.iter().copied(), *point))
+        };
+        points.iter().map(contains).collect()
+    }
+
+    /// Whether the centre of each pixel of a width x height lng/lat image covering the bounds is inside, with row 0
+    /// at the top. Rows are filled between the edges that cross them, rather than testing every pixel.
+    pub fn rasterize(&self, bounds: Bounds, width: usize, height: usize) -> Vec<bool> {
+        let (dx, dy) = bounds.get_pixel_lengths(width, height);
+        let polygons = self.edges_within(bounds);
+        let mut inside = vec![false; width * height];
+        let mut crossings = Vec::new();
+        for (row, pixels) in inside.chunks_mut(width).enumerate() {
+            let y = bounds.max_y - (row as f64 + 0.5) * dy;
+            for (edges, _) in polygons.iter().filter(|(_, b)| y >= b.min_y && y <= b.max_y) {
+                crossings.clear();
+                crossings.extend(edges.iter().filter_map(|edge| crossing(edge, y)));
+                crossings.sort_by(f64::total_cmp);
+                // A pixel is inside when an odd number of crossings are left of it, or on it
+                let mut left = 0;
+                for (col, pixel) in pixels.iter_mut().enumerate() {
+                    let x = bounds.min_x + (col as f64 + 0.5) * dx;
+                    while left < crossings.len() && crossings[left] <= x {
+                        left += 1;
+                    }
+                    *pixel |= left % 2 == 1;
+                }
+            }
+        }
+        inside
+    }
+
+    // The polygons that overlap the bounds, as their edges that cross its parallels, which are the only ones rays
+    // from points in the bounds can cross
+    fn edges_within(&self, bounds: Bounds) -> Vec<(Vec<Edge>, Bounds)> {
+        self.polygons
+            .iter()
+            .filter(|(_, b)| b.intersect(&bounds).is_some())
+            .map(|(rings, b)| {
+                let edges = rings
+                    .iter()
+                    .flat_map(ring_edges)
+                    .filter(|((_, y0), (_, y1))| y0.max(*y1) >= bounds.min_y && y0.min(*y1) <= bounds.max_y)
+                    .collect();
+                (edges, *b)
+            })
+            .collect()
+    }
+}
+
+fn collect_polygons(value: &Value, polygons: &mut Vec<Vec<Ring>>) -> anyhow::Result<()> {
+    let coordinates = || value.get("coordinates").ok_or_else(|| anyhow!("GeoJSON geometry has no coordinates"));
+    match value.get("type").and_then(Value::as_str) {
+        Some("Polygon") => polygons.push(parse_polygon(coordinates()?)?),
+        Some("MultiPolygon") => {
+            let multi = coordinates()?.as_array().ok_or_else(|| anyhow!("Invalid GeoJSON multipolygon"))?;
+            for polygon in multi {
+                polygons.push(parse_polygon(polygon)?);
+            }
+        }
+        Some("Feature") => match value.get("geometry") {
+            Some(geometry) if !geometry.is_null() => collect_polygons(geometry, polygons)?,
+            _ => {}
+        },
+        Some("FeatureCollection") | Some("GeometryCollection") => {
+            let key = if value.get("features").is_some() { "features" } else { "geometries" };
+            for member in value.get(key).and_then(Value::as_array).into_iter().flatten() {
+                collect_polygons(member, polygons)?;
+            }
+        }
+        Some(other) => return Err(anyhow!("Only polygons and multipolygons are supported, not {}", other)),
+        None => return Err(anyhow!("Not a GeoJSON object")),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod region_tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_region() {
+        let square = json!({
+            "type": "Feature",
+            "properties": {},
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [
+                    [[0.0, 0.0], [4.0, 0.0], [4.0, 4.0], [0.0, 4.0], [0.0, 0.0]],
+                    [[1.0, 1.0], [2.0, 1.0], [2.0, 2.0], [1.0, 2.0], [1.0, 1.0]]
+                ]
+            }
+        });
+        let region = Region::from_geojson(&square).unwrap();
+        assert!(region.contains((3.0, 3.0)));
+        assert!(!region.contains((1.5, 1.5)));
+        assert!(!region.contains((5.0, 3.0)));
+
+        let collection = json!({
+            "type": "FeatureCollection",
+            "features": [square, {
+                "type": "Feature",
+                "geometry": {"type": "MultiPolygon", "coordinates": [[[[10.0, 10.0], [11.0, 10.0], [11.0, 11.0]]]]}
+            }]
+        });
+        let region = Region::from_geojson(&collection).unwrap();
+        assert!(region.contains((10.8, 10.5)));
+        let bounds = region.bounds();
+        assert_eq!((bounds.min_x, bounds.min_y, bounds.max_x, bounds.max_y), (0.0, 0.0, 11.0, 11.0));
+
+        assert!(Region::from_geojson(&json!({"type": "Point", "coordinates": [1.0, 2.0]})).is_err());
+        assert!(Region::from_geojson(&json!({"type": "Polygon", "coordinates": [[[0.0, 0.0], [1.0, 1.0]]]})).is_err());
+    }
+
+    #[test]
+    fn test_rasterize() {
+        // A square with a hole, and a triangle that is a separate polygon
+        let region = Region::from_polygons(vec![
+            vec![
+                vec![(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)],
+                vec![(1.0, 1.0), (2.0, 1.0), (2.0, 2.0), (1.0, 2.0)],
+            ],
+            vec![vec![(5.0, 1.0), (7.0, 1.0), (5.0, 3.0)]],
+        ])
+        .unwrap();
+        // Quarter degree pixels over part of the region, so some centres sit on the triangle's diagonal
+        let bounds = Bounds::new(-0.5, 0.5, 6.5, 3.5);
+        let pixels = crate::mask::image_pixels(bounds, 28, 12);
+        let expected: Vec<bool> = pixels.iter().map(|pixel| region.contains(*pixel)).collect();
+        assert_eq!(region.rasterize(bounds, 28, 12), expected);
+        assert_eq!(region.contains_all(&pixels), expected);
+        // 16 x 12 pixels of the square less 4 x 4 in the hole, and the triangle's rows from 6 pixels down to none,
+        // as centres on its diagonal are outside
+        assert_eq!(expected.iter().filter(|inside| **inside).count(), 176 + 27);
+
+        assert!(region.rasterize(Bounds::new(10.0, 10.0, 11.0, 11.0), 4, 4).iter().all(|inside| !inside));
+    }
+}
@@ -0,0 +1,266 @@
+//! Vertical sections of 3-D and 4-D model output: depth profiles at a point, and depth x distance transects along a
+//! path
+
+use crate::dataset::{Dataset, VerticalAxis};
+use crate::expr::Expr;
+use anyhow::anyhow;
+use ndarray::Array2;
+use serde::Serialize;
+
+/// Most points sampled along one transect
+pub const MAX_SAMPLES: usize = 5000;
+/// Points sampled along a transect when no spacing is given
+pub const DEFAULT_SAMPLES: usize = 200;
+
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+/// Great circle distance between lng/lat points in km
+pub fn distance_km((lng0, lat0): (f64, f64), (lng1, lat1): (f64, f64)) -> f64 {
+    let (phi0, phi1) = (lat0.to_radians(), lat1.to_radians());
+    let (d_phi, d_lambda) = ((lat1 - lat0).to_radians(), (lng1 - lng0).to_radians());
+    let a = (d_phi / 2.0).sin().powi(2) + phi0.cos() * phi1.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+fn unit_vector((lng, lat): (f64, f64)) -> [f64; 3] {
+    let (lambda, phi) = (lng.to_radians(), lat.to_radians());
+    [phi.cos() * lambda.cos(), phi.cos() * lambda.sin(), phi.sin()]
+}
+
+// The point a fraction t of the way along the shorter great circle arc between two lng/lat points, which is the
+// arc `distance_km` measures. Longitudes are kept within 180 of the start, then in -180 to 180 if `wrap`.
+fn interpolate(start: (f64, f64), end: (f64, f64), t: f64, wrap: bool) -> (f64, f64) {
+    let (a, b) = (unit_vector(start), unit_vector(end));
+    let angle = (a[0] * b[0] + a[1] * b[1] + a[2] * b[2]).clamp(-1.0, 1.0).acos();
+    if angle < 1e-12 {
+        return start;
+    }
+    let (weight_a, weight_b) = (((1.0 - t) * angle).sin() / angle.sin(), (t * angle).sin() / angle.sin());
+    let [x, y, z] = [0, 1, 2].map(|i| weight_a * a[i] + weight_b * b[i]);
+    let lat = z.atan2(x.hypot(y)).to_degrees();
+    let lng = y.atan2(x).to_degrees();
+    let mut lng = start.0 + (lng - start.0 + 540.0).rem_euclid(360.0) - 180.0;
+    if wrap && lng > 180.0 {
+        lng -= 360.0;
+    } else if wrap && lng < -180.0 {
+        lng += 360.0;
+    }
+    (lng, lat)
+}
+
+/// Points every `spacing_km` along a lng/lat polyline and at its end, with their distances from the start in km.
+/// Each segment follows the shorter great circle between its ends, so segments across the antimeridian take the short
+/// way round. Paths written in -180 to 180 are sampled in it, others keep their longitudes continuous. No spacing
+/// spreads `DEFAULT_SAMPLES` evenly.
+pub fn sample_path(path: &[(f64, f64)], spacing_km: Option<f64>) -> anyhow::Result<Vec<(f64, (f64, f64))>> {
+    if path.len() < 2 {
+        return Err(anyhow!("A transect needs at least two points"));
+    }
+    let lengths: Vec<f64> = path.windows(2).map(|segment| distance_km(segment[0], segment[1])).collect();
+    if lengths.iter().any(|length| *length > EARTH_RADIUS_KM * std::f64::consts::PI * (1.0 - 1e-9)) {
+        return Err(anyhow!("Transect segments between antipodal points have no single great circle"));
+    }
+    let total: f64 = lengths.iter().sum();
+    let spacing = match spacing_km {
+        Some(spacing) if spacing.is_finite() && spacing > 0.0 => spacing,
+        Some(_) => return Err(anyhow!("Transect spacing must be positive")),
+        None => total / (DEFAULT_SAMPLES - 1) as f64,
+    };
+    if total == 0.0 {
+        return Ok(vec![(0.0, path[0])]);
+    }
+    // Distances within a thousandth of a spacing of the end are the end. The count is checked as a float, as tiny
+    // spacings give more steps than a usize holds.
+    let steps = (total / spacing * (1.0 - 1e-3)).ceil();
+    if steps >= MAX_SAMPLES as f64 {
+        return Err(anyhow!("A transect can have at most {} points, so use a wider spacing", MAX_SAMPLES));
+    }
+    let steps = steps as usize;
+
+    let wrap = path.iter().all(|(lng, _)| (-180.0..=180.0).contains(lng));
+    let mut samples = Vec::with_capacity(steps + 1);
+    let (mut segment, mut segment_start) = (0, 0.0);
+    for step in 0..steps {
+        let distance = step as f64 * spacing;
+        while segment + 1 < lengths.len() && distance > segment_start + lengths[segment] {
+            segment_start += lengths[segment];
+            segment += 1;
+        }
+        let t = match lengths[segment] {
+            0.0 => 0.0,
+            length => ((distance - segment_start) / length).min(1.0),
+        };
+        samples.push((distance, interpolate(path[segment], path[segment + 1], t, wrap)));
+    }
+    samples.push((total, path[path.len() - 1]));
+    Ok(samples)
+}
+
+/// Values at every level of a column
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Profile {
+    pub axis: VerticalAxis,
+    pub values: Vec<f64>,
+}
+
+/// Values at every level along a transect
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub axis: VerticalAxis,
+    /// Distance of each point from the start of the path, in km
+    pub distances: Vec<f64>,
+    /// Lng/lat of each point
+    pub points: Vec<(f64, f64)>,
+    /// Values by (level, point)
+    pub values: Array2<f64>,
+}
+
+// The vertical axis of the first variable an expression uses
+fn expr_axis(dset: &Dataset, expr: &Expr) -> anyhow::Result<VerticalAxis> {
+    let name = *expr.variables().first().ok_or_else(|| anyhow!("Expression does not use any variables"))?;
+    dset.vertical_axis(name)?.ok_or_else(|| anyhow!("{} has no vertical dimension", name))
+}
+
+/// The column of the grid cell nearest to a lng/lat point, or None if it's outside the dataset
+pub fn profile(dset: &Dataset, lng: f64, lat: f64, expr: &Expr) -> anyhow::Result<Option<Profile>> {
+    let axis = expr_axis(dset, expr)?;
+    let (x, y) = dset.source_point(lng, lat);
+    let bounds = dset.get_bounds();
+    if !(x >= bounds.min_x && x <= bounds.max_x && y >= bounds.min_y && y <= bounds.max_y) {
+        return Ok(None);
+    }
+    let values = dset.get_expr_columns(expr, &[(x, y)])?;
+    Ok(Some(Profile {
+        axis,
+        values: values.into_raw_vec(),
+    }))
+}
+
+/// The columns of the grid cells nearest to points along a lng/lat path, see `sample_path`
+pub fn section(dset: &Dataset, path: &[(f64, f64)], spacing_km: Option<f64>, expr: &Expr) -> anyhow::Result<Section> {
+    let axis = expr_axis(dset, expr)?;
+    let (distances, points): (Vec<f64>, Vec<(f64, f64)>) = sample_path(path, spacing_km)?.into_iter().unzip();
+    let mut source_points = points.clone();
+    if let Some(crs) = dset.crs() {
+        crs.from_lng_lat(&mut source_points);
+    }
+    let values = dset.get_expr_columns(expr, &source_points)?;
+    Ok(Section {
+        axis,
+        distances,
+        points,
+        values,
+    })
+}
+
+#[cfg(test)]
+mod section_tests {
+    use super::*;
+    use crate::testing::TempPath;
+    use serde_json::json;
+    use std::path::Path;
+
+    fn write_array(dir: &Path, name: &str, dims: &[&str], shape: &[usize], values: &[f64], attrs: serde_json::Value) {
+        let array = dir.join(name);
+        std::fs::create_dir(&array).unwrap();
+        let zarray = json!({"zarr_format": 2, "shape": shape, "chunks": shape, "dtype": "<f8", "compressor": null,
+            "fill_value": "NaN", "order": "C", "filters": null});
+        std::fs::write(array.join(".zarray"), zarray.to_string()).unwrap();
+        let mut attrs = attrs;
+        attrs["_ARRAY_DIMENSIONS"] = json!(dims);
+        std::fs::write(array.join(".zattrs"), attrs.to_string()).unwrap();
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        std::fs::write(array.join(vec!["0"; shape.len()].join(".")), bytes).unwrap();
+    }
+
+    // Temperature on 3 depths over a 4 x 5 grid of 1 degree cells, as 100 * depth index + 10 * row + col, with
+    // a time step of temperature + 1000 before it
+    fn write_store() -> TempPath {
+        let dir = TempPath::dir("section.zarr");
+        std::fs::write(dir.join(".zgroup"), json!({"zarr_format": 2}).to_string()).unwrap();
+
+        write_array(&dir, "lat", &["lat"], &[4], &[10.0, 11.0, 12.0, 13.0], json!({}));
+        write_array(&dir, "lon", &["lon"], &[5], &[20.0, 21.0, 22.0, 23.0, 24.0], json!({}));
+        write_array(&dir, "depth", &["depth"], &[3], &[0.0, 50.0, 200.0], json!({"units": "m", "positive": "down"}));
+        write_array(&dir, "time", &["time"], &[2], &[0.0, 1.0], json!({"units": "days since 2023-01-01"}));
+        let temp: Vec<f64> = (0..2)
+            .flat_map(|t| (0..3).flat_map(move |d| (0..4).flat_map(move |r| (0..5).map(move |c| (t, d, r, c)))))
+            .map(|(t, d, r, c)| (1000 * t + 100 * d + 10 * r + c) as f64)
+            .collect();
+        write_array(&dir, "temp", &["time", "depth", "lat", "lon"], &[2, 3, 4, 5], &temp, json!({}));
+        write_array(&dir, "salt", &["depth", "lat", "lon"], &[3, 4, 5], &temp[..60], json!({}));
+        dir
+    }
+
+    #[test]
+    fn test_sample_path() {
+        let samples = sample_path(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)], Some(50.0)).unwrap();
+        let degree = distance_km((0.0, 0.0), (1.0, 0.0));
+        assert_relative_eq!(degree, 111.195, epsilon = 1e-3);
+        assert_eq!(samples.len(), 6);
+        assert_relative_eq!(samples[2].1 .0, 100.0 / degree, epsilon = 1e-9);
+        assert_relative_eq!(samples[3].1 .1, (150.0 - degree) / degree, epsilon = 1e-9);
+        assert_eq!(samples[5], (2.0 * degree, (1.0, 1.0)));
+
+        assert_eq!(sample_path(&[(0.0, 0.0), (1.0, 0.0)], None).unwrap().len(), DEFAULT_SAMPLES);
+        assert!(sample_path(&[(0.0, 0.0), (1.0, 0.0)], Some(0.01)).is_err());
+        assert!(sample_path(&[(0.0, 0.0), (1.0, 0.0)], Some(1e-300)).is_err());
+        assert!(sample_path(&[(0.0, 0.0), (1.0, 0.0)], Some(f64::MIN_POSITIVE)).is_err());
+        assert!(sample_path(&[(0.0, 0.0)], None).is_err());
+        assert!(sample_path(&[(0.0, 0.0), (180.0, 0.0)], None).is_err());
+
+        // The great circle between points on a parallel bulges towards the pole, at the distance measured
+        let samples = sample_path(&[(0.0, 45.0), (90.0, 45.0)], None).unwrap();
+        let bulge = samples.iter().map(|(_, (_, lat))| *lat).fold(f64::MIN, f64::max);
+        assert_relative_eq!(bulge, 2f64.sqrt().atan().to_degrees(), epsilon = 1e-3);
+        let (distance, point) = samples[50];
+        assert_relative_eq!(distance_km((0.0, 45.0), point), distance, epsilon = 1e-6);
+
+        // Across the antimeridian the short way, in the convention the path is written in
+        let samples = sample_path(&[(170.0, 0.0), (-170.0, 0.0)], Some(degree * 5.0)).unwrap();
+        let lngs: Vec<f64> = samples.iter().map(|(_, (lng, _))| *lng).collect();
+        assert_eq!(lngs.len(), 5);
+        assert_relative_eq!(lngs[1], 175.0, epsilon = 1e-9);
+        assert_relative_eq!(lngs[3], -175.0, epsilon = 1e-9);
+        let samples = sample_path(&[(170.0, 0.0), (190.0, 0.0)], Some(degree * 5.0)).unwrap();
+        assert_relative_eq!(samples[3].1 .0, 185.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_profile_and_section() {
+        let dir = write_store();
+        let mut dset = Dataset::new(&dir, "lat", "lon").unwrap();
+        let axis = dset.vertical_axis("temp").unwrap().unwrap();
+        assert_eq!(axis.values, [0.0, 50.0, 200.0]);
+        assert_eq!((axis.units.as_deref(), axis.positive_up), (Some("m"), false));
+        assert!(dset.vertical_axis("lat").unwrap().is_none());
+
+        let temp = Expr::variable("temp");
+        let column = profile(&dset, 22.2, 11.9, &temp).unwrap().unwrap();
+        assert_eq!(column.values, [22.0, 122.0, 222.0]);
+        assert!(profile(&dset, 40.0, 11.0, &temp).unwrap().is_none());
+
+        // Along the row at 12N every degree, and past the east edge of the grid
+        let path = [(20.2, 12.0), (25.2, 12.0)];
+        let transect = section(&dset, &path, Some(distance_km(path[0], path[1]) / 5.0), &temp).unwrap();
+        assert_eq!(transect.values.dim(), (3, 6));
+        assert_eq!(transect.values.row(1).to_vec()[..4], [120.0, 121.0, 122.0, 123.0]);
+        assert!(transect.values[[0, 4]].is_nan() && transect.values[[0, 5]].is_nan());
+        // The great circle is a hair off the parallel
+        assert_relative_eq!(transect.points[2].0, 22.2, epsilon = 1e-4);
+
+        // Points that are sparse in the grid are read a row at a time
+        let diagonal = section(&dset, &[(20.0, 10.0), (24.0, 13.0)], Some(1000.0), &temp).unwrap();
+        assert_eq!(diagonal.values.row(0).to_vec(), [0.0, 34.0]);
+        let columns = dset.get_columns("temp", &[(20.0, 10.0), (24.0, 13.0), (21.0, 10.0), (23.0, 13.0)]).unwrap();
+        assert_eq!(columns.row(1).to_vec(), [100.0, 134.0, 101.0, 133.0]);
+
+        let sum = Expr::parse("temp + salt").unwrap();
+        let transect = section(&dset, &path[..], Some(200.0), &sum).unwrap();
+        assert_eq!(transect.values[[2, 0]], 440.0);
+
+        dset = Dataset::open(&crate::dataset::DatasetPath::new(dir.to_path_buf(), 1), "lat", "lon").unwrap();
+        assert_eq!(profile(&dset, 20.0, 10.0, &temp).unwrap().unwrap().values, [1000.0, 1100.0, 1200.0]);
+        assert!(profile(&dset, 20.0, 10.0, &Expr::variable("lat")).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+//! Polygons from the .shp part of an ESRI shapefile, e.g. a land mask. Coordinates are used as lng/lat, so the
+//! shapefile should be in WGS 84; the .prj, .dbf and .shx parts aren't read.
+
+use crate::region::{Region, Ring};
+use anyhow::anyhow;
+use std::path::Path;
+
+const FILE_CODE: i32 = 9994;
+const HEADER_LEN: usize = 100;
+const NULL_SHAPE: i32 = 0;
+// Polygon, PolygonZ and PolygonM records start with the same x/y parts
+const POLYGON_TYPES: [i32; 3] = [5, 15, 25];
+
+fn be_i32(bytes: &[u8], offset: usize) -> anyhow::Result<i32> {
+    let bytes = bytes.get(offset..offset + 4).ok_or_else(|| anyhow!("Truncated shapefile"))?;
+    Ok(i32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+fn le_i32(bytes: &[u8], offset: usize) -> anyhow::Result<i32> {
+    let bytes = bytes.get(offset..offset + 4).ok_or_else(|| anyhow!("Truncated shapefile"))?;
+    Ok(i32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn le_f64(bytes: &[u8], offset: usize) -> anyhow::Result<f64> {
+    let bytes = bytes.get(offset..offset + 8).ok_or_else(|| anyhow!("Truncated shapefile"))?;
+    Ok(f64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+// The rings of a polygon record, after its record header
+fn parse_polygon(record: &[u8]) -> anyhow::Result<Vec<Ring>> {
+    // Shape type and bounding box
+    let num_parts = le_i32(record, 36)?;
+    let num_points = le_i32(record, 40)?;
+    if num_parts < 0 || num_points < 0 {
+        return Err(anyhow!("Invalid shapefile polygon"));
+    }
+    let (num_parts, num_points) = (num_parts as usize, num_points as usize);
+    let points_offset = 44 + 4 * num_parts;
+    let mut starts: Vec<usize> =
+        (0..num_parts).map(|i| le_i32(record, 44 + 4 * i).map(|s| s as usize)).collect::<anyhow::Result<_>>()?;
+    starts.push(num_points);
+
+    starts
+        .windows(2)
+        .filter(|part| part[1] > part[0])
+        .map(|part| {
+            (part[0]..part[1])
+                .map(|i| Ok((le_f64(record, points_offset + 16 * i)?, le_f64(record, points_offset + 16 * i + 8)?)))
+                .collect()
+        })
+        .collect()
+}
+
+/// The polygons of a polygon shapefile, one per record, from the .shp file's bytes
+pub fn parse_polygons(bytes: &[u8]) -> anyhow::Result<Vec<Vec<Ring>>> {
+    if be_i32(bytes, 0)? != FILE_CODE {
+        return Err(anyhow!("Not a shapefile"));
+    }
+    let shape_type = le_i32(bytes, 32)?;
+    if !POLYGON_TYPES.contains(&shape_type) {
+        return Err(anyhow!("Only polygon shapefiles can be read, not shape type {}", shape_type));
+    }
+
+    // The header's file length can be wrong, so read records to the end of the file
+    let mut polygons = Vec::new();
+    let mut offset = HEADER_LEN;
+    while offset + 8 <= bytes.len() {
+        // Lengths are in 16-bit words
+        let len = be_i32(bytes, offset + 4)?.max(0) as usize * 2;
+        let record = bytes.get(offset + 8..offset + 8 + len).ok_or_else(|| anyhow!("Truncated shapefile"))?;
+        match le_i32(record, 0)? {
+            NULL_SHAPE => {}
+            shape if POLYGON_TYPES.contains(&shape) => polygons.push(parse_polygon(record)?),
+            shape => return Err(anyhow!("Unexpected shape type {} in a polygon shapefile", shape)),
+        }
+        offset += 8 + len;
+    }
+    Ok(polygons)
+}
+
+/// The polygons of a .shp file as a region
+pub fn read_region(path: &Path) -> anyhow::Result<Region> {
+    let polygons = parse_polygons(&std::fs::read(path)?)?;
+    Region::from_polygons(polygons).ok_or_else(|| anyhow!("Shapefile {:?} has no polygons", path))
+}
+
+#[cfg(test)]
+mod shapefile_tests {
+    use super::*;
+
+    // A polygon shapefile with a record per list of parts
+    fn write_polygons(records: &[Vec<Ring>]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (i, parts) in records.iter().enumerate() {
+            let mut content = Vec::new();
+            content.extend_from_slice(&5i32.to_le_bytes());
+            content.extend_from_slice(&[0; 32]);
+            content.extend_from_slice(&(parts.len() as i32).to_le_bytes());
+            content.extend_from_slice(&(parts.iter().map(Vec::len).sum::<usize>() as i32).to_le_bytes());
+            let mut start = 0;
+            for part in parts {
+                content.extend_from_slice(&(start as i32).to_le_bytes());
+                start += part.len();
+            }
+            for (x, y) in parts.iter().flatten() {
+                content.extend_from_slice(&x.to_le_bytes());
+                content.extend_from_slice(&y.to_le_bytes());
+            }
+            body.extend_from_slice(&(i as i32 + 1).to_be_bytes());
+            body.extend_from_slice(&(content.len() as i32 / 2).to_be_bytes());
+            body.extend_from_slice(&content);
+        }
+
+        let mut header = vec![0; HEADER_LEN];
+        header[0..4].copy_from_slice(&FILE_CODE.to_be_bytes());
+        header[24..28].copy_from_slice((((HEADER_LEN + body.len()) / 2) as i32).to_be_bytes().as_slice());
+        header[28..32].copy_from_slice(&1000i32.to_le_bytes());
+        header[32..36].copy_from_slice(&5i32.to_le_bytes());
+        header.extend_from_slice(&body);
+        header
+    }
+
+    #[test]
+    fn test_parse_polygons() {
+        let outer = vec![(0.0, 0.0), (0.0, 4.0), (4.0, 4.0), (4.0, 0.0), (0.0, 0.0)];
+        let hole = vec![(1.0, 1.0), (2.0, 1.0), (2.0, 2.0), (1.0, 2.0), (1.0, 1.0)];
+        let island = vec![(10.0, 10.0), (10.0, 11.0), (11.0, 11.0), (10.0, 10.0)];
+        let bytes = write_polygons(&[vec![outer.clone(), hole.clone(), island.clone()]]);
+
+        let polygons = parse_polygons(&bytes).unwrap();
+        assert_eq!(polygons, [vec![outer, hole, island]]);
+        let region = Region::from_polygons(polygons).unwrap();
+        assert!(region.contains((3.0, 3.0)));
+        assert!(!region.contains((1.5, 1.5)));
+        assert!(region.contains((10.2, 10.5)));
+
+        assert!(parse_polygons(&bytes[..120]).is_err());
+        assert!(parse_polygons(b"not a shapefile").is_err());
+    }
+}
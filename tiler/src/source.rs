@@ -0,0 +1,177 @@
+//! Where gridded variables are read from: NetCDF files, Zarr stores, or GeoTIFFs
+
+use crate::geotiff::GeoTiff;
+use crate::zarr::ZarrStore;
+use anyhow::anyhow;
+use std::path::Path;
+
+/// A variable or group attribute, with numbers of any type read as f64
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttrValue {
+    Str(String),
+    Numbers(Vec<f64>),
+}
+
+impl AttrValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            AttrValue::Str(s) => Some(s),
+            AttrValue::Numbers(_) => None,
+        }
+    }
+
+    /// The first number of a numeric attribute
+    pub fn as_f64(&self) -> Option<f64> {
+        self.as_f64s().and_then(|v| v.first().copied())
+    }
+
+    /// All numbers of a numeric attribute, since CF parameters can be lists
+    pub fn as_f64s(&self) -> Option<&[f64]> {
+        match self {
+            AttrValue::Numbers(v) => Some(v),
+            AttrValue::Str(_) => None,
+        }
+    }
+}
+
+/// Named n-dimensional variables with attributes, e.g. a NetCDF file
+pub trait GridSource {
+    fn variables(&self) -> Vec<String>;
+
+    /// Names and lengths of a variable's dimensions, or None if there's no such variable
+    fn dimensions(&self, var: &str) -> Option<Vec<(String, usize)>>;
+
+    fn attributes(&self, var: &str) -> Vec<(String, AttrValue)>;
+
+    /// Values of a hyperslab, `count` long from `start` along each dimension
+    fn read(&self, var: &str, start: &[usize], count: &[usize]) -> anyhow::Result<ndarray::ArrayD<f64>>;
+
+    fn attribute(&self, var: &str, name: &str) -> Option<AttrValue> {
+        self.attributes(var).into_iter().find_map(|(n, value)| (n == name).then_some(value))
+    }
+
+    /// Row and column counts of reduced resolution copies of the 2-D variables, finest first
+    fn overviews(&self) -> Vec<(usize, usize)> {
+        Vec::new()
+    }
+
+    /// Like `read`, from one of the `overviews`, in its own rows and columns
+    fn read_overview(
+        &self,
+        var: &str,
+        overview: usize,
+        _start: &[usize],
+        _count: &[usize],
+    ) -> anyhow::Result<ndarray::ArrayD<f64>> {
+        Err(anyhow!("No overview {} of {}", overview, var))
+    }
+
+    /// Every value of a variable, e.g. a coordinate
+    fn read_all(&self, var: &str) -> anyhow::Result<ndarray::ArrayD<f64>> {
+        let dims = self.dimensions(var).ok_or_else(|| anyhow!("No variable {} in dataset", var))?;
+        let count: Vec<usize> = dims.iter().map(|(_, len)| *len).collect();
+        self.read(var, &vec![0; count.len()], &count)
+    }
+}
+
+impl<S: GridSource + ?Sized> GridSource for Box<S> {
+    fn variables(&self) -> Vec<String> {
+        (**self).variables()
+    }
+
+    fn dimensions(&self, var: &str) -> Option<Vec<(String, usize)>> {
+        (**self).dimensions(var)
+    }
+
+    fn attributes(&self, var: &str) -> Vec<(String, AttrValue)> {
+        (**self).attributes(var)
+    }
+
+    fn read(&self, var: &str, start: &[usize], count: &[usize]) -> anyhow::Result<ndarray::ArrayD<f64>> {
+        (**self).read(var, start, count)
+    }
+
+    fn overviews(&self) -> Vec<(usize, usize)> {
+        (**self).overviews()
+    }
+
+    fn read_overview(
+        &self,
+        var: &str,
+        overview: usize,
+        start: &[usize],
+        count: &[usize],
+    ) -> anyhow::Result<ndarray::ArrayD<f64>> {
+        (**self).read_overview(var, overview, start, count)
+    }
+}
+
+/// Open a Zarr store directory, a GeoTIFF file or `http://` URL, or else a NetCDF file
+pub fn open(path: &Path) -> anyhow::Result<Box<dyn GridSource>> {
+    if ZarrStore::is_store(path) {
+        Ok(Box::new(ZarrStore::open(path)?))
+    } else if GeoTiff::is_geotiff(path) {
+        Ok(Box::new(GeoTiff::open(path)?))
+    } else {
+        Ok(Box::new(NetcdfFile(netcdf::open(path)?)))
+    }
+}
+
+pub struct NetcdfFile(pub netcdf::File);
+
+impl From<netcdf::AttrValue> for AttrValue {
+    fn from(value: netcdf::AttrValue) -> Self {
+        use netcdf::AttrValue::*;
+
+        let numbers = match value {
+            Str(s) => return AttrValue::Str(s),
+            Strs(v) => return AttrValue::Str(v.join(", ")),
+            Uchar(v) => vec![v as f64],
+            Schar(v) => vec![v as f64],
+            Ushort(v) => vec![v as f64],
+            Short(v) => vec![v as f64],
+            Uint(v) => vec![v as f64],
+            Int(v) => vec![v as f64],
+            Ulonglong(v) => vec![v as f64],
+            Longlong(v) => vec![v as f64],
+            Float(v) => vec![v as f64],
+            Double(v) => vec![v],
+            Uchars(v) => v.iter().map(|v| *v as f64).collect(),
+            Schars(v) => v.iter().map(|v| *v as f64).collect(),
+            Ushorts(v) => v.iter().map(|v| *v as f64).collect(),
+            Shorts(v) => v.iter().map(|v| *v as f64).collect(),
+            Uints(v) => v.iter().map(|v| *v as f64).collect(),
+            Ints(v) => v.iter().map(|v| *v as f64).collect(),
+            Ulonglongs(v) => v.iter().map(|v| *v as f64).collect(),
+            Longlongs(v) => v.iter().map(|v| *v as f64).collect(),
+            Floats(v) => v.iter().map(|v| *v as f64).collect(),
+            Doubles(v) => v,
+        };
+        AttrValue::Numbers(numbers)
+    }
+}
+
+impl GridSource for NetcdfFile {
+    fn variables(&self) -> Vec<String> {
+        self.0.variables().map(|var| var.name()).collect()
+    }
+
+    fn dimensions(&self, var: &str) -> Option<Vec<(String, usize)>> {
+        let var = self.0.variable(var)?;
+        Some(var.dimensions().iter().map(|dim| (dim.name(), dim.len())).collect())
+    }
+
+    fn attributes(&self, var: &str) -> Vec<(String, AttrValue)> {
+        let Some(var) = self.0.variable(var) else {
+            return Vec::new();
+        };
+        var.attributes()
+            .filter_map(|attr| Some((attr.name().to_string(), attr.value().ok()?.into())))
+            .collect()
+    }
+
+    fn read(&self, var: &str, start: &[usize], count: &[usize]) -> anyhow::Result<ndarray::ArrayD<f64>> {
+        let var = self.0.variable(var).ok_or_else(|| anyhow!("No variable {} in dataset", var))?;
+        Ok(var.values_arr::<f64, _>((start, count))?)
+    }
+}
@@ -0,0 +1,69 @@
+use serde::Serialize;
+
+/// Summary statistics of the valid (non-NaN) values in a region
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Stats {
+    pub count: usize,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+    pub std: f64,
+}
+
+impl Stats {
+    pub fn from_values(values: impl IntoIterator<Item = f64>) -> Self {
+        let mut count = 0;
+        let mut sum = 0.0;
+        let mut sum_sq = 0.0;
+        let mut min = f64::INFINITY;
+        let mut max = f64::NEG_INFINITY;
+
+        for v in values.into_iter().filter(|v| !v.is_nan()) {
+            count += 1;
+            sum += v;
+            sum_sq += v * v;
+            min = min.min(v);
+            max = max.max(v);
+        }
+
+        if count == 0 {
+            return Self {
+                count,
+                mean: f64::NAN,
+                min: f64::NAN,
+                max: f64::NAN,
+                std: f64::NAN,
+            };
+        }
+
+        let mean = sum / count as f64;
+        // Population standard deviation, clamped since rounding can make the variance slightly negative
+        let std = (sum_sq / count as f64 - mean * mean).max(0.0).sqrt();
+        Self {
+            count,
+            mean,
+            min,
+            max,
+            std,
+        }
+    }
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+
+    #[test]
+    fn test_from_values() {
+        let stats = Stats::from_values([2.0, 4.0, f64::NAN, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        assert_eq!(stats.count, 8);
+        assert_eq!(stats.mean, 5.0);
+        assert_eq!(stats.min, 2.0);
+        assert_eq!(stats.max, 9.0);
+        assert_relative_eq!(stats.std, 2.0);
+
+        let stats = Stats::from_values([f64::NAN]);
+        assert_eq!(stats.count, 0);
+        assert!(stats.mean.is_nan());
+    }
+}
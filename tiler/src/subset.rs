@@ -0,0 +1,436 @@
+//! Subsets of variables over a lat/lng box and a run of time steps, for download as NetCDF, GeoTIFF or CSV
+
+use crate::aggregate::TimeStep;
+use crate::bounds::Bounds;
+use crate::dataset::Dataset;
+use crate::source::AttrValue;
+use anyhow::anyhow;
+use ndarray::{s, Array2, Array3, Axis};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The most values, over every variable and time step, that one subset is allowed to hold
+pub const MAX_VALUES: usize = 20_000_000;
+
+/// What to cut out of each time step
+#[derive(Debug, Clone)]
+pub struct SubsetOptions {
+    /// In lng/lat, also for projected grids, which are cut to the box's x/y envelope
+    pub bounds: Bounds,
+    pub variables: Vec<String>,
+    /// Keep every nth row and column
+    pub stride: usize,
+    pub lat_name: String,
+    pub lon_name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubsetFormat {
+    NetCdf,
+    GeoTiff,
+    Csv,
+}
+
+impl SubsetFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SubsetFormat::NetCdf => "nc",
+            SubsetFormat::GeoTiff => "tif",
+            SubsetFormat::Csv => "csv",
+        }
+    }
+}
+
+/// A variable's values over the subset, by time step, row and column
+#[derive(Debug, Clone)]
+pub struct SubsetVariable {
+    pub name: String,
+    pub attributes: Vec<(String, AttrValue)>,
+    pub values: Array3<f64>,
+}
+
+/// A coordinate variable of the subset, with its attributes copied from the source
+#[derive(Debug, Clone)]
+pub struct SubsetCoordinate {
+    pub name: String,
+    pub attributes: Vec<(String, AttrValue)>,
+    pub values: Vec<f64>,
+}
+
+/// Values cut from a run of time steps, with rows from south to north
+#[derive(Debug, Clone)]
+pub struct Subset {
+    pub times: Vec<i64>,
+    pub y: SubsetCoordinate,
+    pub x: SubsetCoordinate,
+    /// Cell width and height after the stride
+    pub cell_size: (f64, f64),
+    /// The grid mapping variable of projected grids
+    pub grid_mapping: Option<(String, Vec<(String, AttrValue)>)>,
+    /// Lng/lat of every cell, row by row, for projected grids
+    pub lng_lats: Option<Vec<(f64, f64)>>,
+    pub variables: Vec<SubsetVariable>,
+}
+
+// The part of a dataset inside a lng/lat box, in its own coordinates
+fn subset_bounds(dset: &Dataset, bounds: Bounds) -> Option<Bounds> {
+    dset.get_bounds().intersect(&dset.source_bounds(bounds)?)
+}
+
+/// How many values a subset of the time steps would hold, to check against a limit before reading it
+pub fn subset_size(steps: &[TimeStep], options: &SubsetOptions) -> anyhow::Result<usize> {
+    let Some(step) = steps.first() else {
+        return Ok(0);
+    };
+    let dset = Dataset::open(&step.path, &options.lat_name, &options.lon_name)?;
+    let Some(bounds) = subset_bounds(&dset, options.bounds) else {
+        return Ok(0);
+    };
+    let (lats, lons) = dset.get_coords(bounds);
+    let stride = options.stride.max(1);
+    Ok(steps.len() * options.variables.len() * lats.len().div_ceil(stride) * lons.len().div_ceil(stride))
+}
+
+// Attributes that describe how values are stored in the source file rather than what they are
+fn is_storage_attribute(name: &str) -> bool {
+    name.starts_with('_') || matches!(name, "missing_value" | "coordinates" | "chunksizes")
+}
+
+fn copy_attributes(dset: &Dataset, var: &str) -> Vec<(String, AttrValue)> {
+    dset.attributes(var).into_iter().filter(|(name, _)| !is_storage_attribute(name)).collect()
+}
+
+/// Read a subset of every time step, which must all be on the same grid. None if the box misses the data.
+pub fn read_subset(steps: &[TimeStep], options: &SubsetOptions) -> anyhow::Result<Option<Subset>> {
+    let stride = options.stride.max(1);
+    let mut subset: Option<Subset> = None;
+    for (t, step) in steps.iter().enumerate() {
+        let dset = Dataset::open(&step.path, &options.lat_name, &options.lon_name)?;
+        let Some(bounds) = subset_bounds(&dset, options.bounds) else {
+            return Ok(None);
+        };
+        let subset = match &mut subset {
+            Some(subset) => subset,
+            None => subset.insert(new_subset(&dset, bounds, steps.len(), options)),
+        };
+        subset.times.push(step.time);
+
+        for variable in subset.variables.iter_mut() {
+            let values = dset.get_values(&variable.name, bounds)?.into_dimensionality::<ndarray::Ix2>()?;
+            let values = values.slice(s![..;stride, ..;stride]);
+            if values.dim() != (variable.values.dim().1, variable.values.dim().2) {
+                return Err(anyhow!("{:?} isn't on the same grid as the first time step", step.path.path));
+            }
+            variable.values.index_axis_mut(Axis(0), t).assign(&values);
+        }
+    }
+    Ok(subset)
+}
+
+// An empty subset shaped by the first time step
+fn new_subset(dset: &Dataset, bounds: Bounds, steps: usize, options: &SubsetOptions) -> Subset {
+    let stride = options.stride.max(1);
+    let (lats, lons) = dset.get_coords(bounds);
+    let ys: Vec<f64> = lats.into_iter().step_by(stride).collect();
+    let xs: Vec<f64> = lons.into_iter().step_by(stride).collect();
+    let (dx, dy) = dset.cell_size(0);
+
+    let variables = options
+        .variables
+        .iter()
+        .map(|name| SubsetVariable {
+            name: name.clone(),
+            attributes: copy_attributes(dset, name),
+            values: Array3::from_elem((steps, ys.len(), xs.len()), f64::NAN),
+        })
+        .collect();
+
+    let (grid_mapping, lng_lats) = match dset.crs() {
+        Some(crs) => {
+            let name = options.variables.iter().find_map(|var| {
+                dset.attributes(var).into_iter().find_map(|(name, value)| match (name.as_str(), value) {
+                    ("grid_mapping", AttrValue::Str(mapping)) => Some(mapping),
+                    _ => None,
+                })
+            });
+            let grid_mapping = name.map(|name| {
+                let attributes = copy_attributes(dset, &name);
+                (name, attributes)
+            });
+            let mut points: Vec<(f64, f64)> = ys.iter().flat_map(|y| xs.iter().map(move |x| (*x, *y))).collect();
+            crs.to_lng_lat(&mut points);
+            (grid_mapping, Some(points))
+        }
+        None => (None, None),
+    };
+
+    Subset {
+        times: Vec::with_capacity(steps),
+        y: SubsetCoordinate {
+            name: dset.lat_name().to_string(),
+            attributes: copy_attributes(dset, dset.lat_name()),
+            values: ys,
+        },
+        x: SubsetCoordinate {
+            name: dset.lon_name().to_string(),
+            attributes: copy_attributes(dset, dset.lon_name()),
+            values: xs,
+        },
+        cell_size: (dx * stride as f64, dy * stride as f64),
+        grid_mapping,
+        lng_lats,
+        variables,
+    }
+}
+
+impl Subset {
+    pub fn encode(&self, format: SubsetFormat) -> anyhow::Result<Vec<u8>> {
+        match format {
+            SubsetFormat::NetCdf => {
+                // netCDF-C only writes to files
+                static COUNTER: AtomicUsize = AtomicUsize::new(0);
+                let path = std::env::temp_dir().join(format!(
+                    "tiler-subset-{}-{}.nc",
+                    std::process::id(),
+                    COUNTER.fetch_add(1, Ordering::Relaxed)
+                ));
+                let written = self.write_netcdf(&path).and_then(|_| Ok(std::fs::read(&path)?));
+                let _ = std::fs::remove_file(&path);
+                written
+            }
+            SubsetFormat::GeoTiff => self.to_geotiff(),
+            SubsetFormat::Csv => Ok(self.to_csv().into_bytes()),
+        }
+    }
+
+    /// Write a CF NetCDF file, with a time coordinate and the source's coordinate and variable attributes
+    pub fn write_netcdf(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        let mut file = netcdf::create(path)?;
+        file.add_attribute("Conventions", "CF-1.8")?;
+        file.add_dimension("time", self.times.len())?;
+        file.add_dimension(&self.y.name, self.y.values.len())?;
+        file.add_dimension(&self.x.name, self.x.values.len())?;
+
+        let times: Vec<f64> = self.times.iter().map(|t| *t as f64).collect();
+        let mut time = file.add_variable::<f64>("time", &["time"])?;
+        time.add_attribute("standard_name", "time")?;
+        time.add_attribute("units", "seconds since 1970-01-01 00:00:00")?;
+        time.add_attribute("calendar", "standard")?;
+        time.put_values(&times, ..)?;
+
+        for coordinate in [&self.y, &self.x] {
+            let mut var = file.add_variable::<f64>(&coordinate.name, &[&coordinate.name])?;
+            add_attributes(&mut var, &coordinate.attributes)?;
+            var.put_values(&coordinate.values, ..)?;
+        }
+        if let Some((name, attributes)) = &self.grid_mapping {
+            let mut var = file.add_variable::<i32>(name, &[])?;
+            add_attributes(&mut var, attributes)?;
+        }
+        for variable in &self.variables {
+            let mut var = file.add_variable::<f32>(&variable.name, &["time", &self.y.name, &self.x.name])?;
+            var.set_fill_value(f32::NAN)?;
+            add_attributes(&mut var, &variable.attributes)?;
+            let values: Vec<f32> = variable.values.iter().map(|v| *v as f32).collect();
+            var.put_values(&values, ..)?;
+        }
+        Ok(())
+    }
+
+    /// A GeoTIFF with a band per variable and time step, for lat/lon grids
+    pub fn to_geotiff(&self) -> anyhow::Result<Vec<u8>> {
+        if self.grid_mapping.is_some() || self.lng_lats.is_some() {
+            return Err(anyhow!("GeoTIFF subsets are only written for lat/lon grids"));
+        }
+        let mut bands = Vec::new();
+        for (t, time) in self.times.iter().enumerate() {
+            for variable in &self.variables {
+                let mut values: Array2<f64> = variable.values.index_axis(Axis(0), t).to_owned();
+                // North up
+                values.invert_axis(Axis(0));
+                bands.push((format!("{} {}", variable.name, crate::time::format(*time)), values));
+            }
+        }
+        let (dx, dy) = self.cell_size;
+        let west = self.x.values.first().copied().unwrap_or(0.0) - dx / 2.0;
+        let north = self.y.values.last().copied().unwrap_or(0.0) + dy / 2.0;
+        crate::geotiff::encode(&bands, (west, north), (dx, dy))
+    }
+
+    /// `lat,lon,time` and a column per variable for each cell and time step, leaving out cells with no data
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("lat,lon,time");
+        for variable in &self.variables {
+            csv.push(',');
+            csv.push_str(&csv_field(&variable.name));
+        }
+        csv.push('\n');
+
+        let cols = self.x.values.len();
+        for (t, time) in self.times.iter().enumerate() {
+            let time = crate::time::format(*time);
+            for (row, y) in self.y.values.iter().enumerate() {
+                for (col, x) in self.x.values.iter().enumerate() {
+                    let values: Vec<f64> = self.variables.iter().map(|v| v.values[[t, row, col]]).collect();
+                    if values.iter().all(|v| v.is_nan()) {
+                        continue;
+                    }
+                    let (lng, lat) = match &self.lng_lats {
+                        Some(points) => points[row * cols + col],
+                        None => (*x, *y),
+                    };
+                    csv.push_str(&format!("{},{},{}", lat, lng, time));
+                    for value in values {
+                        csv.push(',');
+                        if !value.is_nan() {
+                            csv.push_str(&value.to_string());
+                        }
+                    }
+                    csv.push('\n');
+                }
+            }
+        }
+        csv
+    }
+}
+
+// Quoted if it would break the row
+fn csv_field(s: &str) -> String {
+    match s.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string(),
+    }
+}
+
+fn add_attributes(var: &mut netcdf::VariableMut, attributes: &[(String, AttrValue)]) -> anyhow::Result<()> {
+    for (name, value) in attributes {
+        match value {
+            AttrValue::Str(s) => var.add_attribute(name, s.as_str())?,
+            AttrValue::Numbers(v) if v.len() == 1 => var.add_attribute(name, v[0])?,
+            AttrValue::Numbers(v) => var.add_attribute(name, v.clone())?,
+        };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod subset_tests {
+    use super::*;
+    use crate::dataset::DatasetPath;
+    use crate::geotiff::GeoTiff;
+    use crate::source::GridSource;
+    use crate::testing::TempPath;
+    use crate::time::from_ymd;
+
+    // A day of the test grid, as 100 * (day - 1) + 10 * row + col, missing at the top left on the first day, and
+    // its file
+    fn write_day(day: u32) -> (TimeStep, TempPath) {
+        let path = crate::geotiff::write_test_grid(&format!("subset-{}", day), |row, col| match (day, row, col) {
+            (1, 0, 0) => f64::NAN,
+            _ => (100 * (day - 1) + 10 * row as u32 + col as u32) as f64,
+        });
+        let step = TimeStep {
+            time: from_ymd(2023, 4, day),
+            path: DatasetPath::from(path.to_path_buf()),
+        };
+        (step, path)
+    }
+
+    #[test]
+    fn test_subset() {
+        let ((day1, _file1), (day2, _file2)) = (write_day(1), write_day(2));
+        let steps = [day1, day2];
+        let options = SubsetOptions {
+            bounds: Bounds::new(0.0, 0.0, 8.0, 6.0),
+            variables: vec!["band_1".to_string()],
+            stride: 2,
+            lat_name: "lat".to_string(),
+            lon_name: "lon".to_string(),
+        };
+        assert_eq!(subset_size(&steps, &options).unwrap(), 24);
+
+        let subset = read_subset(&steps, &options).unwrap().unwrap();
+        assert_eq!(subset.y.values, [1.5, 3.5, 5.5]);
+        assert_eq!(subset.x.values, [0.5, 2.5, 4.5, 6.5]);
+        assert_eq!(subset.cell_size, (2.0, 2.0));
+        assert_eq!(subset.y.attributes[0], ("standard_name".to_string(), AttrValue::Str("latitude".to_string())));
+        let values = &subset.variables[0].values;
+        assert_eq!(values.dim(), (2, 3, 4));
+        assert_eq!(values[[0, 0, 1]], 42.0);
+        assert!(values[[0, 2, 0]].is_nan());
+        assert_eq!(values[[1, 2, 0]], 100.0);
+
+        let csv = subset.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 24);
+        assert_eq!(lines[0], "lat,lon,time,band_1");
+        assert_eq!(lines[1], "1.5,0.5,2023-04-01T00:00:00Z,40");
+
+        let path = TempPath::write("subset-out.tif", subset.to_geotiff().unwrap());
+        let tiff = GeoTiff::open(&path).unwrap();
+        assert_eq!(tiff.variables(), ["band_1", "band_2", "lat", "lon"]);
+        assert_eq!(tiff.read_all("lat").unwrap().into_raw_vec(), [5.5, 3.5, 1.5]);
+        assert_eq!(tiff.read_all("lon").unwrap()[0], 0.5);
+        assert_eq!(tiff.read("band_2", &[0, 0], &[1, 2]).unwrap().into_raw_vec(), [100.0, 102.0]);
+        assert!(tiff.read("band_1", &[0, 0], &[1, 1]).unwrap()[[0, 0]].is_nan());
+
+        let outside = SubsetOptions {
+            bounds: Bounds::new(20.0, 20.0, 30.0, 30.0),
+            ..options
+        };
+        assert!(read_subset(&steps, &outside).unwrap().is_none());
+        assert_eq!(subset_size(&steps, &outside).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_write_netcdf() {
+        let str = |name: &str, value: &str| (name.to_string(), AttrValue::Str(value.to_string()));
+        let coordinate = |name: &str, standard_name: &str, values: Vec<f64>| SubsetCoordinate {
+            name: name.to_string(),
+            attributes: vec![str("standard_name", standard_name)],
+            values,
+        };
+        let subset = Subset {
+            times: vec![from_ymd(2023, 4, 1), from_ymd(2023, 4, 2)],
+            y: coordinate("lat", "latitude", vec![1.5, 3.5, 5.5]),
+            x: coordinate("lon", "longitude", vec![0.5, 2.5, 4.5, 6.5]),
+            cell_size: (2.0, 2.0),
+            grid_mapping: Some((
+                "crs".to_string(),
+                vec![
+                    str("grid_mapping_name", "latitude_longitude"),
+                    ("semi_major_axis".to_string(), AttrValue::Numbers(vec![6378137.0])),
+                ],
+            )),
+            lng_lats: None,
+            variables: vec![SubsetVariable {
+                name: "chl".to_string(),
+                attributes: vec![str("grid_mapping", "crs"), str("units", "mg m-3")],
+                values: Array3::from_shape_fn((2, 3, 4), |(t, row, col)| match (t, row, col) {
+                    (0, 0, 0) => f64::NAN,
+                    _ => (100 * t + 10 * row + col) as f64,
+                }),
+            }],
+        };
+        let path = TempPath::new("subset-roundtrip.nc");
+        subset.write_netcdf(&path).unwrap();
+        let file = crate::source::open(&path).unwrap();
+
+        let dims = |name: &str, len: usize| (name.to_string(), len);
+        assert_eq!(file.dimensions("chl").unwrap(), [dims("time", 2), dims("lat", 3), dims("lon", 4)]);
+        let units = file.attribute("time", "units");
+        assert_eq!(units.as_ref().and_then(|u| u.as_str()), Some("seconds since 1970-01-01 00:00:00"));
+        assert_eq!(file.read_all("time").unwrap()[1], from_ymd(2023, 4, 2) as f64);
+        assert_eq!(file.attribute("lat", "standard_name"), Some(str("", "latitude").1));
+        assert_eq!(file.read_all("lon").unwrap().into_raw_vec(), [0.5, 2.5, 4.5, 6.5]);
+        assert_eq!(file.attribute("chl", "grid_mapping"), Some(str("", "crs").1));
+        assert_eq!(file.attribute("crs", "grid_mapping_name"), Some(str("", "latitude_longitude").1));
+        assert_eq!(file.attribute("crs", "semi_major_axis"), Some(AttrValue::Numbers(vec![6378137.0])));
+
+        // Missing values are NaN, which is also the fill value
+        assert!(file.attribute("chl", "_FillValue").and_then(|v| v.as_f64()).is_some_and(f64::is_nan));
+        let values = file.read("chl", &[0, 0, 0], &[2, 1, 2]).unwrap();
+        assert!(values[[0, 0, 0]].is_nan());
+        assert_eq!(values[[0, 0, 1]], 1.0);
+        assert_eq!(values[[1, 0, 0]], 100.0);
+    }
+}
@@ -0,0 +1,155 @@
+/// Rough length of a degree of latitude, and of longitude at the equator
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HillshadeOptions {
+    /// Direction the light comes from, in degrees clockwise from north
+    pub azimuth: f64,
+    /// Height of the light above the horizon, in degrees
+    pub altitude: f64,
+    /// Vertical exaggeration, and the conversion to meters if the values aren't in meters
+    pub z_factor: f64,
+}
+
+impl Default for HillshadeOptions {
+    fn default() -> Self {
+        Self {
+            azimuth: 315.0,
+            altitude: 45.0,
+            z_factor: 1.0,
+        }
+    }
+}
+
+/// Size in meters of a cell `dx` by `dy` degrees, centered at `lat`
+pub fn cell_size(dx: f64, dy: f64, lat: f64) -> (f64, f64) {
+    (
+        dx * METERS_PER_DEGREE * lat.to_radians().cos(),
+        dy * METERS_PER_DEGREE,
+    )
+}
+
+/// East and north rates of change per meter, using Horn's 3x3 kernel.
+///
+/// `values` is an image with row 0 to the north and a one pixel halo around it, so the output is
+/// two pixels smaller in each dimension. `cell_sizes` holds the (x, y) cell size in meters for every output row.
+/// Missing neighbors are filled with the center value, and missing centers give NaN.
+pub fn gradients(values: &[f64], width: usize, height: usize, cell_sizes: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let out_width = width.saturating_sub(2);
+    let out_height = height.saturating_sub(2);
+    let mut result = Vec::with_capacity(out_width * out_height);
+
+    for y in 1..=out_height {
+        let (cell_x, cell_y) = cell_sizes[y - 1];
+        for x in 1..=out_width {
+            let center = values[y * width + x];
+            if center.is_nan() {
+                result.push((f64::NAN, f64::NAN));
+                continue;
+            }
+            let at = |dx: isize, dy: isize| {
+                let v = values[(y as isize + dy) as usize * width + (x as isize + dx) as usize];
+                if v.is_nan() {
+                    center
+                } else {
+                    v
+                }
+            };
+
+            let east = at(1, -1) + 2.0 * at(1, 0) + at(1, 1);
+            let west = at(-1, -1) + 2.0 * at(-1, 0) + at(-1, 1);
+            let north = at(-1, -1) + 2.0 * at(0, -1) + at(1, -1);
+            let south = at(-1, 1) + 2.0 * at(0, 1) + at(1, 1);
+            result.push(((east - west) / (8.0 * cell_x), (north - south) / (8.0 * cell_y)));
+        }
+    }
+
+    result
+}
+
+/// Illumination between 0 (facing away from the light) and 1 (facing it). Missing values stay NaN.
+pub fn hillshade(gradients: &[(f64, f64)], options: &HillshadeOptions) -> Vec<f64> {
+    let azimuth = options.azimuth.to_radians();
+    let altitude = options.altitude.to_radians();
+    let light = (
+        azimuth.sin() * altitude.cos(),
+        azimuth.cos() * altitude.cos(),
+        altitude.sin(),
+    );
+
+    gradients
+        .iter()
+        .map(|(dzdx, dzdy)| {
+            // Dot product of the light direction with the unit surface normal
+            let (nx, ny) = (-dzdx * options.z_factor, -dzdy * options.z_factor);
+            let shade = (nx * light.0 + ny * light.1 + light.2) / (nx * nx + ny * ny + 1.0).sqrt();
+            if shade < 0.0 {
+                0.0
+            } else {
+                shade
+            }
+        })
+        .collect()
+}
+
+/// Steepness in units of the data per kilometer, e.g. degrees C per km for SST fronts
+pub fn gradient_magnitude(gradients: &[(f64, f64)]) -> Vec<f64> {
+    gradients.iter().map(|(dzdx, dzdy)| dzdx.hypot(*dzdy) * 1000.0).collect()
+}
+
+#[cfg(test)]
+mod terrain_tests {
+    use super::*;
+
+    // A 3x3 grid with a one pixel halo, rising to the east by 1 per cell
+    fn ramp() -> Vec<f64> {
+        (0..25).map(|i| (i % 5) as f64).collect()
+    }
+
+    #[test]
+    fn test_gradients() {
+        let g = gradients(&ramp(), 5, 5, &[(1.0, 1.0); 3]);
+        assert_eq!(g.len(), 9);
+        for (dzdx, dzdy) in g {
+            assert_relative_eq!(dzdx, 1.0);
+            assert_relative_eq!(dzdy, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_gradients_nan() {
+        let mut values = ramp();
+        values[12] = f64::NAN;
+        values[0] = f64::NAN;
+        let g = gradients(&values, 5, 5, &[(1.0, 1.0); 3]);
+        assert!(g[4].0.is_nan());
+        assert!(!g[0].0.is_nan());
+    }
+
+    #[test]
+    fn test_hillshade() {
+        let flat = hillshade(&[(0.0, 0.0)], &HillshadeOptions::default());
+        assert_relative_eq!(flat[0], 45f64.to_radians().sin());
+
+        // A slope rising to the east faces west, so light from the west hits it head on
+        let options = HillshadeOptions {
+            azimuth: 270.0,
+            altitude: 45.0,
+            z_factor: 1.0,
+        };
+        let lit = hillshade(&[(1.0, 0.0)], &options);
+        assert_relative_eq!(lit[0], 1.0, epsilon = 1e-9);
+
+        let options = HillshadeOptions { azimuth: 90.0, ..options };
+        let dark = hillshade(&[(1.0, 0.0), (f64::NAN, f64::NAN)], &options);
+        assert_relative_eq!(dark[0], 0.0, epsilon = 1e-9);
+        assert!(dark[1].is_nan());
+    }
+
+    #[test]
+    fn test_gradient_magnitude() {
+        let m = gradient_magnitude(&[(0.003, 0.004), (f64::NAN, f64::NAN)]);
+        assert_relative_eq!(m[0], 5.0);
+        assert!(m[1].is_nan());
+    }
+}
@@ -0,0 +1,69 @@
+//! Helpers shared by tests
+
+use crate::dataset::DatasetPath;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A file or directory in the temp directory, named for the test process so parallel runs don't collide, that is
+/// removed when dropped, so a failing assert doesn't leave it behind
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    /// A path for a test to create, e.g. `TempPath::new("subset.nc")`, with anything left by an earlier run removed
+    pub fn new(name: &str) -> Self {
+        let path = Self(std::env::temp_dir().join(format!("tiler-{}-{}", std::process::id(), name)));
+        path.remove();
+        path
+    }
+
+    /// A file with the bytes in it
+    pub fn write(name: &str, bytes: impl AsRef<[u8]>) -> Self {
+        let path = Self::new(name);
+        std::fs::write(&path.0, bytes).unwrap();
+        path
+    }
+
+    /// An empty directory
+    pub fn dir(name: &str) -> Self {
+        let path = Self::new(name);
+        std::fs::create_dir_all(&path.0).unwrap();
+        path
+    }
+
+    pub fn to_path_buf(&self) -> PathBuf {
+        self.0.clone()
+    }
+
+    fn remove(&self) {
+        let _ = match self.0.is_dir() {
+            true => std::fs::remove_dir_all(&self.0),
+            false => std::fs::remove_file(&self.0),
+        };
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+impl From<&TempPath> for DatasetPath {
+    fn from(path: &TempPath) -> Self {
+        DatasetPath::from(path.to_path_buf())
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::anyhow;
+
+// Times are seconds since 1970-01-01T00:00:00Z, in the proleptic Gregorian calendar
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+// Days since 1970-01-01, from http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Midnight UTC at the start of a day
+pub fn from_ymd(year: i64, month: u32, day: u32) -> i64 {
+    days_from_civil(year, month as i64, day as i64) * SECONDS_PER_DAY
+}
+
+/// The (year, month, day) a time falls on
+pub fn to_ymd(time: i64) -> (i64, u32, u32) {
+    civil_from_days(time.div_euclid(SECONDS_PER_DAY))
+}
+
+/// Day of the year a time falls on, from 1
+pub fn day_of_year(time: i64) -> u32 {
+    let (year, _, _) = to_ymd(time);
+    (time.div_euclid(SECONDS_PER_DAY) - days_from_civil(year, 1, 1)) as u32 + 1
+}
+
+/// Add days to a time, e.g. to step through a daily archive
+pub fn add_days(time: i64, days: i64) -> i64 {
+    time + days * SECONDS_PER_DAY
+}
+
+/// Parse an ISO 8601 date or UTC date and time, e.g. `2023-04-12`, `2023-04-12T06:00:00Z` or `2023-04-12 06:00`
+pub fn parse(s: &str) -> anyhow::Result<i64> {
+    let invalid = || anyhow!("Invalid date {:?}, expected YYYY-MM-DD or YYYY-MM-DDTHH:MM:SSZ", s);
+    let s = s.trim();
+    let (date, clock) = match s.find(['T', ' ']) {
+        Some(i) => (&s[..i], s[i + 1..].trim_end_matches('Z')),
+        None => (s, ""),
+    };
+
+    let mut parts = date.splitn(3, '-');
+    let mut next = || parts.next().and_then(|p| p.parse::<i64>().ok()).ok_or_else(invalid);
+    let (year, month, day) = (next()?, next()?, next()?);
+    // Four digit years, which also keeps the arithmetic on them from overflowing
+    if !(1..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0;
+    if !clock.is_empty() {
+        for (part, (scale, limit)) in clock.split(':').zip([(3600.0, 24.0), (60.0, 60.0), (1.0, 61.0)]) {
+            let value = part.parse::<f64>().map_err(|_| invalid())?;
+            if !(0.0..limit).contains(&value) {
+                return Err(invalid());
+            }
+            seconds += (value * scale) as i64;
+        }
+    }
+    Ok(from_ymd(year, month as u32, day as u32) + seconds)
+}
+
+/// Parse the end of an inclusive range like `parse`, where a date alone runs to the last second of that day
+pub fn parse_end(s: &str) -> anyhow::Result<i64> {
+    let time = parse(s)?;
+    match s.trim().contains(['T', ' ']) {
+        true => Ok(time),
+        false => Ok(add_days(time, 1) - 1),
+    }
+}
+
+/// Format as an ISO 8601 UTC date and time, e.g. `2023-04-12T00:00:00Z`
+pub fn format(time: i64) -> String {
+    let (year, month, day) = to_ymd(time);
+    let seconds = time.rem_euclid(SECONDS_PER_DAY);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// CF time coordinate units, e.g. `days since 1970-01-01` or `seconds since 1981-01-01 00:00:00`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeUnits {
+    /// Seconds per unit
+    scale: f64,
+    epoch: i64,
+}
+
+impl TimeUnits {
+    pub fn parse(units: &str) -> anyhow::Result<Self> {
+        let (unit, epoch) = units
+            .split_once(" since ")
+            .ok_or_else(|| anyhow!("Time units {:?} aren't `<unit> since <date>`", units))?;
+        let scale = match unit.trim().to_lowercase().as_str() {
+            "seconds" | "second" | "secs" | "sec" | "s" => 1.0,
+            "minutes" | "minute" | "mins" | "min" => 60.0,
+            "hours" | "hour" | "hrs" | "hr" | "h" => 3600.0,
+            "days" | "day" | "d" => SECONDS_PER_DAY as f64,
+            unit => return Err(anyhow!("Unsupported time unit {:?}", unit)),
+        };
+        // Some files add a time zone offset of zero, e.g. `1970-01-01 00:00:00 +00:00`
+        let epoch = epoch.trim().trim_end_matches(" UTC").trim_end_matches(" +00:00").trim_end_matches(" 0:00");
+        Ok(Self {
+            scale,
+            epoch: parse(epoch)?,
+        })
+    }
+
+    /// The time of a coordinate value, rounded to the second
+    pub fn to_time(&self, value: f64) -> i64 {
+        self.epoch + (value * self.scale).round() as i64
+    }
+}
+
+#[cfg(test)]
+mod time_tests {
+    use super::*;
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(from_ymd(1970, 1, 1), 0);
+        assert_eq!(from_ymd(2023, 4, 12), 1681257600);
+        assert_eq!(to_ymd(1681257600 + 3600), (2023, 4, 12));
+        assert_eq!(to_ymd(from_ymd(2024, 2, 29)), (2024, 2, 29));
+        assert_eq!(to_ymd(-1), (1969, 12, 31));
+        assert_eq!(add_days(from_ymd(2023, 12, 31), 1), from_ymd(2024, 1, 1));
+        assert_eq!(day_of_year(from_ymd(2023, 1, 1) + 3600), 1);
+        assert_eq!(day_of_year(from_ymd(2023, 4, 12)), 102);
+        assert_eq!(day_of_year(from_ymd(2024, 12, 31)), 366);
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!(parse("2023-04-12").unwrap(), 1681257600);
+        assert_eq!(parse("2023-04-12T06:30:00Z").unwrap(), 1681257600 + 6 * 3600 + 1800);
+        assert_eq!(parse("2023-04-12 06:30").unwrap(), 1681257600 + 6 * 3600 + 1800);
+        assert_eq!(format(1681257600 + 6 * 3600 + 1800), "2023-04-12T06:30:00Z");
+        assert!(parse("2023-13-01").is_err());
+        assert!(parse("yesterday").is_err());
+        assert_eq!(parse("0001-01-01").unwrap(), from_ymd(1, 1, 1));
+        assert_eq!(to_ymd(parse_end("9999-12-31").unwrap()), (9999, 12, 31));
+        assert!(parse("99999999999-01-01").is_err());
+        assert!(parse("0000-01-01").is_err());
+        assert!(parse("-5-01-01").is_err());
+        assert!(parse("2023-04-12T1e30").is_err());
+        assert!(parse("2023-04-12T06:60").is_err());
+        assert_eq!(parse_end("2023-04-12").unwrap(), from_ymd(2023, 4, 13) - 1);
+        assert_eq!(parse_end("2023-04-12T06:30:00Z").unwrap(), 1681257600 + 6 * 3600 + 1800);
+    }
+
+    #[test]
+    fn test_time_units() {
+        let units = TimeUnits::parse("days since 1970-01-01").unwrap();
+        assert_eq!(units.to_time(19459.0), 1681257600);
+        let units = TimeUnits::parse("seconds since 1981-01-01 00:00:00").unwrap();
+        assert_eq!(units.to_time(0.0), from_ymd(1981, 1, 1));
+        let units = TimeUnits::parse("hours since 2023-04-12T00:00:00Z").unwrap();
+        assert_eq!(units.to_time(12.0), 1681257600 + 12 * 3600);
+        assert!(TimeUnits::parse("months since 2000-01-01").is_err());
+        assert!(TimeUnits::parse("days").is_err());
+    }
+}
@@ -0,0 +1,587 @@
+use crate::bounds::Bounds;
+use crate::coordinates::{from_tile_coord_to_lat_lng_bounds, TileCoord};
+use anyhow::anyhow;
+use serde::Deserialize;
+use serde_json::json;
+use std::f64::consts::PI;
+
+// WGS84
+const SEMI_MAJOR_AXIS: f64 = 6_378_137.0;
+const ECCENTRICITY: f64 = 0.081_819_190_842_622;
+const WEB_MERCATOR_EXTENT: f64 = PI * SEMI_MAJOR_AXIS;
+// Polar grids cover 4194304 m either side of the pole, like the NSIDC and Arctic SDI tile sets
+const POLAR_EXTENT: f64 = 4_194_304.0;
+// OGC standardized rendering pixel size in meters, for scale denominators
+const PIXEL_SIZE: f64 = 0.00028;
+const METERS_PER_DEGREE: f64 = 2.0 * PI * SEMI_MAJOR_AXIS / 360.0;
+// How many zoom levels the built in sets describe in their JSON
+const MAX_ZOOM: u8 = 24;
+
+/// Map projections the tile matrix sets can be in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Longitude and latitude in degrees
+    Geographic,
+    /// EPSG:3857
+    WebMercator,
+    /// Polar stereographic on WGS84 with a latitude of true scale and a central meridian, in degrees.
+    /// A negative latitude of true scale puts the pole in the south.
+    PolarStereographic { lat_ts: f64, lon_0: f64 },
+}
+
+// Conformal latitude function t from Snyder, Map Projections: A Working Manual, eq. 15-9
+fn snyder_t(lat: f64) -> f64 {
+    let e_sin = ECCENTRICITY * lat.sin();
+    (PI / 4.0 - lat / 2.0).tan() / ((1.0 - e_sin) / (1.0 + e_sin)).powf(ECCENTRICITY / 2.0)
+}
+
+// Snyder eq. 14-15
+fn snyder_m(lat: f64) -> f64 {
+    lat.cos() / (1.0 - (ECCENTRICITY * lat.sin()).powi(2)).sqrt()
+}
+
+impl Projection {
+    /// The projection of a CRS URI or code, e.g. `http://www.opengis.net/def/crs/EPSG/0/3413` or `EPSG:3031`
+    pub fn from_crs(crs: &str) -> Option<Self> {
+        let code = crs.rsplit(['/', ':']).next()?;
+        match code {
+            "CRS84" | "4326" => Some(Projection::Geographic),
+            "3857" | "900913" => Some(Projection::WebMercator),
+            "3413" => Some(Projection::PolarStereographic { lat_ts: 70.0, lon_0: -45.0 }),
+            "3995" => Some(Projection::PolarStereographic { lat_ts: 71.0, lon_0: 0.0 }),
+            "3031" => Some(Projection::PolarStereographic { lat_ts: -71.0, lon_0: 0.0 }),
+            "3976" => Some(Projection::PolarStereographic { lat_ts: -70.0, lon_0: 0.0 }),
+            _ => None,
+        }
+    }
+
+    /// Project a lng/lat in degrees to x/y in the projection's units
+    pub fn forward(&self, lng: f64, lat: f64) -> (f64, f64) {
+        match *self {
+            Projection::Geographic => (lng, lat),
+            Projection::WebMercator => {
+                let lat = lat.clamp(-85.051_128_78, 85.051_128_78).to_radians();
+                (
+                    SEMI_MAJOR_AXIS * lng.to_radians(),
+                    SEMI_MAJOR_AXIS * (PI / 4.0 + lat / 2.0).tan().ln(),
+                )
+            }
+            Projection::PolarStereographic { lat_ts, lon_0 } => {
+                // The south polar case is the north one mirrored, Snyder p. 161
+                let sign = lat_ts.signum();
+                let (lat, lat_ts) = ((sign * lat).to_radians(), (sign * lat_ts).to_radians());
+                let dlon = (sign * (lng - lon_0)).to_radians();
+                let rho = SEMI_MAJOR_AXIS * snyder_m(lat_ts) * snyder_t(lat) / snyder_t(lat_ts);
+                (sign * rho * dlon.sin(), sign * -rho * dlon.cos())
+            }
+        }
+    }
+
+    /// Unproject x/y in the projection's units to a lng/lat in degrees
+    pub fn inverse(&self, x: f64, y: f64) -> (f64, f64) {
+        match *self {
+            Projection::Geographic => (x, y),
+            Projection::WebMercator => {
+                let lat = 2.0 * (y / SEMI_MAJOR_AXIS).exp().atan() - PI / 2.0;
+                ((x / SEMI_MAJOR_AXIS).to_degrees(), lat.to_degrees())
+            }
+            Projection::PolarStereographic { lat_ts, lon_0 } => {
+                let sign = lat_ts.signum();
+                let (x, y) = (sign * x, sign * y);
+                let lat_ts = (sign * lat_ts).to_radians();
+                let rho = x.hypot(y);
+                let t = rho * snyder_t(lat_ts) / (SEMI_MAJOR_AXIS * snyder_m(lat_ts));
+
+                // Snyder eq. 7-9, which converges in a few rounds
+                let mut lat = PI / 2.0 - 2.0 * t.atan();
+                for _ in 0..10 {
+                    let e_sin = ECCENTRICITY * lat.sin();
+                    lat = PI / 2.0 - 2.0 * (t * ((1.0 - e_sin) / (1.0 + e_sin)).powf(ECCENTRICITY / 2.0)).atan();
+                }
+                let lng = sign * x.atan2(-y).to_degrees() + lon_0;
+                // Keep longitudes in -180..180
+                let lng = (lng + 540.0).rem_euclid(360.0) - 180.0;
+                (lng, sign * lat.to_degrees())
+            }
+        }
+    }
+
+    /// The lng/lat box around bounds in the projection's units, from points along their edges, and the whole
+    /// range of longitudes past a pole if one is inside them
+    pub fn lat_lng_envelope(&self, bounds: Bounds) -> Bounds {
+        const STEPS: usize = 16;
+        let mut envelope = Bounds::new(f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        let mut extend = |(lng, lat): (f64, f64)| {
+            envelope = Bounds::new(
+                envelope.min_x.min(lng),
+                envelope.min_y.min(lat),
+                envelope.max_x.max(lng),
+                envelope.max_y.max(lat),
+            );
+        };
+        for i in 0..=STEPS {
+            let t = i as f64 / STEPS as f64;
+            let x = bounds.min_x + t * (bounds.max_x - bounds.min_x);
+            let y = bounds.min_y + t * (bounds.max_y - bounds.min_y);
+            for (x, y) in [(x, bounds.min_y), (x, bounds.max_y), (bounds.min_x, y), (bounds.max_x, y)] {
+                extend(self.inverse(x, y));
+            }
+        }
+        if let Projection::PolarStereographic { lat_ts, .. } = self {
+            let pole = 90.0 * lat_ts.signum();
+            let (x, y) = self.forward(0.0, pole);
+            if x >= bounds.min_x && x <= bounds.max_x && y >= bounds.min_y && y <= bounds.max_y {
+                extend((-180.0, pole));
+                extend((180.0, pole));
+            }
+        }
+        envelope
+    }
+
+    // Meters per projection unit, for scale denominators
+    fn meters_per_unit(&self) -> f64 {
+        match self {
+            Projection::Geographic => METERS_PER_DEGREE,
+            _ => 1.0,
+        }
+    }
+}
+
+/// One zoom level of a tile matrix set, with the origin at the top left corner
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileMatrix {
+    pub id: String,
+    /// Size of a pixel in CRS units
+    pub cell_size: f64,
+    /// Top left corner in CRS units, as (x, y)
+    pub origin: (f64, f64),
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub matrix_width: u32,
+    pub matrix_height: u32,
+}
+
+impl TileMatrix {
+    // A zoom level of a quadtree where zoom 0 is `width` x `height` tiles covering `extent`
+    fn quad(zoom: u8, extent: Bounds, width: u32, height: u32) -> Self {
+        let matrix_width = width << zoom;
+        Self {
+            id: zoom.to_string(),
+            cell_size: (extent.max_x - extent.min_x) / (matrix_width as f64 * 256.0),
+            origin: (extent.min_x, extent.max_y),
+            tile_width: 256,
+            tile_height: 256,
+            matrix_width,
+            matrix_height: height << zoom,
+        }
+    }
+
+    /// Bounds of a tile in CRS units, or None if it's outside the matrix
+    pub fn tile_bounds(&self, x: u32, y: u32) -> Option<Bounds> {
+        if x >= self.matrix_width || y >= self.matrix_height {
+            return None;
+        }
+        let (tile_width, tile_height) = (
+            self.cell_size * self.tile_width as f64,
+            self.cell_size * self.tile_height as f64,
+        );
+        let min_x = self.origin.0 + x as f64 * tile_width;
+        let max_y = self.origin.1 - y as f64 * tile_height;
+        Some(Bounds::new(min_x, max_y - tile_height, min_x + tile_width, max_y))
+    }
+}
+
+/// A tiling scheme: a CRS and the grid of tiles at each zoom level, see OGC Two Dimensional Tile Matrix Set
+pub trait TileMatrixSet: Send + Sync {
+    fn id(&self) -> &str;
+
+    /// CRS URI, e.g. `http://www.opengis.net/def/crs/EPSG/0/3857`
+    fn crs(&self) -> &str;
+
+    fn projection(&self) -> Projection;
+
+    /// The tile matrix at a zoom level, or None past the last one
+    fn matrix(&self, zoom: u8) -> Option<TileMatrix>;
+
+    /// Bounds of a tile in CRS units, or None if it's not in the set
+    fn tile_bounds(&self, tile: &TileCoord) -> Option<Bounds> {
+        self.matrix(tile.zoom())?.tile_bounds(tile.x(), tile.y())
+    }
+
+    /// Bounds of a tile in lng/lat if its rows follow parallels and its columns follow meridians,
+    /// so it can be read as a lat/lon box. Other tiles are sampled pixel by pixel.
+    fn lat_lng_bounds(&self, tile: &TileCoord) -> Option<Bounds> {
+        match self.projection() {
+            Projection::Geographic => self.tile_bounds(tile),
+            _ => None,
+        }
+    }
+
+    /// The set as OGC TileMatrixSet JSON
+    fn to_json(&self) -> serde_json::Value {
+        let meters_per_unit = self.projection().meters_per_unit();
+        let matrices: Vec<serde_json::Value> = (0..=u8::MAX)
+            .map_while(|zoom| self.matrix(zoom))
+            .map(|m| {
+                json!({
+                    "id": m.id,
+                    "scaleDenominator": m.cell_size * meters_per_unit / PIXEL_SIZE,
+                    "cellSize": m.cell_size,
+                    "cornerOfOrigin": "topLeft",
+                    "pointOfOrigin": [m.origin.0, m.origin.1],
+                    "tileWidth": m.tile_width,
+                    "tileHeight": m.tile_height,
+                    "matrixWidth": m.matrix_width,
+                    "matrixHeight": m.matrix_height,
+                })
+            })
+            .collect();
+        json!({
+            "id": self.id(),
+            "crs": self.crs(),
+            "tileMatrices": matrices,
+        })
+    }
+}
+
+/// Google Maps style Web Mercator tiles, the default
+pub struct WebMercatorQuad;
+
+impl TileMatrixSet for WebMercatorQuad {
+    fn id(&self) -> &str {
+        "WebMercatorQuad"
+    }
+
+    fn crs(&self) -> &str {
+        "http://www.opengis.net/def/crs/EPSG/0/3857"
+    }
+
+    fn projection(&self) -> Projection {
+        Projection::WebMercator
+    }
+
+    fn matrix(&self, zoom: u8) -> Option<TileMatrix> {
+        let extent = Bounds::new(-WEB_MERCATOR_EXTENT, -WEB_MERCATOR_EXTENT, WEB_MERCATOR_EXTENT, WEB_MERCATOR_EXTENT);
+        (zoom <= MAX_ZOOM).then(|| TileMatrix::quad(zoom, extent, 1, 1))
+    }
+
+    // Mercator rows are parallels and columns are meridians, so tiles are lat/lon boxes
+    fn lat_lng_bounds(&self, tile: &TileCoord) -> Option<Bounds> {
+        self.tile_bounds(tile)?;
+        Some(from_tile_coord_to_lat_lng_bounds(tile))
+    }
+}
+
+/// Plate carrée tiles on EPSG:4326, two tiles side by side at zoom 0
+pub struct WorldCrs84Quad;
+
+impl TileMatrixSet for WorldCrs84Quad {
+    fn id(&self) -> &str {
+        "WorldCRS84Quad"
+    }
+
+    fn crs(&self) -> &str {
+        "http://www.opengis.net/def/crs/OGC/1.3/CRS84"
+    }
+
+    fn projection(&self) -> Projection {
+        Projection::Geographic
+    }
+
+    fn matrix(&self, zoom: u8) -> Option<TileMatrix> {
+        (zoom <= MAX_ZOOM).then(|| TileMatrix::quad(zoom, Bounds::new(-180.0, -90.0, 180.0, 90.0), 2, 1))
+    }
+}
+
+/// Polar stereographic tiles centered on the pole, one tile at zoom 0
+pub struct PolarQuad {
+    id: &'static str,
+    crs: &'static str,
+    projection: Projection,
+}
+
+impl PolarQuad {
+    /// NSIDC Sea Ice Polar Stereographic North
+    pub fn epsg3413() -> Self {
+        Self {
+            id: "EPSG3413Quad",
+            crs: "http://www.opengis.net/def/crs/EPSG/0/3413",
+            projection: Projection::PolarStereographic { lat_ts: 70.0, lon_0: -45.0 },
+        }
+    }
+
+    /// Antarctic Polar Stereographic
+    pub fn epsg3031() -> Self {
+        Self {
+            id: "EPSG3031Quad",
+            crs: "http://www.opengis.net/def/crs/EPSG/0/3031",
+            projection: Projection::PolarStereographic { lat_ts: -71.0, lon_0: 0.0 },
+        }
+    }
+}
+
+impl TileMatrixSet for PolarQuad {
+    fn id(&self) -> &str {
+        self.id
+    }
+
+    fn crs(&self) -> &str {
+        self.crs
+    }
+
+    fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    fn matrix(&self, zoom: u8) -> Option<TileMatrix> {
+        let extent = Bounds::new(-POLAR_EXTENT, -POLAR_EXTENT, POLAR_EXTENT, POLAR_EXTENT);
+        (zoom <= MAX_ZOOM).then(|| TileMatrix::quad(zoom, extent, 1, 1))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CrsJson {
+    Uri(String),
+    Object { uri: String },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TileMatrixJson {
+    id: String,
+    cell_size: f64,
+    point_of_origin: [f64; 2],
+    #[serde(default)]
+    corner_of_origin: Option<String>,
+    tile_width: u32,
+    tile_height: u32,
+    matrix_width: u32,
+    matrix_height: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TileMatrixSetJson {
+    id: String,
+    crs: CrsJson,
+    tile_matrices: Vec<TileMatrixJson>,
+}
+
+/// A tile matrix set loaded from OGC TileMatrixSet JSON. Zoom levels are the tile matrices in file order.
+pub struct CustomMatrixSet {
+    id: String,
+    crs: String,
+    projection: Projection,
+    matrices: Vec<TileMatrix>,
+}
+
+impl CustomMatrixSet {
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let set: TileMatrixSetJson = serde_json::from_str(json)?;
+        let crs = match set.crs {
+            CrsJson::Uri(uri) | CrsJson::Object { uri } => uri,
+        };
+        let projection = Projection::from_crs(&crs).ok_or_else(|| anyhow!("Unsupported CRS {}", crs))?;
+        // EPSG:4326 lists latitude first
+        let lat_first = crs.ends_with("4326");
+
+        let matrices = set
+            .tile_matrices
+            .into_iter()
+            .map(|m| {
+                if m.corner_of_origin.as_deref().is_some_and(|corner| corner != "topLeft") {
+                    return Err(anyhow!("Tile matrix {} has an unsupported corner of origin", m.id));
+                }
+                let [a, b] = m.point_of_origin;
+                Ok(TileMatrix {
+                    id: m.id,
+                    cell_size: m.cell_size,
+                    origin: if lat_first { (b, a) } else { (a, b) },
+                    tile_width: m.tile_width,
+                    tile_height: m.tile_height,
+                    matrix_width: m.matrix_width,
+                    matrix_height: m.matrix_height,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            id: set.id,
+            crs,
+            projection,
+            matrices,
+        })
+    }
+}
+
+impl TileMatrixSet for CustomMatrixSet {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn crs(&self) -> &str {
+        &self.crs
+    }
+
+    fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    fn matrix(&self, zoom: u8) -> Option<TileMatrix> {
+        self.matrices.get(zoom as usize).cloned()
+    }
+}
+
+/// The tile matrix sets a server offers, looked up by id
+pub struct TileMatrixSets {
+    sets: Vec<Box<dyn TileMatrixSet>>,
+}
+
+impl Default for TileMatrixSets {
+    fn default() -> Self {
+        Self {
+            sets: vec![
+                Box::new(WebMercatorQuad),
+                Box::new(WorldCrs84Quad),
+                Box::new(PolarQuad::epsg3413()),
+                Box::new(PolarQuad::epsg3031()),
+            ],
+        }
+    }
+}
+
+impl TileMatrixSets {
+    /// Add a set, replacing any with the same id
+    pub fn add(&mut self, set: Box<dyn TileMatrixSet>) {
+        self.sets.retain(|s| s.id() != set.id());
+        self.sets.push(set);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&dyn TileMatrixSet> {
+        self.sets.iter().find(|s| s.id() == id).map(|s| s.as_ref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn TileMatrixSet> {
+        self.sets.iter().map(|s| s.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tms_tests {
+    use super::*;
+
+    #[test]
+    fn test_web_mercator_quad() {
+        let tms = WebMercatorQuad;
+        let bounds = tms.tile_bounds(&TileCoord::new(0, 0, 0)).unwrap();
+        assert_relative_eq!(bounds.min_x, -20037508.342789244, epsilon = 1e-6);
+        assert_relative_eq!(bounds.max_y, 20037508.342789244, epsilon = 1e-6);
+        assert!(tms.tile_bounds(&TileCoord::new(2, 0, 1)).is_none());
+
+        // Tiles read as the same lat/lon boxes as before
+        let tile = TileCoord::new(298, 246, 9);
+        let bounds = tms.lat_lng_bounds(&tile).unwrap();
+        assert_relative_eq!(bounds.min_x, 29.531253, epsilon = 0.0001);
+        assert_relative_eq!(bounds.max_y, 7.013666, epsilon = 0.0001);
+
+        let (x, y) = Projection::WebMercator.forward(29.7, 6.5);
+        let (lng, lat) = Projection::WebMercator.inverse(x, y);
+        assert_relative_eq!(lng, 29.7, epsilon = 1e-9);
+        assert_relative_eq!(lat, 6.5, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_world_crs84_quad() {
+        let tms = WorldCrs84Quad;
+        let matrix = tms.matrix(0).unwrap();
+        assert_eq!((matrix.matrix_width, matrix.matrix_height), (2, 1));
+        let bounds = tms.lat_lng_bounds(&TileCoord::new(1, 0, 0)).unwrap();
+        assert_eq!((bounds.min_x, bounds.min_y, bounds.max_x, bounds.max_y), (0.0, -90.0, 180.0, 90.0));
+        let bounds = tms.lat_lng_bounds(&TileCoord::new(0, 1, 1)).unwrap();
+        assert_eq!((bounds.min_x, bounds.min_y, bounds.max_x, bounds.max_y), (-180.0, -90.0, -90.0, 0.0));
+        assert!(tms.tile_bounds(&TileCoord::new(0, 1, 0)).is_none());
+    }
+
+    #[test]
+    fn test_polar_stereographic() {
+        let north = Projection::from_crs("http://www.opengis.net/def/crs/EPSG/0/3413").unwrap();
+        let (x, y) = north.forward(-45.0, 90.0);
+        assert_abs_diff_eq!(x, 0.0, epsilon = 1e-6);
+        assert_abs_diff_eq!(y, 0.0, epsilon = 1e-6);
+        let (x, y) = north.forward(0.0, 75.0);
+        assert_relative_eq!(x, 1155327.272, epsilon = 0.01);
+        assert_relative_eq!(y, -1155327.272, epsilon = 0.01);
+        let (lng, lat) = north.inverse(x, y);
+        assert_abs_diff_eq!(lng, 0.0, epsilon = 1e-9);
+        assert_relative_eq!(lat, 75.0, epsilon = 1e-9);
+
+        let south = Projection::from_crs("EPSG:3031").unwrap();
+        let (x, y) = south.forward(30.0, -75.0);
+        assert_relative_eq!(x, 819391.619, epsilon = 0.01);
+        assert_relative_eq!(y, 1419227.916, epsilon = 0.01);
+        let (lng, lat) = south.inverse(x, y);
+        assert_relative_eq!(lng, 30.0, epsilon = 1e-9);
+        assert_relative_eq!(lat, -75.0, epsilon = 1e-9);
+
+        // Polar tiles aren't lat/lon boxes
+        let tms = PolarQuad::epsg3413();
+        assert!(tms.lat_lng_bounds(&TileCoord::new(0, 0, 1)).is_none());
+        let bounds = tms.tile_bounds(&TileCoord::new(1, 1, 1)).unwrap();
+        assert_eq!((bounds.min_x, bounds.max_y), (0.0, 0.0));
+
+        // A tile with the pole on its corner spans every longitude, and one away from it is bounded by its corners
+        let envelope = north.lat_lng_envelope(bounds);
+        assert_eq!((envelope.min_x, envelope.max_x, envelope.max_y), (-180.0, 180.0, 90.0));
+        let bounds = tms.tile_bounds(&TileCoord::new(3, 3, 2)).unwrap();
+        let envelope = north.lat_lng_envelope(bounds);
+        assert_relative_eq!(envelope.min_y, north.inverse(bounds.max_x, bounds.min_y).1, epsilon = 1e-9);
+        assert_relative_eq!(envelope.max_y, north.inverse(bounds.min_x, bounds.max_y).1, epsilon = 1e-9);
+        assert!(envelope.max_x - envelope.min_x < 90.0);
+    }
+
+    #[test]
+    fn test_custom_matrix_set() {
+        let json = r#"{
+            "id": "ArcticTest",
+            "crs": {"uri": "http://www.opengis.net/def/crs/EPSG/0/3413"},
+            "tileMatrices": [
+                {"id": "a", "scaleDenominator": 1.0, "cellSize": 1000.0, "pointOfOrigin": [-512000.0, 512000.0],
+                 "tileWidth": 512, "tileHeight": 512, "matrixWidth": 2, "matrixHeight": 2},
+                {"id": "b", "scaleDenominator": 0.5, "cellSize": 500.0, "pointOfOrigin": [-512000.0, 512000.0],
+                 "tileWidth": 512, "tileHeight": 512, "matrixWidth": 4, "matrixHeight": 4}
+            ]
+        }"#;
+        let tms = CustomMatrixSet::from_json(json).unwrap();
+        assert_eq!(tms.id(), "ArcticTest");
+        assert!(matches!(tms.projection(), Projection::PolarStereographic { .. }));
+        let bounds = tms.tile_bounds(&TileCoord::new(1, 1, 0)).unwrap();
+        assert_eq!((bounds.min_x, bounds.min_y, bounds.max_x, bounds.max_y), (0.0, -512000.0, 512000.0, 0.0));
+        assert!(tms.matrix(2).is_none());
+
+        // EPSG:4326 origins are lat, lon
+        let json = r#"{"id": "Geo", "crs": "http://www.opengis.net/def/crs/EPSG/0/4326", "tileMatrices": [
+            {"id": "0", "cellSize": 0.5, "pointOfOrigin": [90.0, -180.0],
+             "tileWidth": 256, "tileHeight": 256, "matrixWidth": 3, "matrixHeight": 2}]}"#;
+        let tms = CustomMatrixSet::from_json(json).unwrap();
+        assert_eq!(tms.matrix(0).unwrap().origin, (-180.0, 90.0));
+
+        let json = r#"{"id": "X", "crs": "EPSG:32610", "tileMatrices": []}"#;
+        assert!(CustomMatrixSet::from_json(json).is_err());
+
+        // Round trips through its own JSON
+        let written = WorldCrs84Quad.to_json().to_string();
+        let read = CustomMatrixSet::from_json(&written).unwrap();
+        assert_eq!(read.matrix(3), WorldCrs84Quad.matrix(3));
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut sets = TileMatrixSets::default();
+        assert!(sets.get("WebMercatorQuad").is_some());
+        assert!(sets.get("EPSG3031Quad").is_some());
+        assert!(sets.get("Nope").is_none());
+        sets.add(Box::new(CustomMatrixSet::from_json(&WorldCrs84Quad.to_json().to_string()).unwrap()));
+        assert_eq!(sets.iter().filter(|s| s.id() == "WorldCRS84Quad").count(), 1);
+    }
+}
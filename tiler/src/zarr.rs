@@ -0,0 +1,800 @@
+//! Local Zarr v2 and v3 directory stores, see https://zarr-specs.readthedocs.io. Each array in the store, or the
+//! store itself if it's an array, is a variable. Dimension names come from xarray's `_ARRAY_DIMENSIONS` attribute
+//! in v2 and from `dimension_names` in v3.
+
+use crate::source::{AttrValue, GridSource};
+use anyhow::anyhow;
+use ndarray::{ArrayD, Dimension, IxDyn, Slice};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NumberKind {
+    Float,
+    Int,
+    Uint,
+    Bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DataType {
+    kind: NumberKind,
+    pub(crate) size: usize,
+    big_endian: bool,
+}
+
+impl DataType {
+    // A v2 dtype, e.g. `<f4` or `|u1`
+    fn from_v2(dtype: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow!("Unsupported Zarr dtype {:?}", dtype);
+        let mut chars = dtype.chars();
+        let big_endian = match chars.next() {
+            Some('>') => true,
+            Some('<') | Some('|') => false,
+            _ => return Err(invalid()),
+        };
+        let kind = match chars.next() {
+            Some('f') => NumberKind::Float,
+            Some('i') => NumberKind::Int,
+            Some('u') => NumberKind::Uint,
+            Some('b') => NumberKind::Bool,
+            _ => return Err(invalid()),
+        };
+        let size = chars.as_str().parse().map_err(|_| invalid())?;
+        Self::new(kind, size, big_endian).ok_or_else(invalid)
+    }
+
+    // A v3 data_type, e.g. `float32`, with the endianness of its bytes codec
+    fn from_v3(data_type: &str, big_endian: bool) -> anyhow::Result<Self> {
+        let invalid = || anyhow!("Unsupported Zarr data type {:?}", data_type);
+        let (kind, bits) = if data_type == "bool" {
+            (NumberKind::Bool, "8")
+        } else if let Some(bits) = data_type.strip_prefix("float") {
+            (NumberKind::Float, bits)
+        } else if let Some(bits) = data_type.strip_prefix("uint") {
+            (NumberKind::Uint, bits)
+        } else if let Some(bits) = data_type.strip_prefix("int") {
+            (NumberKind::Int, bits)
+        } else {
+            return Err(invalid());
+        };
+        let bits: usize = bits.parse().map_err(|_| invalid())?;
+        Self::new(kind, bits / 8, big_endian).ok_or_else(invalid)
+    }
+
+    pub(crate) fn new(kind: NumberKind, size: usize, big_endian: bool) -> Option<Self> {
+        let valid = match kind {
+            NumberKind::Float => matches!(size, 4 | 8),
+            NumberKind::Int | NumberKind::Uint => matches!(size, 1 | 2 | 4 | 8),
+            NumberKind::Bool => size == 1,
+        };
+        valid.then_some(Self { kind, size, big_endian })
+    }
+
+    pub(crate) fn decode(&self, bytes: &[u8]) -> Vec<f64> {
+        bytes
+            .chunks_exact(self.size)
+            .map(|b| {
+                let mut buf = [0u8; 8];
+                if self.big_endian {
+                    buf[..self.size].copy_from_slice(b);
+                    buf[..self.size].reverse();
+                } else {
+                    buf[..self.size].copy_from_slice(b);
+                }
+                let bits = u64::from_le_bytes(buf);
+                match (self.kind, self.size) {
+                    (NumberKind::Float, 4) => f32::from_bits(bits as u32) as f64,
+                    (NumberKind::Float, _) => f64::from_bits(bits),
+                    (NumberKind::Int, 1) => bits as u8 as i8 as f64,
+                    (NumberKind::Int, 2) => bits as u16 as i16 as f64,
+                    (NumberKind::Int, 4) => bits as u32 as i32 as f64,
+                    (NumberKind::Int, _) => bits as i64 as f64,
+                    (NumberKind::Uint | NumberKind::Bool, _) => bits as f64,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Byte to byte codecs, which chunks are decoded through in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    Gzip,
+    Zlib,
+    Zstd,
+    /// numcodecs LZ4: a little-endian length, then an LZ4 block
+    Lz4,
+    Blosc,
+    /// A trailing CRC32C checksum, which is dropped
+    Crc32c,
+}
+
+impl Codec {
+    fn from_name(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "gzip" => Ok(Codec::Gzip),
+            "zlib" => Ok(Codec::Zlib),
+            "zstd" => Ok(Codec::Zstd),
+            "lz4" => Ok(Codec::Lz4),
+            "blosc" => Ok(Codec::Blosc),
+            "crc32c" => Ok(Codec::Crc32c),
+            _ => Err(anyhow!("Unsupported Zarr codec {:?}", name)),
+        }
+    }
+
+    fn decode(&self, bytes: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Codec::Gzip => read_all(flate2::read::GzDecoder::new(&bytes[..])),
+            Codec::Zlib => read_all(flate2::read::ZlibDecoder::new(&bytes[..])),
+            Codec::Zstd => zstd_decompress(&bytes),
+            Codec::Lz4 => {
+                let len = bytes.get(..4).ok_or_else(|| anyhow!("Truncated LZ4 chunk"))?;
+                let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+                lz4_decompress(&bytes[4..], len)
+            }
+            Codec::Blosc => blosc_decompress(&bytes),
+            Codec::Crc32c => Ok(bytes[..bytes.len().saturating_sub(4)].to_vec()),
+        }
+    }
+}
+
+pub(crate) fn read_all(mut reader: impl Read) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+pub(crate) fn zstd_decompress(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut src = bytes;
+    let decoder = ruzstd::StreamingDecoder::new(&mut src).map_err(|e| anyhow!("Invalid zstd chunk: {}", e))?;
+    read_all(decoder)
+}
+
+/// Decompress an LZ4 block, see https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md
+fn lz4_decompress(src: &[u8], len: usize) -> anyhow::Result<Vec<u8>> {
+    let truncated = || anyhow!("Truncated LZ4 block");
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 0;
+    // Lengths of 15 continue in the following bytes, until one isn't 255
+    let read_len = |pos: &mut usize, mut n: usize| -> anyhow::Result<usize> {
+        if n == 15 {
+            loop {
+                let b = *src.get(*pos).ok_or_else(truncated)?;
+                *pos += 1;
+                n += b as usize;
+                if b != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(n)
+    };
+
+    while pos < src.len() {
+        let token = src[pos];
+        pos += 1;
+        let literals = read_len(&mut pos, (token >> 4) as usize)?;
+        out.extend_from_slice(src.get(pos..pos + literals).ok_or_else(truncated)?);
+        pos += literals;
+        // The last sequence is only literals
+        if pos >= src.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes([src[pos], *src.get(pos + 1).ok_or_else(truncated)?]) as usize;
+        pos += 2;
+        if offset == 0 || offset > out.len() {
+            return Err(anyhow!("Invalid LZ4 match offset {}", offset));
+        }
+        let match_len = read_len(&mut pos, (token & 15) as usize)? + 4;
+        // Matches can overlap what they copy, so go a byte at a time
+        let from = out.len() - offset;
+        for i in 0..match_len {
+            out.push(out[from + i]);
+        }
+    }
+
+    if out.len() != len {
+        return Err(anyhow!("LZ4 block is {} bytes, expected {}", out.len(), len));
+    }
+    Ok(out)
+}
+
+/// Decompress a Blosc 1 chunk, see https://github.com/Blosc/c-blosc/blob/main/README_CHUNK_FORMAT.rst.
+/// LZ4, zlib and zstd are supported, with or without byte shuffling.
+fn blosc_decompress(src: &[u8]) -> anyhow::Result<Vec<u8>> {
+    const HEADER_LEN: usize = 16;
+    const SHUFFLE: u8 = 0x1;
+    const MEMCPYED: u8 = 0x2;
+    const BITSHUFFLE: u8 = 0x4;
+    const DONT_SPLIT: u8 = 0x10;
+    // Blocks are split into a stream per byte of the type when they're big enough
+    const MAX_SPLITS: usize = 16;
+    const MIN_SPLIT_ELEMENTS: usize = 128;
+
+    let truncated = || anyhow!("Truncated Blosc chunk");
+    let header = src.get(..HEADER_LEN).ok_or_else(truncated)?;
+    let u32_at = |bytes: &[u8], i: usize| -> anyhow::Result<usize> {
+        let b = bytes.get(i..i + 4).ok_or_else(truncated)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+    let (flags, typesize) = (header[2], (header[3] as usize).max(1));
+    let (nbytes, blocksize) = (u32_at(header, 4)?, u32_at(header, 8)?);
+
+    if flags & MEMCPYED != 0 {
+        return Ok(src.get(HEADER_LEN..HEADER_LEN + nbytes).ok_or_else(truncated)?.to_vec());
+    }
+    if flags & BITSHUFFLE != 0 {
+        return Err(anyhow!("Bit shuffled Blosc chunks aren't supported"));
+    }
+    if blocksize == 0 {
+        return Ok(Vec::new());
+    }
+    let decompress: fn(&[u8], usize) -> anyhow::Result<Vec<u8>> = match flags >> 5 {
+        1 => lz4_decompress,
+        3 => |src, _| read_all(flate2::read::ZlibDecoder::new(src)),
+        4 => |src, _| zstd_decompress(src),
+        code => return Err(anyhow!("Unsupported Blosc compressor {}", code)),
+    };
+
+    let mut out = Vec::with_capacity(nbytes);
+    for block in 0..nbytes.div_ceil(blocksize) {
+        let block_len = blocksize.min(nbytes - block * blocksize);
+        let mut pos = u32_at(src, HEADER_LEN + 4 * block)?;
+        let splits = match flags & DONT_SPLIT == 0
+            && typesize <= MAX_SPLITS
+            && block_len / typesize >= MIN_SPLIT_ELEMENTS
+        {
+            true => typesize,
+            false => 1,
+        };
+
+        let mut decoded = Vec::with_capacity(block_len);
+        for _ in 0..splits {
+            let split_len = block_len / splits;
+            let compressed_len = u32_at(src, pos)?;
+            pos += 4;
+            let compressed = src.get(pos..pos + compressed_len).ok_or_else(truncated)?;
+            pos += compressed_len;
+            // Streams that don't compress are stored as they are
+            match compressed_len == split_len {
+                true => decoded.extend_from_slice(compressed),
+                false => decoded.extend(decompress(compressed, split_len)?),
+            }
+        }
+
+        if flags & SHUFFLE != 0 && typesize > 1 {
+            decoded = unshuffle(&decoded, typesize);
+        }
+        out.extend(decoded);
+    }
+    Ok(out)
+}
+
+// Undo a byte shuffle, where all the first bytes of each element come first, then all the second bytes and so on.
+// Bytes past the last whole element aren't shuffled.
+fn unshuffle(src: &[u8], typesize: usize) -> Vec<u8> {
+    let elements = src.len() / typesize;
+    let mut out = vec![0; src.len()];
+    for i in 0..elements {
+        for j in 0..typesize {
+            out[i * typesize + j] = src[j * elements + i];
+        }
+    }
+    out[elements * typesize..].copy_from_slice(&src[elements * typesize..]);
+    out
+}
+
+// Fill values are numbers, or strings for values JSON can't hold
+fn fill_value(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::Bool(b) => Some(*b as u8 as f64),
+        Value::String(s) => match s.as_str() {
+            "NaN" => Some(f64::NAN),
+            "Infinity" => Some(f64::INFINITY),
+            "-Infinity" => Some(f64::NEG_INFINITY),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn attr_value(value: &Value) -> Option<AttrValue> {
+    match value {
+        Value::String(s) => Some(AttrValue::Str(s.clone())),
+        Value::Number(n) => Some(AttrValue::Numbers(vec![n.as_f64()?])),
+        Value::Bool(b) => Some(AttrValue::Numbers(vec![*b as u8 as f64])),
+        Value::Array(values) => match values.first()? {
+            Value::String(_) => {
+                let strings: Option<Vec<&str>> = values.iter().map(Value::as_str).collect();
+                Some(AttrValue::Str(strings?.join(", ")))
+            }
+            _ => Some(AttrValue::Numbers(values.iter().map(Value::as_f64).collect::<Option<_>>()?)),
+        },
+        _ => None,
+    }
+}
+
+fn read_json(path: &Path) -> anyhow::Result<Value> {
+    let json = std::fs::read_to_string(path)?;
+    serde_json::from_str(&json).map_err(|e| anyhow!("Invalid JSON in {:?}: {}", path, e))
+}
+
+fn usizes(value: &Value, name: &str) -> anyhow::Result<Vec<usize>> {
+    value
+        .get(name)
+        .and_then(Value::as_array)
+        .and_then(|values| values.iter().map(|v| v.as_u64().map(|v| v as usize)).collect())
+        .ok_or_else(|| anyhow!("Zarr array has no {}", name))
+}
+
+/// An array's metadata, from `.zarray` and `.zattrs` in v2 or `zarr.json` in v3
+#[derive(Debug, Clone)]
+struct ZarrArray {
+    dir: PathBuf,
+    shape: Vec<usize>,
+    chunks: Vec<usize>,
+    data_type: DataType,
+    fill_value: Option<f64>,
+    dimension_names: Vec<String>,
+    attributes: Vec<(String, AttrValue)>,
+    codecs: Vec<Codec>,
+    /// `c` for v3 default keys, which start `c/`
+    key_prefix: Option<&'static str>,
+    separator: String,
+}
+
+impl ZarrArray {
+    fn open_v2(dir: &Path) -> anyhow::Result<Self> {
+        let meta = read_json(&dir.join(".zarray"))?;
+        if meta.get("order").and_then(Value::as_str) == Some("F") {
+            return Err(anyhow!("Fortran ordered Zarr arrays aren't supported"));
+        }
+        if meta.get("filters").and_then(Value::as_array).is_some_and(|filters| !filters.is_empty()) {
+            return Err(anyhow!("Zarr filters aren't supported"));
+        }
+        let codecs = match meta.get("compressor").and_then(|c| c.get("id")).and_then(Value::as_str) {
+            Some(id) => vec![Codec::from_name(id)?],
+            None => Vec::new(),
+        };
+        let dtype = meta.get("dtype").and_then(Value::as_str).ok_or_else(|| anyhow!("Zarr array has no dtype"))?;
+
+        let attrs = match dir.join(".zattrs").exists() {
+            true => read_json(&dir.join(".zattrs"))?,
+            false => Value::Null,
+        };
+        let dimension_names = attrs
+            .get("_ARRAY_DIMENSIONS")
+            .and_then(Value::as_array)
+            .map(|names| names.iter().filter_map(|n| n.as_str().map(str::to_string)).collect());
+        let attributes = attrs
+            .as_object()
+            .map(|attrs| {
+                attrs
+                    .iter()
+                    .filter(|(name, _)| *name != "_ARRAY_DIMENSIONS")
+                    .filter_map(|(name, value)| Some((name.clone(), attr_value(value)?)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self::new(
+            dir,
+            usizes(&meta, "shape")?,
+            usizes(&meta, "chunks")?,
+            DataType::from_v2(dtype)?,
+            meta.get("fill_value").and_then(fill_value),
+            dimension_names,
+            attributes,
+            codecs,
+            None,
+            meta.get("dimension_separator").and_then(Value::as_str).unwrap_or("."),
+        )
+    }
+
+    fn open_v3(dir: &Path, meta: &Value) -> anyhow::Result<Self> {
+        let chunks = meta
+            .get("chunk_grid")
+            .filter(|grid| grid.get("name").and_then(Value::as_str) == Some("regular"))
+            .and_then(|grid| grid.get("configuration"))
+            .ok_or_else(|| anyhow!("Only regular Zarr chunk grids are supported"))
+            .and_then(|config| usizes(config, "chunk_shape"))?;
+
+        // The array to bytes codec sets the byte order, and any after it are byte to byte
+        let mut big_endian = false;
+        let mut codecs = Vec::new();
+        for codec in meta.get("codecs").and_then(Value::as_array).into_iter().flatten() {
+            let name = codec.get("name").and_then(Value::as_str).unwrap_or_default();
+            let config = codec.get("configuration");
+            match name {
+                "bytes" => big_endian = config.and_then(|c| c.get("endian")).and_then(Value::as_str) == Some("big"),
+                // The v3 blosc codec names the shuffle in its configuration, but the chunk header has it too
+                name => codecs.push(Codec::from_name(name)?),
+            }
+        }
+        let data_type = meta.get("data_type").and_then(Value::as_str).ok_or_else(|| anyhow!("No data_type"))?;
+
+        let key_encoding = meta.get("chunk_key_encoding");
+        let v2_keys = key_encoding.and_then(|e| e.get("name")).and_then(Value::as_str) == Some("v2");
+        let separator = key_encoding
+            .and_then(|e| e.get("configuration"))
+            .and_then(|c| c.get("separator"))
+            .and_then(Value::as_str)
+            .unwrap_or(if v2_keys { "." } else { "/" });
+
+        let dimension_names = meta
+            .get("dimension_names")
+            .and_then(Value::as_array)
+            .and_then(|names| names.iter().map(|n| n.as_str().map(str::to_string)).collect());
+        let attributes = meta
+            .get("attributes")
+            .and_then(Value::as_object)
+            .map(|attrs| attrs.iter().filter_map(|(name, v)| Some((name.clone(), attr_value(v)?))).collect())
+            .unwrap_or_default();
+
+        Self::new(
+            dir,
+            usizes(meta, "shape")?,
+            chunks,
+            DataType::from_v3(data_type, big_endian)?,
+            meta.get("fill_value").and_then(fill_value),
+            dimension_names,
+            attributes,
+            codecs,
+            (!v2_keys).then_some("c"),
+            separator,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        dir: &Path,
+        shape: Vec<usize>,
+        chunks: Vec<usize>,
+        data_type: DataType,
+        fill_value: Option<f64>,
+        dimension_names: Option<Vec<String>>,
+        mut attributes: Vec<(String, AttrValue)>,
+        codecs: Vec<Codec>,
+        key_prefix: Option<&'static str>,
+        separator: &str,
+    ) -> anyhow::Result<Self> {
+        if chunks.len() != shape.len() || chunks.contains(&0) {
+            return Err(anyhow!("Zarr array {:?} has invalid chunks {:?}", dir, chunks));
+        }
+        let dimension_names = dimension_names
+            .filter(|names| names.len() == shape.len())
+            .unwrap_or_else(|| (0..shape.len()).map(|i| format!("dim_{}", i)).collect());
+        // The fill value is where CF readers expect to find it
+        if let Some(fill) = fill_value.filter(|fill| !fill.is_nan()) {
+            if !attributes.iter().any(|(name, _)| name == "_FillValue") {
+                attributes.push(("_FillValue".to_string(), AttrValue::Numbers(vec![fill])));
+            }
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            shape,
+            chunks,
+            data_type,
+            fill_value,
+            dimension_names,
+            attributes,
+            codecs,
+            key_prefix,
+            separator: separator.to_string(),
+        })
+    }
+
+    fn chunk_path(&self, index: &[usize]) -> PathBuf {
+        let mut parts: Vec<String> = self.key_prefix.iter().map(|p| p.to_string()).collect();
+        parts.extend(index.iter().map(|i| i.to_string()));
+        // Scalars are chunk `0` in v2
+        if parts.is_empty() {
+            parts.push("0".to_string());
+        }
+        self.dir.join(parts.join(&self.separator))
+    }
+
+    // A whole chunk, with missing chunks all fill value
+    fn read_chunk(&self, index: &[usize]) -> anyhow::Result<ArrayD<f64>> {
+        let len: usize = self.chunks.iter().product();
+        let values = match std::fs::read(self.chunk_path(index)) {
+            Ok(mut bytes) => {
+                for codec in &self.codecs {
+                    bytes = codec.decode(bytes)?;
+                }
+                let values = self.data_type.decode(&bytes);
+                if values.len() != len {
+                    return Err(anyhow!("Zarr chunk {:?} of {:?} has {} values", index, self.dir, values.len()));
+                }
+                values
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![self.fill_value.unwrap_or(f64::NAN); len],
+            Err(e) => return Err(e.into()),
+        };
+        Ok(ArrayD::from_shape_vec(IxDyn(&self.chunks), values)?)
+    }
+
+    fn read(&self, start: &[usize], count: &[usize]) -> anyhow::Result<ArrayD<f64>> {
+        if start.len() != self.shape.len() || count.len() != self.shape.len() {
+            return Err(anyhow!("Zarr array {:?} has {} dimensions", self.dir, self.shape.len()));
+        }
+        if start.iter().zip(count).zip(&self.shape).any(|((s, c), len)| s + c > *len) {
+            return Err(anyhow!("Read of {:?} from {:?} is past the end of {:?}", count, start, self.shape));
+        }
+        let mut out = ArrayD::from_elem(IxDyn(count), f64::NAN);
+        if count.contains(&0) {
+            return Ok(out);
+        }
+
+        // Copy the overlap with each chunk the read touches
+        let first: Vec<usize> = start.iter().zip(&self.chunks).map(|(s, c)| s / c).collect();
+        let chunk_counts: Vec<usize> = (0..start.len())
+            .map(|axis| (start[axis] + count[axis] - 1) / self.chunks[axis] - first[axis] + 1)
+            .collect();
+        for offset in ndarray::indices(IxDyn(&chunk_counts)) {
+            let index: Vec<usize> = first.iter().zip(offset.slice()).map(|(f, o)| f + o).collect();
+            let chunk = self.read_chunk(&index)?;
+            // The overlap in array coordinates
+            let ranges: Vec<(usize, usize)> = (0..start.len())
+                .map(|axis| {
+                    let chunk_start = index[axis] * self.chunks[axis];
+                    let from = start[axis].max(chunk_start);
+                    let to = (start[axis] + count[axis]).min(chunk_start + self.chunks[axis]);
+                    (from, to)
+                })
+                .collect();
+            let source = chunk.slice_each_axis(|ax| {
+                let chunk_start = index[ax.axis.index()] * self.chunks[ax.axis.index()];
+                let (from, to) = ranges[ax.axis.index()];
+                Slice::from(from - chunk_start..to - chunk_start)
+            });
+            out.slice_each_axis_mut(|ax| {
+                let (from, to) = ranges[ax.axis.index()];
+                let s = start[ax.axis.index()];
+                Slice::from(from - s..to - s)
+            })
+            .assign(&source);
+        }
+        Ok(out)
+    }
+}
+
+/// A Zarr store in a local directory
+pub struct ZarrStore {
+    arrays: BTreeMap<String, ZarrArray>,
+}
+
+impl ZarrStore {
+    /// Whether a path is a Zarr group or array directory
+    pub fn is_store(path: &Path) -> bool {
+        path.is_dir() && [".zgroup", ".zarray", "zarr.json"].iter().any(|name| path.join(name).exists())
+    }
+
+    /// Read the metadata of every array in the store. Nested groups aren't searched.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        // A store that's a lone array is named after its directory, e.g. `chl.zarr` is `chl`
+        let own_name = || path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let mut arrays = BTreeMap::new();
+
+        if path.join("zarr.json").exists() {
+            let meta = read_json(&path.join("zarr.json"))?;
+            if meta.get("node_type").and_then(Value::as_str) == Some("array") {
+                arrays.insert(own_name(), ZarrArray::open_v3(path, &meta)?);
+                return Ok(Self { arrays });
+            }
+        } else if path.join(".zarray").exists() {
+            arrays.insert(own_name(), ZarrArray::open_v2(path)?);
+            return Ok(Self { arrays });
+        }
+
+        for entry in std::fs::read_dir(path)? {
+            let dir = entry?.path();
+            let name = dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            if dir.join(".zarray").exists() {
+                arrays.insert(name, ZarrArray::open_v2(&dir)?);
+            } else if dir.join("zarr.json").exists() {
+                let meta = read_json(&dir.join("zarr.json"))?;
+                if meta.get("node_type").and_then(Value::as_str) == Some("array") {
+                    arrays.insert(name, ZarrArray::open_v3(&dir, &meta)?);
+                }
+            }
+        }
+        Ok(Self { arrays })
+    }
+
+    fn array(&self, var: &str) -> anyhow::Result<&ZarrArray> {
+        self.arrays.get(var).ok_or_else(|| anyhow!("No variable {} in dataset", var))
+    }
+}
+
+impl GridSource for ZarrStore {
+    fn variables(&self) -> Vec<String> {
+        self.arrays.keys().cloned().collect()
+    }
+
+    fn dimensions(&self, var: &str) -> Option<Vec<(String, usize)>> {
+        let array = self.arrays.get(var)?;
+        Some(array.dimension_names.iter().cloned().zip(array.shape.iter().copied()).collect())
+    }
+
+    fn attributes(&self, var: &str) -> Vec<(String, AttrValue)> {
+        self.arrays.get(var).map(|array| array.attributes.clone()).unwrap_or_default()
+    }
+
+    fn read(&self, var: &str, start: &[usize], count: &[usize]) -> anyhow::Result<ArrayD<f64>> {
+        self.array(var)?.read(start, count)
+    }
+}
+
+#[cfg(test)]
+mod zarr_tests {
+    use super::*;
+    use crate::bounds::Bounds;
+    use crate::dataset::Dataset;
+    use crate::testing::TempPath;
+    use std::io::Write;
+
+    fn le_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn write_json(path: &Path, json: Value) {
+        std::fs::write(path, json.to_string()).unwrap();
+    }
+
+    // chl is 10 * row + col over a 4 x 5 grid, in 3 x 2 chunks
+    fn chl(row: usize, col: usize) -> f32 {
+        (10 * row + col) as f32
+    }
+
+    fn chunk_values(row_chunk: usize, col_chunk: usize) -> Vec<f32> {
+        let mut values = Vec::new();
+        for row in row_chunk * 3..row_chunk * 3 + 3 {
+            for col in col_chunk * 2..col_chunk * 2 + 2 {
+                values.push(if row < 4 && col < 5 { chl(row, col) } else { -999.0 });
+            }
+        }
+        values
+    }
+
+    fn write_v2_store(dir: &Path) {
+        write_json(&dir.join(".zgroup"), serde_json::json!({"zarr_format": 2}));
+        for (name, values) in [("lat", vec![48.0, 49.0, 50.0, 51.0]), ("lon", vec![-130.0, -129.0, -128.0, -127.0, -126.0])] {
+            let array = dir.join(name);
+            std::fs::create_dir(&array).unwrap();
+            write_json(
+                &array.join(".zarray"),
+                serde_json::json!({"zarr_format": 2, "shape": [values.len()], "chunks": [values.len()], "dtype": "<f8",
+                    "compressor": null, "fill_value": "NaN", "order": "C", "filters": null}),
+            );
+            write_json(&array.join(".zattrs"), serde_json::json!({"_ARRAY_DIMENSIONS": [name], "units": "degrees"}));
+            std::fs::write(array.join("0"), values.iter().flat_map(|v: &f64| v.to_le_bytes()).collect::<Vec<u8>>())
+                .unwrap();
+        }
+
+        let array = dir.join("chl");
+        std::fs::create_dir(&array).unwrap();
+        write_json(
+            &array.join(".zarray"),
+            serde_json::json!({"zarr_format": 2, "shape": [4, 5], "chunks": [3, 2], "dtype": "<f4",
+                "compressor": {"id": "gzip", "level": 1}, "fill_value": -999.0, "order": "C", "filters": null}),
+        );
+        write_json(&array.join(".zattrs"), serde_json::json!({"_ARRAY_DIMENSIONS": ["lat", "lon"]}));
+        for row_chunk in 0..2 {
+            for col_chunk in 0..3 {
+                // Leave a chunk out, which reads as fill
+                if (row_chunk, col_chunk) != (1, 2) {
+                    let bytes = gzip(&le_bytes(&chunk_values(row_chunk, col_chunk)));
+                    std::fs::write(array.join(format!("{}.{}", row_chunk, col_chunk)), bytes).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_v2_store() {
+        let dir = TempPath::dir("v2.zarr");
+        write_v2_store(&dir);
+        assert!(ZarrStore::is_store(&dir));
+        let store = ZarrStore::open(&dir).unwrap();
+
+        assert_eq!(store.variables(), vec!["chl", "lat", "lon"]);
+        assert_eq!(store.dimensions("chl").unwrap(), vec![("lat".to_string(), 4), ("lon".to_string(), 5)]);
+        assert_eq!(store.attribute("lat", "units"), Some(AttrValue::Str("degrees".to_string())));
+        assert_eq!(store.attribute("chl", "_FillValue"), Some(AttrValue::Numbers(vec![-999.0])));
+
+        // Across all six chunks
+        let values = store.read("chl", &[1, 1], &[3, 4]).unwrap();
+        assert_eq!(values.shape(), &[3, 4]);
+        assert_eq!(values[[0, 0]], 11.0);
+        assert_eq!(values[[2, 2]], 33.0);
+        // From the missing chunk
+        assert_eq!(values[[2, 3]], -999.0);
+        assert!(store.read("chl", &[3, 0], &[2, 1]).is_err());
+
+        // Datasets read it the same as NetCDF, with fill as NaN
+        let dset = Dataset::new(&dir, "lat", "lon").unwrap();
+        let bounds = dset.get_bounds();
+        assert_eq!((bounds.min_x, bounds.max_y), (-130.0, 51.0));
+        assert_eq!(dset.get_value("chl", -129.0, 50.0).unwrap(), Some(21.0));
+        assert!(dset.get_value("chl", -126.0, 51.0).unwrap().unwrap().is_nan());
+        let values = dset.get_values("chl", Bounds::new(-130.0, 48.0, -128.0, 50.0)).unwrap();
+        assert_eq!(values.shape(), &[2, 2]);
+        assert_eq!(values[[1, 1]], 11.0);
+    }
+
+    #[test]
+    fn test_v3_store() {
+        let dir = TempPath::dir("v3.zarr");
+        write_json(&dir.join("zarr.json"), serde_json::json!({"zarr_format": 3, "node_type": "group"}));
+        let array = dir.join("sst");
+        std::fs::create_dir_all(array.join("c").join("0")).unwrap();
+        write_json(
+            &array.join("zarr.json"),
+            serde_json::json!({"zarr_format": 3, "node_type": "array", "shape": [2, 3], "data_type": "int16",
+                "chunk_grid": {"name": "regular", "configuration": {"chunk_shape": [2, 2]}},
+                "chunk_key_encoding": {"name": "default", "configuration": {"separator": "/"}},
+                "fill_value": 0, "dimension_names": ["y", "x"], "attributes": {"scale": [0.5, 2]},
+                "codecs": [{"name": "bytes", "configuration": {"endian": "big"}}, {"name": "gzip"}]}),
+        );
+        let chunk = |values: [i16; 4]| gzip(&values.iter().flat_map(|v| v.to_be_bytes()).collect::<Vec<u8>>());
+        std::fs::write(array.join("c/0/0"), chunk([1, -2, 4, 5])).unwrap();
+        std::fs::write(array.join("c/0/1"), chunk([3, 0, 6, 0])).unwrap();
+
+        let store = ZarrStore::open(&dir).unwrap();
+        assert_eq!(store.dimensions("sst").unwrap(), vec![("y".to_string(), 2), ("x".to_string(), 3)]);
+        assert_eq!(store.attribute("sst", "scale"), Some(AttrValue::Numbers(vec![0.5, 2.0])));
+        let values = store.read_all("sst").unwrap();
+        assert_eq!(values.into_raw_vec(), vec![1.0, -2.0, 3.0, 4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn test_lz4() {
+        // Three literals, then a match that overlaps itself
+        let block = [0x35, b'a', b'b', b'c', 0x03, 0x00];
+        assert_eq!(lz4_decompress(&block, 12).unwrap(), b"abcabcabcabc");
+        // A literal run over 15 bytes long
+        let mut block = vec![0xF0, 0x01];
+        block.extend(b"0123456789abcdef");
+        assert_eq!(lz4_decompress(&block, 16).unwrap(), b"0123456789abcdef");
+        assert!(lz4_decompress(&[0x10, b'a', 0x05, 0x00], 5).is_err());
+    }
+
+    #[test]
+    fn test_blosc() {
+        let values = le_bytes(&[1.0, 2.0, 3.0, 4.0]);
+        // Shuffled, so all the first bytes come first
+        let shuffled: Vec<u8> = (0..4).flat_map(|byte| values.chunks(4).map(move |v| v[byte])).collect();
+        let mut lz4 = vec![0xF0, 0x01];
+        lz4.extend(&shuffled);
+
+        // Header: version, format version, LZ4 and shuffle flags, type size, then lengths and a block start
+        let mut chunk = vec![2, 1, 0x21, 4];
+        chunk.extend(16u32.to_le_bytes());
+        chunk.extend(16u32.to_le_bytes());
+        chunk.extend((24 + lz4.len() as u32).to_le_bytes());
+        chunk.extend(20u32.to_le_bytes());
+        chunk.extend((lz4.len() as u32).to_le_bytes());
+        chunk.extend(&lz4);
+        assert_eq!(blosc_decompress(&chunk).unwrap(), values);
+
+        // Chunks that don't compress are copied after the header
+        let mut chunk = vec![2, 1, 0x02, 4];
+        chunk.extend(16u32.to_le_bytes());
+        chunk.extend(16u32.to_le_bytes());
+        chunk.extend(32u32.to_le_bytes());
+        chunk.extend(&values);
+        assert_eq!(blosc_decompress(&chunk).unwrap(), values);
+    }
+}
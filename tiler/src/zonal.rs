@@ -0,0 +1,176 @@
+//! Statistics over the grid cells inside a GeoJSON polygon or multipolygon, e.g. a marine protected area
+
+use crate::dataset::Dataset;
+use crate::expr::Expr;
+use crate::region::Region;
+use serde::Serialize;
+
+/// Percentiles reported when none are asked for
+pub const DEFAULT_PERCENTILES: [f64; 5] = [10.0, 25.0, 50.0, 75.0, 90.0];
+
+/// A value below which a given percent of the region's area lies
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Percentile {
+    pub percent: f64,
+    pub value: f64,
+}
+
+/// Area weighted statistics of the valid (non-NaN) cells in a region
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ZonalStats {
+    /// Cells with data
+    pub count: usize,
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+    pub std: f64,
+    pub percentiles: Vec<Percentile>,
+}
+
+impl ZonalStats {
+    /// Statistics of values with their cell areas, in any unit. Percentiles are the smallest value with
+    /// at least that percent of the total weight at or below it.
+    pub fn from_weighted(values: impl IntoIterator<Item = (f64, f64)>, percents: &[f64]) -> Self {
+        let mut values: Vec<(f64, f64)> = values.into_iter().filter(|(v, w)| !v.is_nan() && *w > 0.0).collect();
+        let total: f64 = values.iter().map(|(_, w)| w).sum();
+        if values.is_empty() {
+            return Self {
+                count: 0,
+                mean: f64::NAN,
+                min: f64::NAN,
+                max: f64::NAN,
+                std: f64::NAN,
+                percentiles: percents.iter().map(|p| Percentile { percent: *p, value: f64::NAN }).collect(),
+            };
+        }
+
+        values.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mean = values.iter().map(|(v, w)| v * w).sum::<f64>() / total;
+        // Population standard deviation about the weighted mean
+        let variance = values.iter().map(|(v, w)| w * (v - mean) * (v - mean)).sum::<f64>() / total;
+        let percentiles = percents
+            .iter()
+            .map(|percent| {
+                let target = percent / 100.0 * total;
+                let mut cumulative = 0.0;
+                let value = values
+                    .iter()
+                    .find(|(_, w)| {
+                        cumulative += w;
+                        // Rounding can leave the total just short of the target
+                        cumulative >= target * (1.0 - 1e-12)
+                    })
+                    .map_or(values[values.len() - 1].0, |(v, _)| *v);
+                Percentile {
+                    percent: *percent,
+                    value,
+                }
+            })
+            .collect();
+
+        Self {
+            count: values.len(),
+            mean,
+            min: values[0].0,
+            max: values[values.len() - 1].0,
+            std: variance.max(0.0).sqrt(),
+            percentiles,
+        }
+    }
+}
+
+/// Statistics of an expression over the cells whose centres are inside the region, weighted by cell area: the
+/// cosine of the latitude for lat/lon grids, and equal for projected ones. A region smaller than a cell takes
+/// the cell under the middle of its bounds.
+pub fn zonal_stats(dset: &Dataset, region: &Region, expr: &Expr, percents: &[f64]) -> anyhow::Result<ZonalStats> {
+    let region_bounds = region.bounds();
+    let dset_bounds = dset.get_bounds();
+    // A cell of margin keeps the cells along the region's edges
+    let (dx, dy) = dset.cell_size(0);
+    let bounds = dset.source_bounds(region_bounds).and_then(|bounds| dset_bounds.intersect(&bounds.expand(dx, dy)));
+    let Some(bounds) = bounds else {
+        return Ok(ZonalStats::from_weighted([], percents));
+    };
+
+    let values = dset.get_expr_values(expr, bounds)?.into_dimensionality::<ndarray::Ix2>()?;
+    let (ys, xs) = dset.get_coords(bounds);
+    let mut points: Vec<(f64, f64)> = ys.iter().flat_map(|y| xs.iter().map(move |x| (*x, *y))).collect();
+    if let Some(crs) = dset.crs() {
+        crs.to_lng_lat(&mut points);
+    }
+    let weight = |lat: f64| match dset.crs() {
+        Some(_) => 1.0,
+        None => lat.to_radians().cos(),
+    };
+
+    let cells: Vec<(f64, f64)> = points
+        .iter()
+        .zip(values.iter())
+        .zip(region.contains_all(&points))
+        .filter(|(_, inside)| *inside)
+        .map(|(((_, lat), value), _)| (*value, weight(*lat)))
+        .collect();
+    if !cells.is_empty() {
+        return Ok(ZonalStats::from_weighted(cells, percents));
+    }
+
+    let lng = (region_bounds.min_x + region_bounds.max_x) / 2.0;
+    let lat = (region_bounds.min_y + region_bounds.max_y) / 2.0;
+    let (x, y) = dset.source_point(lng, lat);
+    if x.is_nan() || y.is_nan() {
+        return Ok(ZonalStats::from_weighted([], percents));
+    }
+    let value = dset.get_expr_value(expr, x, y)?;
+    Ok(ZonalStats::from_weighted(value.map(|v| (v, 1.0)), percents))
+}
+
+#[cfg(test)]
+mod zonal_tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn square(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Value {
+        json!({
+            "type": "Polygon",
+            "coordinates": [[[min_x, min_y], [max_x, min_y], [max_x, max_y], [min_x, max_y], [min_x, min_y]]]
+        })
+    }
+
+    #[test]
+    fn test_weighted_stats() {
+        let values = [(1.0, 1.0), (2.0, 1.0), (f64::NAN, 5.0), (4.0, 2.0)];
+        let stats = ZonalStats::from_weighted(values, &[25.0, 50.0, 100.0]);
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.mean, 2.75);
+        assert_eq!((stats.min, stats.max), (1.0, 4.0));
+        assert_relative_eq!(stats.std, ((3.0625 + 0.5625 + 2.0 * 1.5625) / 4.0f64).sqrt());
+        let values: Vec<f64> = stats.percentiles.iter().map(|p| p.value).collect();
+        assert_eq!(values, [1.0, 2.0, 4.0]);
+
+        let empty = ZonalStats::from_weighted([], &[50.0]);
+        assert_eq!(empty.count, 0);
+        assert!(empty.mean.is_nan() && empty.percentiles[0].value.is_nan());
+    }
+
+    #[test]
+    fn test_zonal_stats() {
+        let path = crate::geotiff::write_test_grid("zonal", |row, col| (10 * row + col) as f64);
+        let dset = Dataset::new(&path, "lat", "lon").unwrap();
+        let expr = Expr::variable("band_1");
+
+        let region = Region::from_geojson(&square(1.0, 1.0, 3.0, 3.0)).unwrap();
+        let stats = zonal_stats(&dset, &region, &expr, &[50.0]).unwrap();
+        assert_eq!(stats.count, 4);
+        assert_eq!((stats.min, stats.max), (31.0, 42.0));
+        let (north, south) = (2.5f64.to_radians().cos(), 1.5f64.to_radians().cos());
+        assert_relative_eq!(stats.mean, (63.0 * north + 83.0 * south) / (2.0 * (north + south)));
+        // The southern cells are slightly larger, so hold over half of the area
+        assert_eq!(stats.percentiles[0].value, 41.0);
+
+        let inside_cell = Region::from_geojson(&square(4.2, 2.2, 4.4, 2.4)).unwrap();
+        assert_eq!(zonal_stats(&dset, &inside_cell, &expr, &[]).unwrap().mean, 34.0);
+
+        let outside = Region::from_geojson(&square(20.0, 20.0, 21.0, 21.0)).unwrap();
+        assert_eq!(zonal_stats(&dset, &outside, &expr, &[]).unwrap().count, 0);
+    }
+}